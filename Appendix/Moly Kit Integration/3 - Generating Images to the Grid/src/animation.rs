@@ -0,0 +1,256 @@
+use image::{AnimationDecoder, Frame, Frames};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Seek};
+use std::path::Path;
+use std::sync::mpsc::{Receiver, TryRecvError, sync_channel};
+
+use crate::decoders::{DecodeError, DecodedImage};
+use crate::source::ImageSource;
+
+const BUFFERED_FRAMES: usize = 4;
+const MIN_SPEED: f64 = 0.25;
+const MAX_SPEED: f64 = 4.0;
+
+// Browsers treat very short GIF delays as "unspecified" and fall back to
+// 100ms, and many files in the wild rely on that.
+const MIN_FRAME_DELAY: f64 = 0.02;
+const DEFAULT_FRAME_DELAY: f64 = 0.1;
+
+pub struct AnimationFrame {
+    pub index: usize,
+    pub image: DecodedImage,
+    pub delay: f64,
+}
+
+impl AnimationFrame {
+    fn new(index: usize, frame: Frame) -> Self {
+        let (numer, denom) = frame.delay().numer_denom_ms();
+        let delay = numer as f64 / denom.max(1) as f64 / 1000.0;
+        let delay = if delay < MIN_FRAME_DELAY {
+            DEFAULT_FRAME_DELAY
+        } else {
+            delay
+        };
+
+        let buffer = frame.into_buffer();
+        let image = DecodedImage::from_rgba8(
+            buffer.width() as usize,
+            buffer.height() as usize,
+            buffer.as_raw(),
+        );
+
+        Self {
+            index,
+            image,
+            delay,
+        }
+    }
+}
+
+trait ReadSeek: BufRead + Seek + Send {}
+
+impl<T: BufRead + Seek + Send> ReadSeek for T {}
+
+// Files are read as the decoder goes, so checking for an animation only
+// reads the start of them. Archive entries are read whole.
+fn open(source: &ImageSource) -> Result<Box<dyn ReadSeek>, DecodeError> {
+    match source.file_path() {
+        Some(path) => Ok(Box::new(BufReader::new(File::open(path)?))),
+        None => Ok(Box::new(Cursor::new(source.read()?))),
+    }
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+fn decode_error(e: image::ImageError) -> DecodeError {
+    DecodeError::Decode(e.to_string())
+}
+
+// Whether the format can hold an animation, from the extension alone.
+pub fn may_be_animated(source: &ImageSource) -> bool {
+    match extension(&source.path()).as_str() {
+        #[cfg(feature = "gif")]
+        "gif" => true,
+        #[cfg(feature = "apng")]
+        "png" => true,
+        #[cfg(feature = "webp")]
+        "webp" => true,
+        _ => false,
+    }
+}
+
+// Reads the file, so it's best kept off the UI thread.
+pub fn is_animated(source: &ImageSource) -> bool {
+    if !may_be_animated(source) {
+        return false;
+    }
+    let Ok(_reader) = open(source) else {
+        return false;
+    };
+
+    match extension(&source.path()).as_str() {
+        #[cfg(feature = "gif")]
+        "gif" => image::codecs::gif::GifDecoder::new(_reader)
+            .map(|d| d.into_frames().take(2).count() > 1)
+            .unwrap_or(false),
+        #[cfg(feature = "apng")]
+        "png" => image::codecs::png::PngDecoder::new(_reader)
+            .and_then(|d| d.is_apng())
+            .unwrap_or(false),
+        #[cfg(feature = "webp")]
+        "webp" => image::codecs::webp::WebPDecoder::new(_reader)
+            .map(|d| d.has_animation())
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+fn decode_frames(source: &ImageSource) -> Result<Frames<'static>, DecodeError> {
+    let _reader = open(source)?;
+
+    match extension(&source.path()).as_str() {
+        #[cfg(feature = "gif")]
+        "gif" => Ok(image::codecs::gif::GifDecoder::new(_reader)
+            .map_err(decode_error)?
+            .into_frames()),
+        #[cfg(feature = "apng")]
+        "png" => Ok(image::codecs::png::PngDecoder::new(_reader)
+            .and_then(|d| d.apng())
+            .map_err(decode_error)?
+            .into_frames()),
+        #[cfg(feature = "webp")]
+        "webp" => Ok(image::codecs::webp::WebPDecoder::new(_reader)
+            .map_err(decode_error)?
+            .into_frames()),
+        e => Err(DecodeError::Unsupported(format!("animated {e}"))),
+    }
+}
+
+// Frames are decoded on a background thread and handed over through a small
+// bounded channel, so only a few of them are ever held in memory. Dropping
+// the stream disconnects the channel, which stops the thread.
+struct FrameStream {
+    receiver: Receiver<Result<AnimationFrame, DecodeError>>,
+}
+
+impl FrameStream {
+    fn open(source: ImageSource, start: usize) -> Self {
+        let (sender, receiver) = sync_channel(BUFFERED_FRAMES);
+
+        std::thread::spawn(move || {
+            let frames = match decode_frames(&source) {
+                Ok(frames) => frames,
+                Err(e) => {
+                    let _ = sender.send(Err(e));
+                    return;
+                }
+            };
+
+            // Frames may be drawn on top of previous ones, so seeking still
+            // has to decode everything before `start`.
+            for (index, frame) in frames.enumerate().skip(start) {
+                let frame = frame
+                    .map(|frame| AnimationFrame::new(index, frame))
+                    .map_err(decode_error);
+                if sender.send(frame).is_err() {
+                    return;
+                }
+            }
+        });
+
+        Self { receiver }
+    }
+}
+
+pub struct Animation {
+    source: ImageSource,
+    stream: FrameStream,
+    frame: Option<usize>,
+    frame_count: Option<usize>,
+    playing: bool,
+    speed: f64,
+}
+
+impl Animation {
+    // Reads the file, so it's best kept off the UI thread.
+    pub fn open(source: &ImageSource) -> Option<Self> {
+        if !is_animated(source) {
+            return None;
+        }
+
+        Some(Self {
+            source: source.clone(),
+            stream: FrameStream::open(source.clone(), 0),
+            frame: None,
+            frame_count: None,
+            playing: true,
+            speed: 1.0,
+        })
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn toggle_playing(&mut self) {
+        self.playing = !self.playing;
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    pub fn adjust_speed(&mut self, factor: f64) {
+        self.speed = (self.speed * factor).clamp(MIN_SPEED, MAX_SPEED);
+    }
+
+    // The stream already yields the following frame, so stepping forward
+    // only needs to pause.
+    pub fn step_forward(&mut self) {
+        self.playing = false;
+    }
+
+    pub fn step_back(&mut self) {
+        self.playing = false;
+
+        let target = match (self.frame, self.frame_count) {
+            (Some(0), Some(count)) => count - 1,
+            (Some(frame), _) => frame.saturating_sub(1),
+            (None, _) => 0,
+        };
+        self.stream = FrameStream::open(self.source.clone(), target);
+    }
+
+    // Returns `Ok(None)` when the next frame hasn't been decoded yet, or when
+    // the animation just looped back to the start.
+    pub fn poll(&mut self) -> Result<Option<AnimationFrame>, DecodeError> {
+        match self.stream.receiver.try_recv() {
+            Ok(Ok(frame)) => {
+                self.frame = Some(frame.index);
+                Ok(Some(frame))
+            }
+            Ok(Err(e)) => Err(e),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => {
+                self.frame_count = self.frame.map(|frame| frame + 1);
+                self.stream = FrameStream::open(self.source.clone(), 0);
+                Ok(None)
+            }
+        }
+    }
+
+    pub fn status(&self) -> String {
+        let frame = self.frame.map_or(0, |frame| frame + 1);
+        let count = self
+            .frame_count
+            .map_or("?".to_string(), |count| count.to_string());
+        let state = if self.playing { "" } else { "  Paused" };
+
+        format!("Frame {frame}/{count}  {:.2}x{state}", self.speed)
+    }
+}
@@ -1,17 +1,61 @@
+use futures::channel::oneshot;
 use makepad_widgets::*;
 use moly_kit::{
-    ChatTask, ChatWidgetRefExt, OpenAIClient, OpenAIImageClient, protocol::*,
+    ChatTask, ChatWidgetRefExt, OpenAIClient, protocol::*,
     utils::asynchronous::spawn,
 };
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use crate::slideshow_client::SlideshowClient;
+use crate::animation::{self, Animation};
+use crate::archive;
+use crate::args::Args;
+use crate::attachments::AttachmentOptions;
+use crate::captions::{
+    CaptionEvent, CaptionJob, CaptionProgress, CaptionSettings,
+};
+use crate::commands::{Command, CommandScope, Keymap};
+use crate::compare::{
+    CompareMode, CompareViewWidgetRefExt, edited_version_path,
+};
+use crate::conversations::{self, Conversation};
+use crate::decoders::{is_float_texture, registry};
+use crate::display::{ChannelView, DisplaySettings, ToneMapper};
+use crate::generation::{add_to_grid, run_generation_tasks};
+use crate::history::{
+    ConversationHistoryAction, ConversationHistoryWidgetRefExt,
+};
+use crate::loupe::LoupeWidgetRefExt;
+use crate::mcp::{self, Library, LibraryChange, LibraryView, McpServer};
+use crate::palette::{CommandPaletteAction, CommandPaletteWidgetRefExt};
+use crate::providers::{ModelChoice, ModelSelection, Provider, Providers};
+use crate::review::{TagReviewAction, TagReviewWidgetRefExt};
+use crate::scopes::Scopes;
+use crate::search::Search;
+use crate::session::{self, Page, Session, WindowGeometry};
+use crate::slideshow_client::{SlideshowClient, ToolHandler};
+use crate::source::ImageSource;
+use crate::tagging::{TagEvent, TagJob, TagProgress, TagSettings};
+use crate::textures::{
+    CachedTexture, THUMBNAIL_SIZE, TextureCache, TextureSize, spawn_decode,
+};
+use crate::theme::{self, Theme, ThemeWatcher};
+use crate::tools::{self, ToolRequest, blocking};
 
 const IMAGES_PATH: &str = "../../../images";
 
 live_design! {
     use link::widgets::*;
     use moly_kit::widgets::chat::Chat;
+    use crate::compare::Compare;
+    use crate::display::DisplayImage;
+    use crate::history::ConversationHistory;
+    use crate::loupe::Loupe;
+    use crate::palette::CommandPalette;
+    use crate::review::TagReview;
+    use crate::theme::*;
 
     LEFT_ARROW = dep("crate://self/resources/left_arrow.svg");
     RIGHT_ARROW = dep("crate://self/resources/right_arrow.svg");
@@ -27,10 +71,66 @@ live_design! {
         align: {
             x: 1.0,
         },
+        spacing: (THEME_SPACING),
+        padding: (THEME_SPACING),
+        show_bg: true,
+        draw_bg: {
+            color: (THEME_PANEL),
+        },
 
+        search_input = <TextInput> {
+            width: 250,
+            empty_text: "Describe the image to find...",
+        }
+        search_status = <Label> {
+            text: "",
+        }
+        <Filler> {}
+        similar_button = <MenuBarButton> {
+            text: "Find Similar",
+        }
+        background_button = <MenuBarButton> {
+            text: "Background",
+        }
+        compare_button = <MenuBarButton> {
+            text: "Compare",
+        }
+        ask_button = <MenuBarButton> {
+            text: "Ask",
+        }
+        caption_button = <MenuBarButton> {
+            text: "Caption",
+        }
+        tag_button = <MenuBarButton> {
+            text: "Tag",
+        }
+        review_button = <MenuBarButton> {
+            text: "Review Tags",
+        }
+        history_button = <MenuBarButton> {
+            text: "Conversations",
+        }
+        settings_button = <MenuBarButton> {
+            text: "Settings",
+        }
+        animate_button = <MenuBarButton> {
+            text: "Hover Animation",
+        }
         button = <MenuBarButton> {}
     }
 
+    GridItemBadge = <RoundedView> {
+        width: Fit,
+        height: Fit,
+        padding: 4,
+        visible: false,
+        show_bg: true,
+        draw_bg: {
+            color: (THEME_OVERLAY),
+            border_radius: (THEME_RADIUS),
+        },
+    }
+
     ImageGridItem = <View> {
         width: 256,
         height: 256,
@@ -38,8 +138,19 @@ live_design! {
             x: 0.5,
             y: 0.5,
         },
+        flow: Overlay,
+        cursor: Hand,
+        show_bg: true,
+        draw_bg: {
+            instance selected: 0.0,
+            color: (THEME_ACCENT),
 
-        <View> {
+            fn pixel(self) -> vec4 {
+                return self.color * self.selected;
+            }
+        },
+
+        thumbnail = <View> {
             animator: {
                 hover = {
                     default: off,
@@ -72,13 +183,41 @@ live_design! {
                 }
             }
 
-            image = <Image> {
+            image = <DisplayImage> {
                 width: Fill,
                 height: Fill,
                 fit: Biggest,
                 source: (PLACEHOLDER),
             }
         }
+
+        <View> {
+            width: Fill,
+            height: Fill,
+            padding: 16,
+            spacing: 4,
+
+            raw_badge = <GridItemBadge> {
+                <Label> {
+                    text: "RAW+JPEG",
+                }
+            }
+            icc_badge = <GridItemBadge> {
+                <Label> {
+                    text: "ICC",
+                }
+            }
+            review_badge = <GridItemBadge> {
+                count = <Label> {
+                    text: "",
+                }
+            }
+            failed_badge = <GridItemBadge> {
+                <Label> {
+                    text: "Can't open",
+                }
+            }
+        }
     }
 
     ImageGridRow = {{ImageGridRow}} {
@@ -114,15 +253,118 @@ live_design! {
         }
     }
 
+    SelectionChip = <RoundedView> {
+        width: Fit,
+        height: Fit,
+        margin: {
+            right: 6,
+        },
+        align: {
+            y: 0.5,
+        },
+        spacing: 6,
+        padding: 4,
+        show_bg: true,
+        draw_bg: {
+            color: (THEME_OVERLAY),
+            border_radius: (THEME_RADIUS),
+        },
+
+        thumbnail = <Image> {
+            width: 32,
+            height: 32,
+            fit: Biggest,
+            source: (PLACEHOLDER),
+        }
+        name = <Label> {
+            text: "",
+        }
+        remove_button = <Button> {
+            text: "×",
+            padding: 4,
+        }
+    }
+
+    // The images a question is about, shown above the prompt.
+    SelectionChips = {{SelectionChips}} {
+        width: Fill,
+        height: Fit,
+        padding: {
+            bottom: 6,
+        },
+        visible: false,
+
+        chips = <PortalList> {
+            height: 44,
+            flow: Right,
+
+            Chip = <SelectionChip> {}
+        }
+    }
+
     ImageBrowser = <View> {
         flow: Down,
 
         menu_bar = <MenuBar> {}
-        image_grid = <ImageGrid> {}
+        <View> {
+            flow: Right,
+
+            image_grid = <ImageGrid> {}
+            ask_chat = <Chat> {
+                padding: 10,
+                width: 350,
+                visible: false,
+                draw_bg: {
+                    border_radius: 0.0,
+                    color: (THEME_CHAT)
+                }
+                prompt = {
+                    persistent = {
+                        top = {
+                            chips = <SelectionChips> {}
+                        }
+                        center = {
+                            left = {
+                                visible: false
+                            }
+                            text_input = {
+                                empty_text: "Ask about the selected images..."
+                            }
+                        }
+                    }
+                }
+            }
+            tag_review = <TagReview> {}
+        }
+        job_bar = <View> {
+            width: Fill,
+            height: Fit,
+            align: {
+                y: 0.5,
+            },
+            spacing: (THEME_SPACING),
+            padding: (THEME_SPACING),
+            visible: false,
+            show_bg: true,
+            draw_bg: {
+                color: (THEME_PANEL),
+            },
+
+            status = <Label> {
+                width: Fill,
+                text: "",
+            }
+            stop_button = <Button> {
+                text: "Stop",
+            }
+        }
         chat = <Chat> {
             height: Fit,
             padding: 10,
             visible: false,
+            draw_bg: {
+                color: (THEME_CHAT)
+            }
             messages = {
                 visible: false
             }
@@ -146,8 +388,8 @@ live_design! {
         width: 50,
         height: Fill,
         draw_bg: {
-            color: #FFF0,
-            color_down: #FFF2,
+            color: (THEME_BUTTON),
+            color_down: (THEME_BUTTON_PRESSED),
         },
         icon_walk: {
             width: 10
@@ -172,18 +414,146 @@ live_design! {
         }
     }
 
+    ScopeImage = <Image> {
+        width: 256,
+        height: 128,
+        fit: Stretch,
+    }
+
+    AnimationStatus = <View> {
+        width: Fill,
+        height: Fit,
+        padding: 10,
+        visible: false,
+
+        frame = <RoundedView> {
+            width: Fit,
+            height: Fit,
+            padding: 5,
+            show_bg: true,
+            draw_bg: {
+                color: (THEME_OVERLAY),
+                border_radius: (THEME_RADIUS),
+            },
+
+            frame_counter = <Label> {
+                text: "",
+            }
+        }
+    }
+
+    ToneControls = <RoundedView> {
+        width: 250,
+        height: Fit,
+        margin: {
+            top: 50,
+            left: 60,
+        },
+        flow: Down,
+        spacing: 5,
+        padding: 10,
+        visible: false,
+        show_bg: true,
+        draw_bg: {
+            color: (THEME_OVERLAY),
+            border_radius: (THEME_RADIUS),
+        },
+
+        exposure = <Slider> {
+            width: Fill,
+            text: "Exposure",
+            min: -8.0,
+            max: 8.0,
+            step: 0.1,
+            default: 0.0,
+        }
+        tone_mapper = <DropDown> {
+            width: Fill,
+            labels: ["Clip", "Reinhard", "ACES Filmic", "AgX"],
+            values: [Clip, Reinhard, AcesFilmic, AgX],
+        }
+    }
+
+    SlideshowScopes = <View> {
+        width: Fill,
+        height: Fill,
+        align: {
+            y: 1.0,
+        },
+        padding: 10,
+        visible: false,
+
+        panel = <View> {
+            width: Fit,
+            height: Fit,
+            flow: Down,
+            spacing: 5,
+            padding: 5,
+            show_bg: true,
+            draw_bg: {
+                color: (THEME_OVERLAY),
+            },
+
+            histogram = <ScopeImage> {}
+            waveform = <ScopeImage> {}
+            parade = <ScopeImage> {}
+            clipping = <Label> {
+                text: "",
+            }
+        }
+    }
+
+    // The facing page of a two-page comic spread.
+    SpreadPage = <DisplayImage> {
+        width: Fill,
+        height: Fill,
+        fit: Biggest,
+        visible: false,
+    }
+
     Slideshow = <View> {
         <View> {
             flow: Overlay,
 
-            image = <Image> {
-                width: Fill,
-                height: Fill,
-                fit: Biggest,
-                source: (PLACEHOLDER)
+            <View> {
+                flow: Right,
+
+                spread_left = <SpreadPage> {}
+                image = <DisplayImage> {
+                    width: Fill,
+                    height: Fill,
+                    fit: Biggest,
+                    source: (PLACEHOLDER)
+                    draw_bg: {
+                        instance clipping: 0.0,
+
+                        fn analyze(self, color: vec4) -> vec4 {
+                            if self.clipping < 0.5 || color.a <= 0.0 {
+                                return color;
+                            }
+
+                            let peak = max(color.r, max(color.g, color.b));
+                            let p = self.pos * self.rect_size;
+                            let stripe = step(0.5, fract((p.x + p.y) / 12.0));
+
+                            if peak >= 0.996 {
+                                return vec4(vec3(stripe), color.a);
+                            }
+                            if peak <= 0.004 {
+                                return vec4(0.0, 0.3, 1.0, color.a);
+                            }
+                            return color;
+                        }
+                    }
+                }
+                spread_right = <SpreadPage> {}
             }
 
+            scopes = <SlideshowScopes> {}
+            animation_status = <AnimationStatus> {}
             overlay = <SlideshowOverlay> {}
+            loupe = <Loupe> {}
+            tone_controls = <ToneControls> {}
         }
 
         chat = <Chat> {
@@ -192,7 +562,7 @@ live_design! {
             visible: false,
             draw_bg: {
                 border_radius: 0.0,
-                color: #fff
+                color: (THEME_CHAT)
             }
             prompt = {
                 persistent = {
@@ -209,15 +579,107 @@ live_design! {
         }
     }
 
+    ModelDropDown = <DropDown> {
+        width: 400,
+        labels: [],
+    }
+
+    Settings = <View> {
+        flow: Down,
+        spacing: 10,
+        padding: 20,
+
+        <View> {
+            height: Fit,
+            align: {
+                y: 0.5,
+            },
+            spacing: 10,
+
+            back_button = <Button> {
+                text: "Back",
+            }
+            <Label> {
+                text: "Settings",
+            }
+        }
+
+        <Label> {
+            text: "Chat model",
+        }
+        chat_model = <ModelDropDown> {}
+        <Label> {
+            text: "Image model",
+        }
+        image_model = <ModelDropDown> {}
+
+        check_button = <Button> {
+            text: "Check connections",
+        }
+        status = <Label> {
+            width: Fill,
+            text: "",
+        }
+    }
+
     App = {{App}} {
         ui: <Root> {
-            <Window> {
+            main_window = <Window> {
                 body = <View> {
+                    flow: Overlay,
+                    show_bg: true,
+                    draw_bg: {
+                        color: (THEME_BACKGROUND),
+                    },
+
                     page_flip = <PageFlip> {
                         active_page: image_browser,
 
                         image_browser = <ImageBrowser> {}
                         slideshow = <Slideshow> {}
+                        compare = <Compare> {}
+                        settings = <Settings> {}
+                        history = <ConversationHistory> {}
+                    }
+                    command_palette = <CommandPalette> {}
+                    tool_confirm = <View> {
+                        align: {
+                            x: 0.5,
+                            y: 0.5,
+                        },
+                        visible: false,
+
+                        <RoundedView> {
+                            width: 450,
+                            height: Fit,
+                            flow: Down,
+                            spacing: (THEME_SPACING),
+                            padding: 20,
+                            show_bg: true,
+                            draw_bg: {
+                                color: (THEME_PANEL),
+                            },
+
+                            <Label> {
+                                text: "The chat model wants to:",
+                            }
+                            message = <Label> {
+                                width: Fill,
+                                text: "",
+                            }
+                            <View> {
+                                height: Fit,
+                                spacing: 10,
+
+                                <Filler> {}
+                                deny_button = <Button> {
+                                    text: "Deny",
+                                }
+                                allow_button = <Button> {
+                                    text: "Allow",
+                                }
+                            }
+                        }
                     }
                 }
             }
@@ -236,152 +698,2513 @@ struct App {
     state: State,
     #[rust]
     slideshow_client: Option<SlideshowClient>,
+    // Sends the images selected in the grid with questions about them.
+    #[rust]
+    selection_client: Option<SlideshowClient>,
+    // Stops the caption or tag job, while one is running.
+    #[rust]
+    job_cancel: Option<Arc<AtomicBool>>,
+    // Ranks the grid by a description or an image, when set up in
+    // `search.toml`.
+    #[rust]
+    search: Option<Search>,
+    // Stops the indexing thread, while one is running.
+    #[rust]
+    index_cancel: Option<Arc<AtomicBool>>,
+    // Searches once typing pauses.
+    #[rust]
+    search_timer: Timer,
+    // Answers the chat model's request to change files, while the user is
+    // asked.
+    #[rust]
+    tool_permission: Option<oneshot::Sender<bool>>,
+    // How images are prepared for the chat model's provider.
+    #[rust]
+    attachment_options: AttachmentOptions,
+    #[rust]
+    animation: Option<Animation>,
+    #[rust]
+    animation_target: AnimationTarget,
+    // Counts stops, so an animation opened after a stop is dropped.
+    #[rust]
+    animation_generation: u64,
+    #[rust]
+    animation_timer: Timer,
+    #[rust]
+    keymap: Keymap,
+    #[rust]
+    theme_watcher: ThemeWatcher,
+    #[rust]
+    theme_timer: Timer,
+    #[rust]
+    providers: Providers,
+    #[rust]
+    providers_error: Option<String>,
+    #[rust]
+    models: ModelSelection,
+    #[rust]
+    available_models: AvailableModels,
 }
 
-impl App {
-    fn load_image_paths(&mut self, cx: &mut Cx, dir: &Path) {
-        self.state.image_paths.clear();
+// What the providers offer, as last checked from the settings page.
+#[derive(Default)]
+struct AvailableModels {
+    chat: Vec<ModelChoice>,
+    image: Vec<ModelChoice>,
+    // A line per provider with what it returned or why it failed.
+    status: Vec<String>,
+    pending: usize,
+    // Results from an older check are dropped.
+    generation: u64,
+}
 
-        for entry in dir.read_dir().unwrap() {
-            let path = entry.unwrap().path();
-            if path.is_file() {
-                self.state.image_paths.push(path);
-            }
+// The models a drop down offers after its "None" entry, which includes the
+// chosen one even when its provider is unreachable.
+fn model_options(
+    available: &[ModelChoice],
+    chosen: Option<&ModelChoice>,
+) -> Vec<ModelChoice> {
+    let mut options = available.to_vec();
+    if let Some(chosen) = chosen {
+        if !options.contains(chosen) {
+            options.push(chosen.clone());
         }
+    }
+    options
+}
 
-        self.set_current_image(cx, 0);
+// Lists a provider's chat and image models, failing with the reason it
+// couldn't be reached.
+async fn load_provider_models(
+    provider: Provider,
+) -> Result<(Vec<ModelChoice>, Vec<ModelChoice>), String> {
+    let mut chat_context = BotContext::from(provider.chat_client()?);
+    let mut image_context = BotContext::from(provider.image_client()?);
+
+    let mut errors = chat_context.load().await.into_errors();
+    errors.extend(image_context.load().await.into_errors());
+    if !errors.is_empty() {
+        let errors: Vec<String> =
+            errors.iter().map(|error| error.to_string()).collect();
+        return Err(format!(
+            "{}: couldn't list models at {}: {}",
+            provider.name,
+            provider.url,
+            errors.join("; ")
+        ));
     }
 
-    fn set_current_image(&mut self, cx: &mut Cx, image_idx: usize) {
-        self.state.current_image_idx = image_idx;
+    let choices = |context: &BotContext| -> Vec<ModelChoice> {
+        context
+            .bots()
+            .into_iter()
+            .map(|bot| ModelChoice {
+                provider: provider.name.clone(),
+                model: bot.id.id().to_string(),
+            })
+            .collect()
+    };
+    Ok((choices(&chat_context), choices(&image_context)))
+}
 
-        let image = self.ui.image(id!(slideshow.image));
-        if let Some(path) = self.state.image_paths.get(image_idx) {
-            image.load_image_file_by_path_async(cx, &path).unwrap();
-        } else {
-            let placeholder = self.placeholder.as_str();
-            image.load_image_dep_by_path(cx, placeholder).unwrap();
-        }
+// Runs `f` on the UI thread, for its result.
+async fn on_ui<T: Send + 'static>(
+    ui: UiRunner<App>,
+    f: impl FnOnce(&mut App, &mut Cx) -> T + Send + 'static,
+) -> Result<T, String> {
+    let (sender, receiver) = oneshot::channel();
+    ui.defer(move |me, cx, _scope| {
+        let _ = sender.send(f(me, cx));
+    });
+    receiver.await.map_err(|e| e.to_string())
+}
 
-        self.clear_slideshow_chat_messages();
+// Answers the MCP server from the UI thread, so agents see the grid and
+// slideshow as they are and their changes show up in them.
+struct AppLibrary(UiRunner<App>);
 
-        self.ui.redraw(cx);
+impl AppLibrary {
+    fn on_ui<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut App, &mut Cx) -> T + Send + 'static,
+    ) -> Result<T, String> {
+        futures::executor::block_on(on_ui(self.0, f))
     }
+}
 
-    fn go_to_previous_image(&mut self, cx: &mut Cx) {
-        if self.state.current_image_idx > 0 {
-            self.set_current_image(cx, self.state.current_image_idx - 1);
-        }
+impl Library for AppLibrary {
+    fn view(&self) -> Result<LibraryView, String> {
+        self.on_ui(|me, _cx| LibraryView {
+            images: me.state.unranked_sources(),
+            shown: me.state.image_sources.clone(),
+            selected: me.state.selected_sources(),
+            current: me.state.current_image_source().cloned(),
+            slideshow: me.state.page == Page::Slideshow,
+        })
     }
 
-    fn go_to_next_image(&mut self, cx: &mut Cx) {
-        if self.state.current_image_idx + 1 < self.state.num_images() {
-            self.set_current_image(cx, self.state.current_image_idx + 1);
-        }
+    fn apply(&self, change: LibraryChange) -> Result<(), String> {
+        self.on_ui(move |me, cx| me.apply_library_change(cx, change))?
     }
+}
 
-    fn configure_slideshow_chat(&mut self, cx: &mut Cx) {
-        self.configure_slideshow_chat_context(cx);
-        self.configure_slideshow_chat_before_hook(cx);
+// Runs the chat model's calls in order, asking the user once before any of
+// them change files.
+async fn run_tool_calls(
+    ui: UiRunner<App>,
+    calls: Vec<ToolCall>,
+) -> Vec<ToolResult> {
+    let mut requests = Vec::new();
+    for call in &calls {
+        let request = match ToolRequest::parse(call) {
+            Ok(request) => tool_targets(ui, request).await,
+            Err(error) => Err(error),
+        };
+        requests.push(request);
     }
+    let changes: Vec<String> = requests
+        .iter()
+        .flatten()
+        .filter(|(request, _)| request.is_destructive())
+        .map(|(request, targets)| request.describe(targets))
+        .collect();
+    let allowed = if changes.is_empty() {
+        true
+    } else {
+        let answer =
+            on_ui(ui, move |me, cx| me.ask_tool_permission(cx, &changes));
+        match answer.await {
+            Ok(answer) => answer.await.unwrap_or(false),
+            Err(_) => false,
+        }
+    };
 
-    fn configure_slideshow_chat_context(&mut self, cx: &mut Cx) {
-        let url = std::env::var("API_URL").unwrap_or_default();
-        let key = std::env::var("API_KEY").unwrap_or_default();
-        let mut client = OpenAIClient::new(url);
-        client.set_key(&key).unwrap();
-
-        let client = SlideshowClient::from(client);
-        self.slideshow_client = Some(client.clone());
+    let mut results = Vec::new();
+    for (call, request) in calls.iter().zip(requests) {
+        let result = match request {
+            Ok((request, _)) if request.is_destructive() && !allowed => {
+                Err("The user declined".to_string())
+            }
+            Ok((request, targets)) => run_tool(ui, request, targets).await,
+            Err(error) => Err(error),
+        };
+        let (content, is_error) = match result {
+            Ok(content) => (content, false),
+            Err(error) => (error, true),
+        };
+        results.push(ToolResult {
+            tool_call_id: call.id.clone(),
+            content,
+            is_error,
+        });
+    }
+    results
+}
 
-        let mut bot_context = BotContext::from(client);
+// The images the request works on, looked up once so what the user
+// confirms is what changes, even if the selection changes meanwhile.
+async fn tool_targets(
+    ui: UiRunner<App>,
+    request: ToolRequest,
+) -> Result<(ToolRequest, Vec<ImageSource>), String> {
+    let Some(names) = request.images().map(<[String]>::to_vec) else {
+        return Ok((request, Vec::new()));
+    };
+    let targets = on_ui(ui, move |me, _cx| me.tool_sources(&names)).await??;
+    Ok((request, targets))
+}
 
-        let mut chat = self.ui.chat(id!(slideshow.chat));
-        chat.write().set_bot_context(cx, Some(bot_context.clone()));
+// File work runs off the UI thread, which only looks up images and shows
+// the outcome.
+async fn run_tool(
+    ui: UiRunner<App>,
+    request: ToolRequest,
+    targets: Vec<ImageSource>,
+) -> Result<String, String> {
+    match request {
+        ToolRequest::GoToImage { name } => {
+            on_ui(ui, move |me, cx| me.go_to_named_image(cx, &name)).await?
+        }
+        ToolRequest::FilterGrid(filter) => {
+            let sources =
+                on_ui(ui, |me, _cx| me.state.unranked_sources()).await?;
+            let matching: Vec<ImageSource> = blocking(move || {
+                sources
+                    .into_iter()
+                    .filter(|source| filter.matches(source))
+                    .collect()
+            })
+            .await?;
+            on_ui(ui, move |me, cx| me.show_filtered(cx, matching)).await
+        }
+        ToolRequest::SetRating { rating, .. } => {
+            blocking(move || tools::set_rating(&targets, rating.0)).await?
+        }
+        ToolRequest::AddTag { tag, .. } => {
+            blocking(move || tools::add_tag(&targets, &tag)).await?
+        }
+        ToolRequest::OpenSlideshow {} => {
+            on_ui(ui, |me, cx| {
+                if me.state.num_images() == 0 {
+                    return Err("No images are loaded".to_string());
+                }
+                me.set_current_image(cx, 0);
+                me.run_command(cx, Command::OpenSlideshow);
+                Ok(format!("Showing {} images", me.state.num_images()))
+            })
+            .await?
+        }
+        ToolRequest::Rotate { degrees, .. } => {
+            blocking(move || tools::rotate(&targets, degrees)).await?
+        }
+        ToolRequest::Crop {
+            x,
+            y,
+            width,
+            height,
+            ..
+        } => {
+            blocking(move || tools::crop(&targets, (x, y, width, height)))
+                .await?
+        }
+        ToolRequest::Search { query } => {
+            let (search, sources) = on_ui(ui, |me, _cx| {
+                (me.search.clone(), me.state.unranked_sources())
+            })
+            .await?;
+            let search = search.ok_or_else(|| {
+                "Search isn't set up in search.toml".to_string()
+            })?;
+            let ranked =
+                blocking(move || search.search(&query, &sources)).await??;
+            let best: Vec<String> =
+                ranked.iter().take(5).map(|s| s.file_name()).collect();
+            on_ui(ui, move |me, cx| {
+                me.set_active_page(cx, Page::ImageBrowser);
+                me.show_ranking(cx, Ok(ranked));
+            })
+            .await?;
+            Ok(format!("Ranked the grid, best first: {}", best.join(", ")))
+        }
+    }
+}
 
-        let ui = self.ui_runner();
-        spawn(async move {
-            let errors = bot_context.load().await.into_errors();
+const ANIMATION_POLL_INTERVAL: f64 = 0.01;
+const THEME_POLL_INTERVAL: f64 = 2.0;
+const ANIMATION_SPEED_STEP: f64 = 2.0;
+const SEARCH_DELAY: f64 = 0.4;
+// The longest edge vector images are rasterized at, however far they're
+// zoomed into.
+const MAX_VECTOR_RASTER: f64 = 8192.0;
 
-            ui.defer(move |me, cx, _scope| {
-                let mut chat = me.ui.chat(id!(slideshow.chat));
-                let mut messages = chat.read().messages_ref();
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum AnimationTarget {
+    #[default]
+    Slideshow,
+    Thumbnail(usize),
+}
 
-                for error in errors {
-                    messages.write().messages.push(Message::app_error(error));
-                }
+// Whether images can be loaded from `path`, as a folder or an archive.
+fn is_root(path: &Path) -> bool {
+    path.is_dir() || (path.is_file() && archive::archive_kind(path).is_some())
+}
 
-                let model_id = std::env::var("MODEL_ID").unwrap_or_default();
-                let bot = bot_context
-                    .bots()
-                    .into_iter()
-                    .find(|b| b.id.id() == model_id);
+// The files in `folder`, with archives opened as if they were subfolders.
+fn folder_sources(folder: &Path) -> Vec<ImageSource> {
+    let entries = match folder.read_dir() {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Error reading folder {folder:?}: {e}");
+            return Vec::new();
+        }
+    };
+
+    let mut sources = Vec::new();
+    for entry in entries {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(e) => {
+                eprintln!("Error reading folder {folder:?}: {e}");
+                continue;
+            }
+        };
+        if !path.is_file() {
+            continue;
+        }
+
+        if archive::archive_kind(&path).is_some() {
+            sources.extend(archive_sources(&path));
+        } else {
+            sources.push(ImageSource::File(path));
+        }
+    }
+    sources
+}
+
+fn archive_sources(archive: &Path) -> Vec<ImageSource> {
+    match archive::list_entries(archive) {
+        Ok(entries) => entries
+            .into_iter()
+            .map(|entry| ImageSource::ArchiveEntry {
+                archive: archive.to_path_buf(),
+                entry,
+            })
+            .collect(),
+        Err(e) => {
+            eprintln!("Error reading archive {archive:?}: {e}");
+            Vec::new()
+        }
+    }
+}
+
+impl App {
+    // `path` may be a folder or an archive, and archives inside a folder are
+    // browsed as if they were subfolders of it.
+    fn load_image_sources(&mut self, cx: &mut Cx, path: &Path) {
+        session::add_recent_root(
+            &mut self.state.recent_roots,
+            path.to_path_buf(),
+        );
+        self.state.image_sources.clear();
+        self.state.unranked = None;
+        self.state.selected_images.clear();
+        self.update_selection_views(cx);
+        self.state.raw_companions.clear();
+        self.state.color_tagged.clear();
+
+        let sources = if archive::archive_kind(path).is_some() {
+            archive_sources(path)
+        } else {
+            folder_sources(path)
+        };
+
+        let mut raw_sources = Vec::new();
+        for source in sources {
+            let path = source.path();
+            if !registry().is_supported(&path) {
+                continue;
+            }
+
+            if registry().is_raw(&path) {
+                raw_sources.push(source);
+            } else {
+                self.state.image_sources.push(source);
+            }
+        }
+
+        // A RAW+JPEG pair shares one tile, showing the JPEG and keeping the
+        // RAW around for development.
+        for raw_source in raw_sources {
+            let raw_path = raw_source.path();
+            let jpeg_source = self.state.image_sources.iter().find(|s| {
+                let path = s.path();
+                s.archive() == raw_source.archive()
+                    && path.file_stem() == raw_path.file_stem()
+                    && registry().mime_type(&path) == Some("image/jpeg")
+            });
+
+            match jpeg_source {
+                Some(jpeg_source) => {
+                    self.state
+                        .raw_companions
+                        .insert(jpeg_source.clone(), raw_source);
+                }
+                None => self.state.image_sources.push(raw_source),
+            }
+        }
+
+        self.set_current_image(cx, 0);
+        self.scan_color_profiles();
+        self.start_indexing(cx);
+    }
+
+    fn set_current_image(&mut self, cx: &mut Cx, image_idx: usize) {
+        self.state.current_image_idx = image_idx;
+        self.state.current_page = 0;
+        self.state.vector_raster = None;
+
+        let image = self.ui.image(id!(slideshow.image));
+        let source = self.state.image_sources.get(image_idx).cloned();
+        let native_path = source.as_ref().and_then(|source| {
+            let path = source.file_path()?;
+            let native = registry().is_native(path)
+                && !registry().is_high_bit_depth(source)
+                && !self.state.color_tagged.contains(source);
+            native.then(|| path.to_path_buf())
+        });
+        match native_path {
+            Some(path) => {
+                image.load_image_file_by_path_async(cx, &path).unwrap();
+                self.set_slideshow_hdr(cx, false);
+            }
+            None => {
+                // Other formats and archive entries are decoded in the
+                // background by `load_requested_textures`, which replaces the
+                // placeholder.
+                match source.and_then(|source| self.state.texture(&source)) {
+                    Some(texture) => {
+                        self.set_slideshow_texture(cx, Some(texture))
+                    }
+                    None => {
+                        let placeholder = self.placeholder.as_str();
+                        image.load_image_dep_by_path(cx, placeholder).unwrap();
+                        self.set_slideshow_hdr(cx, false);
+                    }
+                }
+            }
+        }
+
+        self.update_spread(cx);
+        self.switch_conversation(cx);
+        self.update_image_analysis(cx);
+        self.play_animation(cx, AnimationTarget::Slideshow);
+        if self.state.soft_proof {
+            self.soft_proof_current_image(cx);
+        }
+
+        self.ui.redraw(cx);
+    }
+
+    // Tagged images can't use makepad's own loaders, which ignore embedded
+    // profiles, so they are found up front and decoded like other formats.
+    fn scan_color_profiles(&mut self) {
+        let sources = self.state.image_sources.clone();
+        let ui = self.ui_runner();
+        std::thread::spawn(move || {
+            let tagged: Vec<ImageSource> = sources
+                .into_iter()
+                .filter(|source| registry().has_embedded_profile(source))
+                .collect();
+
+            ui.defer(move |me, cx, _scope| {
+                let current_tagged = me
+                    .state
+                    .current_image_source()
+                    .is_some_and(|source| tagged.contains(source));
+
+                me.state.color_tagged.extend(tagged);
+                if current_tagged {
+                    me.set_current_image(cx, me.state.current_image_idx);
+                }
+                me.ui.redraw(cx);
+            });
+        });
+    }
+
+    fn toggle_soft_proof(&mut self, cx: &mut Cx) {
+        self.state.soft_proof = !self.state.soft_proof;
+        self.set_current_image(cx, self.state.current_image_idx);
+    }
+
+    fn soft_proof_current_image(&mut self, _cx: &mut Cx) {
+        let Some(source) = self.state.current_image_source().cloned() else {
+            return;
+        };
+
+        let ui = self.ui_runner();
+        std::thread::spawn(move || {
+            let result = registry().soft_proof_file(&source);
+            ui.defer(move |me, cx, _scope| {
+                if me.state.current_image_source() != Some(&source)
+                    || !me.state.soft_proof
+                {
+                    return;
+                }
+
+                match result {
+                    Ok(decoded) => {
+                        let texture = decoded.into_texture(cx);
+                        me.set_slideshow_texture(cx, Some(texture));
+                        me.ui.redraw(cx);
+                    }
+                    Err(e) => {
+                        eprintln!("Error soft proofing {source}: {e}");
+                        me.state.soft_proof = false;
+                    }
+                }
+            });
+        });
+    }
+
+    fn set_slideshow_texture(&mut self, cx: &mut Cx, texture: Option<Texture>) {
+        let hdr = texture.as_ref().is_some_and(|t| is_float_texture(cx, t));
+        self.ui.image(id!(slideshow.image)).set_texture(cx, texture);
+        self.set_slideshow_hdr(cx, hdr);
+    }
+
+    fn set_slideshow_hdr(&mut self, cx: &mut Cx, hdr: bool) {
+        self.state.display.hdr = hdr;
+        self.state
+            .display
+            .apply_tone_mapping(cx, &self.ui.image(id!(slideshow.image)));
+    }
+
+    fn update_tone_mapping(
+        &mut self,
+        cx: &mut Cx,
+        update: impl FnOnce(&mut DisplaySettings),
+    ) {
+        update(&mut self.state.display);
+        self.state
+            .display
+            .apply_tone_mapping(cx, &self.ui.image(id!(slideshow.image)));
+        self.update_image_analysis(cx);
+        self.ui.redraw(cx);
+    }
+
+    fn toggle_tone_controls(&mut self, cx: &mut Cx) {
+        self.state.show_tone_controls = !self.state.show_tone_controls;
+        self.ui
+            .view(id!(slideshow.tone_controls))
+            .set_visible(cx, self.state.show_tone_controls);
+        self.ui.redraw(cx);
+    }
+
+    fn go_to_previous_image(&mut self, cx: &mut Cx) {
+        if self.state.current_image_idx > 0 {
+            let step = self.state.images_per_step();
+            let image_idx = self.state.current_image_idx.saturating_sub(step);
+            self.set_current_image(cx, image_idx);
+        }
+    }
+
+    fn go_to_next_image(&mut self, cx: &mut Cx) {
+        let image_idx =
+            self.state.current_image_idx + self.state.images_per_step();
+        if image_idx < self.state.num_images() {
+            self.set_current_image(cx, image_idx);
+        }
+    }
+
+    // Right-to-left comics turn their pages the other way around.
+    fn go_left(&mut self, cx: &mut Cx) {
+        if self.state.right_to_left && self.state.is_comic() {
+            self.go_to_next_image(cx);
+        } else {
+            self.go_to_previous_image(cx);
+        }
+    }
+
+    fn go_right(&mut self, cx: &mut Cx) {
+        if self.state.right_to_left && self.state.is_comic() {
+            self.go_to_previous_image(cx);
+        } else {
+            self.go_to_next_image(cx);
+        }
+    }
+
+    fn toggle_spread(&mut self, cx: &mut Cx) {
+        self.state.spread = !self.state.spread;
+        self.update_spread(cx);
+    }
+
+    fn toggle_right_to_left(&mut self, cx: &mut Cx) {
+        self.state.right_to_left = !self.state.right_to_left;
+        self.update_spread(cx);
+    }
+
+    // The facing page goes on the right, or on the left when reading
+    // right-to-left. It always comes from the texture cache, since comic
+    // pages are archive entries that makepad can't load by itself.
+    fn update_spread(&mut self, cx: &mut Cx) {
+        let partner = self.state.spread_partner().cloned();
+        let texture = partner.and_then(|source| self.state.texture(&source));
+        let (facing, other) = if self.state.right_to_left {
+            (id!(slideshow.spread_left), id!(slideshow.spread_right))
+        } else {
+            (id!(slideshow.spread_right), id!(slideshow.spread_left))
+        };
+
+        self.ui.image(other).set_visible(cx, false);
+        let facing = self.ui.image(facing);
+        facing.set_visible(cx, texture.is_some());
+        facing.set_texture(cx, texture);
+        self.ui.redraw(cx);
+    }
+
+    fn show_page(&mut self, _cx: &mut Cx, page: usize) {
+        let Some(source) = self.state.current_image_source().cloned() else {
+            return;
+        };
+
+        let ui = self.ui_runner();
+        std::thread::spawn(move || {
+            if page >= registry().page_count(&source) {
+                return;
+            }
+
+            let result = registry().decode_file_page(&source, page);
+            ui.defer(move |me, cx, _scope| {
+                if me.state.current_image_source() != Some(&source) {
+                    return;
+                }
+
+                match result {
+                    Ok(decoded) => {
+                        me.state.current_page = page;
+                        let texture = decoded.into_texture(cx);
+                        me.set_slideshow_texture(cx, Some(texture));
+                        me.ui.redraw(cx);
+                    }
+                    Err(e) => {
+                        eprintln!(
+                            "Error decoding page {page} of {source}: {e}"
+                        );
+                    }
+                }
+            });
+        });
+    }
+
+    // Thumbnails are decoded in full too, but only kept at tile size.
+    fn load_requested_textures(&mut self) {
+        for (source, size) in self.state.texture_requests.drain(..) {
+            let ui = self.ui_runner();
+            spawn_decode(move || {
+                let result =
+                    registry().decode_file_preview(&source).map(|decoded| {
+                        match size {
+                            TextureSize::Full => decoded,
+                            TextureSize::Thumbnail => {
+                                decoded.downscaled(THUMBNAIL_SIZE)
+                            }
+                        }
+                    });
+                ui.defer(move |me, cx, _scope| match result {
+                    Ok(decoded) => {
+                        let texture = decoded.into_texture(cx);
+                        match size {
+                            TextureSize::Full => {
+                                let spread_page =
+                                    me.state.spread_partner() == Some(&source);
+                                let current = me.state.current_image_source()
+                                    == Some(&source);
+                                me.state.textures.insert(
+                                    source,
+                                    size,
+                                    texture.clone(),
+                                );
+                                if current {
+                                    me.set_slideshow_texture(cx, Some(texture));
+                                }
+                                if spread_page {
+                                    me.update_spread(cx);
+                                }
+                                // Compared vector images have rasters of
+                                // their own, sharper than the preview.
+                                if !registry().is_vector(&source.path()) {
+                                    me.ui
+                                        .compare_view(id!(compare.view))
+                                        .set_texture(cx, &source, &texture);
+                                }
+                            }
+                            TextureSize::Thumbnail => {
+                                let selected =
+                                    me.state.is_selected_source(&source);
+                                me.state.textures.insert(source, size, texture);
+                                if selected {
+                                    me.update_selection_views(cx);
+                                }
+                            }
+                        }
+                        me.ui.redraw(cx);
+                    }
+                    Err(e) => {
+                        eprintln!("Error decoding {source}: {e}");
+                        me.state.textures.mark_failed(source, size);
+                        me.ui.redraw(cx);
+                    }
+                });
+            });
+        }
+    }
+
+    fn develop_current_image(&mut self, _cx: &mut Cx) {
+        let Some(source) = self.state.current_image_source().cloned() else {
+            return;
+        };
+        let Some(raw_source) = self.state.raw_source(&source) else {
+            return;
+        };
+
+        let show_scopes = self.state.show_scopes;
+        let display = self.state.display.clone();
+        let ui = self.ui_runner();
+        std::thread::spawn(move || {
+            let pixels = match registry().decode_file(&raw_source) {
+                Ok(pixels) => Arc::new(pixels),
+                Err(e) => {
+                    eprintln!("Error developing {raw_source}: {e}");
+                    return;
+                }
+            };
+            let scopes =
+                show_scopes.then(|| Scopes::compute(&pixels, &display));
+
+            ui.defer(move |me, cx, _scope| {
+                if me.state.current_image_source() != Some(&source) {
+                    return;
+                }
+
+                let texture = (*pixels).clone().into_texture(cx);
+                me.set_slideshow_texture(cx, Some(texture));
+                if let Some(scopes) = scopes {
+                    me.set_scopes(cx, scopes);
+                }
+                me.ui
+                    .loupe(id!(slideshow.loupe))
+                    .set_pixels(cx, Some(pixels));
+                me.ui.redraw(cx);
+            });
+        });
+    }
+
+    fn play_animation(&mut self, cx: &mut Cx, target: AnimationTarget) {
+        self.stop_animation(cx);
+
+        let image_idx = match target {
+            AnimationTarget::Slideshow => self.state.current_image_idx,
+            AnimationTarget::Thumbnail(image_idx) => image_idx,
+        };
+        let Some(source) = self.state.image_sources.get(image_idx).cloned()
+        else {
+            return;
+        };
+
+        self.animation_target = target;
+        self.show_animation_status(cx);
+        if !animation::may_be_animated(&source) {
+            return;
+        }
+
+        // Opening reads the file, which waits on a thread of its own.
+        let generation = self.animation_generation;
+        let ui = self.ui_runner();
+        std::thread::spawn(move || {
+            let Some(animation) = Animation::open(&source) else {
+                return;
+            };
+            ui.defer(move |me, cx, _scope| {
+                if me.animation_generation != generation {
+                    return;
+                }
+                me.animation = Some(animation);
+                me.schedule_animation(cx, 0.0);
+                me.show_animation_status(cx);
+            });
+        });
+    }
+
+    fn show_animation_status(&mut self, cx: &mut Cx) {
+        if self.animation_target == AnimationTarget::Slideshow {
+            self.ui
+                .view(id!(slideshow.animation_status))
+                .set_visible(cx, self.animation.is_some());
+            self.update_animation_status(cx);
+        }
+    }
+
+    fn stop_animation(&mut self, cx: &mut Cx) {
+        cx.stop_timer(self.animation_timer);
+        self.animation = None;
+        self.animation_generation += 1;
+
+        if self.state.hover_frame.take().is_some() {
+            self.ui.redraw(cx);
+        }
+    }
+
+    fn schedule_animation(&mut self, cx: &mut Cx, delay: f64) {
+        cx.stop_timer(self.animation_timer);
+        self.animation_timer = cx.start_timeout(delay);
+    }
+
+    fn advance_animation(&mut self, cx: &mut Cx) {
+        let Some(animation) = self.animation.as_mut() else {
+            return;
+        };
+
+        let frame = match animation.poll() {
+            Ok(Some(frame)) => frame,
+            Ok(None) => {
+                self.schedule_animation(cx, ANIMATION_POLL_INTERVAL);
+                return;
+            }
+            Err(e) => {
+                eprintln!("Error playing animation: {e}");
+                self.stop_animation(cx);
+                return;
+            }
+        };
+
+        if animation.is_playing() {
+            let delay = frame.delay / animation.speed();
+            self.schedule_animation(cx, delay);
+        }
+
+        let texture = frame.image.into_texture(cx);
+        match self.animation_target {
+            AnimationTarget::Slideshow => {
+                self.set_slideshow_texture(cx, Some(texture));
+                self.update_animation_status(cx);
+            }
+            AnimationTarget::Thumbnail(image_idx) => {
+                self.state.hover_frame = Some((image_idx, texture));
+            }
+        }
+        self.ui.redraw(cx);
+    }
+
+    fn update_animation(
+        &mut self,
+        cx: &mut Cx,
+        update: impl FnOnce(&mut Animation),
+    ) {
+        let Some(animation) = self.animation.as_mut() else {
+            return;
+        };
+
+        let was_playing = animation.is_playing();
+        update(animation);
+
+        if animation.is_playing() && !was_playing {
+            self.schedule_animation(cx, 0.0);
+        } else if !animation.is_playing() {
+            cx.stop_timer(self.animation_timer);
+        }
+        self.update_animation_status(cx);
+    }
+
+    fn step_animation(&mut self, cx: &mut Cx, forward: bool) {
+        self.update_animation(cx, |animation| {
+            if forward {
+                animation.step_forward();
+            } else {
+                animation.step_back();
+            }
+        });
+
+        if self.animation.is_some() {
+            self.schedule_animation(cx, 0.0);
+        }
+    }
+
+    fn update_animation_status(&mut self, cx: &mut Cx) {
+        if self.animation_target != AnimationTarget::Slideshow {
+            return;
+        }
+
+        let status = self
+            .animation
+            .as_ref()
+            .map(|animation| animation.status())
+            .unwrap_or_default();
+        self.ui
+            .label(id!(slideshow.animation_status.frame_counter))
+            .set_text(cx, &status);
+    }
+
+    // Vector images are rasterized again whenever the size they take on
+    // screen changes, so they stay crisp at any window size and display scale.
+    fn update_vector_raster(&mut self, cx: &mut Cx) {
+        let Some(source) = self.state.current_image_source().cloned() else {
+            return;
+        };
+        if !registry().is_vector(&source.path()) {
+            return;
+        }
+
+        let rect = self.ui.image(id!(slideshow.image)).area().rect(cx);
+        let size = rect.size * self.state.dpi_factor;
+        let (width, height) =
+            (size.x.round() as usize, size.y.round() as usize);
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        if let Some((raster_source, raster_width, raster_height)) =
+            &self.state.vector_raster
+        {
+            let unchanged = *raster_source == source
+                && raster_width.abs_diff(width) <= 1
+                && raster_height.abs_diff(height) <= 1;
+            if unchanged {
+                return;
+            }
+        }
+        self.state.vector_raster = Some((source.clone(), width, height));
+
+        let ui = self.ui_runner();
+        std::thread::spawn(move || {
+            let result = registry().rasterize_file(&source, width, height);
+            ui.defer(move |me, cx, _scope| {
+                if me.state.current_image_source() != Some(&source) {
+                    return;
+                }
+
+                match result {
+                    Ok(decoded) => {
+                        let texture = decoded.into_texture(cx);
+                        me.set_slideshow_texture(cx, Some(texture));
+                        me.ui.redraw(cx);
+                    }
+                    Err(e) => {
+                        eprintln!("Error rasterizing {source}: {e}");
+                    }
+                }
+            });
+        });
+    }
+
+    // Compared vector images are rasterized for the zoom as well, so they
+    // stay crisp zoomed in.
+    fn update_compare_rasters(&mut self, cx: &mut Cx) {
+        if self.state.page != Page::Compare {
+            return;
+        }
+
+        let view = self.ui.compare_view(id!(compare.view));
+        let zoom = view.zoom() as f64;
+        let rect = view.area().rect(cx);
+        let mut size = rect.size * self.state.dpi_factor * zoom;
+        let long_edge = size.x.max(size.y);
+        if long_edge > MAX_VECTOR_RASTER {
+            size = size * (MAX_VECTOR_RASTER / long_edge);
+        }
+        let (width, height) =
+            (size.x.round() as usize, size.y.round() as usize);
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        for (id, source) in view.sources().into_iter().enumerate() {
+            let Some(source) = source else {
+                continue;
+            };
+            if !registry().is_vector(&source.path()) {
+                continue;
+            }
+            if let Some((raster_source, raster_width, raster_height)) =
+                &self.state.compare_rasters[id]
+            {
+                let unchanged = *raster_source == source
+                    && raster_width.abs_diff(width) <= 1
+                    && raster_height.abs_diff(height) <= 1;
+                if unchanged {
+                    continue;
+                }
+            }
+            let raster = (source.clone(), width, height);
+            self.state.compare_rasters[id] = Some(raster.clone());
+
+            let ui = self.ui_runner();
+            std::thread::spawn(move || {
+                let result = registry().rasterize_file(&source, width, height);
+                ui.defer(move |me, cx, _scope| {
+                    // Zooming on may have asked for another size since.
+                    if me.state.compare_rasters[id] != Some(raster) {
+                        return;
+                    }
+
+                    match result {
+                        Ok(decoded) => {
+                            let texture = decoded.into_texture(cx);
+                            me.ui
+                                .compare_view(id!(compare.view))
+                                .set_texture(cx, &source, &texture);
+                        }
+                        Err(e) => {
+                            eprintln!("Error rasterizing {source}: {e}");
+                        }
+                    }
+                });
+            });
+        }
+    }
+
+    fn toggle_scopes(&mut self, cx: &mut Cx) {
+        self.state.show_scopes = !self.state.show_scopes;
+        let clipping = self.state.show_scopes as u8 as f64;
+
+        self.ui
+            .view(id!(slideshow.scopes))
+            .set_visible(cx, self.state.show_scopes);
+        self.ui.image(id!(slideshow.image)).apply_over(
+            cx,
+            live! {
+                draw_bg: { clipping: (clipping) }
+            },
+        );
+
+        self.update_image_analysis(cx);
+        self.ui.redraw(cx);
+    }
+
+    fn cycle_background(&mut self, cx: &mut Cx) {
+        self.state.display.cycle_background();
+        self.state
+            .display
+            .apply_background(cx, &self.ui.image(id!(slideshow.image)));
+        self.ui.redraw(cx);
+    }
+
+    // `None` follows the desktop's light or dark preference.
+    fn set_theme(&mut self, cx: &mut Cx, name: Option<String>) {
+        self.state.theme = Theme::load(name.as_deref());
+        self.theme_watcher.watch(name.as_deref());
+        self.state.theme_name = name;
+        self.apply_theme(cx);
+    }
+
+    // Grid items pick up the theme as they're drawn, everything else here.
+    fn apply_theme(&mut self, cx: &mut Cx) {
+        let theme = &self.state.theme;
+
+        self.ui.view(id!(body)).apply_over(
+            cx,
+            live! {
+                draw_bg: { color: (theme.background) }
+            },
+        );
+        self.ui.view(id!(menu_bar)).apply_over(
+            cx,
+            live! {
+                spacing: (theme.spacing),
+                padding: (theme.spacing),
+                draw_bg: { color: (theme.panel) }
+            },
+        );
+
+        for id in [id!(left_button), id!(right_button)] {
+            self.ui.button(id).apply_over(
+                cx,
+                live! {
+                    draw_bg: {
+                        color: (theme.button),
+                        color_down: (theme.button_pressed),
+                    }
+                },
+            );
+        }
+
+        for id in [
+            id!(image_browser.chat),
+            id!(image_browser.ask_chat),
+            id!(slideshow.chat),
+        ] {
+            self.ui.widget(id).apply_over(
+                cx,
+                live! {
+                    draw_bg: { color: (theme.chat) }
+                },
+            );
+        }
+
+        for id in [id!(animation_status.frame), id!(slideshow.tone_controls)] {
+            self.ui.view(id).apply_over(
+                cx,
+                live! {
+                    draw_bg: {
+                        color: (theme.overlay),
+                        border_radius: (theme.radius),
+                    }
+                },
+            );
+        }
+        self.ui.view(id!(scopes.panel)).apply_over(
+            cx,
+            live! {
+                draw_bg: { color: (theme.overlay) }
+            },
+        );
+
+        self.ui.redraw(cx);
+    }
+
+    fn update_channel_view(
+        &mut self,
+        cx: &mut Cx,
+        update: impl FnOnce(&mut DisplaySettings),
+    ) {
+        update(&mut self.state.display);
+        self.state
+            .display
+            .apply_channels(cx, &self.ui.image(id!(slideshow.image)));
+        self.update_image_analysis(cx);
+        self.ui.redraw(cx);
+    }
+
+    fn toggle_loupe(&mut self, cx: &mut Cx) {
+        self.state.show_loupe = !self.state.show_loupe;
+        self.ui
+            .loupe(id!(slideshow.loupe))
+            .set_active(cx, self.state.show_loupe);
+        self.update_image_analysis(cx);
+    }
+
+    fn update_image_analysis(&mut self, cx: &mut Cx) {
+        self.ui.loupe(id!(slideshow.loupe)).set_pixels(cx, None);
+
+        if !self.state.show_scopes && !self.state.show_loupe {
+            return;
+        }
+
+        let Some(source) = self.state.current_image_source().cloned() else {
+            return;
+        };
+
+        let show_scopes = self.state.show_scopes;
+        let display = self.state.display.clone();
+        let ui = self.ui_runner();
+        std::thread::spawn(move || {
+            let pixels = match registry().decode_file_preview(&source) {
+                Ok(pixels) => Arc::new(pixels),
+                Err(e) => {
+                    eprintln!("Error analyzing {source}: {e}");
+                    return;
+                }
+            };
+            let scopes =
+                show_scopes.then(|| Scopes::compute(&pixels, &display));
+
+            ui.defer(move |me, cx, _scope| {
+                if me.state.current_image_source() != Some(&source) {
+                    return;
+                }
+
+                if let Some(scopes) = scopes {
+                    me.set_scopes(cx, scopes);
+                }
+                me.ui
+                    .loupe(id!(slideshow.loupe))
+                    .set_pixels(cx, Some(pixels));
+            });
+        });
+    }
+
+    fn set_scopes(&mut self, cx: &mut Cx, scopes: Scopes) {
+        let mut clipping = format!(
+            "Highlights clipped: {:.1}%  Shadows clipped: {:.1}%",
+            scopes.highlights_clipped * 100.0,
+            scopes.shadows_clipped * 100.0,
+        );
+        if scopes.peak > 1.0 {
+            clipping = format!("{clipping}\nPeak: {:.3}", scopes.peak);
+        }
+
+        let rasters = [
+            (id!(slideshow.scopes.histogram), scopes.histogram),
+            (id!(slideshow.scopes.waveform), scopes.waveform),
+            (id!(slideshow.scopes.parade), scopes.parade),
+        ];
+        for (path, raster) in rasters {
+            let texture = raster.into_texture(cx);
+            self.ui.image(path).set_texture(cx, Some(texture));
+        }
+
+        self.ui
+            .label(id!(slideshow.scopes.clipping))
+            .set_text(cx, &clipping);
+        self.ui.redraw(cx);
+    }
+
+    fn open_compare(&mut self, cx: &mut Cx) {
+        let Some((a, b)) = self.state.compare_pair() else {
+            eprintln!("Error: Select two images to compare");
+            return;
+        };
+
+        self.state.compare_rasters = [None, None];
+        let textures = [self.state.texture(&a), self.state.texture(&b)];
+        self.ui.compare_view(id!(compare.view)).set_images(
+            cx,
+            [a, b],
+            textures,
+        );
+        self.set_active_page(cx, Page::Compare);
+    }
+
+    fn restore_session(&mut self, cx: &mut Cx, session: Session) {
+        if let Some(window) = session.window {
+            let window_ref = self.ui.window(id!(main_window));
+            window_ref
+                .reposition(cx, dvec2(window.position[0], window.position[1]));
+            window_ref.resize(cx, dvec2(window.size[0], window.size[1]));
+            self.state.window = Some(window);
+        }
+
+        // Only the images still in the folder, which may have changed.
+        let grid_order = session.grid_order.map(|order| {
+            order
+                .into_iter()
+                .filter(|s| self.state.image_sources.contains(s))
+                .collect::<Vec<_>>()
+        });
+        if let Some(order) = grid_order.filter(|order| !order.is_empty()) {
+            self.state.unranked = Some(self.state.image_sources.clone());
+            self.set_grid_order(cx, order);
+            self.ui
+                .text_input(id!(search_input))
+                .set_text(cx, &session.search);
+        }
+
+        let image_idx = session.current_image.and_then(|current| {
+            self.state.image_sources.iter().position(|s| *s == current)
+        });
+        if let Some(image_idx) = image_idx {
+            self.set_current_image(cx, image_idx);
+        }
+        self.state.restore_grid_row = Some(session.grid_row);
+
+        if session.show_scopes {
+            self.toggle_scopes(cx);
+        }
+        if session.show_loupe {
+            self.toggle_loupe(cx);
+        }
+        if session.show_tone_controls {
+            self.toggle_tone_controls(cx);
+        }
+        self.state.spread = session.spread;
+        self.state.right_to_left = session.right_to_left;
+        self.update_spread(cx);
+        self.set_theme(cx, session.theme);
+        self.state.display.background = session.background;
+        self.state
+            .display
+            .apply_background(cx, &self.ui.image(id!(slideshow.image)));
+
+        self.state.hide_image_chat = session.hide_image_chat;
+        self.state.hide_slideshow_chat = session.hide_slideshow_chat;
+        self.state.show_selection_chat = session.show_selection_chat;
+        self.ui.chat(id!(image_browser.ask_chat)).write().visible =
+            session.show_selection_chat;
+
+        // Comparisons depend on a selection, which isn't saved.
+        if session.page == Page::Slideshow {
+            self.set_active_page(cx, Page::Slideshow);
+        }
+    }
+
+    fn save_session(&self) {
+        self.save_conversation();
+
+        let session = Session {
+            roots: self.state.recent_roots.clone(),
+            page: self.state.page,
+            current_image: self.state.current_image_source().cloned(),
+            grid_row: self.state.grid_row,
+            window: self.state.window,
+            show_scopes: self.state.show_scopes,
+            show_loupe: self.state.show_loupe,
+            show_tone_controls: self.state.show_tone_controls,
+            spread: self.state.spread,
+            right_to_left: self.state.right_to_left,
+            background: self.state.display.background,
+            theme: self.state.theme_name.clone(),
+            hide_image_chat: self.state.hide_image_chat,
+            hide_slideshow_chat: self.state.hide_slideshow_chat,
+            show_selection_chat: self.state.show_selection_chat,
+            grid_order: self
+                .state
+                .unranked
+                .is_some()
+                .then(|| self.state.image_sources.clone()),
+            search: self.ui.text_input(id!(search_input)).text(),
+        };
+        if let Err(e) = session.save() {
+            eprintln!("Error saving session: {e}");
+        }
+    }
+
+    fn run_command(&mut self, cx: &mut Cx, command: Command) {
+        match command {
+            Command::CommandPalette => self.open_command_palette(cx),
+            Command::OpenSlideshow => {
+                self.play_animation(cx, AnimationTarget::Slideshow);
+                self.set_active_page(cx, Page::Slideshow);
+            }
+            Command::BackToGrid => self.set_active_page(cx, Page::ImageBrowser),
+            Command::OpenCompare => self.open_compare(cx),
+            Command::OpenSettings => self.open_settings(cx),
+            Command::ShowConversations => self.open_conversations(cx),
+            Command::CompareWipe => {
+                self.set_compare_mode(cx, CompareMode::Wipe)
+            }
+            Command::CompareSideBySide => {
+                self.set_compare_mode(cx, CompareMode::SideBySide)
+            }
+            Command::CompareDifference => {
+                self.set_compare_mode(cx, CompareMode::Difference)
+            }
+            Command::GoLeft => self.go_left(cx),
+            Command::GoRight => self.go_right(cx),
+            Command::PreviousImage => self.go_to_previous_image(cx),
+            Command::NextImage => self.go_to_next_image(cx),
+            Command::PreviousPage => {
+                if let Some(page) = self.state.current_page.checked_sub(1) {
+                    self.show_page(cx, page);
+                }
+            }
+            Command::NextPage => {
+                self.show_page(cx, self.state.current_page + 1)
+            }
+            Command::ToggleChat => self.toggle_chat(cx),
+            Command::AskAboutSelection => self.toggle_selection_chat(cx),
+            Command::CaptionImages => self.start_captioning(cx),
+            Command::TagImages => self.start_tagging(cx),
+            Command::ReviewTags => self.toggle_tag_review(cx),
+            Command::FindSimilar => self.find_similar(cx),
+            Command::ToggleAnimation => {
+                self.update_animation(cx, |a| a.toggle_playing())
+            }
+            Command::PreviousFrame => self.step_animation(cx, false),
+            Command::NextFrame => self.step_animation(cx, true),
+            Command::SlowerAnimation => self.update_animation(cx, |a| {
+                a.adjust_speed(1.0 / ANIMATION_SPEED_STEP);
+            }),
+            Command::FasterAnimation => self.update_animation(cx, |a| {
+                a.adjust_speed(ANIMATION_SPEED_STEP);
+            }),
+            Command::ToggleHoverAnimation => {
+                self.state.animate_on_hover = !self.state.animate_on_hover;
+            }
+            Command::DevelopRaw => self.develop_current_image(cx),
+            Command::ToggleToneControls => self.toggle_tone_controls(cx),
+            Command::ToggleScopes => self.toggle_scopes(cx),
+            Command::ToggleSoftProof => self.toggle_soft_proof(cx),
+            Command::ToggleLoupe => self.toggle_loupe(cx),
+            Command::CycleBackground => self.cycle_background(cx),
+            Command::CycleTheme => {
+                let next = theme::next_theme(self.state.theme_name.as_deref());
+                self.set_theme(cx, next);
+            }
+            Command::ToggleSpread => self.toggle_spread(cx),
+            Command::ToggleRightToLeft => self.toggle_right_to_left(cx),
+            Command::ShowColor => self.update_channel_view(cx, |d| {
+                d.channel = ChannelView::Color;
+            }),
+            Command::ToggleRed => self.update_channel_view(cx, |d| {
+                d.toggle_channel(ChannelView::Red);
+            }),
+            Command::ToggleGreen => self.update_channel_view(cx, |d| {
+                d.toggle_channel(ChannelView::Green);
+            }),
+            Command::ToggleBlue => self.update_channel_view(cx, |d| {
+                d.toggle_channel(ChannelView::Blue);
+            }),
+            Command::ToggleAlpha => self.update_channel_view(cx, |d| {
+                d.toggle_channel(ChannelView::Alpha);
+            }),
+            Command::ToggleLuminance => self.update_channel_view(cx, |d| {
+                d.toggle_channel(ChannelView::Luminance);
+            }),
+            Command::ToggleFalseColor => self.update_channel_view(cx, |d| {
+                d.toggle_channel(ChannelView::FalseColor);
+            }),
+            Command::CycleColormap => {
+                self.update_channel_view(cx, |d| d.cycle_colormap())
+            }
+            Command::LowerRemapMin => {
+                self.update_channel_view(cx, |d| d.adjust_remap(-1, 0))
+            }
+            Command::RaiseRemapMin => {
+                self.update_channel_view(cx, |d| d.adjust_remap(1, 0))
+            }
+            Command::LowerRemapMax => {
+                self.update_channel_view(cx, |d| d.adjust_remap(0, -1))
+            }
+            Command::RaiseRemapMax => {
+                self.update_channel_view(cx, |d| d.adjust_remap(0, 1))
+            }
+        }
+    }
+
+    fn open_command_palette(&mut self, cx: &mut Cx) {
+        self.ui.command_palette(id!(command_palette)).open(
+            cx,
+            &self.keymap,
+            CommandScope::of_page(self.state.page),
+        );
+    }
+
+    fn open_settings(&mut self, cx: &mut Cx) {
+        self.set_active_page(cx, Page::Settings);
+        self.check_providers(cx);
+    }
+
+    // Asks every provider for its models, both to fill the drop downs and
+    // to report the ones that can't be reached.
+    fn check_providers(&mut self, cx: &mut Cx) {
+        let generation = self.available_models.generation + 1;
+        self.available_models = AvailableModels {
+            generation,
+            pending: self.providers.providers.len(),
+            ..AvailableModels::default()
+        };
+        if let Some(error) = &self.providers_error {
+            self.available_models.status.push(error.clone());
+        }
+        self.update_settings(cx);
+
+        for provider in self.providers.providers.clone() {
+            let name = provider.name.clone();
+            let ui = self.ui_runner();
+            spawn(async move {
+                let result = load_provider_models(provider).await;
+
+                ui.defer(move |me, cx, _scope| {
+                    let available = &mut me.available_models;
+                    if available.generation != generation {
+                        return;
+                    }
+
+                    available.pending -= 1;
+                    match result {
+                        Ok((chat, image)) => {
+                            available.status.push(format!(
+                                "{name}: {} chat models, {} image models",
+                                chat.len(),
+                                image.len()
+                            ));
+                            available.chat.extend(chat);
+                            available.image.extend(image);
+                        }
+                        Err(error) => available.status.push(error),
+                    }
+                    me.update_settings(cx);
+                });
+            });
+        }
+    }
+
+    fn update_settings(&mut self, cx: &mut Cx) {
+        let available = &self.available_models;
+        let drop_downs = [
+            (
+                id!(settings.chat_model),
+                &available.chat,
+                self.models.chat.as_ref(),
+            ),
+            (
+                id!(settings.image_model),
+                &available.image,
+                self.models.image.as_ref(),
+            ),
+        ];
+        for (id, models, chosen) in drop_downs {
+            let options = model_options(models, chosen);
+            let selected = chosen
+                .and_then(|chosen| options.iter().position(|m| m == chosen))
+                .map_or(0, |idx| idx + 1);
+            let labels = std::iter::once("None".to_string())
+                .chain(options.iter().map(|m| m.to_string()))
+                .collect();
+
+            let drop_down = self.ui.drop_down(id);
+            drop_down.set_labels(cx, labels);
+            drop_down.set_selected_item(cx, selected);
+        }
+
+        let mut status = available.status.join("\n");
+        if available.pending > 0 {
+            status.push_str("\nChecking providers...");
+        }
+        self.ui
+            .label(id!(settings.status))
+            .set_text(cx, status.trim_start());
+        self.ui.redraw(cx);
+    }
+
+    // Picks the model a settings drop down landed on, where 0 is "None".
+    fn choose_model(&mut self, cx: &mut Cx, image: bool, index: usize) {
+        let (available, chosen) = if image {
+            (&self.available_models.image, &mut self.models.image)
+        } else {
+            (&self.available_models.chat, &mut self.models.chat)
+        };
+        let options = model_options(available, chosen.as_ref());
+        *chosen = index
+            .checked_sub(1)
+            .and_then(|idx| options.get(idx).cloned());
+
+        if let Err(e) = self.models.save() {
+            eprintln!("Error saving model selection: {e}");
+        }
+        if image {
+            self.configure_image_browser_chat_context(cx);
+        } else {
+            self.configure_slideshow_chat_context(cx);
+            self.configure_selection_chat_context(cx);
+        }
+    }
+
+    fn set_compare_mode(&mut self, cx: &mut Cx, mode: CompareMode) {
+        self.ui.compare_view(id!(compare.view)).set_mode(cx, mode);
+    }
+
+    // Writes alt text for the selected images, or all of them when none
+    // are selected, into their XMP sidecars.
+    fn start_captioning(&mut self, cx: &mut Cx) {
+        if self.job_cancel.is_some() {
+            return;
+        }
+
+        let sources = self.job_sources();
+        let (client, model) = match self.job_client() {
+            Ok(chosen) => chosen,
+            Err(error) => {
+                self.set_job_status(cx, &error, false);
+                return;
+            }
+        };
+
+        let total = sources.len();
+        let job = CaptionJob::new(
+            client,
+            &model,
+            sources,
+            CaptionSettings::load(),
+            self.attachment_options.clone(),
+        );
+        self.job_cancel = Some(job.cancel_flag());
+        self.set_job_status(
+            cx,
+            &format!("Captioning {total} images with {model}..."),
+            true,
+        );
+
+        let ui = self.ui_runner();
+        let progress_ui = self.ui_runner();
+        spawn(async move {
+            let result = job
+                .run(move |progress, event| {
+                    let (progress, event) = (*progress, event.clone());
+                    progress_ui.defer(move |me, cx, _scope| {
+                        me.show_caption_progress(cx, &progress, &event);
+                    });
+                })
+                .await;
+
+            ui.defer(move |me, cx, _scope| {
+                me.job_cancel = None;
+                let status = match result {
+                    Ok(progress) => format!(
+                        "Captioned {}, skipped {}, failed {}",
+                        progress.done, progress.skipped, progress.failed
+                    ),
+                    Err(error) => format!("Captioning failed: {error}"),
+                };
+                me.set_job_status(cx, &status, false);
+            });
+        });
+    }
+
+    fn show_caption_progress(
+        &mut self,
+        cx: &mut Cx,
+        progress: &CaptionProgress,
+        event: &CaptionEvent,
+    ) {
+        let last = match event {
+            CaptionEvent::Captioned { source, .. } => source.file_name(),
+            CaptionEvent::Failed { source, error } => {
+                eprintln!("Error captioning {source}: {error}");
+                format!("{} failed", source.file_name())
+            }
+        };
+        let status = format!(
+            "Captioning {} of {}, {} failed. Last: {last}",
+            progress.finished(),
+            progress.total,
+            progress.failed
+        );
+        self.set_job_status(cx, &status, true);
+    }
+
+    // Tags the selected images, or all of them when none are selected,
+    // from the vocabulary. Confident tags go to their XMP keywords and the
+    // rest wait in the review panel.
+    fn start_tagging(&mut self, cx: &mut Cx) {
+        if self.job_cancel.is_some() {
+            return;
+        }
+
+        let sources = self.job_sources();
+        let (client, model) = match self.job_client() {
+            Ok(chosen) => chosen,
+            Err(error) => {
+                self.set_job_status(cx, &error, false);
+                return;
+            }
+        };
+
+        let total = sources.len();
+        let job = TagJob::new(
+            client,
+            &model,
+            sources,
+            TagSettings::load(),
+            self.attachment_options.clone(),
+        );
+        self.job_cancel = Some(job.cancel_flag());
+        self.set_job_status(
+            cx,
+            &format!("Tagging {total} images with {model}..."),
+            true,
+        );
+
+        let ui = self.ui_runner();
+        let progress_ui = self.ui_runner();
+        spawn(async move {
+            let result = job
+                .run(move |progress, event| {
+                    let (progress, event) = (*progress, event.clone());
+                    progress_ui.defer(move |me, cx, _scope| {
+                        me.show_tag_progress(cx, &progress, event);
+                    });
+                })
+                .await;
+
+            ui.defer(move |me, cx, _scope| {
+                me.job_cancel = None;
+                let status = match result {
+                    Ok(progress) => format!(
+                        "Tagged {}, failed {}",
+                        progress.done, progress.failed
+                    ),
+                    Err(error) => format!("Tagging failed: {error}"),
+                };
+                me.set_job_status(cx, &status, false);
+                if !me.state.pending_tags.is_empty() {
+                    me.set_tag_review_visible(cx, true);
+                }
+            });
+        });
+    }
+
+    fn show_tag_progress(
+        &mut self,
+        cx: &mut Cx,
+        progress: &TagProgress,
+        event: TagEvent,
+    ) {
+        let last = match event {
+            TagEvent::Tagged {
+                source,
+                applied,
+                queued,
+            } => {
+                let name = source.file_name();
+                self.ui
+                    .tag_review(id!(image_browser.tag_review))
+                    .queue(cx, &source, queued);
+                self.update_tag_badges(cx);
+                if applied.is_empty() {
+                    name
+                } else {
+                    format!("{name}: {}", applied.join(", "))
+                }
+            }
+            TagEvent::Failed { source, error } => {
+                eprintln!("Error tagging {source}: {error}");
+                format!("{} failed", source.file_name())
+            }
+        };
+        let status = format!(
+            "Tagging {} of {}, {} failed. Last: {last}",
+            progress.finished(),
+            progress.total,
+            progress.failed
+        );
+        self.set_job_status(cx, &status, true);
+    }
+
+    fn toggle_tag_review(&mut self, cx: &mut Cx) {
+        if self.state.page != Page::ImageBrowser {
+            self.set_active_page(cx, Page::ImageBrowser);
+        }
+
+        self.set_tag_review_visible(cx, !self.state.show_tag_review);
+    }
+
+    fn set_tag_review_visible(&mut self, cx: &mut Cx, visible: bool) {
+        self.state.show_tag_review = visible;
+        self.ui
+            .view(id!(image_browser.tag_review))
+            .set_visible(cx, visible);
+        self.ui.redraw(cx);
+    }
+
+    fn update_tag_badges(&mut self, cx: &mut Cx) {
+        self.state.pending_tags = self
+            .ui
+            .tag_review(id!(image_browser.tag_review))
+            .pending_counts();
+        self.ui.redraw(cx);
+    }
+
+    // Jobs work on the selected images, or all of them when none are.
+    fn job_sources(&self) -> Vec<ImageSource> {
+        let sources = self.state.selected_sources();
+        if sources.is_empty() {
+            self.state.image_sources.clone()
+        } else {
+            sources
+        }
+    }
+
+    // A client for the chosen chat model, with the model's name.
+    fn job_client(&self) -> Result<(OpenAIClient, String), String> {
+        let (provider, choice) =
+            self.chosen_model(self.models.chat.as_ref(), "chat")?;
+        Ok((provider.chat_client()?, choice.model.clone()))
+    }
+
+    // Embeds the images the search index has no current vector for, in
+    // the background. Loading another folder starts over.
+    fn start_indexing(&mut self, cx: &mut Cx) {
+        if let Some(cancel) = self.index_cancel.take() {
+            cancel.store(true, Ordering::Relaxed);
+        }
+        let Some(search) = self.search.clone() else {
+            return;
+        };
+
+        let sources = self.state.unranked_sources();
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.index_cancel = Some(Arc::clone(&cancel));
+        self.set_search_status(cx, "");
+
+        let ui = self.ui_runner();
+        std::thread::spawn(move || {
+            let failed = search.update(&sources, &cancel, |done, total| {
+                let cancel = Arc::clone(&cancel);
+                ui.defer(move |me, cx, _scope| {
+                    if !cancel.load(Ordering::Relaxed) {
+                        let status = format!("Indexing {done} of {total}");
+                        me.set_search_status(cx, &status);
+                    }
+                });
+            });
+            if cancel.load(Ordering::Relaxed) {
+                return;
+            }
+            ui.defer(move |me, cx, _scope| {
+                me.index_cancel = None;
+                let status = match failed {
+                    0 => String::new(),
+                    failed => format!("{failed} images couldn't be indexed"),
+                };
+                me.set_search_status(cx, &status);
+            });
+        });
+    }
+
+    // Ranks the grid by the description in the search box, or puts it back
+    // in order when the box is empty.
+    fn run_search(&mut self, cx: &mut Cx) {
+        let query = self.ui.text_input(id!(search_input)).text();
+        let query = query.trim().to_string();
+        if query.is_empty() {
+            if let Some(sources) = self.state.unranked.take() {
+                self.set_grid_order(cx, sources);
+            }
+            return;
+        }
+        let Some(search) = self.search.clone() else {
+            self.set_search_status(cx, "Set up search in search.toml");
+            return;
+        };
+
+        let sources = self.state.unranked_sources();
+        let ui = self.ui_runner();
+        std::thread::spawn(move || {
+            let result = search.search(&query, &sources);
+            ui.defer(move |me, cx, _scope| {
+                // Dropped when the query changed meanwhile.
+                let current = me.ui.text_input(id!(search_input)).text();
+                if current.trim() == query {
+                    me.show_ranking(cx, result);
+                }
+            });
+        });
+    }
+
+    // Ranks the grid by likeness to the image in the slideshow, or the
+    // first one selected in the grid.
+    fn find_similar(&mut self, cx: &mut Cx) {
+        let source = match self.state.page {
+            Page::ImageBrowser => {
+                self.state.selected_sources().first().cloned()
+            }
+            _ => None,
+        };
+        let Some(source) =
+            source.or_else(|| self.state.current_image_source().cloned())
+        else {
+            return;
+        };
+        self.set_active_page(cx, Page::ImageBrowser);
+        let Some(search) = self.search.clone() else {
+            self.set_search_status(cx, "Set up search in search.toml");
+            return;
+        };
+
+        self.ui.text_input(id!(search_input)).set_text(cx, "");
+        self.set_search_status(cx, &format!("Like {}", source.file_name()));
+        let sources = self.state.unranked_sources();
+        let ui = self.ui_runner();
+        std::thread::spawn(move || {
+            let result = search.similar(&source, &sources);
+            ui.defer(move |me, cx, _scope| me.show_ranking(cx, result));
+        });
+    }
+
+    fn show_ranking(
+        &mut self,
+        cx: &mut Cx,
+        result: Result<Vec<ImageSource>, String>,
+    ) {
+        match result {
+            // From before another folder was loaded.
+            Ok(ranked) if !self.state.has_same_images(&ranked) => {}
+            Ok(ranked) => {
+                if self.state.unranked.is_none() {
+                    self.state.unranked =
+                        Some(self.state.image_sources.clone());
+                }
+                self.set_grid_order(cx, ranked);
+            }
+            Err(error) => self.set_search_status(cx, &error),
+        }
+    }
+
+    // Shows the same images in another order, keeping the selection and
+    // the current image.
+    fn set_grid_order(&mut self, cx: &mut Cx, sources: Vec<ImageSource>) {
+        let selected = self.state.selected_sources();
+        let current = self.state.current_image_source().cloned();
+        self.state.image_sources = sources;
+
+        let position = |source: &ImageSource| {
+            self.state.image_sources.iter().position(|s| s == source)
+        };
+        self.state.selected_images =
+            selected.iter().filter_map(position).collect();
+        self.state.current_image_idx =
+            current.as_ref().and_then(position).unwrap_or(0);
+        self.state.hover_frame = None;
+        self.state.restore_grid_row = Some(0);
+        self.update_selection_views(cx);
+        self.ui.redraw(cx);
+    }
+
+    // Runs the chat model's calls to the viewer's tools.
+    fn tool_handler(&self) -> ToolHandler {
+        let ui = self.ui_runner();
+        Arc::new(move |calls| Box::pin(run_tool_calls(ui, calls)))
+    }
+
+    // Asks before the chat model changes files. Asking again denies what
+    // was waiting.
+    fn ask_tool_permission(
+        &mut self,
+        cx: &mut Cx,
+        changes: &[String],
+    ) -> oneshot::Receiver<bool> {
+        let (sender, receiver) = oneshot::channel();
+        if let Some(earlier) = self.tool_permission.replace(sender) {
+            let _ = earlier.send(false);
+        }
+        self.ui
+            .label(id!(tool_confirm.message))
+            .set_text(cx, &changes.join("\n"));
+        self.ui.view(id!(tool_confirm)).set_visible(cx, true);
+        self.ui.redraw(cx);
+        receiver
+    }
+
+    fn answer_tool_permission(&mut self, cx: &mut Cx, allowed: bool) {
+        if let Some(sender) = self.tool_permission.take() {
+            let _ = sender.send(allowed);
+        }
+        self.ui.view(id!(tool_confirm)).set_visible(cx, false);
+        self.ui.redraw(cx);
+    }
+
+    // The images a tool names, or else the selected ones, or else the
+    // current one.
+    fn tool_sources(
+        &self,
+        names: &[String],
+    ) -> Result<Vec<ImageSource>, String> {
+        if names.is_empty() {
+            let selected = self.state.selected_sources();
+            if !selected.is_empty() {
+                return Ok(selected);
+            }
+            return self
+                .state
+                .current_image_source()
+                .map(|source| vec![source.clone()])
+                .ok_or_else(|| "No images are loaded".to_string());
+        }
+        names
+            .iter()
+            .map(|name| {
+                tools::find_image(&self.state.image_sources, name)
+                    .map(|idx| self.state.image_sources[idx].clone())
+                    .ok_or_else(|| format!("No image named {name}"))
+            })
+            .collect()
+    }
+
+    fn go_to_named_image(
+        &mut self,
+        cx: &mut Cx,
+        name: &str,
+    ) -> Result<String, String> {
+        let idx = tools::find_image(&self.state.image_sources, name)
+            .ok_or_else(|| format!("No image named {name}"))?;
+        self.set_current_image(cx, idx);
+        self.run_command(cx, Command::OpenSlideshow);
+        Ok(format!(
+            "Showing {}",
+            self.state.image_sources[idx].file_name()
+        ))
+    }
+
+    // Shows the `matching` images of the folder, or all of them again.
+    fn show_filtered(
+        &mut self,
+        cx: &mut Cx,
+        matching: Vec<ImageSource>,
+    ) -> String {
+        let all = self.state.unranked_sources();
+        let shown = matching.len();
+        if shown == all.len() {
+            self.state.unranked = None;
+            self.set_grid_order(cx, all);
+        } else {
+            self.state.unranked = Some(all.clone());
+            self.set_grid_order(cx, matching);
+        }
+        self.set_active_page(cx, Page::ImageBrowser);
+        format!("Showing {shown} of {} images", all.len())
+    }
+
+    // Serves the agents asked for on the command line.
+    fn start_mcp_servers(&mut self, stdio: bool, socket: Option<&str>) {
+        let server = McpServer::new(AppLibrary(self.ui_runner()));
+        if stdio {
+            mcp::serve_stdio(server.clone());
+        }
+        let Some(address) = socket else {
+            return;
+        };
+        if let Err(e) = mcp::serve_socket(server, address) {
+            eprintln!("Error serving MCP at {address}: {e}");
+        }
+    }
+
+    fn apply_library_change(
+        &mut self,
+        cx: &mut Cx,
+        change: LibraryChange,
+    ) -> Result<(), String> {
+        match change {
+            LibraryChange::ShowImage(source) => {
+                self.show_in_grid(cx, std::slice::from_ref(&source));
+                let idx = self
+                    .state
+                    .image_sources
+                    .iter()
+                    .position(|s| *s == source)
+                    .ok_or_else(|| format!("{source} isn't loaded"))?;
+                self.set_current_image(cx, idx);
+                self.run_command(cx, Command::OpenSlideshow);
+            }
+            LibraryChange::NextImage => {
+                self.run_command(cx, Command::NextImage)
+            }
+            LibraryChange::PreviousImage => {
+                self.run_command(cx, Command::PreviousImage)
+            }
+            LibraryChange::ShowGrid => {
+                self.run_command(cx, Command::BackToGrid)
+            }
+            LibraryChange::Select(sources) => {
+                self.show_in_grid(cx, &sources);
+                self.state.selected_images = sources
+                    .iter()
+                    .filter_map(|source| {
+                        self.state
+                            .image_sources
+                            .iter()
+                            .position(|s| s == source)
+                    })
+                    .collect();
+                self.update_selection_views(cx);
+                self.ui.redraw(cx);
+            }
+        }
+        Ok(())
+    }
+
+    // Undoes a filter that hides any of `sources`.
+    fn show_in_grid(&mut self, cx: &mut Cx, sources: &[ImageSource]) {
+        let hidden = sources
+            .iter()
+            .any(|source| !self.state.image_sources.contains(source));
+        if !hidden {
+            return;
+        }
+        if let Some(all) = self.state.unranked.take() {
+            self.set_grid_order(cx, all);
+        }
+    }
+
+    fn set_search_status(&mut self, cx: &mut Cx, status: &str) {
+        self.ui.label(id!(search_status)).set_text(cx, status);
+        self.ui.redraw(cx);
+    }
+
+    // Images already sent still finish.
+    fn stop_job(&mut self, cx: &mut Cx) {
+        match &self.job_cancel {
+            Some(cancel) => {
+                cancel.store(true, Ordering::Relaxed);
+                self.set_job_status(cx, "Stopping...", true);
+            }
+            None => {
+                self.ui.view(id!(job_bar)).set_visible(cx, false);
+            }
+        }
+    }
+
+    // The stop button closes the bar once nothing is running.
+    fn set_job_status(&mut self, cx: &mut Cx, status: &str, running: bool) {
+        self.ui.view(id!(job_bar)).set_visible(cx, true);
+        self.ui.label(id!(job_bar.status)).set_text(cx, status);
+        self.ui
+            .button(id!(job_bar.stop_button))
+            .set_text(cx, if running { "Stop" } else { "Close" });
+        self.ui.redraw(cx);
+    }
+
+    // Shows or hides the chat about the images selected in the grid.
+    fn toggle_selection_chat(&mut self, cx: &mut Cx) {
+        if self.state.page != Page::ImageBrowser {
+            self.set_active_page(cx, Page::ImageBrowser);
+        }
+
+        let mut chat = self.ui.chat(id!(image_browser.ask_chat));
+        let visible = chat.read().visible;
+        chat.write().visible = !visible;
+        self.state.show_selection_chat = !visible;
+        self.ui.redraw(cx);
+    }
+
+    fn update_selection_views(&mut self, cx: &mut Cx) {
+        let selected = self.state.selected_images.clone();
+        let chips = selected
+            .into_iter()
+            .map(|image_idx| {
+                let source = self.state.image_sources[image_idx].clone();
+                let texture = self.state.thumbnail(&source);
+                (image_idx, source, texture)
+            })
+            .collect();
+        self.ui
+            .selection_chips(id!(image_browser.ask_chat.chips))
+            .set_chips(cx, chips);
+        self.ui
+            .tag_review(id!(image_browser.tag_review))
+            .set_filter(cx, self.state.selected_sources());
+    }
+
+    // Shows or hides the chat of the page in view.
+    fn toggle_chat(&mut self, cx: &mut Cx) {
+        let (mut chat, hidden) = match self.state.page {
+            Page::ImageBrowser => (
+                self.ui.chat(id!(image_browser.chat)),
+                &mut self.state.hide_image_chat,
+            ),
+            Page::Slideshow => (
+                self.ui.chat(id!(slideshow.chat)),
+                &mut self.state.hide_slideshow_chat,
+            ),
+            Page::Compare | Page::Settings | Page::Conversations => return,
+        };
+
+        let visible = chat.read().visible;
+        chat.write().visible = !visible;
+        *hidden = visible;
+        self.ui.redraw(cx);
+    }
+
+    // Whether keys go into a text field rather than to the keymap.
+    fn is_typing(&self, cx: &Cx) -> bool {
+        [
+            id!(search_input),
+            id!(history.query),
+            id!(command_palette.query),
+            id!(image_browser.chat.prompt.text_input),
+            id!(image_browser.ask_chat.prompt.text_input),
+            id!(slideshow.chat.prompt.text_input),
+        ]
+        .into_iter()
+        .any(|path| cx.has_key_focus(self.ui.text_input(path).area()))
+    }
+
+    fn set_active_page(&mut self, cx: &mut Cx, page: Page) {
+        self.state.page = page;
+        self.ui
+            .page_flip(id!(page_flip))
+            .set_active_page(cx, page.live_id());
+    }
+
+    fn configure_slideshow_chat(&mut self, cx: &mut Cx) {
+        self.configure_slideshow_chat_context(cx);
+        self.configure_ask_chat_before_hook(
+            id!(slideshow.chat),
+            Self::perform_chat_send,
+        );
+    }
+
+    fn configure_selection_chat(&mut self, cx: &mut Cx) {
+        self.configure_selection_chat_context(cx);
+        self.configure_ask_chat_before_hook(
+            id!(image_browser.ask_chat),
+            Self::perform_selection_send,
+        );
+    }
+
+    // The chosen model with the provider serving it, or why there's none.
+    fn chosen_model<'a>(
+        &'a self,
+        choice: Option<&'a ModelChoice>,
+        kind: &str,
+    ) -> Result<(&'a Provider, &'a ModelChoice), String> {
+        if let Some(error) = &self.providers_error {
+            return Err(error.clone());
+        }
+        let Some(choice) = choice else {
+            return Err(format!(
+                "No {kind} model chosen. Pick one in Settings"
+            ));
+        };
+        let provider =
+            self.providers.get(&choice.provider).ok_or_else(|| {
+                format!(
+                    "Provider '{}' of {kind} model {choice} isn't configured",
+                    choice.provider
+                )
+            })?;
+        Ok((provider, choice))
+    }
+
+    fn configure_slideshow_chat_context(&mut self, cx: &mut Cx) {
+        self.slideshow_client =
+            self.configure_ask_chat_context(cx, id!(slideshow.chat));
+        self.ui.chat(id!(slideshow.chat)).write().visible =
+            !self.state.hide_slideshow_chat;
+        self.ui.redraw(cx);
+    }
+
+    fn configure_selection_chat_context(&mut self, cx: &mut Cx) {
+        self.selection_client =
+            self.configure_ask_chat_context(cx, id!(image_browser.ask_chat));
+    }
+
+    // Points a chat about images at the chosen chat model, returning the
+    // client to attach the images to.
+    fn configure_ask_chat_context(
+        &mut self,
+        cx: &mut Cx,
+        chat_path: &'static [LiveId],
+    ) -> Option<SlideshowClient> {
+        let mut chat = self.ui.chat(chat_path);
+        let mut messages = chat.read().messages_ref();
+        messages
+            .write()
+            .messages
+            .retain(|m| m.from != EntityId::App);
+
+        let chosen = self
+            .chosen_model(self.models.chat.as_ref(), "chat")
+            .and_then(|(provider, choice)| {
+                let mut options = AttachmentOptions::load();
+                if let Some(formats) = &provider.accepted_formats {
+                    options.accepted_formats = formats.clone();
+                }
+                Ok((provider.chat_client()?, choice.clone(), options))
+            });
+        let (client, choice, options) = match chosen {
+            Ok(chosen) => chosen,
+            Err(error) => {
+                chat.write().set_bot_context(cx, None);
+                messages.write().messages.push(Message::app_error(error));
+                return None;
+            }
+        };
+
+        let client = SlideshowClient::new(client);
+        client.set_tools(tools::app_tools(), self.tool_handler());
+        self.attachment_options = options;
+
+        let mut bot_context = BotContext::from(client.clone());
+        chat.write().set_bot_context(cx, Some(bot_context.clone()));
+
+        let ui = self.ui_runner();
+        spawn(async move {
+            let errors = bot_context.load().await.into_errors();
+
+            ui.defer(move |me, cx, _scope| {
+                let mut chat = me.ui.chat(chat_path);
+                let mut messages = chat.read().messages_ref();
+
+                for error in errors {
+                    messages.write().messages.push(Message::app_error(
+                        format!("{}: {error}", choice.provider),
+                    ));
+                }
+
+                let bot = bot_context
+                    .bots()
+                    .into_iter()
+                    .find(|b| b.id.id() == choice.model);
 
                 if let Some(bot) = bot {
                     chat.write().set_bot_id(cx, Some(bot.id));
                 } else {
                     messages.write().messages.push(Message::app_error(
-                        format!("Model ID '{}' not found", model_id),
+                        format!(
+                            "Model '{}' not found at provider '{}'",
+                            choice.model, choice.provider
+                        ),
                     ));
                 }
 
-                chat.write().visible = true;
-                me.ui.redraw(cx);
+                me.ui.redraw(cx);
+            });
+        });
+
+        Some(client)
+    }
+
+    // Sends from the chat through `send`, which attaches the images first.
+    fn configure_ask_chat_before_hook(
+        &mut self,
+        chat_path: &[LiveId],
+        send: fn(&mut Self, &mut Cx),
+    ) {
+        let ui = self.ui_runner();
+        let mut chat = self.ui.chat(chat_path);
+        chat.write().set_hook_before(move |task_group, _chat, _cx| {
+            let before_len = task_group.len();
+            task_group.retain(|task| *task != ChatTask::Send);
+            if task_group.len() != before_len {
+                ui.defer(move |me, cx, _scope| {
+                    send(me, cx);
+                });
+            }
+        });
+    }
+
+    // A line from the app in a chat, such as what was attached.
+    fn add_chat_note(&self, chat_path: &[LiveId], text: String) {
+        self.ui
+            .chat(chat_path)
+            .read()
+            .messages_ref()
+            .write()
+            .messages
+            .push(Message {
+                from: EntityId::App,
+                content: MessageContent {
+                    text,
+                    ..Default::default()
+                },
+                ..Default::default()
+            });
+    }
+
+    // Prepares the current image in the background, then sends it along
+    // with the message, saying in the chat how much smaller it got.
+    fn perform_chat_send(&mut self, _cx: &mut Cx) {
+        let Some(client) = self.slideshow_client.clone() else {
+            return;
+        };
+        let Some(source) = self.state.current_image_source().cloned() else {
+            return;
+        };
+        let options = self.attachment_options.clone();
+
+        let ui = self.ui_runner();
+        std::thread::spawn(move || {
+            let result = client.prepare_send(&source, &options);
+            ui.defer(move |me, cx, _scope| {
+                let chat_path = id!(slideshow.chat);
+                let mut chat = me.ui.chat(chat_path);
+                match result {
+                    Ok(summary) => {
+                        if let Some(summary) = summary {
+                            me.add_chat_note(chat_path, summary);
+                        }
+                        chat.write().perform(cx, &[ChatTask::Send]);
+                    }
+                    Err(e) => {
+                        chat.read()
+                            .messages_ref()
+                            .write()
+                            .messages
+                            .push(Message::app_error(e));
+                        me.ui.redraw(cx);
+                    }
+                }
             });
         });
     }
 
-    fn configure_slideshow_chat_before_hook(&mut self, _cx: &mut Cx) {
+    // Like `perform_chat_send`, with the images selected in the grid, as
+    // many as fit the limits.
+    fn perform_selection_send(&mut self, _cx: &mut Cx) {
+        let Some(client) = self.selection_client.clone() else {
+            return;
+        };
+        let sources = self.state.selected_sources();
+        let options = self.attachment_options.clone();
+
         let ui = self.ui_runner();
-        let mut chat = self.ui.chat(id!(slideshow.chat));
-        chat.write().set_hook_before(move |task_group, _chat, _cx| {
-            let before_len = task_group.len();
-            task_group.retain(|task| *task != ChatTask::Send);
-            if task_group.len() != before_len {
-                ui.defer(move |me, cx, _scope| {
-                    me.perform_chat_send(cx);
-                });
-            }
+        std::thread::spawn(move || {
+            let prepared = client.attach_images(&sources, &options);
+            ui.defer(move |me, cx, _scope| {
+                let chat_path = id!(image_browser.ask_chat);
+                let mut chat = me.ui.chat(chat_path);
+                for error in prepared.errors {
+                    chat.read()
+                        .messages_ref()
+                        .write()
+                        .messages
+                        .push(Message::app_error(error));
+                }
+                if let Some(summary) = prepared.summary() {
+                    me.add_chat_note(chat_path, summary);
+                }
+                chat.write().perform(cx, &[ChatTask::Send]);
+            });
         });
     }
 
-    fn perform_chat_send(&mut self, cx: &mut Cx) {
-        let Some(client) = self.slideshow_client.as_mut() else {
+    // Saves the thread of the image being left and brings back the one of
+    // the current image, found by the hash of its content.
+    fn switch_conversation(&mut self, cx: &mut Cx) {
+        self.save_conversation();
+        self.state.conversation = None;
+        self.clear_slideshow_chat_messages();
+
+        let Some(source) = self.state.current_image_source().cloned() else {
             return;
         };
+        if let Some(hash) = self.state.content_hashes.get(&source).cloned() {
+            self.restore_conversation(cx, source, hash);
+            return;
+        }
 
-        let path =
-            self.state.image_paths[self.state.current_image_idx].as_path();
-        let extension = path.extension().and_then(|e| e.to_str());
+        let ui = self.ui_runner();
+        std::thread::spawn(move || {
+            let hash = match conversations::content_hash(&source) {
+                Ok(hash) => hash,
+                Err(e) => {
+                    eprintln!("Error hashing {source}: {e}");
+                    return;
+                }
+            };
 
-        let mime = extension.map(|e| match e {
-            "jpg" | "jpeg" => "image/jpeg".to_string(),
-            "png" => "image/png".to_string(),
-            e => format!("image/{e}"),
+            ui.defer(move |me, cx, _scope| {
+                me.state.content_hashes.insert(source.clone(), hash.clone());
+                if me.state.current_image_source() == Some(&source) {
+                    me.restore_conversation(cx, source, hash);
+                }
+            });
         });
+    }
 
-        let filename = path
-            .file_name()
-            .and_then(|f| f.to_str())
-            .unwrap_or_default();
+    fn restore_conversation(
+        &mut self,
+        cx: &mut Cx,
+        source: ImageSource,
+        hash: String,
+    ) {
+        if let Some(conversation) = Conversation::load(&hash) {
+            let chat = self.ui.chat(id!(slideshow.chat));
+            let mut messages = chat.read().messages_ref();
+            let mut messages = messages.write();
 
-        let mut chat = self.ui.chat(id!(slideshow.chat));
+            // Anything sent while the hash was computed goes after it.
+            let mut restored = conversation.to_messages();
+            restored.append(&mut messages.messages);
+            messages.messages = restored;
+        }
 
-        match std::fs::read(path) {
-            Ok(bytes) => {
-                let attachment =
-                    Attachment::from_bytes(filename.to_string(), mime, &bytes);
-                client.set_attachment(Some(attachment));
-                chat.write().perform(cx, &[ChatTask::Send]);
-            }
-            Err(e) => {
-                chat.read()
-                    .messages_ref()
-                    .write()
-                    .messages
-                    .push(Message::app_error(e));
+        self.state.conversation = Some((source, hash));
+        self.ui.redraw(cx);
+    }
+
+    fn save_conversation(&self) {
+        let Some((source, hash)) = &self.state.conversation else {
+            return;
+        };
+
+        let chat = self.ui.chat(id!(slideshow.chat));
+        let messages = chat.read().messages_ref().read().messages.clone();
+        let conversation =
+            Conversation::new(hash.clone(), source.clone(), &messages);
+        if let Err(e) = conversation.save() {
+            eprintln!("Error saving conversation about {source}: {e}");
+        }
+    }
+
+    fn open_conversations(&mut self, cx: &mut Cx) {
+        self.save_conversation();
+        self.set_active_page(cx, Page::Conversations);
+        self.ui.conversation_history(id!(history)).reload(cx);
+    }
+
+    // Shows an image from the conversation list in the slideshow, opening
+    // the folder it was last seen in if it isn't in the current one.
+    fn open_conversation(&mut self, cx: &mut Cx, source: ImageSource) {
+        let find = |state: &State| {
+            state.image_sources.iter().position(|s| *s == source)
+        };
+
+        let mut image_idx = find(&self.state);
+        if image_idx.is_none() {
+            let root = source
+                .archive()
+                .or_else(|| source.file_path()?.parent())
+                .filter(|root| is_root(root))
+                .map(Path::to_path_buf);
+            if let Some(root) = root {
+                self.load_image_sources(cx, &root);
+                image_idx = find(&self.state);
             }
         }
+
+        let Some(image_idx) = image_idx else {
+            self.ui.conversation_history(id!(history)).set_status(
+                cx,
+                &format!("{source} is no longer where it was last seen"),
+            );
+            return;
+        };
+
+        self.set_current_image(cx, image_idx);
+        self.ui.chat(id!(slideshow.chat)).write().visible = true;
+        self.state.hide_slideshow_chat = false;
+        self.set_active_page(cx, Page::Slideshow);
     }
 
     fn clear_slideshow_chat_messages(&self) {
@@ -400,15 +3223,23 @@ impl App {
     }
 
     fn configure_image_browser_chat_context(&self, cx: &mut Cx) {
-        let url = std::env::var("API_URL").unwrap_or_default();
-        let key = std::env::var("API_KEY").unwrap_or_default();
+        let mut chat = self.ui.chat(id!(image_browser.chat));
 
-        let mut client = OpenAIImageClient::new(url);
-        client.set_key(&key).unwrap();
+        let chosen = self
+            .chosen_model(self.models.image.as_ref(), "image")
+            .and_then(|(provider, choice)| {
+                Ok((provider.image_client()?, choice.clone()))
+            });
+        let (client, choice) = match chosen {
+            Ok(chosen) => chosen,
+            Err(error) => {
+                eprintln!("Error: {error}");
+                chat.write().set_bot_context(cx, None);
+                return;
+            }
+        };
 
         let mut bot_context = BotContext::from(client);
-
-        let mut chat = self.ui.chat(id!(image_browser.chat));
         chat.write().set_bot_context(cx, Some(bot_context.clone()));
 
         let ui = self.ui_runner();
@@ -416,32 +3247,39 @@ impl App {
             let errors = bot_context.load().await.into_errors();
 
             for error in errors {
-                eprintln!("Error: {error}");
+                eprintln!("Error: {}: {error}", choice.provider);
             }
 
             ui.defer(move |me, cx, _scope| {
                 let mut chat = me.ui.chat(id!(image_browser.chat));
 
-                let model_id =
-                    std::env::var("IMAGE_MODEL_ID").unwrap_or_default();
-
                 let bot = bot_context
                     .bots()
                     .into_iter()
-                    .find(|b| b.id.id() == model_id);
+                    .find(|b| b.id.id() == choice.model);
 
                 if let Some(bot) = bot {
                     chat.write().set_bot_id(cx, Some(bot.id));
                 } else {
-                    eprintln!("Error: Image Model ID '{}' not found", model_id);
+                    eprintln!(
+                        "Error: Image model '{}' not found at provider '{}'",
+                        choice.model, choice.provider
+                    );
                 }
 
-                chat.write().visible = true;
+                chat.write().visible = !me.state.hide_image_chat;
                 me.ui.redraw(cx);
             });
         });
     }
 
+    fn add_generated_image(&mut self, cx: &mut Cx, source: ImageSource) {
+        let unranked = self.state.unranked.as_mut();
+        add_to_grid(source, &mut self.state.image_sources, unranked);
+        self.start_indexing(cx);
+        self.ui.redraw(cx);
+    }
+
     fn configure_image_browser_chat_before_hook(&mut self, _cx: &mut Cx) {
         let ui = self.ui_runner();
         self.ui
@@ -449,77 +3287,19 @@ impl App {
             .write()
             .set_hook_before(move |task_group, chat, cx| {
                 let aborted_tasks = std::mem::take(task_group);
-                for task in aborted_tasks {
-                    match task {
-                        ChatTask::Send => {
-                            chat.perform(cx, &[ChatTask::Send]);
-                        }
-                        ChatTask::ClearPrompt => {
-                            chat.perform(cx, &[ChatTask::ClearPrompt]);
-                        }
-                        ChatTask::InsertMessage(_, message) => {
-                            match &message.from {
-                                EntityId::User => {
-                                    chat.perform(
-                                        cx,
-                                        &[ChatTask::InsertMessage(0, message)],
-                                    );
-                                }
-                                EntityId::Bot(_) => {
-                                    chat.perform(
-                                        cx,
-                                        &[ChatTask::InsertMessage(1, message)],
-                                    );
-                                }
-                                _ => {}
-                            }
-                        }
-                        ChatTask::UpdateMessage(_, message) => {
-                            if !message.metadata.is_writing {
-                                continue;
-                            }
-
-                            let attachment = message
-                                .content
-                                .attachments
-                                .first()
-                                .cloned();
-
-                            let Some(attachment) = attachment else {
-                                return;
-                            };
-
-                            spawn(async move {
-                                match attachment.read().await {
-                                    Ok(bytes) => {
-                                        let now = std::time::SystemTime::now().duration_since(
-                                            std::time::UNIX_EPOCH,
-                                        ).unwrap().as_secs();
-
-                                        let filename = format!("generated_image_{now}.png");
-                                        let path = Path::new(IMAGES_PATH).join(&filename);
-
-                                        println!("Saving generated image to {path:?}");
-
-                                        if let Err(e) = std::fs::write(&path, &bytes) {
-                                            eprintln!("Error saving generated image to {path:?}: {e}");
-                                        }
-
-                                        ui.defer(move |me, cx, _scope| {
-                                            me.state.image_paths.push(path);
-                                            me.ui.redraw(cx);
-                                        });
-                                    },
-                                    Err(e) => {
-                                        eprintln!("Error reading image generation: {e}");
-                                    }
-                                }
-                            });
-
-                            chat.messages_ref().write().messages.clear();
-                        }
-                        _ => {}
-                    }
+                let saved = move |source: ImageSource| {
+                    ui.defer(move |me, cx, _scope| {
+                        me.add_generated_image(cx, source);
+                    });
+                };
+                let generated = run_generation_tasks(
+                    aborted_tasks,
+                    Path::new(IMAGES_PATH),
+                    |task| chat.perform(cx, &[task]),
+                    saved,
+                );
+                if generated {
+                    chat.messages_ref().write().messages.clear();
                 }
             });
     }
@@ -529,14 +3309,52 @@ impl LiveRegister for App {
     fn live_register(cx: &mut Cx) {
         makepad_widgets::live_design(cx);
         moly_kit::live_design(cx);
+        crate::compare::live_design(cx);
+        crate::display::live_design(cx);
+        crate::loupe::live_design(cx);
+        crate::history::live_design(cx);
+        crate::theme::live_design(cx);
+        crate::palette::live_design(cx);
+        crate::review::live_design(cx);
     }
 }
 
 impl LiveHook for App {
     fn after_new_from_doc(&mut self, cx: &mut Cx) {
-        self.load_image_paths(cx, IMAGES_PATH.as_ref());
+        let args = Args::parse();
+        let session = if args.fresh {
+            Session::default()
+        } else {
+            Session::load().unwrap_or_default()
+        };
+
+        self.state.recent_roots = session.roots.clone();
+        self.state.display = DisplaySettings::load();
+        let root = args
+            .root
+            .or_else(|| session.roots.first().cloned())
+            .filter(|root| is_root(root))
+            .unwrap_or_else(|| PathBuf::from(IMAGES_PATH));
+        self.load_image_sources(cx, &root);
+        self.restore_session(cx, session);
+        self.keymap = Keymap::load();
+        self.theme_timer = cx.start_interval(THEME_POLL_INTERVAL);
+        match Providers::load() {
+            Ok(providers) => self.providers = providers,
+            Err(error) => self.providers_error = Some(error),
+        }
+        self.models = ModelSelection::load();
+        match Search::load(&self.providers) {
+            Ok(search) => self.search = search,
+            Err(error) => eprintln!("Search unavailable: {error}"),
+        }
+        self.start_indexing(cx);
         self.configure_slideshow_chat(cx);
+        self.configure_selection_chat(cx);
         self.configure_image_browser_chat(cx);
+        self.ui.tag_review(id!(image_browser.tag_review)).reload(cx);
+        self.update_tag_badges(cx);
+        self.start_mcp_servers(args.mcp, args.mcp_socket.as_deref());
     }
 }
 
@@ -547,38 +3365,244 @@ impl AppMain for App {
         self.match_event(cx, event);
         let mut scope = Scope::with_data(&mut self.state);
         self.ui.handle_event(cx, event, &mut scope);
+        self.load_requested_textures();
+        self.update_vector_raster(cx);
+        self.update_compare_rasters(cx);
+
+        if self.animation_timer.is_event(event).is_some() {
+            self.advance_animation(cx);
+        }
+
+        if self.search_timer.is_event(event).is_some() {
+            self.run_search(cx);
+        }
+
+        if self.theme_timer.is_event(event).is_some()
+            && self.theme_watcher.poll()
+        {
+            self.state.theme = Theme::load(self.state.theme_name.as_deref());
+            self.apply_theme(cx);
+        }
+
+        let scope = CommandScope::of_page(self.state.page);
+        match event {
+            // The palette opens from any page, even while typing.
+            Event::KeyDown(e)
+                if self.keymap.command(e, scope)
+                    == Some(Command::CommandPalette) =>
+            {
+                self.open_command_palette(cx);
+            }
+            Event::KeyDown(e) if !self.is_typing(cx) => {
+                if let Some(command) = self.keymap.command(e, scope) {
+                    self.run_command(cx, command);
+                }
+            }
+            Event::WindowGeomChange(e) => {
+                self.state.window = Some(WindowGeometry::from(&e.new_geom));
+            }
+            Event::WindowCloseRequested(_) | Event::Shutdown => {
+                self.save_session();
+            }
+            _ => {}
+        }
     }
 }
 
 impl MatchEvent for App {
     fn handle_actions(&mut self, cx: &mut Cx, actions: &Actions) {
-        let page_flip = self.ui.page_flip(id!(page_flip));
-
         if self.ui.button(id!(button)).clicked(&actions) {
-            self.clear_slideshow_chat_messages();
-            page_flip.set_active_page(cx, live_id!(slideshow));
+            self.run_command(cx, Command::OpenSlideshow);
         }
 
-        if self.ui.button(id!(left_button)).clicked(&actions) {
-            self.go_to_previous_image(cx);
+        if self.ui.button(id!(animate_button)).clicked(&actions) {
+            self.run_command(cx, Command::ToggleHoverAnimation);
         }
-        if self.ui.button(id!(right_button)).clicked(&actions) {
-            self.go_to_next_image(cx);
+
+        if self
+            .ui
+            .text_input(id!(search_input))
+            .changed(&actions)
+            .is_some()
+        {
+            cx.stop_timer(self.search_timer);
+            self.search_timer = cx.start_timeout(SEARCH_DELAY);
+        }
+        if self.ui.button(id!(similar_button)).clicked(&actions) {
+            self.run_command(cx, Command::FindSimilar);
+        }
+
+        if self
+            .ui
+            .button(id!(tool_confirm.allow_button))
+            .clicked(&actions)
+        {
+            self.answer_tool_permission(cx, true);
+        }
+        if self
+            .ui
+            .button(id!(tool_confirm.deny_button))
+            .clicked(&actions)
+        {
+            self.answer_tool_permission(cx, false);
+        }
+
+        if self.ui.button(id!(background_button)).clicked(&actions) {
+            self.cycle_background(cx);
+        }
+
+        if self.ui.button(id!(compare_button)).clicked(&actions) {
+            self.open_compare(cx);
+        }
+
+        if self.ui.button(id!(ask_button)).clicked(&actions) {
+            self.run_command(cx, Command::AskAboutSelection);
+        }
+
+        if self.ui.button(id!(caption_button)).clicked(&actions) {
+            self.run_command(cx, Command::CaptionImages);
+        }
+        if self.ui.button(id!(tag_button)).clicked(&actions) {
+            self.run_command(cx, Command::TagImages);
+        }
+        if self.ui.button(id!(review_button)).clicked(&actions) {
+            self.run_command(cx, Command::ReviewTags);
+        }
+        if self
+            .ui
+            .button(id!(tag_review.close_button))
+            .clicked(&actions)
+        {
+            self.toggle_tag_review(cx);
+        }
+        if self.ui.button(id!(job_bar.stop_button)).clicked(&actions) {
+            self.stop_job(cx);
+        }
+
+        if self.ui.button(id!(history_button)).clicked(&actions) {
+            self.run_command(cx, Command::ShowConversations);
+        }
+        if self.ui.button(id!(history.back_button)).clicked(&actions) {
+            self.run_command(cx, Command::BackToGrid);
+        }
+
+        if self.ui.button(id!(settings_button)).clicked(&actions) {
+            self.run_command(cx, Command::OpenSettings);
+        }
+        if self.ui.button(id!(settings.back_button)).clicked(&actions) {
+            self.run_command(cx, Command::BackToGrid);
+        }
+        if self.ui.button(id!(settings.check_button)).clicked(&actions) {
+            self.check_providers(cx);
+        }
+        if let Some(index) = self
+            .ui
+            .drop_down(id!(settings.chat_model))
+            .selected(&actions)
+        {
+            self.choose_model(cx, false, index);
+        }
+        if let Some(index) = self
+            .ui
+            .drop_down(id!(settings.image_model))
+            .selected(&actions)
+        {
+            self.choose_model(cx, true, index);
         }
 
-        if let Some(event) = self.ui.view(id!(overlay)).key_down(&actions) {
-            match event.key_code {
-                KeyCode::Escape => {
-                    page_flip.set_active_page(cx, live_id!(image_browser))
+        for action in actions {
+            match action.as_widget_action().cast() {
+                ImageGridAction::ItemClicked { image_idx, toggle } => {
+                    self.state.select_image(image_idx, toggle);
+                    self.update_selection_views(cx);
+                    self.ui.redraw(cx);
+                }
+                ImageGridAction::ItemHovered { image_idx }
+                    if self.state.animate_on_hover =>
+                {
+                    let target = AnimationTarget::Thumbnail(image_idx);
+                    self.play_animation(cx, target);
+                }
+                ImageGridAction::ItemUnhovered { image_idx } => {
+                    let target = AnimationTarget::Thumbnail(image_idx);
+                    if self.animation_target == target {
+                        self.stop_animation(cx);
+                    }
                 }
-                KeyCode::ArrowLeft => self.go_to_previous_image(cx),
-                KeyCode::ArrowRight => self.go_to_next_image(cx),
                 _ => {}
             }
+
+            if let SelectionChipsAction::Remove { image_idx } =
+                action.as_widget_action().cast()
+            {
+                self.state.select_image(image_idx, true);
+                self.update_selection_views(cx);
+                self.ui.redraw(cx);
+            }
+
+            if let TagReviewAction::Changed = action.as_widget_action().cast() {
+                self.update_tag_badges(cx);
+            }
+
+            if let ConversationHistoryAction::Open(source) =
+                action.as_widget_action().cast()
+            {
+                self.open_conversation(cx, source);
+            }
+
+            if let CommandPaletteAction::Run(command) =
+                action.as_widget_action().cast()
+            {
+                self.run_command(cx, command);
+            }
+        }
+
+        if self.ui.button(id!(left_button)).clicked(&actions) {
+            self.go_left(cx);
+        }
+        if self.ui.button(id!(right_button)).clicked(&actions) {
+            self.go_right(cx);
+        }
+
+        if let Some(exposure) = self
+            .ui
+            .slider(id!(slideshow.tone_controls.exposure))
+            .slided(&actions)
+        {
+            self.update_tone_mapping(cx, |d| d.exposure = exposure as f32);
+        }
+        if let Some(index) = self
+            .ui
+            .drop_down(id!(slideshow.tone_controls.tone_mapper))
+            .selected(&actions)
+        {
+            let tone_mapper = ToneMapper::ALL[index];
+            self.update_tone_mapping(cx, |d| d.tone_mapper = tone_mapper);
+        }
+
+        if self.ui.button(id!(compare.back_button)).clicked(&actions) {
+            self.run_command(cx, Command::BackToGrid);
+        }
+        if self.ui.button(id!(wipe_button)).clicked(&actions) {
+            self.run_command(cx, Command::CompareWipe);
+        }
+        if self.ui.button(id!(side_by_side_button)).clicked(&actions) {
+            self.run_command(cx, Command::CompareSideBySide);
+        }
+        if self.ui.button(id!(difference_button)).clicked(&actions) {
+            self.run_command(cx, Command::CompareDifference);
         }
     }
 }
 
+#[derive(Clone, Debug, DefaultNone)]
+pub enum ImageGridAction {
+    ItemClicked { image_idx: usize, toggle: bool },
+    ItemHovered { image_idx: usize },
+    ItemUnhovered { image_idx: usize },
+    None,
+}
+
 #[derive(Live, LiveHook, Widget)]
 pub struct ImageGrid {
     #[deref]
@@ -594,9 +3618,16 @@ impl Widget for ImageGrid {
     ) -> DrawStep {
         while let Some(item) = self.view.draw_walk(cx, scope, walk).step() {
             let state = scope.data.get_mut::<State>().unwrap();
+            state.dpi_factor = cx.current_dpi_factor();
 
             if let Some(mut list) = item.as_portal_list().borrow_mut() {
                 list.set_item_range(cx, 0, state.num_rows());
+                if let Some(row_idx) = state.restore_grid_row.take() {
+                    let row_idx =
+                        row_idx.min(state.num_rows().saturating_sub(1));
+                    list.set_first_id_and_scroll(row_idx, 0.0);
+                }
+                state.grid_row = list.first_id();
 
                 while let Some(row_idx) = list.next_visible_item(cx) {
                     if row_idx >= state.num_rows() {
@@ -621,6 +3652,8 @@ impl Widget for ImageGrid {
 pub struct ImageGridRow {
     #[deref]
     view: View,
+    #[rust]
+    row_idx: usize,
 }
 
 impl Widget for ImageGridRow {
@@ -633,6 +3666,7 @@ impl Widget for ImageGridRow {
         while let Some(item) = self.view.draw_walk(cx, scope, walk).step() {
             let state = scope.data.get_mut::<State>().unwrap();
             let row_idx = *scope.props.get::<usize>().unwrap();
+            self.row_idx = row_idx;
 
             if let Some(mut list) = item.as_portal_list().borrow_mut() {
                 list.set_item_range(cx, 0, state.num_images_for_row(row_idx));
@@ -647,10 +3681,71 @@ impl Widget for ImageGridRow {
                     let image = item.image(id!(image));
                     let first_image_idx = state.first_image_for_row(row_idx);
                     let image_idx = first_image_idx + item_idx;
-                    let image_path = &state.image_paths[image_idx];
-                    // Seems like the `async` version of this is broken for png files,
-                    // like the ones generated by AI. So switching to sync version for now.
-                    image.load_image_file_by_path(cx, &image_path).unwrap();
+                    let source = state.image_sources[image_idx].clone();
+                    let hover_frame = state
+                        .hover_frame
+                        .as_ref()
+                        .filter(|(idx, _)| *idx == image_idx);
+                    // Every format is decoded off the UI thread, so scrolling
+                    // never waits on a file.
+                    if let Some((_, texture)) = hover_frame {
+                        image.set_texture(cx, Some(texture.clone()));
+                    } else {
+                        image.set_texture(cx, state.thumbnail(&source));
+                    }
+                    state.display.apply_background(cx, &image);
+
+                    let hdr = state
+                        .textures
+                        .peek(&source, TextureSize::Thumbnail)
+                        .is_some_and(|t| is_float_texture(cx, t));
+                    let hdr = hdr as u8 as f64;
+                    image.apply_over(
+                        cx,
+                        live! {
+                            draw_bg: { hdr: (hdr) }
+                        },
+                    );
+
+                    let theme = &state.theme;
+                    let paired = state.raw_companions.contains_key(&source);
+                    let tagged = state.color_tagged.contains(&source);
+                    let failed = state
+                        .textures
+                        .has_failed(&source, TextureSize::Thumbnail);
+                    let pending =
+                        state.pending_tags.get(&source).copied().unwrap_or(0);
+                    item.label(id!(review_badge.count))
+                        .set_text(cx, &format!("{pending} to review"));
+                    for (id, visible) in [
+                        (id!(raw_badge), paired),
+                        (id!(icc_badge), tagged),
+                        (id!(review_badge), pending > 0),
+                        (id!(failed_badge), failed),
+                    ] {
+                        let badge = item.view(id);
+                        badge.set_visible(cx, visible);
+                        badge.apply_over(
+                            cx,
+                            live! {
+                                draw_bg: {
+                                    color: (theme.overlay),
+                                    border_radius: (theme.radius),
+                                }
+                            },
+                        );
+                    }
+
+                    let selected = state.is_selected(image_idx) as u8 as f64;
+                    item.apply_over(
+                        cx,
+                        live! {
+                            draw_bg: {
+                                selected: (selected),
+                                color: (theme.accent),
+                            }
+                        },
+                    );
 
                     item.draw_all(cx, &mut Scope::empty());
                 }
@@ -660,19 +3755,184 @@ impl Widget for ImageGridRow {
     }
 
     fn handle_event(&mut self, cx: &mut Cx, event: &Event, scope: &mut Scope) {
-        self.view.handle_event(cx, event, scope)
+        let actions =
+            cx.capture_actions(|cx| self.view.handle_event(cx, event, scope));
+
+        let Some(state) = scope.data.get::<State>() else {
+            return;
+        };
+        let first_image_idx = state.first_image_for_row(self.row_idx);
+
+        let items = self.view.portal_list(id!(items));
+        for (item_idx, item) in items.items_with_actions(&actions) {
+            let image_idx = first_image_idx + item_idx;
+
+            // The same hits that drive the thumbnail's hover animator.
+            let thumbnail = item.view(id!(thumbnail));
+            if thumbnail.finger_hover_in(&actions).is_some() {
+                cx.widget_action(
+                    self.widget_uid(),
+                    &scope.path,
+                    ImageGridAction::ItemHovered { image_idx },
+                );
+            }
+            if thumbnail.finger_hover_out(&actions).is_some() {
+                cx.widget_action(
+                    self.widget_uid(),
+                    &scope.path,
+                    ImageGridAction::ItemUnhovered { image_idx },
+                );
+            }
+
+            let Some(event) = item.as_view().finger_up(&actions) else {
+                continue;
+            };
+
+            if event.is_over {
+                let modifiers = event.modifiers;
+                cx.widget_action(
+                    self.widget_uid(),
+                    &scope.path,
+                    ImageGridAction::ItemClicked {
+                        image_idx,
+                        toggle: modifiers.control || modifiers.logo,
+                    },
+                );
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, DefaultNone)]
+pub enum SelectionChipsAction {
+    Remove { image_idx: usize },
+    None,
+}
+
+#[derive(Live, LiveHook, Widget)]
+pub struct SelectionChips {
+    #[deref]
+    view: View,
+    // Grid index, source and thumbnail of each selected image, if it's
+    // decoded yet.
+    #[rust]
+    chips: Vec<(usize, ImageSource, Option<Texture>)>,
+}
+
+impl Widget for SelectionChips {
+    fn draw_walk(
+        &mut self,
+        cx: &mut Cx2d,
+        scope: &mut Scope,
+        walk: Walk,
+    ) -> DrawStep {
+        while let Some(item) = self.view.draw_walk(cx, scope, walk).step() {
+            let Some(mut list) = item.as_portal_list().borrow_mut() else {
+                continue;
+            };
+            list.set_item_range(cx, 0, self.chips.len());
+
+            while let Some(chip_idx) = list.next_visible_item(cx) {
+                let Some((_, source, texture)) = self.chips.get(chip_idx)
+                else {
+                    continue;
+                };
+
+                let chip = list.item(cx, chip_idx, live_id!(Chip));
+                chip.label(id!(name)).set_text(cx, &source.file_name());
+                chip.image(id!(thumbnail)).set_texture(cx, texture.clone());
+
+                chip.draw_all(cx, &mut Scope::empty());
+            }
+        }
+        DrawStep::done()
+    }
+
+    fn handle_event(&mut self, cx: &mut Cx, event: &Event, scope: &mut Scope) {
+        let actions =
+            cx.capture_actions(|cx| self.view.handle_event(cx, event, scope));
+
+        let chips = self.view.portal_list(id!(chips));
+        for (chip_idx, chip) in chips.items_with_actions(&actions) {
+            let Some((image_idx, _, _)) = self.chips.get(chip_idx) else {
+                continue;
+            };
+            if chip.button(id!(remove_button)).clicked(&actions) {
+                cx.widget_action(
+                    self.widget_uid(),
+                    &scope.path,
+                    SelectionChipsAction::Remove {
+                        image_idx: *image_idx,
+                    },
+                );
+            }
+        }
+    }
+}
+
+impl SelectionChipsRef {
+    pub fn set_chips(
+        &self,
+        cx: &mut Cx,
+        chips: Vec<(usize, ImageSource, Option<Texture>)>,
+    ) {
+        let Some(mut inner) = self.borrow_mut() else {
+            return;
+        };
+
+        inner.view.set_visible(cx, !chips.is_empty());
+        inner.chips = chips;
+        inner.redraw(cx);
     }
 }
 
 struct State {
-    image_paths: Vec<PathBuf>,
+    image_sources: Vec<ImageSource>,
     max_images_per_row: usize,
     current_image_idx: usize,
+    current_page: usize,
+    selected_images: Vec<usize>,
+    show_scopes: bool,
+    show_loupe: bool,
+    show_tone_controls: bool,
+    show_tag_review: bool,
+    display: DisplaySettings,
+    textures: TextureCache,
+    texture_requests: Vec<(ImageSource, TextureSize)>,
+    raw_companions: HashMap<ImageSource, ImageSource>,
+    animate_on_hover: bool,
+    hover_frame: Option<(usize, Texture)>,
+    dpi_factor: f64,
+    color_tagged: HashSet<ImageSource>,
+    soft_proof: bool,
+    vector_raster: Option<(ImageSource, usize, usize)>,
+    compare_rasters: [Option<(ImageSource, usize, usize)>; 2],
+    spread: bool,
+    right_to_left: bool,
+    page: Page,
+    recent_roots: Vec<PathBuf>,
+    grid_row: usize,
+    restore_grid_row: Option<usize>,
+    window: Option<WindowGeometry>,
+    theme: Theme,
+    // `None` follows the desktop's light or dark preference.
+    theme_name: Option<String>,
+    content_hashes: HashMap<ImageSource, String>,
+    // The image whose thread is in the slideshow chat, with its hash.
+    conversation: Option<(ImageSource, String)>,
+    // How many tag suggestions wait for review, by image.
+    pending_tags: HashMap<ImageSource, usize>,
+    // The folder's order while the grid is ranked by a search.
+    unranked: Option<Vec<ImageSource>>,
+    // Chats hidden with their toggles stay hidden once their models load.
+    hide_image_chat: bool,
+    hide_slideshow_chat: bool,
+    show_selection_chat: bool,
 }
 
 impl State {
     fn num_images(&self) -> usize {
-        self.image_paths.len()
+        self.image_sources.len()
     }
 
     fn num_rows(&self) -> usize {
@@ -688,14 +3948,170 @@ impl State {
         let num_remaining_images = self.num_images() - first_image_idx;
         num_remaining_images.min(self.max_images_per_row)
     }
+
+    fn current_image_source(&self) -> Option<&ImageSource> {
+        self.image_sources.get(self.current_image_idx)
+    }
+
+    fn texture(&mut self, source: &ImageSource) -> Option<Texture> {
+        self.texture_of_size(source, TextureSize::Full)
+    }
+
+    fn thumbnail(&mut self, source: &ImageSource) -> Option<Texture> {
+        self.texture_of_size(source, TextureSize::Thumbnail)
+    }
+
+    // Requests textures it doesn't have yet.
+    fn texture_of_size(
+        &mut self,
+        source: &ImageSource,
+        size: TextureSize,
+    ) -> Option<Texture> {
+        match self.textures.get(source, size) {
+            Some(CachedTexture::Loaded(texture)) => return Some(texture),
+            Some(CachedTexture::Pending | CachedTexture::Failed) => {
+                return None;
+            }
+            None => {}
+        }
+
+        self.textures.mark_pending(source, size);
+        self.texture_requests.push((source.clone(), size));
+        None
+    }
+
+    fn raw_source(&self, source: &ImageSource) -> Option<ImageSource> {
+        if registry().is_raw(&source.path()) {
+            return Some(source.clone());
+        }
+        self.raw_companions.get(source).cloned()
+    }
+
+    fn is_comic(&self) -> bool {
+        self.current_image_source()
+            .is_some_and(|source| source.is_comic())
+    }
+
+    // The page shown next to the current one in a two-page spread, which
+    // has to come from the same comic.
+    fn spread_partner(&self) -> Option<&ImageSource> {
+        if !self.spread || !self.is_comic() {
+            return None;
+        }
+
+        let current = self.current_image_source()?;
+        self.image_sources
+            .get(self.current_image_idx + 1)
+            .filter(|next| next.archive() == current.archive())
+    }
+
+    fn images_per_step(&self) -> usize {
+        if self.spread && self.is_comic() { 2 } else { 1 }
+    }
+
+    fn is_selected(&self, image_idx: usize) -> bool {
+        self.selected_images.contains(&image_idx)
+    }
+
+    // Whether `sources` are the folder's images, even while the grid is
+    // filtered.
+    fn has_same_images(&self, sources: &[ImageSource]) -> bool {
+        let current: HashSet<&ImageSource> = self
+            .unranked
+            .as_ref()
+            .unwrap_or(&self.image_sources)
+            .iter()
+            .collect();
+        sources.len() == current.len()
+            && sources.iter().all(|s| current.contains(s))
+    }
+
+    // The images in the folder's order, even while the grid is ranked or
+    // filtered.
+    fn unranked_sources(&self) -> Vec<ImageSource> {
+        self.unranked
+            .clone()
+            .unwrap_or_else(|| self.image_sources.clone())
+    }
+
+    fn selected_sources(&self) -> Vec<ImageSource> {
+        self.selected_images
+            .iter()
+            .map(|&idx| self.image_sources[idx].clone())
+            .collect()
+    }
+
+    fn is_selected_source(&self, source: &ImageSource) -> bool {
+        self.selected_images
+            .iter()
+            .any(|&idx| self.image_sources[idx] == *source)
+    }
+
+    fn select_image(&mut self, image_idx: usize, toggle: bool) {
+        if !toggle {
+            self.selected_images = vec![image_idx];
+        } else if let Some(pos) =
+            self.selected_images.iter().position(|&i| i == image_idx)
+        {
+            self.selected_images.remove(pos);
+        } else {
+            self.selected_images.push(image_idx);
+        }
+    }
+
+    fn compare_pair(&self) -> Option<(ImageSource, ImageSource)> {
+        if let [a, b, ..] = self.selected_images[..] {
+            return Some((
+                self.image_sources[a].clone(),
+                self.image_sources[b].clone(),
+            ));
+        }
+
+        let current = self.image_sources.get(self.current_image_idx)?;
+        let edited = edited_version_path(current.file_path()?)?;
+        Some((current.clone(), ImageSource::File(edited)))
+    }
 }
 
 impl Default for State {
     fn default() -> Self {
         Self {
-            image_paths: Vec::new(),
+            image_sources: Vec::new(),
             max_images_per_row: 4,
             current_image_idx: 0,
+            current_page: 0,
+            selected_images: Vec::new(),
+            show_scopes: false,
+            show_loupe: false,
+            show_tone_controls: false,
+            show_tag_review: false,
+            display: DisplaySettings::default(),
+            textures: TextureCache::default(),
+            texture_requests: Vec::new(),
+            raw_companions: HashMap::new(),
+            animate_on_hover: true,
+            hover_frame: None,
+            dpi_factor: 1.0,
+            color_tagged: HashSet::new(),
+            soft_proof: false,
+            vector_raster: None,
+            compare_rasters: [None, None],
+            spread: false,
+            right_to_left: false,
+            page: Page::ImageBrowser,
+            recent_roots: Vec::new(),
+            grid_row: 0,
+            restore_grid_row: None,
+            window: None,
+            theme: Theme::default(),
+            theme_name: None,
+            content_hashes: HashMap::new(),
+            conversation: None,
+            pending_tags: HashMap::new(),
+            unranked: None,
+            hide_image_chat: false,
+            hide_slideshow_chat: false,
+            show_selection_chat: false,
         }
     }
 }
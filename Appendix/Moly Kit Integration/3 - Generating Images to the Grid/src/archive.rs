@@ -0,0 +1,358 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::str::Chars;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+
+use crate::config;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    SevenZ,
+    Rar,
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+pub fn archive_kind(path: &Path) -> Option<ArchiveKind> {
+    match extension(path).as_str() {
+        #[cfg(feature = "zip")]
+        "zip" | "cbz" => Some(ArchiveKind::Zip),
+        #[cfg(feature = "tar")]
+        "tar" | "cbt" => Some(ArchiveKind::Tar),
+        #[cfg(feature = "7z")]
+        "7z" | "cb7" => Some(ArchiveKind::SevenZ),
+        #[cfg(feature = "rar")]
+        "rar" | "cbr" => Some(ArchiveKind::Rar),
+        _ => None,
+    }
+}
+
+// Comic book archives get two-page spreads and right-to-left reading.
+pub fn is_comic(path: &Path) -> bool {
+    matches!(extension(path).as_str(), "cbz" | "cbt" | "cb7" | "cbr")
+}
+
+fn other_error(e: impl std::fmt::Display) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+const ARCHIVES_FILE: &str = "archives.toml";
+const DEFAULT_MAX_ENTRY_SIZE: u64 = 256 * 1024 * 1024;
+// Indexes kept at once, the most recently used last.
+const MAX_INDEXES: usize = 4;
+
+// The limits from `archives.toml`, e.g.
+//
+//     max_entry_size = 67108864
+//
+// Entries bigger than that many bytes once extracted aren't read, so a broken
+// or hostile archive can't take all the memory.
+#[derive(Debug, serde::Deserialize)]
+#[serde(default)]
+struct ArchiveSettings {
+    max_entry_size: u64,
+}
+
+impl Default for ArchiveSettings {
+    fn default() -> Self {
+        Self {
+            max_entry_size: DEFAULT_MAX_ENTRY_SIZE,
+        }
+    }
+}
+
+impl ArchiveSettings {
+    fn get() -> &'static Self {
+        static SETTINGS: OnceLock<ArchiveSettings> = OnceLock::new();
+        SETTINGS.get_or_init(Self::load)
+    }
+
+    fn load() -> Self {
+        let Some(path) = config::config_file(ARCHIVES_FILE) else {
+            return Self::default();
+        };
+        let Ok(text) = std::fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        toml::from_str(&text)
+            .inspect_err(|e| eprintln!("Error reading {path:?}: {e}"))
+            .unwrap_or_default()
+    }
+}
+
+fn too_big(name: &str) -> io::Error {
+    let limit = ArchiveSettings::get().max_entry_size;
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{name} is bigger than the {limit} bytes allowed"),
+    )
+}
+
+// Reads an entry that says it's `size` bytes, refusing it past the limit.
+// The size comes from the archive, so reading stops at the limit either way.
+fn read_limited(
+    reader: impl Read,
+    size: u64,
+    name: &str,
+) -> io::Result<Vec<u8>> {
+    let limit = ArchiveSettings::get().max_entry_size;
+    if size > limit {
+        return Err(too_big(name));
+    }
+
+    let mut bytes = Vec::with_capacity(size as usize);
+    reader.take(limit + 1).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > limit {
+        return Err(too_big(name));
+    }
+    Ok(bytes)
+}
+
+// Where each entry of an archive is, so showing a page doesn't open and scan
+// the whole archive again.
+enum Entries {
+    #[cfg(feature = "zip")]
+    Zip(zip::ZipArchive<BufReader<File>>),
+    // The offset and size of each file's data.
+    #[cfg(feature = "tar")]
+    Tar(HashMap<String, (u64, u64)>),
+    // 7z and rar can only be read front to back, so their files are read
+    // while listing them. `None` for those over the size limit.
+    #[cfg(any(feature = "7z", feature = "rar"))]
+    Read(HashMap<String, Option<Vec<u8>>>),
+}
+
+struct Index {
+    // The files, in reading order.
+    names: Vec<String>,
+    entries: Entries,
+}
+
+impl Index {
+    fn build(path: &Path) -> io::Result<Self> {
+        let kind = archive_kind(path).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::Unsupported, "not an archive")
+        })?;
+        let limit = ArchiveSettings::get().max_entry_size;
+
+        let (mut names, entries): (Vec<String>, Entries) = match kind {
+            #[cfg(feature = "zip")]
+            ArchiveKind::Zip => {
+                let archive =
+                    zip::ZipArchive::new(BufReader::new(File::open(path)?))
+                        .map_err(other_error)?;
+                let names = archive
+                    .file_names()
+                    .filter(|name| !name.ends_with('/'))
+                    .map(str::to_string)
+                    .collect();
+                (names, Entries::Zip(archive))
+            }
+            #[cfg(feature = "tar")]
+            ArchiveKind::Tar => {
+                let mut archive = tar::Archive::new(File::open(path)?);
+                let mut files = HashMap::new();
+                for entry in archive.entries_with_seek()? {
+                    let entry = entry?;
+                    if entry.header().entry_type().is_file() {
+                        let name = entry.path()?.to_string_lossy().into_owned();
+                        files.insert(
+                            name,
+                            (entry.raw_file_position(), entry.size()),
+                        );
+                    }
+                }
+                (files.keys().cloned().collect(), Entries::Tar(files))
+            }
+            #[cfg(feature = "7z")]
+            ArchiveKind::SevenZ => {
+                let mut reader = sevenz_rust::SevenZReader::open(
+                    path,
+                    sevenz_rust::Password::empty(),
+                )
+                .map_err(other_error)?;
+
+                let mut files = HashMap::new();
+                reader
+                    .for_each_entries(|entry, data| {
+                        if !entry.has_stream() || entry.is_directory() {
+                            return Ok(true);
+                        }
+                        let name = entry.name().to_string();
+                        let bytes = if entry.size() > limit {
+                            // Solid blocks still have to be read through.
+                            io::copy(data, &mut io::sink())?;
+                            None
+                        } else {
+                            Some(read_limited(data, entry.size(), &name)?)
+                        };
+                        files.insert(name, bytes);
+                        Ok(true)
+                    })
+                    .map_err(other_error)?;
+                (files.keys().cloned().collect(), Entries::Read(files))
+            }
+            #[cfg(feature = "rar")]
+            ArchiveKind::Rar => {
+                let mut archive = unrar::Archive::new(path)
+                    .open_for_processing()
+                    .map_err(other_error)?;
+
+                let mut files = HashMap::new();
+                while let Some(header) =
+                    archive.read_header().map_err(other_error)?
+                {
+                    let entry = header.entry();
+                    let name = entry.filename.to_string_lossy().into_owned();
+                    let (is_file, size) =
+                        (entry.is_file(), entry.unpacked_size);
+                    archive = if !is_file {
+                        header.skip().map_err(other_error)?
+                    } else if size > limit {
+                        files.insert(name, None);
+                        header.skip().map_err(other_error)?
+                    } else {
+                        let (bytes, rest) =
+                            header.read().map_err(other_error)?;
+                        files.insert(name, Some(bytes));
+                        rest
+                    };
+                }
+                (files.keys().cloned().collect(), Entries::Read(files))
+            }
+            #[allow(unreachable_patterns)]
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "not an archive",
+                ));
+            }
+        };
+
+        names.sort_by(|a: &String, b: &String| natural_cmp(a, b));
+        Ok(Self { names, entries })
+    }
+
+    fn read(&mut self, path: &Path, name: &str) -> io::Result<Vec<u8>> {
+        let not_found = || {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{name} in {path:?}"),
+            )
+        };
+
+        match &mut self.entries {
+            #[cfg(feature = "zip")]
+            Entries::Zip(archive) => {
+                let file = archive.by_name(name).map_err(|_| not_found())?;
+                let size = file.size();
+                read_limited(file, size, name)
+            }
+            #[cfg(feature = "tar")]
+            Entries::Tar(files) => {
+                let &(offset, size) = files.get(name).ok_or_else(not_found)?;
+                let mut file = File::open(path)?;
+                file.seek(SeekFrom::Start(offset))?;
+                read_limited(file.take(size), size, name)
+            }
+            #[cfg(any(feature = "7z", feature = "rar"))]
+            Entries::Read(files) => match files.get(name) {
+                Some(Some(bytes)) => Ok(bytes.clone()),
+                Some(None) => Err(too_big(name)),
+                None => Err(not_found()),
+            },
+        }
+    }
+}
+
+// The index of the archive at `path`, made on first use and again whenever
+// the archive changes.
+fn index(path: &Path) -> io::Result<Arc<Mutex<Index>>> {
+    type Indexes = Vec<(PathBuf, Option<SystemTime>, Arc<Mutex<Index>>)>;
+    static INDEXES: Mutex<Indexes> = Mutex::new(Vec::new());
+
+    let modified = std::fs::metadata(path)?.modified().ok();
+    {
+        let mut indexes = INDEXES.lock().unwrap();
+        if let Some(i) = indexes.iter().position(|(p, ..)| p == path) {
+            let entry = indexes.remove(i);
+            if entry.1 == modified {
+                let index = entry.2.clone();
+                indexes.push(entry);
+                return Ok(index);
+            }
+        }
+    }
+
+    // Built without holding the others up, as big archives take a while.
+    let index = Arc::new(Mutex::new(Index::build(path)?));
+    let mut indexes = INDEXES.lock().unwrap();
+    indexes.retain(|(p, ..)| p != path);
+    indexes.push((path.to_path_buf(), modified, index.clone()));
+    if indexes.len() > MAX_INDEXES {
+        indexes.remove(0);
+    }
+    Ok(index)
+}
+
+// Lists the files in the archive in reading order.
+pub fn list_entries(path: &Path) -> io::Result<Vec<String>> {
+    Ok(index(path)?.lock().unwrap().names.clone())
+}
+
+pub fn read_entry(path: &Path, name: &str) -> io::Result<Vec<u8>> {
+    index(path)?.lock().unwrap().read(path, name)
+}
+
+fn take_number(chars: &mut Peekable<Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        digits.push(c);
+    }
+    digits
+}
+
+// Orders "page2" before "page10", which plain string ordering doesn't.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_number(&mut a);
+                let y = take_number(&mut b);
+                let x = x.trim_start_matches('0');
+                let y = y.trim_start_matches('0');
+
+                let order = x.len().cmp(&y.len()).then_with(|| x.cmp(y));
+                if order != Ordering::Equal {
+                    return order;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let order = x.to_ascii_lowercase().cmp(&y.to_ascii_lowercase());
+                if order != Ordering::Equal {
+                    return order;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use std::path::PathBuf;
+
+#[derive(Debug, Default)]
+pub struct Args {
+    // Ignores the saved session and starts like a first launch.
+    pub fresh: bool,
+    // A folder or archive to open instead of the last one.
+    pub root: Option<PathBuf>,
+    // Captions the folder without opening a window.
+    pub caption: bool,
+    // Replaces the caption prompt from `captions.toml`.
+    pub prompt: Option<String>,
+    // Serves MCP over stdin and stdout alongside the window.
+    pub mcp: bool,
+    // Serves MCP on a local socket at this path.
+    pub mcp_socket: Option<String>,
+}
+
+impl Args {
+    pub fn parse() -> Self {
+        let mut args = Self::default();
+        for arg in std::env::args().skip(1) {
+            match arg.as_str() {
+                "--fresh" => args.fresh = true,
+                "--caption" => args.caption = true,
+                "--mcp" => args.mcp = true,
+                flag if flag.starts_with("--prompt=") => {
+                    args.prompt = Some(flag["--prompt=".len()..].to_string());
+                }
+                flag if flag.starts_with("--mcp-socket=") => {
+                    let address = &flag["--mcp-socket=".len()..];
+                    args.mcp_socket = Some(address.to_string());
+                }
+                flag if flag.starts_with("--") => {
+                    eprintln!("Ignoring unknown option {flag}");
+                }
+                path => args.root = Some(PathBuf::from(path)),
+            }
+        }
+        args
+    }
+}
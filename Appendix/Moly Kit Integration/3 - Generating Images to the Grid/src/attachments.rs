@@ -0,0 +1,528 @@
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::{self, FilterType};
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageReader, RgbaImage};
+use moly_kit::protocol::*;
+use serde::Deserialize;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use crate::config;
+use crate::decoders::registry;
+use crate::source::ImageSource;
+
+const ATTACHMENTS_FILE: &str = "attachments.toml";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttachmentFormat {
+    Jpeg,
+    Webp,
+}
+
+impl AttachmentFormat {
+    fn mime_type(self) -> &'static str {
+        match self {
+            Self::Jpeg => "image/jpeg",
+            Self::Webp => "image/webp",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+            Self::Webp => "webp",
+        }
+    }
+}
+
+// How images are prepared before they're sent to a vision model, read from
+// `attachments.toml`:
+//
+//     max_long_edge = 2048
+//     format = "webp"
+//     quality = 80
+//     strip_metadata = true
+//     accepted_formats = ["image/jpeg", "image/png"]
+//     max_images = 8
+//     token_budget = 10000
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct AttachmentOptions {
+    pub max_long_edge: u32,
+    // What images are converted to when they have to be re-encoded.
+    pub format: AttachmentFormat,
+    // 1 to 100.
+    pub quality: u8,
+    // Drops EXIF, GPS and other metadata, which can say where and when a
+    // photo was taken.
+    pub strip_metadata: bool,
+    // The types the provider takes as they are. Anything else is converted.
+    pub accepted_formats: Vec<String>,
+    // Limits for asking about several images at once.
+    pub max_images: usize,
+    pub token_budget: u32,
+}
+
+impl Default for AttachmentOptions {
+    fn default() -> Self {
+        Self {
+            max_long_edge: 2048,
+            format: AttachmentFormat::Jpeg,
+            quality: 85,
+            strip_metadata: true,
+            accepted_formats: [
+                "image/jpeg",
+                "image/png",
+                "image/webp",
+                "image/gif",
+            ]
+            .map(String::from)
+            .to_vec(),
+            max_images: 8,
+            token_budget: 10_000,
+        }
+    }
+}
+
+impl AttachmentOptions {
+    pub fn load() -> Self {
+        let Some(path) = config::config_file(ATTACHMENTS_FILE) else {
+            return Self::default();
+        };
+        let Ok(text) = std::fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        toml::from_str(&text)
+            .inspect_err(|e| eprintln!("Error reading {path:?}: {e}"))
+            .unwrap_or_default()
+    }
+
+    fn accepts(&self, mime_type: &str) -> bool {
+        self.accepted_formats.iter().any(|m| m == mime_type)
+    }
+}
+
+pub struct PreparedAttachment {
+    pub attachment: Attachment,
+    pub original_size: usize,
+    pub size: usize,
+    pub width: u32,
+    pub height: u32,
+    // Set when the image was decoded and encoded again.
+    pub reencoded: Option<AttachmentFormat>,
+}
+
+impl PreparedAttachment {
+    // A line for the chat saying what was done to the image, if anything.
+    pub fn summary(&self) -> Option<String> {
+        if self.size == self.original_size && self.reencoded.is_none() {
+            return None;
+        }
+
+        let name = &self.attachment.name;
+        let sent = format_size(self.size);
+        let original = format_size(self.original_size);
+        Some(match self.reencoded {
+            Some(format) => format!(
+                "Attached {name} as a {}×{} {} of {sent}, down from {original}",
+                self.width,
+                self.height,
+                format.extension().to_uppercase(),
+            ),
+            None => format!(
+                "Attached {name} without metadata, {sent} down from {original}"
+            ),
+        })
+    }
+
+    // Roughly what the image costs in a vision model's context, following
+    // OpenAI's tiling: fit in 2048×2048, shorten the short side to 768, then
+    // 170 tokens per 512 px tile plus 85.
+    pub fn tokens(&self) -> u32 {
+        let (mut width, mut height) = (self.width as f64, self.height as f64);
+        let fit = (2048.0 / width.max(height)).min(1.0);
+        (width, height) = (width * fit, height * fit);
+        let shorten = (768.0 / width.min(height)).min(1.0);
+        (width, height) = (width * shorten, height * shorten);
+
+        let tiles = (width / 512.0).ceil() * (height / 512.0).ceil();
+        85 + 170 * tiles as u32
+    }
+}
+
+// Images prepared to be asked about together, as many as the limits allow.
+pub struct PreparedImages {
+    pub attachments: Vec<PreparedAttachment>,
+    // Why the images after the attached ones were left out, if any were.
+    pub left_out: Option<String>,
+    // Images that couldn't be prepared, which are skipped.
+    pub errors: Vec<String>,
+}
+
+impl PreparedImages {
+    pub fn tokens(&self) -> u32 {
+        self.attachments.iter().map(|a| a.tokens()).sum()
+    }
+
+    // A line for the chat saying what was attached and what wasn't.
+    pub fn summary(&self) -> Option<String> {
+        if self.attachments.is_empty() && self.left_out.is_none() {
+            return None;
+        }
+
+        let count = self.attachments.len();
+        let mut summary = format!(
+            "Attached {count} image{}, about {} tokens",
+            if count == 1 { "" } else { "s" },
+            self.tokens()
+        );
+        let size: usize = self.attachments.iter().map(|a| a.size).sum();
+        let original_size: usize =
+            self.attachments.iter().map(|a| a.original_size).sum();
+        if size < original_size {
+            summary += &format!(
+                ", {} down from {}",
+                format_size(size),
+                format_size(original_size)
+            );
+        }
+        if let Some(left_out) = &self.left_out {
+            summary += &format!(". {left_out}");
+        }
+        Some(summary)
+    }
+}
+
+fn format_size(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["bytes", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} bytes")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+// Gets an image ready for a vision model: small enough, in a format the
+// provider takes and, unless disabled, without metadata.
+pub fn prepare(
+    source: &ImageSource,
+    options: &AttachmentOptions,
+) -> Result<PreparedAttachment, String> {
+    let bytes = source
+        .read()
+        .map_err(|e| format!("Error reading {source}: {e}"))?;
+    let original_size = bytes.len();
+    let mime_type = registry().mime_type(&source.path());
+
+    let dimensions = ImageReader::new(Cursor::new(&bytes))
+        .with_guessed_format()
+        .ok()
+        .and_then(|reader| reader.into_dimensions().ok());
+    let fits =
+        dimensions.is_some_and(|(w, h)| w.max(h) <= options.max_long_edge);
+
+    // Sent as is when possible, which keeps the original quality. Without
+    // its EXIF, a rotated image would go sideways, so it's re-encoded.
+    let passthrough = match mime_type {
+        Some(mime_type) if fits && options.accepts(mime_type) => {
+            if !options.strip_metadata {
+                Some(bytes.clone())
+            } else if orientation(&bytes) == Orientation::NoTransforms {
+                strip_metadata(&bytes, mime_type)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    };
+    if let Some(bytes) = passthrough {
+        let attachment = Attachment::from_bytes(
+            source.file_name(),
+            mime_type.map(String::from),
+            &bytes,
+        );
+        let (width, height) = dimensions.unwrap_or_default();
+        return Ok(PreparedAttachment {
+            attachment,
+            original_size,
+            size: bytes.len(),
+            width,
+            height,
+            reencoded: None,
+        });
+    }
+
+    let image = fit_long_edge(decode_rgba(source)?, options.max_long_edge);
+
+    let format = options.format;
+    let encoded = encode(&image, format, options.quality.clamp(1, 100))
+        .map_err(|e| format!("Error encoding {source}: {e}"))?;
+    let name = PathBuf::from(source.file_name())
+        .with_extension(format.extension())
+        .to_string_lossy()
+        .into_owned();
+    let attachment = Attachment::from_bytes(
+        name,
+        Some(format.mime_type().to_string()),
+        &encoded,
+    );
+
+    Ok(PreparedAttachment {
+        attachment,
+        original_size,
+        size: encoded.len(),
+        width: image.width(),
+        height: image.height(),
+        reencoded: Some(format),
+    })
+}
+
+// Prepares `sources` in order until `max_images` of them are attached or
+// the next one would go over `token_budget`.
+pub fn prepare_all(
+    sources: &[ImageSource],
+    options: &AttachmentOptions,
+) -> PreparedImages {
+    let mut prepared = PreparedImages {
+        attachments: Vec::new(),
+        left_out: None,
+        errors: Vec::new(),
+    };
+
+    let mut tokens = 0;
+    for (i, source) in sources.iter().enumerate() {
+        let remaining = sources.len() - i;
+        if prepared.attachments.len() == options.max_images {
+            prepared.left_out = Some(format!(
+                "{remaining} left out over the limit of {} images",
+                options.max_images
+            ));
+            break;
+        }
+
+        let attachment = match prepare(source, options) {
+            Ok(attachment) => attachment,
+            Err(e) => {
+                prepared.errors.push(e);
+                continue;
+            }
+        };
+        tokens += attachment.tokens();
+        if tokens > options.token_budget {
+            prepared.left_out = Some(format!(
+                "{remaining} left out over the budget of {} tokens",
+                options.token_budget
+            ));
+            break;
+        }
+        prepared.attachments.push(attachment);
+    }
+    prepared
+}
+
+// Decodes any image the viewer can open, turned upright as its EXIF says,
+// since images encoded from it go without.
+pub(crate) fn decode_rgba(source: &ImageSource) -> Result<RgbaImage, String> {
+    let decoded = registry()
+        .decode_file(source)
+        .map_err(|e| format!("Error decoding {source}: {e}"))?;
+    let rgba: Vec<u8> = decoded
+        .data
+        .iter()
+        .flat_map(|p| {
+            let [a, r, g, b] = p.to_be_bytes();
+            [r, g, b, a]
+        })
+        .collect();
+    let image =
+        RgbaImage::from_raw(decoded.width as u32, decoded.height as u32, rgba)
+            .ok_or_else(|| format!("Error decoding {source}: bad size"))?;
+
+    let mime_type = registry().mime_type(&source.path());
+    let oriented = matches!(
+        mime_type,
+        Some("image/jpeg" | "image/png" | "image/webp" | "image/tiff")
+    );
+    let orientation = match source.read() {
+        Ok(bytes) if oriented => orientation(&bytes),
+        _ => Orientation::NoTransforms,
+    };
+    if orientation == Orientation::NoTransforms {
+        return Ok(image);
+    }
+    let mut image = DynamicImage::ImageRgba8(image);
+    image.apply_orientation(orientation);
+    Ok(image.into_rgba8())
+}
+
+// The EXIF orientation, for the formats the image crate reads it from.
+pub(crate) fn orientation(bytes: &[u8]) -> Orientation {
+    ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .ok()
+        .and_then(|reader| reader.into_decoder().ok())
+        .and_then(|mut decoder| decoder.orientation().ok())
+        .unwrap_or(Orientation::NoTransforms)
+}
+
+// Scales the image down so neither side is over `max_long_edge`.
+pub(crate) fn fit_long_edge(image: RgbaImage, max_long_edge: u32) -> RgbaImage {
+    let long_edge = image.width().max(image.height());
+    if long_edge <= max_long_edge {
+        return image;
+    }
+    let scale = max_long_edge as f64 / long_edge as f64;
+    let width = ((image.width() as f64 * scale).round() as u32).max(1);
+    let height = ((image.height() as f64 * scale).round() as u32).max(1);
+    imageops::resize(&image, width, height, FilterType::CatmullRom)
+}
+
+pub(crate) fn encode(
+    image: &RgbaImage,
+    format: AttachmentFormat,
+    quality: u8,
+) -> Result<Vec<u8>, String> {
+    let mut encoded = Vec::new();
+    match format {
+        AttachmentFormat::Jpeg => {
+            // JPEG has no alpha, so transparency goes over white.
+            let mut rgb = image::RgbImage::new(image.width(), image.height());
+            for (dst, src) in rgb.pixels_mut().zip(image.pixels()) {
+                let a = src[3] as u32;
+                let over_white =
+                    |c: u8| ((c as u32 * a + 255 * (255 - a)) / 255) as u8;
+                *dst = image::Rgb([
+                    over_white(src[0]),
+                    over_white(src[1]),
+                    over_white(src[2]),
+                ]);
+            }
+            JpegEncoder::new_with_quality(&mut encoded, quality)
+                .encode_image(&rgb)
+                .map_err(|e| e.to_string())?;
+        }
+        #[cfg(feature = "lossy-webp")]
+        AttachmentFormat::Webp => {
+            let encoder =
+                webp::Encoder::from_rgba(image, image.width(), image.height());
+            encoded = encoder.encode(quality as f32).to_vec();
+        }
+        // Without libwebp, WebP can only be written losslessly.
+        #[cfg(all(feature = "webp", not(feature = "lossy-webp")))]
+        AttachmentFormat::Webp => {
+            image::codecs::webp::WebPEncoder::new_lossless(&mut encoded)
+                .encode(
+                    image,
+                    image.width(),
+                    image.height(),
+                    image::ExtendedColorType::Rgba8,
+                )
+                .map_err(|e| e.to_string())?;
+        }
+        #[cfg(not(any(feature = "webp", feature = "lossy-webp")))]
+        AttachmentFormat::Webp => {
+            return Err("built without WebP support".to_string());
+        }
+    }
+    Ok(encoded)
+}
+
+// Removes metadata without re-encoding, for the formats where that's a
+// matter of dropping a few segments. `None` means the image has to be
+// re-encoded instead.
+fn strip_metadata(bytes: &[u8], mime_type: &str) -> Option<Vec<u8>> {
+    match mime_type {
+        "image/jpeg" => strip_jpeg(bytes),
+        "image/png" => strip_png(bytes),
+        // GIFs carry no camera metadata.
+        "image/gif" => Some(bytes.to_vec()),
+        _ => None,
+    }
+}
+
+// Drops the APP1 (EXIF, XMP) and APP13 (IPTC) segments, keeping the rest,
+// including ICC profiles in APP2.
+fn strip_jpeg(bytes: &[u8]) -> Option<Vec<u8>> {
+    if !bytes.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    let mut stripped = bytes[..2].to_vec();
+    let mut pos = 2;
+    loop {
+        let marker = *bytes.get(pos + 1)?;
+        if bytes[pos] != 0xFF {
+            return None;
+        }
+        // Start of scan: the entropy-coded data and the rest follow.
+        if marker == 0xDA {
+            stripped.extend_from_slice(&bytes[pos..]);
+            return Some(stripped);
+        }
+
+        let length =
+            u16::from_be_bytes([*bytes.get(pos + 2)?, *bytes.get(pos + 3)?])
+                as usize;
+        let end = pos + 2 + length;
+        let segment = bytes.get(pos..end)?;
+        if marker != 0xE1 && marker != 0xED {
+            stripped.extend_from_slice(segment);
+        }
+        pos = end;
+    }
+}
+
+// Drops the eXIf and text chunks, which is where PNG keeps metadata.
+fn strip_png(bytes: &[u8]) -> Option<Vec<u8>> {
+    const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+    if !bytes.starts_with(SIGNATURE) {
+        return None;
+    }
+
+    let mut stripped = SIGNATURE.to_vec();
+    let mut pos = SIGNATURE.len();
+    while pos < bytes.len() {
+        let length =
+            u32::from_be_bytes(bytes.get(pos..pos + 4)?.try_into().ok()?)
+                as usize;
+        let kind = bytes.get(pos + 4..pos + 8)?;
+        // Length, type, data and CRC.
+        let chunk = bytes.get(pos..pos + 12 + length)?;
+        if !matches!(kind, b"eXIf" | b"tEXt" | b"iTXt" | b"zTXt") {
+            stripped.extend_from_slice(chunk);
+        }
+        pos += 12 + length;
+    }
+    Some(stripped)
+}
+
+pub(crate) fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for group in bytes.chunks(3) {
+        let b = [
+            group[0],
+            group.get(1).copied().unwrap_or(0),
+            group.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= group.len() {
+                let index = (n >> (18 - 6 * i)) & 0x3f;
+                encoded.push(ALPHABET[index as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
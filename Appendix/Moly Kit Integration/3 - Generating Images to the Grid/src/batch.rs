@@ -0,0 +1,93 @@
+use futures::StreamExt;
+use moly_kit::protocol::*;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::attachments::AttachmentOptions;
+use crate::source::ImageSource;
+use crate::vision::VisionModel;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BatchProgress {
+    pub total: usize,
+    pub done: usize,
+    pub failed: usize,
+    // Left out before asking, e.g. done by an earlier run.
+    pub skipped: usize,
+}
+
+impl BatchProgress {
+    pub fn finished(&self) -> usize {
+        self.done + self.failed + self.skipped
+    }
+}
+
+// What the caption and tag jobs share: a vision model asked about a few
+// images at a time, counting how each went and stopping when cancelled.
+pub(crate) struct BatchRunner {
+    vision: VisionModel,
+    concurrency: usize,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl BatchRunner {
+    pub async fn connect(
+        client: Box<dyn BotClient>,
+        model: &str,
+        options: AttachmentOptions,
+        retries: u32,
+        concurrency: usize,
+        cancelled: Arc<AtomicBool>,
+    ) -> Result<Self, String> {
+        let vision =
+            VisionModel::connect(client, model, options, retries).await?;
+        Ok(Self {
+            vision,
+            concurrency: concurrency.max(1),
+            cancelled,
+        })
+    }
+
+    // Replies come through `schema` where the model can call tools.
+    pub fn with_schema(mut self, schema: Tool) -> Self {
+        self.vision = self.vision.with_schema(schema);
+        self
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    // Asks about each image with its own prompt. `finish` turns each reply
+    // into a result as it comes in, and `report` gets it with the counts so
+    // far. Images not sent before cancelling are left for a later run.
+    pub async fn run<T>(
+        &self,
+        sources: Vec<ImageSource>,
+        progress: &mut BatchProgress,
+        prompt: impl Fn(&ImageSource) -> String,
+        mut finish: impl FnMut(&ImageSource, String) -> Result<T, String>,
+        mut report: impl FnMut(&BatchProgress, ImageSource, Result<T, String>),
+    ) {
+        let (vision, prompt, stop) = (&self.vision, &prompt, &self.cancelled);
+        let mut replies = futures::stream::iter(sources)
+            .map(|source| async move {
+                if stop.load(Ordering::Relaxed) {
+                    return (source, Err("Cancelled".to_string()));
+                }
+                let reply = vision.ask(&source, prompt(&source)).await;
+                (source, reply)
+            })
+            .buffer_unordered(self.concurrency);
+
+        while let Some((source, reply)) = replies.next().await {
+            let result = reply.and_then(|reply| finish(&source, reply));
+            match result {
+                Ok(_) => progress.done += 1,
+                Err(_) if self.is_cancelled() => continue,
+                Err(_) => progress.failed += 1,
+            }
+            report(progress, source, result);
+        }
+    }
+}
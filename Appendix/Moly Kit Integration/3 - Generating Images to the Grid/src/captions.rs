@@ -0,0 +1,372 @@
+use moly_kit::protocol::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+use crate::args::Args;
+use crate::attachments::AttachmentOptions;
+use crate::batch::{BatchProgress, BatchRunner};
+use crate::config;
+use crate::decoders::registry;
+use crate::providers::{ModelSelection, Providers};
+use crate::source::ImageSource;
+use crate::xmp::Xmp;
+
+const CAPTIONS_FILE: &str = "captions.toml";
+const PROGRESS_DIR: &str = "caption_progress";
+
+// How captions are written, read from `captions.toml`. The prompt may use
+// `{file_name}` and `{folder}`.
+//
+//     prompt = "Write alt text for {file_name} in one sentence."
+//     concurrency = 4
+//     retries = 3
+//     overwrite = false
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct CaptionSettings {
+    pub prompt: String,
+    // How many images are sent to the model at once.
+    pub concurrency: usize,
+    // Further attempts after a failed request, a little apart.
+    pub retries: u32,
+    // Whether images that already have a description get a new one.
+    pub overwrite: bool,
+}
+
+impl Default for CaptionSettings {
+    fn default() -> Self {
+        Self {
+            prompt: "Write alt text for this image for a website, in one \
+                     or two sentences. Describe what someone who can't see \
+                     it needs to know. Reply with the alt text only."
+                .to_string(),
+            concurrency: 4,
+            retries: 3,
+            overwrite: false,
+        }
+    }
+}
+
+impl CaptionSettings {
+    pub fn load() -> Self {
+        let Some(path) = config::config_file(CAPTIONS_FILE) else {
+            return Self::default();
+        };
+        let Ok(text) = std::fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        toml::from_str(&text)
+            .inspect_err(|e| eprintln!("Error reading {path:?}: {e}"))
+            .unwrap_or_default()
+    }
+
+    fn prompt_for(&self, source: &ImageSource) -> String {
+        let path = source.path();
+        let folder = path
+            .parent()
+            .and_then(|p| p.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.prompt
+            .replace("{file_name}", &source.file_name())
+            .replace("{folder}", &folder)
+    }
+}
+
+// One file per set of images and prompt, so captioning another folder, or
+// the same one differently, doesn't take up where this job stopped.
+pub fn progress_file(sources: &[ImageSource], prompt: &str) -> Option<PathBuf> {
+    let key = progress_key(sources, prompt);
+    let dir = config::config_file(PROGRESS_DIR)?;
+    Some(dir.join(format!("{key}.json")))
+}
+
+fn progress_key(sources: &[ImageSource], prompt: &str) -> String {
+    let mut paths: Vec<String> =
+        sources.iter().map(|s| s.to_string()).collect();
+    paths.sort();
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(prompt.as_bytes());
+    for path in paths {
+        hasher.update(b"\0");
+        hasher.update(path.as_bytes());
+    }
+    hasher.finalize().to_hex().to_string()
+}
+
+// The images a job has finished, so an interrupted one picks up where it
+// stopped. It only counts for the same images and prompt, and goes away once
+// a job ends without failures.
+#[derive(Default, Serialize, Deserialize)]
+struct Progress {
+    key: String,
+    done: HashSet<ImageSource>,
+}
+
+impl Progress {
+    fn load(path: &Path, key: &str) -> Self {
+        let progress = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|json| serde_json::from_str::<Self>(&json).ok())
+            .filter(|p| p.key == key);
+        progress.unwrap_or_else(|| Self {
+            key: key.to_string(),
+            done: HashSet::new(),
+        })
+    }
+
+    fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let json = serde_json::to_string(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        std::fs::write(path, json)
+    }
+}
+
+pub type CaptionProgress = BatchProgress;
+
+#[derive(Clone, Debug)]
+pub enum CaptionEvent {
+    Captioned {
+        source: ImageSource,
+        caption: String,
+    },
+    Failed {
+        source: ImageSource,
+        error: String,
+    },
+}
+
+// Writes a caption for each image with a vision model, into the XMP
+// `dc:description` of its sidecar.
+pub struct CaptionJob {
+    client: Box<dyn BotClient>,
+    model: String,
+    sources: Vec<ImageSource>,
+    settings: CaptionSettings,
+    attachment_options: AttachmentOptions,
+    progress_file: Option<PathBuf>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CaptionJob {
+    pub fn new(
+        client: impl BotClient + 'static,
+        model: &str,
+        sources: Vec<ImageSource>,
+        settings: CaptionSettings,
+        attachment_options: AttachmentOptions,
+    ) -> Self {
+        Self {
+            client: Box::new(client),
+            model: model.to_string(),
+            progress_file: progress_file(&sources, &settings.prompt),
+            sources,
+            settings,
+            attachment_options,
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn with_progress_file(mut self, path: Option<PathBuf>) -> Self {
+        self.progress_file = path;
+        self
+    }
+
+    // Setting it stops the job before the next image. Images already sent
+    // still finish.
+    pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.cancelled)
+    }
+
+    pub async fn run(
+        self,
+        mut on_update: impl FnMut(&CaptionProgress, &CaptionEvent) + Send,
+    ) -> Result<CaptionProgress, String> {
+        let Self {
+            client,
+            model,
+            sources,
+            settings,
+            attachment_options,
+            progress_file,
+            cancelled,
+        } = self;
+        let runner = BatchRunner::connect(
+            client,
+            &model,
+            attachment_options,
+            settings.retries,
+            settings.concurrency,
+            cancelled,
+        )
+        .await?;
+
+        let key = progress_key(&sources, &settings.prompt);
+        let mut progress = progress_file
+            .as_deref()
+            .map(|path| Progress::load(path, &key))
+            .unwrap_or_default();
+        let mut counts = CaptionProgress {
+            total: sources.len(),
+            ..Default::default()
+        };
+
+        let pending: Vec<ImageSource> = sources
+            .into_iter()
+            .filter(|source| {
+                let skip = progress.done.contains(source)
+                    || (!settings.overwrite && has_description(source));
+                counts.skipped += skip as usize;
+                !skip
+            })
+            .collect();
+
+        runner
+            .run(
+                pending,
+                &mut counts,
+                |source| settings.prompt_for(source),
+                |source, reply| {
+                    let caption = clean_caption(&reply);
+                    write_caption(source, &caption)?;
+                    progress.done.insert(source.clone());
+                    if let Some(path) = &progress_file {
+                        if let Err(e) = progress.save(path) {
+                            eprintln!("Error saving {path:?}: {e}");
+                        }
+                    }
+                    Ok(caption)
+                },
+                |counts, source, result| {
+                    let event = match result {
+                        Ok(caption) => {
+                            CaptionEvent::Captioned { source, caption }
+                        }
+                        Err(error) => CaptionEvent::Failed { source, error },
+                    };
+                    on_update(counts, &event);
+                },
+            )
+            .await;
+
+        if counts.failed == 0 && !runner.is_cancelled() {
+            if let Some(path) = &progress_file {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+        Ok(counts)
+    }
+}
+
+// Without the quotes models like to put around it.
+fn clean_caption(reply: &str) -> String {
+    reply.trim_matches('"').trim().to_string()
+}
+
+fn has_description(source: &ImageSource) -> bool {
+    Xmp::load(source).is_ok_and(|xmp| xmp.description().is_some())
+}
+
+fn write_caption(source: &ImageSource, caption: &str) -> Result<(), String> {
+    Xmp::edit(source, |xmp| xmp.set_description(caption))
+        .map_err(|e| e.to_string())
+}
+
+// The images directly in `folder` that can be opened, by name.
+fn folder_sources(folder: &Path) -> io::Result<Vec<ImageSource>> {
+    let mut paths: Vec<PathBuf> = folder
+        .read_dir()?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.is_file() && registry().is_supported(path))
+        .collect();
+    paths.sort();
+    Ok(paths.into_iter().map(ImageSource::File).collect())
+}
+
+// Captions the folder given on the command line with the chat model chosen
+// in settings, printing progress to stderr. Returns the exit code.
+pub fn run_headless(args: &Args) -> i32 {
+    let Some(folder) = &args.root else {
+        eprintln!("Usage: image_viewer --caption [--prompt=TEXT] FOLDER");
+        return 2;
+    };
+
+    let result = (|| {
+        let sources = folder_sources(folder)
+            .map_err(|e| format!("{}: {e}", folder.display()))?;
+        let providers = Providers::load()?;
+        let choice = ModelSelection::load()
+            .chat
+            .ok_or("No chat model chosen. Pick one in Settings")?;
+        let provider = providers.get(&choice.provider).ok_or_else(|| {
+            format!("Provider '{}' isn't configured", choice.provider)
+        })?;
+
+        let mut settings = CaptionSettings::load();
+        if let Some(prompt) = &args.prompt {
+            settings.prompt = prompt.clone();
+        }
+        let mut options = AttachmentOptions::load();
+        if let Some(formats) = &provider.accepted_formats {
+            options.accepted_formats = formats.clone();
+        }
+        Ok::<_, String>(CaptionJob::new(
+            provider.chat_client()?,
+            &choice.model,
+            sources,
+            settings,
+            options,
+        ))
+    })();
+    let job = match result {
+        Ok(job) => job,
+        Err(error) => {
+            eprintln!("{error}");
+            return 1;
+        }
+    };
+
+    // The clients need the async runtime the app uses.
+    let (sender, receiver) = std::sync::mpsc::channel();
+    moly_kit::utils::asynchronous::spawn(async move {
+        let result = job
+            .run(|progress, event| match event {
+                CaptionEvent::Captioned { source, caption } => eprintln!(
+                    "[{}/{}] {}: {caption}",
+                    progress.finished(),
+                    progress.total,
+                    source.file_name()
+                ),
+                CaptionEvent::Failed { source, error } => eprintln!(
+                    "[{}/{}] {}: {error}",
+                    progress.finished(),
+                    progress.total,
+                    source.file_name()
+                ),
+            })
+            .await;
+        let _ = sender.send(result);
+    });
+
+    match receiver.recv() {
+        Ok(Ok(progress)) => {
+            eprintln!(
+                "Captioned {}, skipped {}, failed {}",
+                progress.done, progress.skipped, progress.failed
+            );
+            (progress.failed > 0) as i32
+        }
+        Ok(Err(error)) => {
+            eprintln!("{error}");
+            1
+        }
+        Err(_) => 1,
+    }
+}
@@ -0,0 +1,154 @@
+#[cfg(feature = "icc")]
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "icc")]
+use crate::config;
+use crate::decoders::DecodedImage;
+
+const COLOR_FILE: &str = "color.toml";
+
+// The profiles from `color.toml`, e.g.
+//
+//     display_profile = "/usr/share/color/icc/display.icc"
+//     proof_profile = "/usr/share/color/icc/ISOcoated_v2.icc"
+//
+// Without a display profile the display is assumed to be sRGB.
+#[cfg(feature = "icc")]
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+struct ColorSettings {
+    display_profile: Option<PathBuf>,
+    proof_profile: Option<PathBuf>,
+}
+
+#[cfg(feature = "icc")]
+impl ColorSettings {
+    // Read once, as every decode needs them.
+    fn get() -> &'static Self {
+        static SETTINGS: std::sync::OnceLock<ColorSettings> =
+            std::sync::OnceLock::new();
+        SETTINGS.get_or_init(Self::load)
+    }
+
+    fn load() -> Self {
+        let Some(path) = config::config_file(COLOR_FILE) else {
+            return Self::default();
+        };
+        let Ok(text) = std::fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        toml::from_str(&text)
+            .inspect_err(|e| eprintln!("Error reading {path:?}: {e}"))
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(feature = "icc")]
+pub fn embedded_profile(bytes: &[u8]) -> Option<Vec<u8>> {
+    use image::ImageDecoder;
+
+    let mut decoder = image::ImageReader::new(std::io::Cursor::new(bytes))
+        .with_guessed_format()
+        .ok()?
+        .into_decoder()
+        .ok()?;
+    decoder.icc_profile().ok().flatten()
+}
+
+#[cfg(not(feature = "icc"))]
+pub fn embedded_profile(_bytes: &[u8]) -> Option<Vec<u8>> {
+    None
+}
+
+#[cfg(feature = "icc")]
+fn load_profile(path: Option<&Path>) -> Result<Option<lcms2::Profile>, String> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+
+    let bytes = std::fs::read(path).map_err(|e| format!("{path:?}: {e}"))?;
+    lcms2::Profile::new_icc(&bytes)
+        .map(Some)
+        .map_err(|e| format!("{path:?}: {e}"))
+}
+
+// Converts 8-bit pixels from their embedded profile, or sRGB when untagged,
+// to the display profile. With `proof` set, the result also simulates how
+// the image would reproduce on the proofing profile's device.
+//
+// Float images are left alone, they are already treated as linear sRGB.
+#[cfg(feature = "icc")]
+pub fn manage(
+    image: &mut DecodedImage,
+    profile: Option<&[u8]>,
+    proof: bool,
+) -> Result<(), String> {
+    use lcms2::{Flags, Intent, PixelFormat, Profile, Transform};
+
+    if image.is_hdr() {
+        return Ok(());
+    }
+
+    let settings = ColorSettings::get();
+    let display = load_profile(settings.display_profile.as_deref())?;
+    if profile.is_none() && display.is_none() && !proof {
+        return Ok(());
+    }
+
+    let source = match profile {
+        Some(profile) => {
+            Profile::new_icc(profile).map_err(|e| e.to_string())?
+        }
+        None => Profile::new_srgb(),
+    };
+    let display = display.unwrap_or_else(Profile::new_srgb);
+
+    let transform: Transform<[u8; 4], [u8; 4]> = if proof {
+        let proofing = load_profile(settings.proof_profile.as_deref())?
+            .ok_or_else(|| {
+                format!("Set proof_profile in {COLOR_FILE} to soft proof")
+            })?;
+        Transform::new_proofing(
+            &source,
+            PixelFormat::BGRA_8,
+            &display,
+            PixelFormat::BGRA_8,
+            &proofing,
+            Intent::Perceptual,
+            Intent::AbsoluteColorimetric,
+            Flags::SOFT_PROOFING,
+        )
+    } else {
+        Transform::new(
+            &source,
+            PixelFormat::BGRA_8,
+            &display,
+            PixelFormat::BGRA_8,
+            Intent::Perceptual,
+        )
+    }
+    .map_err(|e| e.to_string())?;
+
+    // Packed ARGB words are BGRA bytes in memory on little endian targets.
+    let mut pixels: Vec<[u8; 4]> =
+        image.data.iter().map(|p| p.to_le_bytes()).collect();
+    transform.transform_in_place(&mut pixels);
+    image.data = pixels.into_iter().map(u32::from_le_bytes).collect();
+
+    Ok(())
+}
+
+#[cfg(not(feature = "icc"))]
+pub fn manage(
+    _image: &mut DecodedImage,
+    _profile: Option<&[u8]>,
+    proof: bool,
+) -> Result<(), String> {
+    if proof {
+        return Err(format!(
+            "Soft proofing needs the `icc` feature and a proof_profile in \
+             {COLOR_FILE}"
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,473 @@
+use makepad_widgets::*;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::config;
+use crate::session::Page;
+
+const KEYBINDINGS_FILE: &str = "keybindings.toml";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    CommandPalette,
+    OpenSlideshow,
+    BackToGrid,
+    OpenCompare,
+    OpenSettings,
+    ShowConversations,
+    CompareWipe,
+    CompareSideBySide,
+    CompareDifference,
+    GoLeft,
+    GoRight,
+    PreviousImage,
+    NextImage,
+    PreviousPage,
+    NextPage,
+    ToggleChat,
+    AskAboutSelection,
+    CaptionImages,
+    TagImages,
+    ReviewTags,
+    FindSimilar,
+    ToggleAnimation,
+    PreviousFrame,
+    NextFrame,
+    SlowerAnimation,
+    FasterAnimation,
+    ToggleHoverAnimation,
+    DevelopRaw,
+    ToggleToneControls,
+    ToggleScopes,
+    ToggleSoftProof,
+    ToggleLoupe,
+    CycleBackground,
+    CycleTheme,
+    ToggleSpread,
+    ToggleRightToLeft,
+    ShowColor,
+    ToggleRed,
+    ToggleGreen,
+    ToggleBlue,
+    ToggleAlpha,
+    ToggleLuminance,
+    ToggleFalseColor,
+    CycleColormap,
+    LowerRemapMin,
+    RaiseRemapMin,
+    LowerRemapMax,
+    RaiseRemapMax,
+}
+
+// Where a command's keys work. Global ones work on every page, the others
+// only on their own, so a key can mean something else on each page.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommandScope {
+    Global,
+    Grid,
+    Slideshow,
+    Compare,
+}
+
+impl CommandScope {
+    // Settings and conversations have no commands of their own.
+    pub fn of_page(page: Page) -> Self {
+        match page {
+            Page::ImageBrowser => Self::Grid,
+            Page::Slideshow => Self::Slideshow,
+            Page::Compare => Self::Compare,
+            Page::Settings | Page::Conversations => Self::Global,
+        }
+    }
+
+    // Whether keys bound in both scopes could ever be pressed at once.
+    fn overlaps(self, other: Self) -> bool {
+        self == Self::Global || other == Self::Global || self == other
+    }
+}
+
+struct CommandInfo {
+    command: Command,
+    scope: CommandScope,
+    name: &'static str,
+    title: &'static str,
+    keys: &'static [&'static str],
+}
+
+const fn info(
+    command: Command,
+    scope: CommandScope,
+    name: &'static str,
+    title: &'static str,
+    keys: &'static [&'static str],
+) -> CommandInfo {
+    CommandInfo {
+        command,
+        scope,
+        name,
+        title,
+        keys,
+    }
+}
+
+// Every command with where its keys work, its name in keybinding files, its
+// title in the command palette and its default keys.
+use CommandScope::{Compare, Global, Grid, Slideshow};
+
+#[rustfmt::skip]
+const COMMANDS: &[CommandInfo] = &[
+    info(Command::CommandPalette, Global, "command_palette", "Show all commands", &["Ctrl+Shift+P"]),
+    info(Command::OpenSlideshow, Grid, "open_slideshow", "Open slideshow", &[]),
+    info(Command::BackToGrid, Global, "back_to_grid", "Back to grid", &["Escape"]),
+    info(Command::OpenCompare, Grid, "open_compare", "Compare images", &[]),
+    info(Command::OpenSettings, Global, "open_settings", "Open settings", &["Ctrl+,"]),
+    info(Command::ShowConversations, Global, "show_conversations", "Browse conversations", &["Ctrl+H"]),
+    info(Command::CompareWipe, Compare, "compare_wipe", "Compare: wipe", &[]),
+    info(Command::CompareSideBySide, Compare, "compare_side_by_side", "Compare: side by side", &[]),
+    info(Command::CompareDifference, Compare, "compare_difference", "Compare: difference", &[]),
+    info(Command::GoLeft, Slideshow, "go_left", "Go left", &["ArrowLeft"]),
+    info(Command::GoRight, Slideshow, "go_right", "Go right", &["ArrowRight"]),
+    info(Command::PreviousImage, Slideshow, "previous_image", "Previous image", &[]),
+    info(Command::NextImage, Slideshow, "next_image", "Next image", &[]),
+    info(Command::PreviousPage, Grid, "previous_page", "Previous page", &["PageUp"]),
+    info(Command::NextPage, Grid, "next_page", "Next page", &["PageDown"]),
+    info(Command::ToggleChat, Global, "toggle_chat", "Toggle chat", &["T"]),
+    info(Command::AskAboutSelection, Global, "ask_about_selection", "Ask about selected images", &[]),
+    info(Command::CaptionImages, Global, "caption_images", "Write alt text for images", &[]),
+    info(Command::TagImages, Global, "tag_images", "Tag images from the vocabulary", &[]),
+    info(Command::ReviewTags, Global, "review_tags", "Review tag suggestions", &[]),
+    info(Command::FindSimilar, Global, "find_similar", "Find similar images", &[]),
+    info(Command::ToggleAnimation, Slideshow, "toggle_animation", "Play/pause animation", &["Space"]),
+    info(Command::PreviousFrame, Slideshow, "previous_frame", "Previous frame", &[","]),
+    info(Command::NextFrame, Slideshow, "next_frame", "Next frame", &["."]),
+    info(Command::SlowerAnimation, Slideshow, "slower_animation", "Slower animation", &["-"]),
+    info(Command::FasterAnimation, Slideshow, "faster_animation", "Faster animation", &["="]),
+    info(Command::ToggleHoverAnimation, Grid, "toggle_hover_animation", "Toggle hover animation", &[]),
+    info(Command::DevelopRaw, Slideshow, "develop_raw", "Develop RAW", &["D"]),
+    info(Command::ToggleToneControls, Slideshow, "toggle_tone_controls", "Toggle exposure controls", &["E"]),
+    info(Command::ToggleScopes, Slideshow, "toggle_scopes", "Toggle scopes", &["H"]),
+    info(Command::ToggleSoftProof, Slideshow, "toggle_soft_proof", "Toggle soft proofing", &["P"]),
+    info(Command::ToggleLoupe, Slideshow, "toggle_loupe", "Toggle loupe", &["L"]),
+    info(Command::CycleBackground, Slideshow, "cycle_background", "Cycle background", &["K"]),
+    info(Command::CycleTheme, Global, "cycle_theme", "Cycle theme", &[]),
+    info(Command::ToggleSpread, Slideshow, "toggle_spread", "Toggle two-page spread", &["S"]),
+    info(Command::ToggleRightToLeft, Slideshow, "toggle_right_to_left", "Toggle right-to-left reading", &["O"]),
+    info(Command::ShowColor, Slideshow, "show_color", "Show all channels", &["C"]),
+    info(Command::ToggleRed, Slideshow, "toggle_red", "Toggle red channel", &["R"]),
+    info(Command::ToggleGreen, Slideshow, "toggle_green", "Toggle green channel", &["G"]),
+    info(Command::ToggleBlue, Slideshow, "toggle_blue", "Toggle blue channel", &["B"]),
+    info(Command::ToggleAlpha, Slideshow, "toggle_alpha", "Toggle alpha channel", &["A"]),
+    info(Command::ToggleLuminance, Slideshow, "toggle_luminance", "Toggle luminance", &["Y"]),
+    info(Command::ToggleFalseColor, Slideshow, "toggle_false_color", "Toggle false color", &["F"]),
+    info(Command::CycleColormap, Slideshow, "cycle_colormap", "Cycle colormap", &["M"]),
+    info(Command::LowerRemapMin, Slideshow, "lower_remap_min", "Lower remap minimum", &["Shift+["]),
+    info(Command::RaiseRemapMin, Slideshow, "raise_remap_min", "Raise remap minimum", &["Shift+]"]),
+    info(Command::LowerRemapMax, Slideshow, "lower_remap_max", "Lower remap maximum", &["["]),
+    info(Command::RaiseRemapMax, Slideshow, "raise_remap_max", "Raise remap maximum", &["]"]),
+];
+
+impl Command {
+    fn info(self) -> &'static CommandInfo {
+        COMMANDS.iter().find(|info| info.command == self).unwrap()
+    }
+
+    pub fn all() -> impl Iterator<Item = Command> {
+        COMMANDS.iter().map(|info| info.command)
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        COMMANDS
+            .iter()
+            .find(|info| info.name == name)
+            .map(|info| info.command)
+    }
+
+    pub fn name(self) -> &'static str {
+        self.info().name
+    }
+
+    pub fn title(self) -> &'static str {
+        self.info().title
+    }
+
+    pub fn scope(self) -> CommandScope {
+        self.info().scope
+    }
+
+    // Whether the command's keys work where `scope` is active.
+    pub fn works_in(self, scope: CommandScope) -> bool {
+        let own = self.scope();
+        own == CommandScope::Global || own == scope
+    }
+}
+
+#[rustfmt::skip]
+const KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("A", KeyCode::KeyA), ("B", KeyCode::KeyB), ("C", KeyCode::KeyC),
+    ("D", KeyCode::KeyD), ("E", KeyCode::KeyE), ("F", KeyCode::KeyF),
+    ("G", KeyCode::KeyG), ("H", KeyCode::KeyH), ("I", KeyCode::KeyI),
+    ("J", KeyCode::KeyJ), ("K", KeyCode::KeyK), ("L", KeyCode::KeyL),
+    ("M", KeyCode::KeyM), ("N", KeyCode::KeyN), ("O", KeyCode::KeyO),
+    ("P", KeyCode::KeyP), ("Q", KeyCode::KeyQ), ("R", KeyCode::KeyR),
+    ("S", KeyCode::KeyS), ("T", KeyCode::KeyT), ("U", KeyCode::KeyU),
+    ("V", KeyCode::KeyV), ("W", KeyCode::KeyW), ("X", KeyCode::KeyX),
+    ("Y", KeyCode::KeyY), ("Z", KeyCode::KeyZ),
+    ("0", KeyCode::Key0), ("1", KeyCode::Key1), ("2", KeyCode::Key2),
+    ("3", KeyCode::Key3), ("4", KeyCode::Key4), ("5", KeyCode::Key5),
+    ("6", KeyCode::Key6), ("7", KeyCode::Key7), ("8", KeyCode::Key8),
+    ("9", KeyCode::Key9),
+    ("F1", KeyCode::F1), ("F2", KeyCode::F2), ("F3", KeyCode::F3),
+    ("F4", KeyCode::F4), ("F5", KeyCode::F5), ("F6", KeyCode::F6),
+    ("F7", KeyCode::F7), ("F8", KeyCode::F8), ("F9", KeyCode::F9),
+    ("F10", KeyCode::F10), ("F11", KeyCode::F11), ("F12", KeyCode::F12),
+    ("Escape", KeyCode::Escape), ("Space", KeyCode::Space),
+    ("Enter", KeyCode::ReturnKey), ("Tab", KeyCode::Tab),
+    ("Backspace", KeyCode::Backspace), ("Delete", KeyCode::Delete),
+    ("Insert", KeyCode::Insert), ("Home", KeyCode::Home),
+    ("End", KeyCode::End), ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown), ("ArrowLeft", KeyCode::ArrowLeft),
+    ("ArrowRight", KeyCode::ArrowRight), ("ArrowUp", KeyCode::ArrowUp),
+    ("ArrowDown", KeyCode::ArrowDown),
+    (",", KeyCode::Comma), (".", KeyCode::Period), ("-", KeyCode::Minus),
+    ("=", KeyCode::Equals), ("[", KeyCode::LBracket),
+    ("]", KeyCode::RBracket), (";", KeyCode::Semicolon),
+    ("'", KeyCode::Quote), ("/", KeyCode::Slash),
+    ("\\", KeyCode::Backslash), ("`", KeyCode::Backtick),
+];
+
+// A key together with the modifiers that have to be held with it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyChord {
+    key: KeyCode,
+    control: bool,
+    shift: bool,
+    alt: bool,
+    logo: bool,
+}
+
+impl KeyChord {
+    pub fn from_event(event: &KeyEvent) -> Self {
+        Self {
+            key: event.key_code,
+            control: event.modifiers.control,
+            shift: event.modifiers.shift,
+            alt: event.modifiers.alt,
+            logo: event.modifiers.logo,
+        }
+    }
+
+    // Parses chords like `Ctrl+Shift+P`, with the key last.
+    pub fn parse(chord: &str) -> Result<Self, String> {
+        let (modifiers, key) = chord.rsplit_once('+').unwrap_or(("", chord));
+
+        let key = KEY_NAMES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key.trim()))
+            .map(|(_, key)| *key)
+            .ok_or_else(|| format!("unknown key `{key}` in `{chord}`"))?;
+
+        let mut parsed = Self {
+            key,
+            control: false,
+            shift: false,
+            alt: false,
+            logo: false,
+        };
+        for modifier in modifiers.split('+').filter(|m| !m.is_empty()) {
+            match modifier.trim().to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => parsed.control = true,
+                "shift" => parsed.shift = true,
+                "alt" | "option" => parsed.alt = true,
+                "cmd" | "super" | "logo" | "win" => parsed.logo = true,
+                _ => {
+                    return Err(format!(
+                        "unknown modifier `{modifier}` in `{chord}`"
+                    ));
+                }
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+impl fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let modifiers = [
+            (self.control, "Ctrl+"),
+            (self.alt, "Alt+"),
+            (self.shift, "Shift+"),
+            (self.logo, "Cmd+"),
+        ];
+        for (held, name) in modifiers {
+            if held {
+                f.write_str(name)?;
+            }
+        }
+
+        let key = KEY_NAMES
+            .iter()
+            .find(|(_, key)| *key == self.key)
+            .map_or("?", |(name, _)| name);
+        f.write_str(key)
+    }
+}
+
+// Maps chords to commands. The user's `keybindings.toml` replaces the
+// default keys of the commands it mentions, for example:
+//
+//     next_image = "N"
+//     toggle_chat = ["T", "Ctrl+K"]
+//     toggle_loupe = []
+//
+// Keys only run commands that work on the current page.
+pub struct Keymap {
+    bindings: Vec<(KeyChord, Command)>,
+}
+
+impl Keymap {
+    pub fn with_defaults() -> Self {
+        let bindings = COMMANDS
+            .iter()
+            .flat_map(|info| {
+                info.keys.iter().map(|key| {
+                    let chord = KeyChord::parse(key)
+                        .expect("default keybindings should be valid");
+                    (chord, info.command)
+                })
+            })
+            .collect();
+        Self { bindings }
+    }
+
+    pub fn load() -> Self {
+        let mut keymap = Self::with_defaults();
+        let Some(path) = config::config_file(KEYBINDINGS_FILE) else {
+            return keymap;
+        };
+        let Ok(text) = std::fs::read_to_string(&path) else {
+            return keymap;
+        };
+
+        if let Err(e) = keymap.apply_user_bindings(&text) {
+            eprintln!("Error reading {path:?}: {e}");
+        }
+        keymap
+    }
+
+    // Applies the bindings in a `keybindings.toml`, keeping the valid ones
+    // when others are wrong.
+    pub fn apply_user_bindings(&mut self, text: &str) -> Result<(), String> {
+        #[derive(serde::Deserialize)]
+        #[serde(untagged)]
+        enum Keys {
+            One(String),
+            Many(Vec<String>),
+        }
+
+        let user: HashMap<String, Keys> =
+            toml::from_str(text).map_err(|e| e.to_string())?;
+
+        let mut errors = Vec::new();
+        for (name, keys) in user {
+            let Some(command) = Command::from_name(&name) else {
+                errors.push(format!("unknown command `{name}`"));
+                continue;
+            };
+
+            let keys = match keys {
+                Keys::One(key) => vec![key],
+                Keys::Many(keys) => keys,
+            };
+            self.bindings.retain(|(_, c)| *c != command);
+            for key in keys {
+                match KeyChord::parse(&key) {
+                    Ok(chord) => self.bind(chord, command),
+                    Err(e) => errors.push(e),
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join(", "))
+        }
+    }
+
+    // A chord runs one command in each scope, so binding it again takes it
+    // away from the default command that had it where the scopes overlap.
+    fn bind(&mut self, chord: KeyChord, command: Command) {
+        self.bindings.retain(|(c, other)| {
+            *c != chord || !other.scope().overlaps(command.scope())
+        });
+        self.bindings.push((chord, command));
+    }
+
+    pub fn command(
+        &self,
+        event: &KeyEvent,
+        scope: CommandScope,
+    ) -> Option<Command> {
+        self.command_for(KeyChord::from_event(event), scope)
+    }
+
+    pub fn command_for(
+        &self,
+        chord: KeyChord,
+        scope: CommandScope,
+    ) -> Option<Command> {
+        self.bindings
+            .iter()
+            .find(|(c, command)| *c == chord && command.works_in(scope))
+            .map(|(_, command)| *command)
+    }
+
+    pub fn binding(&self, command: Command) -> Option<KeyChord> {
+        self.bindings
+            .iter()
+            .find(|(_, c)| *c == command)
+            .map(|(chord, _)| *chord)
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::with_defaults()
+    }
+}
+
+// Scores `text` by how well the characters of `query` appear in it, in
+// order. Consecutive characters and word starts score higher, and `None`
+// means some character is missing.
+pub fn fuzzy_score(query: &str, text: &str) -> Option<i32> {
+    let mut score = 0;
+    let mut previous_end = None;
+    let mut text_chars = text.char_indices();
+
+    for q in query.chars().filter(|c| !c.is_whitespace()) {
+        let q = q.to_ascii_lowercase();
+        loop {
+            let (i, c) = text_chars.next()?;
+            if c.to_ascii_lowercase() != q {
+                continue;
+            }
+
+            score += 1;
+            if previous_end == Some(i) {
+                score += 4;
+            }
+            let word_start = text[..i]
+                .chars()
+                .next_back()
+                .is_none_or(|p| p == ' ' || p == '_' || p == '-');
+            if word_start {
+                score += 3;
+            }
+            previous_end = Some(i + c.len_utf8());
+            break;
+        }
+    }
+
+    // Prefer short titles among equally good matches.
+    Some(score * 100 - text.len() as i32)
+}
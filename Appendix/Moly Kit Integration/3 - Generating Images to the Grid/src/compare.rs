@@ -0,0 +1,330 @@
+use makepad_widgets::*;
+use std::path::{Path, PathBuf};
+
+use crate::source::ImageSource;
+
+const MIN_ZOOM: f32 = 0.1;
+const MAX_ZOOM: f32 = 32.0;
+const ZOOM_STEP: f32 = 1.1;
+
+live_design! {
+    use link::widgets::*;
+    use link::shaders::*;
+
+    DrawCompare = {{DrawCompare}} {
+        texture image_a: texture2d
+        texture image_b: texture2d
+
+        fn fit_uv(self, pos: vec2, rect: vec2, image: vec2) -> vec2 {
+            let scale = min(rect.x / image.x, rect.y / image.y) * self.zoom;
+            let size = image * scale;
+            let offset = (rect - size) * 0.5 + self.pan;
+            return (pos * rect - offset) / size;
+        }
+
+        fn outside(uv: vec2) -> bool {
+            return uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0;
+        }
+
+        fn sample_a(self, uv: vec2) -> vec4 {
+            if outside(uv) {
+                return vec4(0.0);
+            }
+            return sample2d(self.image_a, uv);
+        }
+
+        fn sample_b(self, uv: vec2) -> vec4 {
+            if outside(uv) {
+                return vec4(0.0);
+            }
+            return sample2d(self.image_b, uv);
+        }
+
+        fn pixel(self) -> vec4 {
+            let size = self.rect_size;
+
+            if self.mode < 0.5 {
+                let a = self.sample_a(self.fit_uv(self.pos, size, self.size_a));
+                let b = self.sample_b(self.fit_uv(self.pos, size, self.size_b));
+
+                if abs(self.pos.x - self.split) * size.x < 1.0 {
+                    return vec4(1.0);
+                }
+                return mix(a, b, step(self.split, self.pos.x));
+            }
+
+            if self.mode < 1.5 {
+                let half = vec2(size.x * 0.5, size.y);
+                if self.pos.x < 0.5 {
+                    let pos = vec2(self.pos.x * 2.0, self.pos.y);
+                    return self.sample_a(self.fit_uv(pos, half, self.size_a));
+                }
+                let pos = vec2(self.pos.x * 2.0 - 1.0, self.pos.y);
+                return self.sample_b(self.fit_uv(pos, half, self.size_b));
+            }
+
+            let a = self.sample_a(self.fit_uv(self.pos, size, self.size_a));
+            let b = self.sample_b(self.fit_uv(self.pos, size, self.size_b));
+            let diff = abs(a - b);
+            let amount = max(max(diff.r, diff.g), max(diff.b, diff.a));
+            let highlight = clamp(amount * self.gain, 0.0, 1.0);
+            let base = vec3(dot(a.rgb, vec3(0.2126, 0.7152, 0.0722)) * 0.4);
+            let color = mix(base, vec3(1.0, 0.0, 1.0), highlight);
+            return vec4(color * max(a.a, b.a), max(a.a, b.a));
+        }
+    }
+
+    pub CompareView = {{CompareView}} {
+        width: Fill,
+        height: Fill,
+        draw_compare: {
+            split: 0.5,
+            zoom: 1.0,
+            gain: 8.0,
+        }
+    }
+
+    CompareToolbarButton = <Button> {
+        grab_key_focus: false,
+    }
+
+    pub Compare = <View> {
+        flow: Down,
+
+        toolbar = <View> {
+            width: Fill,
+            height: Fit,
+            padding: 5,
+            spacing: 5,
+
+            back_button = <CompareToolbarButton> {
+                text: "Back",
+            }
+            <Filler> {}
+            wipe_button = <CompareToolbarButton> {
+                text: "Wipe",
+            }
+            side_by_side_button = <CompareToolbarButton> {
+                text: "Side by Side",
+            }
+            difference_button = <CompareToolbarButton> {
+                text: "Difference",
+            }
+        }
+
+        area = <View> {
+            cursor: Arrow,
+            capture_overload: true,
+
+            view = <CompareView> {}
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompareMode {
+    #[default]
+    Wipe,
+    SideBySide,
+    Difference,
+}
+
+impl CompareMode {
+    fn as_shader_value(self) -> f32 {
+        match self {
+            CompareMode::Wipe => 0.0,
+            CompareMode::SideBySide => 1.0,
+            CompareMode::Difference => 2.0,
+        }
+    }
+}
+
+// Edited copies are expected next to the original, as `<stem>_edited.<ext>`.
+pub fn edited_version_path(path: &Path) -> Option<PathBuf> {
+    edited_path(path).filter(|edited| edited.is_file())
+}
+
+// Where the edited copy of `path` goes, whether there is one or not.
+pub fn edited_path(path: &Path) -> Option<PathBuf> {
+    let stem = path.file_stem()?.to_str()?;
+    let mut filename = format!("{stem}_edited");
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        filename = format!("{filename}.{extension}");
+    }
+    Some(path.with_file_name(filename))
+}
+
+#[derive(Live, LiveHook, LiveRegister)]
+#[repr(C)]
+pub struct DrawCompare {
+    #[deref]
+    draw_super: DrawQuad,
+    #[live]
+    mode: f32,
+    #[live]
+    split: f32,
+    #[live]
+    zoom: f32,
+    #[live]
+    gain: f32,
+    #[live]
+    pan: Vec2,
+    #[live]
+    size_a: Vec2,
+    #[live]
+    size_b: Vec2,
+}
+
+#[derive(Live, LiveHook, Widget)]
+pub struct CompareView {
+    #[redraw]
+    #[live]
+    draw_compare: DrawCompare,
+    #[walk]
+    walk: Walk,
+    #[rust]
+    sources: [Option<ImageSource>; 2],
+    #[rust]
+    textures: [Option<Texture>; 2],
+    #[rust]
+    mode: CompareMode,
+    #[rust]
+    pan_start: Vec2,
+}
+
+impl Widget for CompareView {
+    fn draw_walk(
+        &mut self,
+        cx: &mut Cx2d,
+        _scope: &mut Scope,
+        walk: Walk,
+    ) -> DrawStep {
+        for (id, texture) in self.textures.iter().enumerate() {
+            let Some(texture) = texture else {
+                return DrawStep::done();
+            };
+
+            let size = texture
+                .get_format(cx)
+                .vec_width_height()
+                .map(|(w, h)| vec2(w as f32, h as f32))
+                .unwrap_or(vec2(1.0, 1.0));
+
+            if id == 0 {
+                self.draw_compare.size_a = size;
+            } else {
+                self.draw_compare.size_b = size;
+            }
+            self.draw_compare.draw_vars.set_texture(id, texture);
+        }
+
+        self.draw_compare.mode = self.mode.as_shader_value();
+        self.draw_compare.draw_walk(cx, walk);
+        DrawStep::done()
+    }
+
+    fn handle_event(&mut self, cx: &mut Cx, event: &Event, _scope: &mut Scope) {
+        match event.hits(cx, self.draw_compare.area()) {
+            Hit::FingerDown(e) if e.tap_count == 2 => {
+                self.reset_view(cx);
+            }
+            Hit::FingerDown(e) => {
+                self.pan_start = self.draw_compare.pan;
+                if self.mode == CompareMode::Wipe {
+                    self.move_split(cx, e.abs, e.rect);
+                }
+            }
+            Hit::FingerMove(e) => {
+                if self.mode == CompareMode::Wipe {
+                    self.move_split(cx, e.abs, e.rect);
+                } else {
+                    let delta = (e.abs - e.abs_start).into_vec2();
+                    self.draw_compare.pan = self.pan_start + delta;
+                    self.redraw(cx);
+                }
+            }
+            Hit::FingerScroll(e) => {
+                let factor = if e.scroll.y < 0.0 {
+                    ZOOM_STEP
+                } else {
+                    1.0 / ZOOM_STEP
+                };
+                self.draw_compare.zoom =
+                    (self.draw_compare.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+                self.redraw(cx);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl CompareView {
+    fn move_split(&mut self, cx: &mut Cx, abs: DVec2, rect: Rect) {
+        let split = (abs.x - rect.pos.x) / rect.size.x;
+        self.draw_compare.split = split.clamp(0.0, 1.0) as f32;
+        self.redraw(cx);
+    }
+
+    fn reset_view(&mut self, cx: &mut Cx) {
+        self.draw_compare.zoom = 1.0;
+        self.draw_compare.pan = Vec2::default();
+        self.draw_compare.split = 0.5;
+        self.redraw(cx);
+    }
+}
+
+impl CompareViewRef {
+    // The textures come from the app's cache, like the slideshow's, so any
+    // format it decodes can be compared. Those still decoding are `None` and
+    // handed over with `set_texture` once ready.
+    pub fn set_images(
+        &self,
+        cx: &mut Cx,
+        sources: [ImageSource; 2],
+        textures: [Option<Texture>; 2],
+    ) {
+        let Some(mut inner) = self.borrow_mut() else {
+            return;
+        };
+
+        inner.sources = sources.map(Some);
+        inner.textures = textures;
+        inner.reset_view(cx);
+    }
+
+    pub fn set_texture(
+        &self,
+        cx: &mut Cx,
+        source: &ImageSource,
+        texture: &Texture,
+    ) {
+        let Some(mut inner) = self.borrow_mut() else {
+            return;
+        };
+
+        let inner = &mut *inner;
+        for (shown, slot) in inner.sources.iter().zip(&mut inner.textures) {
+            if shown.as_ref() == Some(source) {
+                *slot = Some(texture.clone());
+            }
+        }
+        inner.redraw(cx);
+    }
+
+    pub fn sources(&self) -> [Option<ImageSource>; 2] {
+        self.borrow()
+            .map(|inner| inner.sources.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn zoom(&self) -> f32 {
+        self.borrow().map_or(1.0, |inner| inner.draw_compare.zoom)
+    }
+
+    pub fn set_mode(&self, cx: &mut Cx, mode: CompareMode) {
+        if let Some(mut inner) = self.borrow_mut() {
+            inner.mode = mode;
+            inner.redraw(cx);
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use std::path::PathBuf;
+
+const APP_NAME: &str = "image_viewer";
+const CONFIG_DIR_VAR: &str = "IMAGE_VIEWER_CONFIG_DIR";
+
+// Where the app keeps its own files. `IMAGE_VIEWER_CONFIG_DIR` overrides the
+// platform's usual location, which is handy for testing with a clean slate.
+pub fn config_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os(CONFIG_DIR_VAR) {
+        return Some(PathBuf::from(dir));
+    }
+
+    let base = if cfg!(target_os = "windows") {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        std::env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME")
+                    .map(|home| PathBuf::from(home).join(".config"))
+            })
+    };
+    base.map(|base| base.join(APP_NAME))
+}
+
+pub fn config_file(name: &str) -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join(name))
+}
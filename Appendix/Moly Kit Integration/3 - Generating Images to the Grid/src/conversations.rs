@@ -0,0 +1,205 @@
+use moly_kit::protocol::*;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config;
+use crate::source::ImageSource;
+
+const CONVERSATIONS_DIR: &str = "conversations";
+const SNIPPET_CHARS: usize = 80;
+
+// Identifies an image by its bytes, so its conversation follows it through
+// renames and moves.
+pub fn content_hash(source: &ImageSource) -> io::Result<String> {
+    let bytes = source.read()?;
+    Ok(blake3::hash(&bytes).to_hex().to_string())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Speaker {
+    User,
+    Bot,
+}
+
+// Only the text is kept. Attachments are the image itself.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StoredMessage {
+    pub from: Speaker,
+    pub text: String,
+    // The model and provider of a bot message, as in its `BotId`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bot: Option<(String, String)>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Conversation {
+    pub hash: String,
+    // Where the image was last seen.
+    pub source: ImageSource,
+    // Seconds since the Unix epoch.
+    pub updated: u64,
+    pub messages: Vec<StoredMessage>,
+}
+
+fn conversations_dir() -> Option<PathBuf> {
+    config::config_dir().map(|dir| dir.join(CONVERSATIONS_DIR))
+}
+
+fn conversation_file(hash: &str) -> Option<PathBuf> {
+    conversations_dir().map(|dir| dir.join(format!("{hash}.json")))
+}
+
+impl Conversation {
+    // Keeps the user and bot messages of a chat, skipping app errors and
+    // replies still being written.
+    pub fn new(
+        hash: String,
+        source: ImageSource,
+        messages: &[Message],
+    ) -> Self {
+        let messages = messages
+            .iter()
+            .filter(|m| !m.metadata.is_writing)
+            .filter_map(|m| {
+                let (from, bot) = match &m.from {
+                    EntityId::User => (Speaker::User, None),
+                    EntityId::Bot(id) => (
+                        Speaker::Bot,
+                        Some((id.id().to_string(), id.provider().to_string())),
+                    ),
+                    _ => return None,
+                };
+                Some(StoredMessage {
+                    from,
+                    text: m.content.text.clone(),
+                    bot,
+                })
+            })
+            .collect();
+
+        let updated = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Self {
+            hash,
+            source,
+            updated,
+            messages,
+        }
+    }
+
+    pub fn to_messages(&self) -> Vec<Message> {
+        self.messages
+            .iter()
+            .map(|m| {
+                let from = match (m.from, &m.bot) {
+                    (Speaker::Bot, Some((model, provider))) => {
+                        EntityId::Bot(BotId::new(model, provider))
+                    }
+                    _ => EntityId::User,
+                };
+                Message {
+                    from,
+                    content: MessageContent {
+                        text: m.text.clone(),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }
+            })
+            .collect()
+    }
+
+    pub fn load(hash: &str) -> Option<Self> {
+        let path = conversation_file(hash)?;
+        let json = std::fs::read_to_string(&path).ok()?;
+        serde_json::from_str(&json)
+            .inspect_err(|e| eprintln!("Error reading {path:?}: {e}"))
+            .ok()
+    }
+
+    // Every saved conversation, most recent first.
+    pub fn all() -> Vec<Self> {
+        let Some(entries) = conversations_dir().and_then(|d| d.read_dir().ok())
+        else {
+            return Vec::new();
+        };
+
+        let mut conversations: Vec<Self> = entries
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                let json = std::fs::read_to_string(path).ok()?;
+                serde_json::from_str(&json).ok()
+            })
+            .collect();
+        conversations.sort_by_key(|c| std::cmp::Reverse(c.updated));
+        conversations
+    }
+
+    // An empty conversation removes the saved one.
+    pub fn save(&self) -> io::Result<()> {
+        let path = conversation_file(&self.hash).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "no config directory")
+        })?;
+
+        if self.messages.is_empty() {
+            return match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        std::fs::write(path, json)
+    }
+
+    // A line to show for `query`, or `None` when neither the image's name
+    // nor any message contains it. An empty query shows the last message.
+    pub fn snippet(&self, query: &str) -> Option<String> {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() {
+            let last = self.messages.last()?;
+            return Some(truncate(&last.text, 0));
+        }
+
+        let found = self.messages.iter().find_map(|m| {
+            let text = m.text.to_lowercase();
+            let byte_idx = text.find(&query)?;
+            Some(truncate(&m.text, text[..byte_idx].chars().count()))
+        });
+        if found.is_some() {
+            return found;
+        }
+
+        let name = self.source.file_name().to_lowercase();
+        name.contains(&query)
+            .then(|| self.messages.first().map(|m| truncate(&m.text, 0)))
+            .flatten()
+    }
+}
+
+// A single line of `text` starting a little before the `at`th character.
+fn truncate(text: &str, at: usize) -> String {
+    let line: Vec<char> = text
+        .chars()
+        .map(|c| if c.is_whitespace() { ' ' } else { c })
+        .collect();
+    let start = at.saturating_sub(SNIPPET_CHARS / 4).min(line.len());
+    let end = (start + SNIPPET_CHARS).min(line.len());
+
+    let mut snippet: String = line[start..end].iter().collect();
+    if start > 0 {
+        snippet.insert(0, '…');
+    }
+    if end < line.len() {
+        snippet.push('…');
+    }
+    snippet
+}
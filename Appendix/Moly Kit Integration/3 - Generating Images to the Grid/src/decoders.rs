@@ -0,0 +1,1207 @@
+use image::DynamicImage;
+use makepad_widgets::*;
+use std::fmt;
+use std::io::Read;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::color;
+use crate::source::ImageSource;
+
+const HEADER_SIZE: usize = 64;
+
+// `data` always holds 8-bit display pixels. High bit depth images also keep
+// their linear RGBA values in `hdr`, which is what gets uploaded as a texture.
+#[derive(Clone)]
+pub struct DecodedImage {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u32>,
+    pub hdr: Option<Vec<f32>>,
+}
+
+fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+impl DecodedImage {
+    pub fn from_rgba8(width: usize, height: usize, rgba: &[u8]) -> Self {
+        let data = rgba
+            .chunks_exact(4)
+            .map(|p| {
+                (p[3] as u32) << 24
+                    | (p[0] as u32) << 16
+                    | (p[1] as u32) << 8
+                    | p[2] as u32
+            })
+            .collect();
+
+        Self {
+            width,
+            height,
+            data,
+            hdr: None,
+        }
+    }
+
+    pub fn from_linear_f32(
+        width: usize,
+        height: usize,
+        rgba: Vec<f32>,
+    ) -> Self {
+        let to_u8 = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+        let display: Vec<u8> = rgba
+            .chunks_exact(4)
+            .flat_map(|p| {
+                [
+                    to_u8(linear_to_srgb(p[0])),
+                    to_u8(linear_to_srgb(p[1])),
+                    to_u8(linear_to_srgb(p[2])),
+                    to_u8(p[3]),
+                ]
+            })
+            .collect();
+
+        Self {
+            hdr: Some(rgba),
+            ..Self::from_rgba8(width, height, &display)
+        }
+    }
+
+    pub fn from_srgb_f32(width: usize, height: usize, rgba: Vec<f32>) -> Self {
+        let linear = rgba
+            .chunks_exact(4)
+            .flat_map(|p| {
+                [
+                    srgb_to_linear(p[0]),
+                    srgb_to_linear(p[1]),
+                    srgb_to_linear(p[2]),
+                    p[3],
+                ]
+            })
+            .collect();
+
+        Self::from_linear_f32(width, height, linear)
+    }
+
+    pub fn from_dynamic(image: DynamicImage) -> Self {
+        let (width, height) = (image.width() as usize, image.height() as usize);
+
+        match image {
+            DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => {
+                Self::from_linear_f32(
+                    width,
+                    height,
+                    image.into_rgba32f().into_raw(),
+                )
+            }
+            DynamicImage::ImageLuma16(_)
+            | DynamicImage::ImageLumaA16(_)
+            | DynamicImage::ImageRgb16(_)
+            | DynamicImage::ImageRgba16(_) => Self::from_srgb_f32(
+                width,
+                height,
+                image.into_rgba32f().into_raw(),
+            ),
+            image => {
+                Self::from_rgba8(width, height, image.into_rgba8().as_raw())
+            }
+        }
+    }
+
+    pub fn is_hdr(&self) -> bool {
+        self.hdr.is_some()
+    }
+
+    // Linear values for high bit depth images, normalized 8-bit values
+    // otherwise.
+    pub fn sample(&self, x: usize, y: usize) -> [f32; 4] {
+        let i = y * self.width + x;
+        if let Some(hdr) = &self.hdr {
+            return [
+                hdr[i * 4],
+                hdr[i * 4 + 1],
+                hdr[i * 4 + 2],
+                hdr[i * 4 + 3],
+            ];
+        }
+
+        let p = self.data[i];
+        [p >> 16, p >> 8, p, p >> 24].map(|c| (c & 0xFF) as f32 / 255.0)
+    }
+
+    // Averages blocks of pixels to fit within `max_long_edge`.
+    pub fn downscaled(self, max_long_edge: usize) -> Self {
+        let long_edge = self.width.max(self.height);
+        if long_edge <= max_long_edge {
+            return self;
+        }
+        let scale = |n: usize| (n * max_long_edge / long_edge).max(1);
+        let (width, height) = (scale(self.width), scale(self.height));
+        // The source pixels `i` of `n` covers, out of `total`.
+        let span = |i: usize, n: usize, total: usize| {
+            let start = i * total / n;
+            start..((i + 1) * total / n).clamp(start + 1, total)
+        };
+
+        let mut rgba = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            let ys = span(y, height, self.height);
+            for x in 0..width {
+                let xs = span(x, width, self.width);
+                let mut sum = [0.0f32; 4];
+                for sy in ys.clone() {
+                    for sx in xs.clone() {
+                        let pixel = self.sample(sx, sy);
+                        for (s, v) in sum.iter_mut().zip(pixel) {
+                            *s += v;
+                        }
+                    }
+                }
+                let count = (ys.len() * xs.len()) as f32;
+                rgba.extend(sum.map(|s| s / count));
+            }
+        }
+
+        if self.is_hdr() {
+            return Self::from_linear_f32(width, height, rgba);
+        }
+        let bytes: Vec<u8> =
+            rgba.iter().map(|v| (v * 255.0).round() as u8).collect();
+        Self::from_rgba8(width, height, &bytes)
+    }
+
+    pub fn into_texture(self, cx: &mut Cx) -> Texture {
+        let format = match self.hdr {
+            Some(hdr) => TextureFormat::VecRGBAf32 {
+                width: self.width,
+                height: self.height,
+                data: Some(hdr),
+                updated: TextureUpdated::Full,
+            },
+            None => TextureFormat::VecBGRAu8_32 {
+                width: self.width,
+                height: self.height,
+                data: Some(self.data),
+                updated: TextureUpdated::Full,
+            },
+        };
+        Texture::new_with_format(cx, format)
+    }
+}
+
+pub fn is_float_texture(cx: &Cx, texture: &Texture) -> bool {
+    matches!(texture.get_format(cx), TextureFormat::VecRGBAf32 { .. })
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    Io(std::io::Error),
+    Unsupported(String),
+    Decode(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Io(e) => write!(f, "{e}"),
+            DecodeError::Unsupported(e) => write!(f, "unsupported format: {e}"),
+            DecodeError::Decode(e) => write!(f, "decoding failed: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<std::io::Error> for DecodeError {
+    fn from(e: std::io::Error) -> Self {
+        DecodeError::Io(e)
+    }
+}
+
+pub trait ImageDecoder: Send + Sync {
+    fn extensions(&self) -> &'static [&'static str];
+
+    fn mime_type(&self) -> &'static str;
+
+    fn decode(&self, bytes: &[u8]) -> Result<DecodedImage, DecodeError>;
+
+    // A cheaper, possibly lower quality decode used for thumbnails and the
+    // first paint in the slideshow.
+    fn decode_preview(
+        &self,
+        bytes: &[u8],
+    ) -> Result<DecodedImage, DecodeError> {
+        self.decode(bytes)
+    }
+
+    // Whether makepad's `Image` can load these files by path on its own.
+    fn is_native(&self) -> bool {
+        false
+    }
+
+    // Whether the file, judging by its first bytes, has more precision than
+    // makepad's own loaders keep.
+    fn is_high_bit_depth(&self, _header: &[u8]) -> bool {
+        false
+    }
+
+    // Whether `decode` develops sensor data, which is too slow to do eagerly.
+    fn is_raw(&self) -> bool {
+        false
+    }
+
+    // Vector formats have no fixed resolution, so they can be rasterized again
+    // whenever the size they are displayed at changes.
+    fn is_vector(&self) -> bool {
+        false
+    }
+
+    fn rasterize(
+        &self,
+        bytes: &[u8],
+        _max_width: usize,
+        _max_height: usize,
+    ) -> Result<DecodedImage, DecodeError> {
+        self.decode(bytes)
+    }
+
+    fn page_count(&self, _bytes: &[u8]) -> usize {
+        1
+    }
+
+    fn decode_page(
+        &self,
+        bytes: &[u8],
+        _page: usize,
+    ) -> Result<DecodedImage, DecodeError> {
+        self.decode(bytes)
+    }
+}
+
+pub struct DecoderRegistry {
+    decoders: Vec<Box<dyn ImageDecoder>>,
+}
+
+impl DecoderRegistry {
+    pub fn new() -> Self {
+        Self {
+            decoders: Vec::new(),
+        }
+    }
+
+    pub fn with_default_decoders() -> Self {
+        let mut registry = Self::new();
+        registry.register(NativeDecoder::Png);
+        registry.register(NativeDecoder::Jpeg);
+
+        #[cfg(feature = "webp")]
+        registry.register(ImageCrateDecoder {
+            extensions: &["webp"],
+            mime_type: "image/webp",
+            format: image::ImageFormat::WebP,
+        });
+        #[cfg(feature = "exr")]
+        registry.register(ImageCrateDecoder {
+            extensions: &["exr"],
+            mime_type: "image/x-exr",
+            format: image::ImageFormat::OpenExr,
+        });
+        #[cfg(feature = "hdr")]
+        registry.register(ImageCrateDecoder {
+            extensions: &["hdr"],
+            mime_type: "image/vnd.radiance",
+            format: image::ImageFormat::Hdr,
+        });
+        #[cfg(feature = "gif")]
+        registry.register(ImageCrateDecoder {
+            extensions: &["gif"],
+            mime_type: "image/gif",
+            format: image::ImageFormat::Gif,
+        });
+        #[cfg(feature = "avif")]
+        registry.register(ImageCrateDecoder {
+            extensions: &["avif"],
+            mime_type: "image/avif",
+            format: image::ImageFormat::Avif,
+        });
+        #[cfg(feature = "bmp")]
+        registry.register(ImageCrateDecoder {
+            extensions: &["bmp"],
+            mime_type: "image/bmp",
+            format: image::ImageFormat::Bmp,
+        });
+        #[cfg(feature = "tga")]
+        registry.register(ImageCrateDecoder {
+            extensions: &["tga"],
+            mime_type: "image/x-tga",
+            format: image::ImageFormat::Tga,
+        });
+        #[cfg(feature = "ico")]
+        registry.register(ImageCrateDecoder {
+            extensions: &["ico"],
+            mime_type: "image/x-icon",
+            format: image::ImageFormat::Ico,
+        });
+        #[cfg(feature = "qoi")]
+        registry.register(ImageCrateDecoder {
+            extensions: &["qoi"],
+            mime_type: "image/qoi",
+            format: image::ImageFormat::Qoi,
+        });
+        #[cfg(feature = "pnm")]
+        registry.register(ImageCrateDecoder {
+            extensions: &["pbm", "pgm", "ppm", "pnm", "pam"],
+            mime_type: "image/x-portable-anymap",
+            format: image::ImageFormat::Pnm,
+        });
+        #[cfg(feature = "tiff")]
+        registry.register(tiff_decoder::TiffDecoder);
+        #[cfg(feature = "heic")]
+        registry.register(heic_decoder::HeicDecoder);
+        #[cfg(feature = "jxl")]
+        registry.register(jxl_decoder::JxlDecoder);
+        #[cfg(feature = "raw")]
+        registry.register(raw_decoder::RawDecoder);
+        #[cfg(feature = "svg")]
+        registry.register(svg_decoder::SvgDecoder);
+        #[cfg(feature = "pdf")]
+        registry.register(pdf_decoder::PdfDecoder);
+        #[cfg(feature = "eps")]
+        registry.register(eps_decoder::EpsDecoder);
+
+        registry
+    }
+
+    pub fn register(&mut self, decoder: impl ImageDecoder + 'static) {
+        self.decoders.push(Box::new(decoder));
+    }
+
+    pub fn find(&self, path: &Path) -> Option<&dyn ImageDecoder> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        self.decoders
+            .iter()
+            .find(|d| d.extensions().contains(&extension.as_str()))
+            .map(|d| d.as_ref())
+    }
+
+    pub fn is_supported(&self, path: &Path) -> bool {
+        self.find(path).is_some()
+    }
+
+    pub fn is_native(&self, path: &Path) -> bool {
+        self.find(path).is_some_and(|d| d.is_native())
+    }
+
+    pub fn is_high_bit_depth(&self, source: &ImageSource) -> bool {
+        let Some(decoder) = self.find(&source.path()) else {
+            return false;
+        };
+
+        let header = match source {
+            ImageSource::File(path) => {
+                let mut header = Vec::with_capacity(HEADER_SIZE);
+                std::fs::File::open(path)
+                    .and_then(|f| {
+                        f.take(HEADER_SIZE as u64).read_to_end(&mut header)
+                    })
+                    .map(|_| header)
+            }
+            // Archive entries can't be read partially anyway.
+            ImageSource::ArchiveEntry { .. } => source.read(),
+        };
+        header.is_ok_and(|header| decoder.is_high_bit_depth(&header))
+    }
+
+    pub fn is_raw(&self, path: &Path) -> bool {
+        self.find(path).is_some_and(|d| d.is_raw())
+    }
+
+    pub fn is_vector(&self, path: &Path) -> bool {
+        self.find(path).is_some_and(|d| d.is_vector())
+    }
+
+    pub fn mime_type(&self, path: &Path) -> Option<&'static str> {
+        self.find(path).map(|d| d.mime_type())
+    }
+
+    pub fn decode_file(
+        &self,
+        source: &ImageSource,
+    ) -> Result<DecodedImage, DecodeError> {
+        self.decode_file_page(source, 0)
+    }
+
+    pub fn decode_file_preview(
+        &self,
+        source: &ImageSource,
+    ) -> Result<DecodedImage, DecodeError> {
+        self.decode_file_with(source, false, |d, bytes| d.decode_preview(bytes))
+    }
+
+    pub fn rasterize_file(
+        &self,
+        source: &ImageSource,
+        max_width: usize,
+        max_height: usize,
+    ) -> Result<DecodedImage, DecodeError> {
+        self.decode_file_with(source, false, |d, bytes| {
+            d.rasterize(bytes, max_width, max_height)
+        })
+    }
+
+    pub fn decode_file_page(
+        &self,
+        source: &ImageSource,
+        page: usize,
+    ) -> Result<DecodedImage, DecodeError> {
+        self.decode_file_with(source, false, |d, bytes| {
+            d.decode_page(bytes, page)
+        })
+    }
+
+    // Like `decode_file_preview`, simulating the proofing profile.
+    pub fn soft_proof_file(
+        &self,
+        source: &ImageSource,
+    ) -> Result<DecodedImage, DecodeError> {
+        self.decode_file_with(source, true, |d, bytes| d.decode_preview(bytes))
+    }
+
+    pub fn has_embedded_profile(&self, source: &ImageSource) -> bool {
+        self.is_supported(&source.path())
+            && source
+                .read()
+                .is_ok_and(|bytes| color::embedded_profile(&bytes).is_some())
+    }
+
+    fn decode_file_with(
+        &self,
+        source: &ImageSource,
+        proof: bool,
+        decode: impl FnOnce(
+            &dyn ImageDecoder,
+            &[u8],
+        ) -> Result<DecodedImage, DecodeError>,
+    ) -> Result<DecodedImage, DecodeError> {
+        let decoder = self
+            .find(&source.path())
+            .ok_or_else(|| DecodeError::Unsupported(source.to_string()))?;
+
+        let bytes = source.read()?;
+        let mut image = decode(decoder, &bytes)?;
+
+        let profile = color::embedded_profile(&bytes);
+        let managed = color::manage(&mut image, profile.as_deref(), proof);
+        match managed {
+            Ok(()) => Ok(image),
+            Err(e) if proof => Err(DecodeError::Decode(e)),
+            Err(e) => {
+                eprintln!(
+                    "Error converting {source} to the display profile: {e}"
+                );
+                Ok(image)
+            }
+        }
+    }
+
+    pub fn page_count(&self, source: &ImageSource) -> usize {
+        let Some(decoder) = self.find(&source.path()) else {
+            return 0;
+        };
+
+        match source.read() {
+            Ok(bytes) => decoder.page_count(&bytes),
+            Err(_) => 0,
+        }
+    }
+}
+
+pub fn registry() -> &'static DecoderRegistry {
+    static REGISTRY: OnceLock<DecoderRegistry> = OnceLock::new();
+    REGISTRY.get_or_init(DecoderRegistry::with_default_decoders)
+}
+
+enum NativeDecoder {
+    Png,
+    Jpeg,
+}
+
+impl ImageDecoder for NativeDecoder {
+    fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            NativeDecoder::Png => &["png"],
+            NativeDecoder::Jpeg => &["jpg", "jpeg"],
+        }
+    }
+
+    fn mime_type(&self) -> &'static str {
+        match self {
+            NativeDecoder::Png => "image/png",
+            NativeDecoder::Jpeg => "image/jpeg",
+        }
+    }
+
+    fn is_native(&self) -> bool {
+        true
+    }
+
+    // The bit depth is the first byte after the size in the IHDR chunk.
+    fn is_high_bit_depth(&self, header: &[u8]) -> bool {
+        cfg!(feature = "png16")
+            && matches!(self, NativeDecoder::Png)
+            && header.get(24) == Some(&16)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<DecodedImage, DecodeError> {
+        #[cfg(feature = "png16")]
+        if self.is_high_bit_depth(bytes) {
+            let image = image::load_from_memory_with_format(
+                bytes,
+                image::ImageFormat::Png,
+            )
+            .map_err(|e| DecodeError::Decode(e.to_string()))?;
+            return Ok(DecodedImage::from_dynamic(image));
+        }
+
+        let buffer = match self {
+            NativeDecoder::Png => ImageBuffer::from_png(bytes),
+            NativeDecoder::Jpeg => ImageBuffer::from_jpg(bytes),
+        }
+        .map_err(|e| DecodeError::Decode(format!("{e:?}")))?;
+
+        Ok(DecodedImage {
+            width: buffer.width,
+            height: buffer.height,
+            data: buffer.data,
+            hdr: None,
+        })
+    }
+}
+
+#[cfg(any(
+    feature = "webp",
+    feature = "gif",
+    feature = "avif",
+    feature = "bmp",
+    feature = "tga",
+    feature = "ico",
+    feature = "qoi",
+    feature = "pnm"
+))]
+struct ImageCrateDecoder {
+    extensions: &'static [&'static str],
+    mime_type: &'static str,
+    format: image::ImageFormat,
+}
+
+#[cfg(any(
+    feature = "webp",
+    feature = "gif",
+    feature = "avif",
+    feature = "bmp",
+    feature = "tga",
+    feature = "ico",
+    feature = "qoi",
+    feature = "pnm"
+))]
+impl ImageDecoder for ImageCrateDecoder {
+    fn extensions(&self) -> &'static [&'static str] {
+        self.extensions
+    }
+
+    fn mime_type(&self) -> &'static str {
+        self.mime_type
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<DecodedImage, DecodeError> {
+        let image = image::load_from_memory_with_format(bytes, self.format)
+            .map_err(|e| DecodeError::Decode(e.to_string()))?;
+        Ok(DecodedImage::from_dynamic(image))
+    }
+}
+
+#[cfg(any(feature = "svg", feature = "pdf"))]
+const VECTOR_PREVIEW_SIZE: usize = 512;
+
+#[cfg(feature = "tiff")]
+mod tiff_decoder {
+    use super::*;
+    use std::io::Cursor;
+    use tiff::ColorType;
+    use tiff::decoder::{Decoder, DecodingResult};
+
+    pub struct TiffDecoder;
+
+    enum SampleDepth {
+        Bits8,
+        Bits16,
+        Float,
+    }
+
+    fn decoder(bytes: &[u8]) -> Result<Decoder<Cursor<&[u8]>>, DecodeError> {
+        Decoder::new(Cursor::new(bytes))
+            .map_err(|e| DecodeError::Decode(e.to_string()))
+    }
+
+    impl ImageDecoder for TiffDecoder {
+        fn extensions(&self) -> &'static [&'static str] {
+            &["tif", "tiff"]
+        }
+
+        fn mime_type(&self) -> &'static str {
+            "image/tiff"
+        }
+
+        fn decode(&self, bytes: &[u8]) -> Result<DecodedImage, DecodeError> {
+            self.decode_page(bytes, 0)
+        }
+
+        fn page_count(&self, bytes: &[u8]) -> usize {
+            let Ok(mut decoder) = decoder(bytes) else {
+                return 0;
+            };
+
+            let mut count = 1;
+            while decoder.more_images() && decoder.next_image().is_ok() {
+                count += 1;
+            }
+            count
+        }
+
+        fn decode_page(
+            &self,
+            bytes: &[u8],
+            page: usize,
+        ) -> Result<DecodedImage, DecodeError> {
+            let error = |e: tiff::TiffError| DecodeError::Decode(e.to_string());
+            let mut decoder = decoder(bytes)?;
+            decoder.seek_to_image(page).map_err(error)?;
+
+            let (width, height) = decoder.dimensions().map_err(error)?;
+            let color_type = decoder.colortype().map_err(error)?;
+            let (samples, depth): (Vec<f32>, SampleDepth) = match decoder
+                .read_image()
+                .map_err(error)?
+            {
+                DecodingResult::U8(samples) => (
+                    samples.iter().map(|&s| s as f32 / 255.0).collect(),
+                    SampleDepth::Bits8,
+                ),
+                DecodingResult::U16(samples) => (
+                    samples.iter().map(|&s| s as f32 / 65535.0).collect(),
+                    SampleDepth::Bits16,
+                ),
+                DecodingResult::F32(samples) => (samples, SampleDepth::Float),
+                _ => {
+                    return Err(DecodeError::Unsupported(format!(
+                        "TIFF sample format {color_type:?}"
+                    )));
+                }
+            };
+
+            let rgba: Vec<f32> = match color_type {
+                ColorType::Gray(_) => {
+                    samples.iter().flat_map(|&v| [v, v, v, 1.0]).collect()
+                }
+                ColorType::GrayA(_) => samples
+                    .chunks_exact(2)
+                    .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                    .collect(),
+                ColorType::RGB(_) => samples
+                    .chunks_exact(3)
+                    .flat_map(|p| [p[0], p[1], p[2], 1.0])
+                    .collect(),
+                ColorType::RGBA(_) => samples,
+                other => {
+                    return Err(DecodeError::Unsupported(format!(
+                        "TIFF color type {other:?}"
+                    )));
+                }
+            };
+
+            let (width, height) = (width as usize, height as usize);
+            Ok(match depth {
+                SampleDepth::Bits8 => {
+                    let rgba: Vec<u8> = rgba
+                        .iter()
+                        .map(|&v| (v * 255.0).round() as u8)
+                        .collect();
+                    DecodedImage::from_rgba8(width, height, &rgba)
+                }
+                SampleDepth::Bits16 => {
+                    DecodedImage::from_srgb_f32(width, height, rgba)
+                }
+                SampleDepth::Float => {
+                    DecodedImage::from_linear_f32(width, height, rgba)
+                }
+            })
+        }
+    }
+}
+
+#[cfg(feature = "heic")]
+mod heic_decoder {
+    use super::*;
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    pub struct HeicDecoder;
+
+    impl ImageDecoder for HeicDecoder {
+        fn extensions(&self) -> &'static [&'static str] {
+            &["heic", "heif"]
+        }
+
+        fn mime_type(&self) -> &'static str {
+            "image/heic"
+        }
+
+        fn decode(&self, bytes: &[u8]) -> Result<DecodedImage, DecodeError> {
+            let error =
+                |e: libheif_rs::HeifError| DecodeError::Decode(e.to_string());
+
+            let context = HeifContext::read_from_bytes(bytes).map_err(error)?;
+            let handle = context.primary_image_handle().map_err(error)?;
+            let image = LibHeif::new()
+                .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgba), None)
+                .map_err(error)?;
+
+            let plane = image.planes().interleaved.ok_or_else(|| {
+                DecodeError::Decode("missing interleaved plane".into())
+            })?;
+
+            let (width, height) = (plane.width as usize, plane.height as usize);
+            let mut rgba = Vec::with_capacity(width * height * 4);
+            for row in plane.data.chunks(plane.stride).take(height) {
+                rgba.extend_from_slice(&row[..width * 4]);
+            }
+
+            Ok(DecodedImage::from_rgba8(width, height, &rgba))
+        }
+    }
+}
+
+#[cfg(feature = "jxl")]
+mod jxl_decoder {
+    use super::*;
+    use jxl_oxide::JxlImage;
+
+    pub struct JxlDecoder;
+
+    impl ImageDecoder for JxlDecoder {
+        fn extensions(&self) -> &'static [&'static str] {
+            &["jxl"]
+        }
+
+        fn mime_type(&self) -> &'static str {
+            "image/jxl"
+        }
+
+        fn decode(&self, bytes: &[u8]) -> Result<DecodedImage, DecodeError> {
+            let error =
+                |e: jxl_oxide::Error| DecodeError::Decode(e.to_string());
+
+            let image = JxlImage::builder().read(bytes).map_err(error)?;
+            let render = image.render_frame(0).map_err(error)?;
+            let frame = render.image_all_channels();
+
+            let channels = frame.channels();
+            let to_u8 = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+            let rgba: Vec<u8> = frame
+                .buf()
+                .chunks_exact(channels)
+                .flat_map(|p| match channels {
+                    1 => [to_u8(p[0]), to_u8(p[0]), to_u8(p[0]), 255],
+                    2 => [to_u8(p[0]), to_u8(p[0]), to_u8(p[0]), to_u8(p[1])],
+                    3 => [to_u8(p[0]), to_u8(p[1]), to_u8(p[2]), 255],
+                    _ => [to_u8(p[0]), to_u8(p[1]), to_u8(p[2]), to_u8(p[3])],
+                })
+                .collect();
+
+            Ok(DecodedImage::from_rgba8(
+                frame.width(),
+                frame.height(),
+                &rgba,
+            ))
+        }
+    }
+}
+
+#[cfg(feature = "raw")]
+mod raw_decoder {
+    use super::*;
+    use rawler::RawlerError;
+    use rawler::decoders::RawDecodeParams;
+    use rawler::imgop::develop::RawDevelop;
+    use rawler::rawsource::RawSource;
+
+    const TONE_CURVE_STRENGTH: f32 = 0.5;
+
+    pub struct RawDecoder;
+
+    fn error(e: RawlerError) -> DecodeError {
+        DecodeError::Decode(e.to_string())
+    }
+
+    // Blends towards a smoothstep S-curve for a little more contrast than
+    // the linear output of the development pipeline.
+    fn tone_curve(v: u8) -> u8 {
+        let v = v as f32 / 255.0;
+        let s = v * v * (3.0 - 2.0 * v);
+        let v = v + (s - v) * TONE_CURVE_STRENGTH;
+        (v.clamp(0.0, 1.0) * 255.0).round() as u8
+    }
+
+    impl ImageDecoder for RawDecoder {
+        fn extensions(&self) -> &'static [&'static str] {
+            &["cr3", "cr2", "nef", "arw", "raf", "dng"]
+        }
+
+        fn mime_type(&self) -> &'static str {
+            "image/x-raw"
+        }
+
+        fn is_raw(&self) -> bool {
+            true
+        }
+
+        // Most cameras embed a full size JPEG, which is much faster to show
+        // than demosaicing the sensor data.
+        fn decode_preview(
+            &self,
+            bytes: &[u8],
+        ) -> Result<DecodedImage, DecodeError> {
+            let source = RawSource::new_from_slice(bytes);
+            let decoder = rawler::get_decoder(&source).map_err(error)?;
+            let params = RawDecodeParams::default();
+
+            let Some(preview) =
+                decoder.preview_image(&source, &params).map_err(error)?
+            else {
+                return self.decode(bytes);
+            };
+
+            let image = preview.into_rgba8();
+            Ok(DecodedImage::from_rgba8(
+                image.width() as usize,
+                image.height() as usize,
+                image.as_raw(),
+            ))
+        }
+
+        // The default development steps demosaic, apply the as-shot camera
+        // white balance and convert to sRGB.
+        fn decode(&self, bytes: &[u8]) -> Result<DecodedImage, DecodeError> {
+            let source = RawSource::new_from_slice(bytes);
+            let decoder = rawler::get_decoder(&source).map_err(error)?;
+            let params = RawDecodeParams::default();
+
+            let raw =
+                decoder.raw_image(&source, &params, false).map_err(error)?;
+            let developed = RawDevelop::default()
+                .develop_intermediate(&raw)
+                .map_err(error)?
+                .to_dynamic_image()
+                .ok_or_else(|| {
+                    DecodeError::Decode("development produced no image".into())
+                })?
+                .into_rgb8();
+
+            let rgba: Vec<u8> = developed
+                .as_raw()
+                .chunks_exact(3)
+                .flat_map(|p| {
+                    [tone_curve(p[0]), tone_curve(p[1]), tone_curve(p[2]), 255]
+                })
+                .collect();
+
+            Ok(DecodedImage::from_rgba8(
+                developed.width() as usize,
+                developed.height() as usize,
+                &rgba,
+            ))
+        }
+    }
+}
+
+#[cfg(feature = "svg")]
+mod svg_decoder {
+    use super::*;
+    use resvg::tiny_skia::{Pixmap, Transform};
+    use resvg::usvg::fontdb::Database;
+    use resvg::usvg::{Options, Tree};
+    use std::sync::Arc;
+
+    pub struct SvgDecoder;
+
+    // Loading the system fonts takes a while, and every rasterization parses
+    // the document again.
+    fn fonts() -> Arc<Database> {
+        static FONTS: OnceLock<Arc<Database>> = OnceLock::new();
+        let fonts = FONTS.get_or_init(|| {
+            let mut fonts = Database::new();
+            fonts.load_system_fonts();
+            Arc::new(fonts)
+        });
+        Arc::clone(fonts)
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Tree, DecodeError> {
+        let options = Options {
+            fontdb: fonts(),
+            ..Options::default()
+        };
+
+        Tree::from_data(bytes, &options)
+            .map_err(|e| DecodeError::Decode(e.to_string()))
+    }
+
+    fn render(tree: &Tree, scale: f32) -> Result<DecodedImage, DecodeError> {
+        let size = tree.size();
+        let width = (size.width() * scale).ceil().max(1.0) as u32;
+        let height = (size.height() * scale).ceil().max(1.0) as u32;
+
+        let mut pixmap = Pixmap::new(width, height).ok_or_else(|| {
+            DecodeError::Decode(format!("invalid size {width}x{height}"))
+        })?;
+        resvg::render(
+            tree,
+            Transform::from_scale(scale, scale),
+            &mut pixmap.as_mut(),
+        );
+
+        let rgba: Vec<u8> = pixmap
+            .pixels()
+            .iter()
+            .flat_map(|p| {
+                let c = p.demultiply();
+                [c.red(), c.green(), c.blue(), c.alpha()]
+            })
+            .collect();
+
+        Ok(DecodedImage::from_rgba8(
+            width as usize,
+            height as usize,
+            &rgba,
+        ))
+    }
+
+    impl ImageDecoder for SvgDecoder {
+        fn extensions(&self) -> &'static [&'static str] {
+            &["svg", "svgz"]
+        }
+
+        fn mime_type(&self) -> &'static str {
+            "image/svg+xml"
+        }
+
+        fn is_vector(&self) -> bool {
+            true
+        }
+
+        fn decode(&self, bytes: &[u8]) -> Result<DecodedImage, DecodeError> {
+            render(&parse(bytes)?, 1.0)
+        }
+
+        fn decode_preview(
+            &self,
+            bytes: &[u8],
+        ) -> Result<DecodedImage, DecodeError> {
+            self.rasterize(bytes, VECTOR_PREVIEW_SIZE, VECTOR_PREVIEW_SIZE)
+        }
+
+        fn rasterize(
+            &self,
+            bytes: &[u8],
+            max_width: usize,
+            max_height: usize,
+        ) -> Result<DecodedImage, DecodeError> {
+            let tree = parse(bytes)?;
+            let size = tree.size();
+            let scale = (max_width as f32 / size.width())
+                .min(max_height as f32 / size.height());
+            render(&tree, scale)
+        }
+    }
+}
+
+// Renders the first page through a system installed pdfium library.
+#[cfg(feature = "pdf")]
+mod pdf_decoder {
+    use super::*;
+    use pdfium_render::prelude::*;
+
+    const DEFAULT_PAGE_SIZE: usize = 2048;
+
+    pub struct PdfDecoder;
+
+    impl ImageDecoder for PdfDecoder {
+        fn extensions(&self) -> &'static [&'static str] {
+            &["pdf"]
+        }
+
+        fn mime_type(&self) -> &'static str {
+            "application/pdf"
+        }
+
+        fn is_vector(&self) -> bool {
+            true
+        }
+
+        fn decode(&self, bytes: &[u8]) -> Result<DecodedImage, DecodeError> {
+            self.rasterize(bytes, DEFAULT_PAGE_SIZE, DEFAULT_PAGE_SIZE)
+        }
+
+        fn decode_preview(
+            &self,
+            bytes: &[u8],
+        ) -> Result<DecodedImage, DecodeError> {
+            self.rasterize(bytes, VECTOR_PREVIEW_SIZE, VECTOR_PREVIEW_SIZE)
+        }
+
+        fn rasterize(
+            &self,
+            bytes: &[u8],
+            max_width: usize,
+            max_height: usize,
+        ) -> Result<DecodedImage, DecodeError> {
+            let error = |e: PdfiumError| DecodeError::Decode(e.to_string());
+
+            let bindings = Pdfium::bind_to_system_library().map_err(error)?;
+            let pdfium = Pdfium::new(bindings);
+            let document = pdfium
+                .load_pdf_from_byte_slice(bytes, None)
+                .map_err(error)?;
+            let page = document.pages().first().map_err(error)?;
+
+            let config = PdfRenderConfig::new()
+                .set_maximum_width(max_width as i32)
+                .set_maximum_height(max_height as i32);
+            let bitmap = page.render_with_config(&config).map_err(error)?;
+
+            Ok(DecodedImage::from_rgba8(
+                bitmap.width() as usize,
+                bitmap.height() as usize,
+                &bitmap.as_rgba_bytes(),
+            ))
+        }
+    }
+}
+
+// EPS files can't be rendered without a PostScript interpreter, so only the
+// preview embedded by the authoring application is shown: either a TIFF in a
+// DOS binary header, or an EPSI hex bitmap in the comments.
+#[cfg(feature = "eps")]
+mod eps_decoder {
+    use super::*;
+
+    const DOS_MAGIC: [u8; 4] = [0xC5, 0xD0, 0xD3, 0xC6];
+
+    pub struct EpsDecoder;
+
+    fn read_u32(bytes: &[u8], offset: usize) -> Option<usize> {
+        let bytes = bytes.get(offset..offset + 4)?;
+        Some(u32::from_le_bytes(bytes.try_into().ok()?) as usize)
+    }
+
+    fn tiff_preview(bytes: &[u8]) -> Option<&[u8]> {
+        if !bytes.starts_with(&DOS_MAGIC) {
+            return None;
+        }
+
+        // After the PostScript's and the WMF preview's offset and length.
+        let start = read_u32(bytes, 20)?;
+        let length = read_u32(bytes, 24)?;
+        if length == 0 {
+            return None;
+        }
+        bytes.get(start..start.checked_add(length)?)
+    }
+
+    fn epsi_preview(bytes: &[u8]) -> Option<DecodedImage> {
+        let text = String::from_utf8_lossy(bytes);
+        let mut lines = text.lines();
+
+        let header = lines
+            .by_ref()
+            .find_map(|l| l.strip_prefix("%%BeginPreview:"))?;
+        let [width, height, depth]: [usize; 3] = header
+            .split_whitespace()
+            .take(3)
+            .map(|v| v.parse().ok())
+            .collect::<Option<Vec<_>>>()?
+            .try_into()
+            .ok()?;
+        if !matches!(depth, 1 | 2 | 4 | 8) {
+            return None;
+        }
+
+        let hex: Vec<u8> = lines
+            .take_while(|l| !l.starts_with("%%EndPreview"))
+            .flat_map(|l| l.trim_start_matches('%').split_whitespace())
+            .flat_map(str::bytes)
+            .collect();
+        // Anything else, including what lossy decoding replaced, is a
+        // broken preview.
+        if !hex.iter().all(u8::is_ascii_hexdigit) {
+            return None;
+        }
+        let digit = |c: u8| (c as char).to_digit(16).unwrap_or(0) as u8;
+        let data: Vec<u8> = hex
+            .chunks_exact(2)
+            .map(|pair| digit(pair[0]) << 4 | digit(pair[1]))
+            .collect();
+
+        let row_bytes = width.checked_mul(depth)?.div_ceil(8);
+        if data.len() < row_bytes.checked_mul(height)? {
+            return None;
+        }
+        let max = (1 << depth) - 1;
+        let mut rgba = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            let row = data.get(y * row_bytes..(y + 1) * row_bytes)?;
+            for x in 0..width {
+                let bit = x * depth;
+                let shift = 8 - depth - bit % 8;
+                let value = (row[bit / 8] >> shift) as usize & max;
+                // EPSI stores 0 as white.
+                let v = (255 - value * 255 / max) as u8;
+                rgba.extend_from_slice(&[v, v, v, 255]);
+            }
+        }
+
+        Some(DecodedImage::from_rgba8(width, height, &rgba))
+    }
+
+    impl ImageDecoder for EpsDecoder {
+        fn extensions(&self) -> &'static [&'static str] {
+            &["eps", "epsi"]
+        }
+
+        fn mime_type(&self) -> &'static str {
+            "application/postscript"
+        }
+
+        fn decode(&self, bytes: &[u8]) -> Result<DecodedImage, DecodeError> {
+            if let Some(tiff) = tiff_preview(bytes) {
+                return tiff_decoder::TiffDecoder.decode(tiff);
+            }
+
+            epsi_preview(bytes).ok_or_else(|| {
+                DecodeError::Unsupported("EPS without a preview".into())
+            })
+        }
+    }
+}
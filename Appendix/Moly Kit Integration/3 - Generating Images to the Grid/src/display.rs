@@ -0,0 +1,579 @@
+use makepad_widgets::*;
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::theme::parse_color;
+
+const DISPLAY_FILE: &str = "display.toml";
+
+live_design! {
+    use link::widgets::*;
+    use link::shaders::*;
+
+    pub DisplayImage = <Image> {
+        draw_bg: {
+            instance background: 0.0,
+            instance background_color: #808080,
+            instance channel: 0.0,
+            instance colormap: 0.0,
+            instance remap_min: 0.0,
+            instance remap_max: 1.0,
+            instance hdr: 0.0,
+            instance exposure: 0.0,
+            instance tone_mapper: 0.0,
+
+            fn reinhard(color: vec3) -> vec3 {
+                return color / (vec3(1.0) + color);
+            }
+
+            // Narkowicz's fit of the ACES reference rendering transform.
+            fn aces_filmic(color: vec3) -> vec3 {
+                let x = color * 0.6;
+                let mapped = (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14);
+                return clamp(mapped, vec3(0.0), vec3(1.0));
+            }
+
+            fn agx_contrast(x: vec3) -> vec3 {
+                let x2 = x * x;
+                let x4 = x2 * x2;
+                return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4
+                    - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+            }
+
+            // Minimal AgX with the usual polynomial contrast approximation.
+            fn agx(color: vec3) -> vec3 {
+                let min_ev = -12.47393;
+                let max_ev = 4.026069;
+
+                let inset = vec3(
+                    dot(color, vec3(0.842479062253094, 0.0784335999999992, 0.0792237451477643)),
+                    dot(color, vec3(0.0423282422610123, 0.878468636469772, 0.0791661274605434)),
+                    dot(color, vec3(0.0423756549057051, 0.0784336, 0.879142973793104))
+                );
+                let encoded = clamp(log2(max(inset, vec3(0.0000000001))), vec3(min_ev), vec3(max_ev));
+                let curve = agx_contrast((encoded - min_ev) / (max_ev - min_ev));
+                let outset = vec3(
+                    dot(curve, vec3(1.19687900512017, -0.0980208811401368, -0.0990297440797205)),
+                    dot(curve, vec3(-0.0528968517574562, 1.15190312990417, -0.0989611768448433)),
+                    dot(curve, vec3(-0.0529716355144438, -0.0980434501171241, 1.15107367264116))
+                );
+                return pow(max(outset, vec3(0.0)), vec3(2.2));
+            }
+
+            fn tone_map(self, color: vec4) -> vec4 {
+                if self.hdr < 0.5 && self.exposure == 0.0 && self.tone_mapper < 0.5 {
+                    return color;
+                }
+
+                let linear = color.rgb;
+                if self.hdr < 0.5 {
+                    linear = pow(linear, vec3(2.2));
+                }
+                linear = max(linear * exp2(self.exposure), vec3(0.0));
+
+                let mapped = clamp(linear, vec3(0.0), vec3(1.0));
+                if self.tone_mapper > 2.5 {
+                    mapped = agx(linear);
+                } else if self.tone_mapper > 1.5 {
+                    mapped = aces_filmic(linear);
+                } else if self.tone_mapper > 0.5 {
+                    mapped = reinhard(linear);
+                }
+                return vec4(pow(mapped, vec3(1.0 / 2.2)), color.a);
+            }
+
+            fn viridis(t: float) -> vec3 {
+                let c0 = vec3(0.2777273272234177, 0.005407344544966578, 0.3340998053353061);
+                let c1 = vec3(0.1050930431085774, 1.404613529898575, 1.384590162594685);
+                let c2 = vec3(-0.3308618287255563, 0.214847559468213, 0.09509516302823659);
+                let c3 = vec3(-4.634230498983486, -5.799100973351585, -19.33244095627987);
+                let c4 = vec3(6.228269936347081, 14.17993336680509, 56.69055260068105);
+                let c5 = vec3(4.776384997670288, -13.74514537774601, -65.35303263337234);
+                let c6 = vec3(-5.435455855934631, 4.645852612178535, 26.3124352495832);
+                return c0 + t * (c1 + t * (c2 + t * (c3 + t * (c4 + t * (c5 + t * c6)))));
+            }
+
+            fn magma(t: float) -> vec3 {
+                let c0 = vec3(-0.002136485053939582, -0.000749655052795221, -0.005386127855323933);
+                let c1 = vec3(0.2516605407371642, 0.6775232436837668, 2.494026599312351);
+                let c2 = vec3(8.353717279216625, -3.577719514958484, 0.3144679030132573);
+                let c3 = vec3(-27.66873308576866, 14.26473078096533, -13.64921318813922);
+                let c4 = vec3(52.17613981234068, -27.94360607168351, 12.94416944238394);
+                let c5 = vec3(-50.76852536473588, 29.04658282127291, 4.23415299384598);
+                let c6 = vec3(18.65570506591883, -11.48977351997711, -5.601961508734096);
+                return c0 + t * (c1 + t * (c2 + t * (c3 + t * (c4 + t * (c5 + t * c6)))));
+            }
+
+            fn false_color(luma: float) -> vec3 {
+                if luma >= 0.97 {
+                    return vec3(1.0, 0.0, 0.0);
+                }
+                if luma >= 0.9 {
+                    return vec3(1.0, 1.0, 0.0);
+                }
+                if luma >= 0.6 && luma < 0.65 {
+                    return vec3(1.0, 0.6, 0.7);
+                }
+                if luma >= 0.42 && luma < 0.52 {
+                    return vec3(0.2, 0.8, 0.2);
+                }
+                if luma < 0.025 {
+                    return vec3(0.5, 0.0, 0.5);
+                }
+                if luma < 0.1 {
+                    return vec3(0.0, 0.0, 1.0);
+                }
+                if luma < 0.2 {
+                    return vec3(0.0, 0.6, 0.7);
+                }
+                return vec3(luma);
+            }
+
+            fn isolate(self, color: vec4) -> vec4 {
+                if self.channel < 0.5 {
+                    return color;
+                }
+
+                let luma = dot(color.rgb, vec3(0.2126, 0.7152, 0.0722));
+                if self.channel > 5.5 {
+                    return vec4(false_color(luma), color.a);
+                }
+
+                let alpha = color.a;
+                let value = luma;
+                if self.channel < 1.5 {
+                    value = color.r;
+                } else if self.channel < 2.5 {
+                    value = color.g;
+                } else if self.channel < 3.5 {
+                    value = color.b;
+                } else if self.channel < 4.5 {
+                    value = color.a;
+                    alpha = 1.0;
+                }
+
+                let range = max(self.remap_max - self.remap_min, 0.0001);
+                value = clamp((value - self.remap_min) / range, 0.0, 1.0);
+
+                if self.colormap > 1.5 {
+                    return vec4(magma(value), alpha);
+                }
+                if self.colormap > 0.5 {
+                    return vec4(viridis(value), alpha);
+                }
+                return vec4(vec3(value), alpha);
+            }
+
+            fn checkerboard(self) -> vec3 {
+                let cell = floor(self.pos * self.rect_size / 8.0);
+                let odd = mod(cell.x + cell.y, 2.0);
+                return mix(vec3(0.8), vec3(0.6), odd);
+            }
+
+            fn composite(self, color: vec4) -> vec4 {
+                if self.background > 3.5 {
+                    return vec4(vec3(color.a), 1.0);
+                }
+
+                let background = self.background_color.rgb;
+                if self.background < 0.5 {
+                    background = self.checkerboard();
+                } else if self.background < 1.5 {
+                    background = vec3(0.0);
+                } else if self.background < 2.5 {
+                    background = vec3(1.0);
+                }
+                return vec4(mix(background, color.rgb, color.a), 1.0);
+            }
+
+            fn analyze(self, color: vec4) -> vec4 {
+                return color;
+            }
+
+            fn pixel(self) -> vec4 {
+                let color = self.get_color();
+                color = self.composite(self.analyze(self.isolate(self.tone_map(color))));
+                return Pal::premul(vec4(color.xyz, color.w * self.opacity));
+            }
+        }
+    }
+}
+
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Background {
+    #[default]
+    Checkerboard,
+    Black,
+    White,
+    // `DisplaySettings::custom_background`.
+    Custom,
+    AlphaOnly,
+}
+
+impl Background {
+    fn as_shader_value(self) -> f64 {
+        match self {
+            Background::Checkerboard => 0.0,
+            Background::Black => 1.0,
+            Background::White => 2.0,
+            Background::Custom => 3.0,
+            Background::AlphaOnly => 4.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChannelView {
+    #[default]
+    Color,
+    Red,
+    Green,
+    Blue,
+    Alpha,
+    Luminance,
+    FalseColor,
+}
+
+impl ChannelView {
+    fn as_shader_value(self) -> f64 {
+        match self {
+            ChannelView::Color => 0.0,
+            ChannelView::Red => 1.0,
+            ChannelView::Green => 2.0,
+            ChannelView::Blue => 3.0,
+            ChannelView::Alpha => 4.0,
+            ChannelView::Luminance => 5.0,
+            ChannelView::FalseColor => 6.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Colormap {
+    #[default]
+    Grayscale,
+    Viridis,
+    Magma,
+}
+
+impl Colormap {
+    fn as_shader_value(self) -> f64 {
+        match self {
+            Colormap::Grayscale => 0.0,
+            Colormap::Viridis => 1.0,
+            Colormap::Magma => 2.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ToneMapper {
+    #[default]
+    Clip,
+    Reinhard,
+    AcesFilmic,
+    AgX,
+}
+
+impl ToneMapper {
+    pub const ALL: [ToneMapper; 4] = [
+        ToneMapper::Clip,
+        ToneMapper::Reinhard,
+        ToneMapper::AcesFilmic,
+        ToneMapper::AgX,
+    ];
+
+    fn as_shader_value(self) -> f64 {
+        match self {
+            ToneMapper::Clip => 0.0,
+            ToneMapper::Reinhard => 1.0,
+            ToneMapper::AcesFilmic => 2.0,
+            ToneMapper::AgX => 3.0,
+        }
+    }
+}
+
+const REMAP_STEP: f32 = 0.05;
+
+// Polynomial fits of the colormaps, as in the shader.
+const VIRIDIS: [[f32; 3]; 7] = [
+    [0.277_727_33, 0.005_407_344_5, 0.334_099_8],
+    [0.105_093_04, 1.404_613_5, 1.384_590_2],
+    [-0.330_861_83, 0.214_847_56, 0.095_095_16],
+    [-4.634_230_5, -5.799_101, -19.332_441],
+    [6.228_27, 14.179_933, 56.690_55],
+    [4.776_385, -13.745_145, -65.353_03],
+    [-5.435_456, 4.645_852_6, 26.312_435],
+];
+const MAGMA: [[f32; 3]; 7] = [
+    [-0.002_136_485, -0.000_749_655, -0.005_386_128],
+    [0.251_660_54, 0.677_523_2, 2.494_026_6],
+    [8.353_717, -3.577_719_5, 0.314_467_9],
+    [-27.668_733, 14.264_731, -13.649_213],
+    [52.176_14, -27.943_607, 12.944_169],
+    [-50.768_524, 29.046_583, 4.234_153],
+    [18.655_705, -11.489_774, -5.601_961_5],
+];
+
+#[derive(Clone, Debug)]
+pub struct DisplaySettings {
+    pub background: Background,
+    pub custom_background: Vec4,
+    pub channel: ChannelView,
+    pub colormap: Colormap,
+    pub remap_min: f32,
+    pub remap_max: f32,
+    pub exposure: f32,
+    pub tone_mapper: ToneMapper,
+    // Whether the displayed texture holds linear float values.
+    pub hdr: bool,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        Self {
+            background: Background::default(),
+            custom_background: vec4(0.5, 0.5, 0.5, 1.0),
+            channel: ChannelView::default(),
+            colormap: Colormap::default(),
+            remap_min: 0.0,
+            remap_max: 1.0,
+            exposure: 0.0,
+            tone_mapper: ToneMapper::default(),
+            hdr: false,
+        }
+    }
+}
+
+impl DisplaySettings {
+    // The defaults, with the custom background from `display.toml`, e.g.
+    //
+    //     custom_background = "#202020"
+    pub fn load() -> Self {
+        #[derive(Default, Deserialize)]
+        #[serde(default)]
+        struct DisplayFile {
+            custom_background: Option<String>,
+        }
+
+        let mut settings = Self::default();
+        let Some(path) = config::config_file(DISPLAY_FILE) else {
+            return settings;
+        };
+        let Ok(text) = std::fs::read_to_string(&path) else {
+            return settings;
+        };
+        let file: DisplayFile = toml::from_str(&text)
+            .inspect_err(|e| eprintln!("Error reading {path:?}: {e}"))
+            .unwrap_or_default();
+        if let Some(color) = file.custom_background {
+            match parse_color(&color) {
+                Some(color) => settings.custom_background = color,
+                None => eprintln!("Error reading {path:?}: invalid {color}"),
+            }
+        }
+        settings
+    }
+
+    pub fn cycle_background(&mut self) {
+        self.background = match self.background {
+            Background::Checkerboard => Background::Black,
+            Background::Black => Background::White,
+            Background::White => Background::Custom,
+            Background::Custom => Background::AlphaOnly,
+            Background::AlphaOnly => Background::Checkerboard,
+        };
+    }
+
+    pub fn toggle_channel(&mut self, channel: ChannelView) {
+        if self.channel == channel {
+            self.channel = ChannelView::Color;
+        } else {
+            self.channel = channel;
+        }
+    }
+
+    pub fn cycle_colormap(&mut self) {
+        self.colormap = match self.colormap {
+            Colormap::Grayscale => Colormap::Viridis,
+            Colormap::Viridis => Colormap::Magma,
+            Colormap::Magma => Colormap::Grayscale,
+        };
+    }
+
+    pub fn adjust_remap(&mut self, min_delta: i32, max_delta: i32) {
+        let min = self.remap_min + min_delta as f32 * REMAP_STEP;
+        let max = self.remap_max + max_delta as f32 * REMAP_STEP;
+        self.remap_min = min.clamp(0.0, self.remap_max - REMAP_STEP);
+        self.remap_max = max.clamp(self.remap_min + REMAP_STEP, 1.0);
+    }
+
+    // The color the shader shows for a pixel, so the scopes measure what's
+    // on screen rather than the file.
+    pub fn displayed(&self, color: [f32; 4]) -> [f32; 4] {
+        self.isolate(self.tone_map(color))
+    }
+
+    fn tone_map(&self, color: [f32; 4]) -> [f32; 4] {
+        if !self.hdr
+            && self.exposure == 0.0
+            && self.tone_mapper == ToneMapper::Clip
+        {
+            return color;
+        }
+
+        let [r, g, b, a] = color;
+        let gain = self.exposure.exp2();
+        let linear = [r, g, b].map(|c| {
+            let c = if self.hdr { c } else { c.max(0.0).powf(2.2) };
+            (c * gain).max(0.0)
+        });
+        let mapped = match self.tone_mapper {
+            ToneMapper::Clip => linear.map(|c| c.clamp(0.0, 1.0)),
+            ToneMapper::Reinhard => linear.map(|c| c / (1.0 + c)),
+            ToneMapper::AcesFilmic => linear.map(|c| {
+                let x = c * 0.6;
+                let mapped =
+                    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14);
+                mapped.clamp(0.0, 1.0)
+            }),
+            ToneMapper::AgX => agx(linear),
+        };
+        let [r, g, b] = mapped.map(|c| c.powf(1.0 / 2.2));
+        [r, g, b, a]
+    }
+
+    fn isolate(&self, color: [f32; 4]) -> [f32; 4] {
+        let [r, g, b, a] = color;
+        let luma = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        let (value, alpha) = match self.channel {
+            ChannelView::Color => return color,
+            ChannelView::FalseColor => {
+                let [r, g, b] = false_color(luma);
+                return [r, g, b, a];
+            }
+            ChannelView::Red => (r, a),
+            ChannelView::Green => (g, a),
+            ChannelView::Blue => (b, a),
+            ChannelView::Alpha => (a, 1.0),
+            ChannelView::Luminance => (luma, a),
+        };
+
+        let range = (self.remap_max - self.remap_min).max(0.0001);
+        let value = ((value - self.remap_min) / range).clamp(0.0, 1.0);
+        let [r, g, b] = match self.colormap {
+            Colormap::Grayscale => [value; 3],
+            Colormap::Viridis => polynomial(&VIRIDIS, value),
+            Colormap::Magma => polynomial(&MAGMA, value),
+        };
+        [r, g, b, alpha]
+    }
+
+    pub fn apply_channels(&self, cx: &mut Cx, image: &ImageRef) {
+        let channel = self.channel.as_shader_value();
+        let colormap = self.colormap.as_shader_value();
+        let remap_min = self.remap_min as f64;
+        let remap_max = self.remap_max as f64;
+
+        image.apply_over(
+            cx,
+            live! {
+                draw_bg: {
+                    channel: (channel),
+                    colormap: (colormap),
+                    remap_min: (remap_min),
+                    remap_max: (remap_max),
+                }
+            },
+        );
+    }
+
+    pub fn apply_tone_mapping(&self, cx: &mut Cx, image: &ImageRef) {
+        let hdr = self.hdr as u8 as f64;
+        let exposure = self.exposure as f64;
+        let tone_mapper = self.tone_mapper.as_shader_value();
+
+        image.apply_over(
+            cx,
+            live! {
+                draw_bg: {
+                    hdr: (hdr),
+                    exposure: (exposure),
+                    tone_mapper: (tone_mapper),
+                }
+            },
+        );
+    }
+
+    pub fn apply_background(&self, cx: &mut Cx, image: &ImageRef) {
+        let background = self.background.as_shader_value();
+        let background_color = self.custom_background;
+
+        image.apply_over(
+            cx,
+            live! {
+                draw_bg: {
+                    background: (background),
+                    background_color: (background_color),
+                }
+            },
+        );
+    }
+}
+
+fn polynomial(coefficients: &[[f32; 3]; 7], t: f32) -> [f32; 3] {
+    coefficients
+        .iter()
+        .rev()
+        .fold([0.0; 3], |acc, c| [0, 1, 2].map(|i| c[i] + t * acc[i]))
+}
+
+fn false_color(luma: f32) -> [f32; 3] {
+    match luma {
+        l if l >= 0.97 => [1.0, 0.0, 0.0],
+        l if l >= 0.9 => [1.0, 1.0, 0.0],
+        l if (0.6..0.65).contains(&l) => [1.0, 0.6, 0.7],
+        l if (0.42..0.52).contains(&l) => [0.2, 0.8, 0.2],
+        l if l < 0.025 => [0.5, 0.0, 0.5],
+        l if l < 0.1 => [0.0, 0.0, 1.0],
+        l if l < 0.2 => [0.0, 0.6, 0.7],
+        l => [l; 3],
+    }
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn agx(color: [f32; 3]) -> [f32; 3] {
+    const MIN_EV: f32 = -12.473_93;
+    const MAX_EV: f32 = 4.026_069;
+    const INSET: [[f32; 3]; 3] = [
+        [0.842_479_06, 0.078_433_6, 0.079_223_745],
+        [0.042_328_242, 0.878_468_6, 0.079_166_13],
+        [0.042_375_655, 0.078_433_6, 0.879_142_97],
+    ];
+    const OUTSET: [[f32; 3]; 3] = [
+        [1.196_879, -0.098_020_88, -0.099_029_744],
+        [-0.052_896_852, 1.151_903_1, -0.098_961_18],
+        [-0.052_971_635, -0.098_043_45, 1.151_073_7],
+    ];
+
+    let curve = INSET.map(|row| {
+        let encoded = dot(color, row).max(1e-10).log2().clamp(MIN_EV, MAX_EV);
+        agx_contrast((encoded - MIN_EV) / (MAX_EV - MIN_EV))
+    });
+    OUTSET.map(|row| dot(curve, row).max(0.0).powf(2.2))
+}
+
+fn agx_contrast(x: f32) -> f32 {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x
+        + 0.4298 * x2
+        + 0.1191 * x
+        - 0.00232
+}
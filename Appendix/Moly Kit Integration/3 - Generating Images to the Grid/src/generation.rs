@@ -0,0 +1,113 @@
+use moly_kit::ChatTask;
+use moly_kit::protocol::*;
+use moly_kit::utils::asynchronous::spawn;
+use std::path::{Path, PathBuf};
+
+use crate::source::ImageSource;
+
+// Writes an image returned by the image model into `dir`, named after the
+// time it arrived.
+pub async fn save_generated_image(
+    attachment: Attachment,
+    dir: &Path,
+) -> Result<PathBuf, String> {
+    let bytes = attachment
+        .read()
+        .await
+        .map_err(|e| format!("Error reading image generation: {e}"))?;
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    let path = dir.join(format!("generated_image_{now}.png"));
+
+    std::fs::write(&path, &bytes).map_err(|e| {
+        format!("Error saving generated image to {path:?}: {e}")
+    })?;
+    Ok(path)
+}
+
+// What the image chat does with one of its tasks.
+pub enum GenerationStep {
+    Perform(ChatTask),
+    // The reply carries an image: save it to the grid and clear the chat.
+    Save(Attachment),
+}
+
+// The image chat keeps only the last prompt and reply, newest on top, and
+// hands generated images to the grid instead of keeping them as messages.
+pub fn generation_step(task: ChatTask) -> Option<GenerationStep> {
+    match task {
+        ChatTask::Send | ChatTask::ClearPrompt => {
+            Some(GenerationStep::Perform(task))
+        }
+        ChatTask::InsertMessage(_, message) => {
+            let index = match message.from {
+                EntityId::User => 0,
+                EntityId::Bot(_) => 1,
+                _ => return None,
+            };
+            Some(GenerationStep::Perform(ChatTask::InsertMessage(
+                index, message,
+            )))
+        }
+        ChatTask::UpdateMessage(_, message) => {
+            if !message.metadata.is_writing {
+                return None;
+            }
+            let attachment = message.content.attachments.into_iter().next();
+            attachment.map(GenerationStep::Save)
+        }
+        _ => None,
+    }
+}
+
+// What the image chat's hook does with the tasks it held back: `perform`
+// runs the ones the chat keeps, and each generated image is saved into
+// `dir` in the background, then handed to `saved`. Returns whether an image
+// came, after which the chat is cleared.
+pub fn run_generation_tasks(
+    tasks: Vec<ChatTask>,
+    dir: &Path,
+    mut perform: impl FnMut(ChatTask),
+    saved: impl Fn(ImageSource) + Clone + Send + 'static,
+) -> bool {
+    let mut generated = false;
+    for step in tasks.into_iter().filter_map(generation_step) {
+        let attachment = match step {
+            GenerationStep::Perform(task) => {
+                perform(task);
+                continue;
+            }
+            GenerationStep::Save(attachment) => attachment,
+        };
+
+        generated = true;
+        let (dir, saved) = (dir.to_path_buf(), saved.clone());
+        spawn(async move {
+            match save_generated_image(attachment, &dir).await {
+                Ok(path) => {
+                    // Stdout may carry MCP.
+                    eprintln!("Saved generated image to {path:?}");
+                    saved(ImageSource::File(path));
+                }
+                Err(e) => eprintln!("{e}"),
+            }
+        });
+    }
+    generated
+}
+
+// Generated images go at the end of the grid and, while it shows a ranking
+// or filter, of the folder order it goes back to, so undoing that keeps them.
+pub fn add_to_grid(
+    source: ImageSource,
+    shown: &mut Vec<ImageSource>,
+    unranked: Option<&mut Vec<ImageSource>>,
+) {
+    if let Some(unranked) = unranked {
+        unranked.push(source.clone());
+    }
+    shown.push(source);
+}
@@ -0,0 +1,203 @@
+use makepad_widgets::*;
+
+use crate::conversations::Conversation;
+use crate::source::ImageSource;
+
+live_design! {
+    use link::widgets::*;
+
+    ConversationItem = <View> {
+        width: Fill,
+        height: Fit,
+        flow: Down,
+        spacing: 2,
+        padding: {
+            left: 10,
+            right: 10,
+            top: 6,
+            bottom: 6,
+        },
+        cursor: Hand,
+
+        <View> {
+            width: Fill,
+            height: Fit,
+
+            name = <Label> {
+                text: "",
+            }
+            <Filler> {}
+            count = <Label> {
+                text: "",
+                draw_text: {
+                    color: #aaa,
+                },
+            }
+        }
+        snippet = <Label> {
+            width: Fill,
+            text: "",
+            draw_text: {
+                color: #aaa,
+            },
+        }
+    }
+
+    pub ConversationHistory = {{ConversationHistory}} {
+        flow: Down,
+        spacing: 10,
+        padding: 20,
+
+        <View> {
+            height: Fit,
+            align: {
+                y: 0.5,
+            },
+            spacing: 10,
+
+            back_button = <Button> {
+                text: "Back",
+            }
+            <Label> {
+                text: "Conversations",
+            }
+        }
+
+        query = <TextInput> {
+            width: Fill,
+            empty_text: "Search conversations...",
+        }
+        status = <Label> {
+            width: Fill,
+            text: "",
+        }
+        results = <PortalList> {
+            flow: Down,
+
+            Item = <ConversationItem> {}
+        }
+    }
+}
+
+#[derive(Clone, Debug, DefaultNone)]
+pub enum ConversationHistoryAction {
+    Open(ImageSource),
+    None,
+}
+
+#[derive(Live, LiveHook, Widget)]
+pub struct ConversationHistory {
+    #[deref]
+    view: View,
+    #[rust]
+    conversations: Vec<Conversation>,
+    // Indices into `conversations` with the line to show for each.
+    #[rust]
+    matches: Vec<(usize, String)>,
+}
+
+impl Widget for ConversationHistory {
+    fn draw_walk(
+        &mut self,
+        cx: &mut Cx2d,
+        scope: &mut Scope,
+        walk: Walk,
+    ) -> DrawStep {
+        while let Some(item) = self.view.draw_walk(cx, scope, walk).step() {
+            let Some(mut list) = item.as_portal_list().borrow_mut() else {
+                continue;
+            };
+            list.set_item_range(cx, 0, self.matches.len());
+
+            while let Some(item_idx) = list.next_visible_item(cx) {
+                let Some((conversation_idx, snippet)) =
+                    self.matches.get(item_idx)
+                else {
+                    continue;
+                };
+                let conversation = &self.conversations[*conversation_idx];
+
+                let item = list.item(cx, item_idx, live_id!(Item));
+                item.label(id!(name))
+                    .set_text(cx, &conversation.source.file_name());
+                let count = conversation.messages.len();
+                item.label(id!(count))
+                    .set_text(cx, &format!("{count} messages"));
+                item.label(id!(snippet)).set_text(cx, snippet);
+
+                item.draw_all(cx, &mut Scope::empty());
+            }
+        }
+        DrawStep::done()
+    }
+
+    fn handle_event(&mut self, cx: &mut Cx, event: &Event, scope: &mut Scope) {
+        let actions =
+            cx.capture_actions(|cx| self.view.handle_event(cx, event, scope));
+
+        if let Some(query) = self.view.text_input(id!(query)).changed(&actions)
+        {
+            self.filter(cx, &query);
+            self.redraw(cx);
+        }
+
+        let results = self.view.portal_list(id!(results));
+        for (item_idx, item) in results.items_with_actions(&actions) {
+            let clicked = item
+                .as_view()
+                .finger_up(&actions)
+                .is_some_and(|e| e.is_over);
+            let Some((conversation_idx, _)) = self.matches.get(item_idx) else {
+                continue;
+            };
+            if clicked {
+                let source =
+                    self.conversations[*conversation_idx].source.clone();
+                cx.widget_action(
+                    self.widget_uid(),
+                    &scope.path,
+                    ConversationHistoryAction::Open(source),
+                );
+            }
+        }
+    }
+}
+
+impl ConversationHistory {
+    fn filter(&mut self, cx: &mut Cx, query: &str) {
+        self.matches = self
+            .conversations
+            .iter()
+            .enumerate()
+            .filter_map(|(i, c)| c.snippet(query).map(|snippet| (i, snippet)))
+            .collect();
+
+        let status = if self.conversations.is_empty() {
+            "No saved conversations yet".to_string()
+        } else {
+            format!("{} of {}", self.matches.len(), self.conversations.len())
+        };
+        self.view.label(id!(status)).set_text(cx, &status);
+    }
+}
+
+impl ConversationHistoryRef {
+    // Reads the saved conversations again, keeping the search.
+    pub fn reload(&self, cx: &mut Cx) {
+        let Some(mut inner) = self.borrow_mut() else {
+            return;
+        };
+
+        inner.conversations = Conversation::all();
+        let query = inner.view.text_input(id!(query)).text();
+        inner.filter(cx, &query);
+        inner.redraw(cx);
+    }
+
+    pub fn set_status(&self, cx: &mut Cx, status: &str) {
+        let Some(inner) = self.borrow() else {
+            return;
+        };
+        inner.view.label(id!(status)).set_text(cx, status);
+    }
+}
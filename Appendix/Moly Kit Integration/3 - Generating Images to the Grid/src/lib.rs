@@ -1,2 +1,36 @@
+mod animation;
 pub mod app;
-mod slideshow_client;
+mod archive;
+pub mod args;
+pub mod attachments;
+pub mod batch;
+pub mod captions;
+mod color;
+pub mod commands;
+mod compare;
+mod config;
+mod conversations;
+mod decoders;
+mod display;
+pub mod generation;
+mod history;
+mod loupe;
+pub mod mcp;
+#[cfg(feature = "test-util")]
+pub mod mock;
+#[cfg(feature = "test-util")]
+pub mod mock_server;
+mod palette;
+mod providers;
+mod review;
+mod scopes;
+pub mod search;
+mod session;
+pub mod slideshow_client;
+pub mod source;
+pub mod tagging;
+mod textures;
+mod theme;
+pub mod tools;
+mod vision;
+mod xmp;
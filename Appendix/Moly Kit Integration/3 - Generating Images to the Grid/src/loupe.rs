@@ -0,0 +1,307 @@
+use makepad_widgets::*;
+use std::sync::Arc;
+
+use crate::decoders::DecodedImage;
+
+const MAGNIFIER_SIZE: f64 = 165.0;
+const CURSOR_OFFSET: f64 = 20.0;
+const PANEL_HEIGHT: f64 = 100.0;
+const LINE_HEIGHT: f64 = 15.0;
+
+live_design! {
+    use link::widgets::*;
+    use link::shaders::*;
+
+    DrawMagnifier = {{DrawMagnifier}} {
+        texture image: texture2d
+
+        fn pixel(self) -> vec4 {
+            let p = self.center + (self.pos - 0.5) * self.span;
+            let texel = floor(p);
+
+            if texel.x < 0.0 || texel.y < 0.0
+                || texel.x >= self.image_size.x || texel.y >= self.image_size.y {
+                return vec4(0.1, 0.1, 0.1, 1.0);
+            }
+
+            let color = sample2d(self.image, (texel + 0.5) / self.image_size);
+            let cell_size = self.rect_size.x / self.span;
+            let edge = min(fract(p.x), fract(p.y)) * cell_size;
+
+            if texel.x == floor(self.center.x) && texel.y == floor(self.center.y) {
+                let inner = fract(p) * cell_size;
+                let outer = (1.0 - fract(p)) * cell_size;
+                if min(min(inner.x, inner.y), min(outer.x, outer.y)) < 2.0 {
+                    return vec4(1.0, 0.2, 0.2, 1.0);
+                }
+            }
+
+            if edge < 1.0 {
+                return vec4(0.0, 0.0, 0.0, 1.0);
+            }
+            return vec4(color.rgb * color.a, 1.0);
+        }
+    }
+
+    pub Loupe = {{Loupe}} {
+        width: Fill,
+        height: Fill,
+        draw_bg: {
+            fn pixel(self) -> vec4 {
+                return vec4(0.0);
+            }
+        }
+        draw_magnifier: {
+            span: 15.0,
+        }
+        draw_panel: {
+            color: #000C,
+        }
+        draw_text: {
+            color: #fff,
+        }
+    }
+}
+
+#[derive(Live, LiveHook, LiveRegister)]
+#[repr(C)]
+pub struct DrawMagnifier {
+    #[deref]
+    draw_super: DrawQuad,
+    #[live]
+    center: Vec2,
+    #[live]
+    image_size: Vec2,
+    #[live]
+    span: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct PixelSample {
+    x: usize,
+    y: usize,
+    rgba: [u8; 4],
+    // Linear values for high bit depth images, unclamped.
+    value: [f32; 4],
+}
+
+impl PixelSample {
+    fn lines(&self) -> Vec<String> {
+        let [r, g, b, a] = self.rgba;
+        let [rf, gf, bf, af] = self.value;
+        let [r16, g16, b16, a16] = self.rgba.map(|c| c as u16 * 257);
+        let [rn, gn, bn, _] = self.rgba.map(|c| c as f32 / 255.0);
+        let (h, s, l) = rgb_to_hsl(rn, gn, bn);
+
+        vec![
+            format!("x: {}  y: {}", self.x, self.y),
+            format!("8-bit: {r} {g} {b} {a}"),
+            format!("16-bit: {r16} {g16} {b16} {a16}"),
+            format!("float: {rf:.4} {gf:.4} {bf:.4} {af:.4}"),
+            format!("hex: #{r:02X}{g:02X}{b:02X}{a:02X}"),
+            format!("hsl: {h:.0}° {:.0}% {:.0}%", s * 100.0, l * 100.0),
+        ]
+    }
+}
+
+#[derive(Live, LiveHook, Widget)]
+pub struct Loupe {
+    #[redraw]
+    #[live]
+    draw_bg: DrawQuad,
+    #[live]
+    draw_magnifier: DrawMagnifier,
+    #[live]
+    draw_panel: DrawColor,
+    #[live]
+    draw_text: DrawText,
+    #[walk]
+    walk: Walk,
+    #[rust]
+    active: bool,
+    #[rust]
+    pixels: Option<Arc<DecodedImage>>,
+    #[rust]
+    texture: Option<Texture>,
+    #[rust]
+    cursor: Option<DVec2>,
+    #[rust]
+    sample: Option<PixelSample>,
+}
+
+impl Widget for Loupe {
+    fn draw_walk(
+        &mut self,
+        cx: &mut Cx2d,
+        _scope: &mut Scope,
+        walk: Walk,
+    ) -> DrawStep {
+        if !self.active {
+            return DrawStep::done();
+        }
+
+        let rect = cx.walk_turtle(walk);
+        self.draw_bg.draw_abs(cx, rect);
+
+        let (Some(cursor), Some(sample), Some(texture)) =
+            (self.cursor, self.sample, &self.texture)
+        else {
+            return DrawStep::done();
+        };
+
+        let mut pos = cursor + dvec2(CURSOR_OFFSET, CURSOR_OFFSET);
+        if pos.x + MAGNIFIER_SIZE > rect.pos.x + rect.size.x {
+            pos.x = cursor.x - CURSOR_OFFSET - MAGNIFIER_SIZE;
+        }
+        if pos.y + MAGNIFIER_SIZE + PANEL_HEIGHT > rect.pos.y + rect.size.y {
+            pos.y = cursor.y - CURSOR_OFFSET - MAGNIFIER_SIZE - PANEL_HEIGHT;
+        }
+
+        let magnifier = Rect {
+            pos,
+            size: dvec2(MAGNIFIER_SIZE, MAGNIFIER_SIZE),
+        };
+        self.draw_magnifier.draw_vars.set_texture(0, texture);
+        self.draw_magnifier.center =
+            vec2(sample.x as f32 + 0.5, sample.y as f32 + 0.5);
+        self.draw_magnifier.draw_abs(cx, magnifier);
+
+        let panel = Rect {
+            pos: pos + dvec2(0.0, MAGNIFIER_SIZE),
+            size: dvec2(MAGNIFIER_SIZE, PANEL_HEIGHT),
+        };
+        self.draw_panel.draw_abs(cx, panel);
+
+        for (i, line) in sample.lines().iter().enumerate() {
+            let offset = dvec2(5.0, 5.0 + i as f64 * LINE_HEIGHT);
+            self.draw_text.draw_abs(cx, panel.pos + offset, line);
+        }
+
+        DrawStep::done()
+    }
+
+    fn handle_event(&mut self, cx: &mut Cx, event: &Event, _scope: &mut Scope) {
+        if !self.active {
+            return;
+        }
+
+        match event.hits(cx, self.draw_bg.area()) {
+            Hit::FingerHoverIn(e) | Hit::FingerHoverOver(e) => {
+                self.move_cursor(cx, e.abs);
+            }
+            Hit::FingerHoverOut(_) => {
+                self.cursor = None;
+                self.redraw(cx);
+            }
+            Hit::FingerUp(e) if e.is_over => {
+                if let Some(sample) = self.sample {
+                    cx.copy_to_clipboard(&sample.lines().join("\n"));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Loupe {
+    fn move_cursor(&mut self, cx: &mut Cx, abs: DVec2) {
+        self.cursor = Some(abs);
+        self.sample = self.sample_at(cx, abs);
+        self.redraw(cx);
+    }
+
+    // Mirrors the `fit: Biggest` placement of the slideshow image, which is
+    // centered within the same area as this widget.
+    fn sample_at(&self, cx: &Cx, abs: DVec2) -> Option<PixelSample> {
+        let pixels = self.pixels.as_ref()?;
+        let rect = self.draw_bg.area().rect(cx);
+
+        let image_size = dvec2(pixels.width as f64, pixels.height as f64);
+        let scale =
+            (rect.size.x / image_size.x).min(rect.size.y / image_size.y);
+        let offset = rect.pos + (rect.size - image_size * scale) * 0.5;
+        let local = (abs - offset) / scale;
+
+        if local.x < 0.0 || local.y < 0.0 {
+            return None;
+        }
+
+        let (x, y) = (local.x as usize, local.y as usize);
+        if x >= pixels.width || y >= pixels.height {
+            return None;
+        }
+
+        let pixel = pixels.data[y * pixels.width + x];
+        let rgba = [
+            (pixel >> 16) as u8,
+            (pixel >> 8) as u8,
+            pixel as u8,
+            (pixel >> 24) as u8,
+        ];
+        let value = pixels.sample(x, y);
+        Some(PixelSample { x, y, rgba, value })
+    }
+}
+
+impl LoupeRef {
+    pub fn set_active(&self, cx: &mut Cx, active: bool) {
+        if let Some(mut inner) = self.borrow_mut() {
+            inner.active = active;
+            inner.cursor = None;
+            inner.redraw(cx);
+        }
+    }
+
+    pub fn set_pixels(&self, cx: &mut Cx, pixels: Option<Arc<DecodedImage>>) {
+        let Some(mut inner) = self.borrow_mut() else {
+            return;
+        };
+
+        inner.texture = pixels.as_ref().map(|pixels| {
+            Texture::new_with_format(
+                cx,
+                TextureFormat::VecBGRAu8_32 {
+                    width: pixels.width,
+                    height: pixels.height,
+                    data: Some(pixels.data.clone()),
+                    updated: TextureUpdated::Full,
+                },
+            )
+        });
+        if let Some(pixels) = &pixels {
+            inner.draw_magnifier.image_size =
+                vec2(pixels.width as f32, pixels.height as f32);
+        }
+
+        inner.pixels = pixels;
+        inner.sample = None;
+        inner.redraw(cx);
+    }
+}
+
+fn rgb_to_hsl(r: f32, g: f32, b: f32) -> (f32, f32, f32) {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let l = (max + min) / 2.0;
+
+    if max == min {
+        return (0.0, 0.0, l);
+    }
+
+    let d = max - min;
+    let s = if l > 0.5 {
+        d / (2.0 - max - min)
+    } else {
+        d / (max + min)
+    };
+
+    let h = if max == r {
+        (g - b) / d + if g < b { 6.0 } else { 0.0 }
+    } else if max == g {
+        (b - r) / d + 2.0
+    } else {
+        (r - g) / d + 4.0
+    };
+
+    (h * 60.0, s, l)
+}
@@ -1,3 +1,8 @@
 fn main() {
+    let args = image_viewer::args::Args::parse();
+    if args.caption {
+        std::process::exit(image_viewer::captions::run_headless(&args));
+    }
+
     image_viewer::app::app_main();
 }
@@ -0,0 +1,639 @@
+use moly_kit::protocol::Tool;
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::attachments::{self, AttachmentFormat};
+use crate::source::ImageSource;
+use crate::tools::{self, ExportFormat, Rating, tool};
+use crate::xmp::Xmp;
+
+const PROTOCOL_VERSION: &str = "2025-06-18";
+const SUPPORTED_VERSIONS: &[&str] = &["2024-11-05", "2025-03-26", "2025-06-18"];
+
+const IMAGES_URI: &str = "image-viewer://images";
+const THUMBNAIL_SIZE: u32 = 256;
+
+// The viewer as agents see it. Calls come from the transport threads and
+// wait for the UI to answer, so they see and change what it shows.
+pub trait Library: Send + Sync + 'static {
+    fn view(&self) -> Result<LibraryView, String>;
+    fn apply(&self, change: LibraryChange) -> Result<(), String>;
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct LibraryView {
+    // In the folder's order, which resource URIs count in.
+    pub images: Vec<ImageSource>,
+    // In the grid's order, which may be ranked or filtered.
+    pub shown: Vec<ImageSource>,
+    pub selected: Vec<ImageSource>,
+    pub current: Option<ImageSource>,
+    pub slideshow: bool,
+}
+
+#[derive(Clone, Debug)]
+pub enum LibraryChange {
+    // Shows the image in the slideshow.
+    ShowImage(ImageSource),
+    NextImage,
+    PreviousImage,
+    ShowGrid,
+    // Replaces the grid's selection.
+    Select(Vec<ImageSource>),
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SelectMode {
+    #[default]
+    Replace,
+    Add,
+    Remove,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "name", content = "arguments", rename_all = "snake_case")]
+enum McpTool {
+    GoToImage {
+        image: String,
+    },
+    NextImage {},
+    PreviousImage {},
+    ShowGrid {},
+    SelectImages {
+        images: Vec<String>,
+        #[serde(default)]
+        mode: SelectMode,
+    },
+    AddTags {
+        tags: Vec<String>,
+        #[serde(default)]
+        images: Vec<String>,
+    },
+    RemoveTags {
+        tags: Vec<String>,
+        #[serde(default)]
+        images: Vec<String>,
+    },
+    SetRating {
+        rating: Rating,
+        #[serde(default)]
+        images: Vec<String>,
+    },
+    ExportImages {
+        folder: PathBuf,
+        #[serde(default)]
+        images: Vec<String>,
+        #[serde(default)]
+        format: ExportFormat,
+        max_size: Option<u32>,
+    },
+}
+
+fn mcp_tools() -> Vec<Tool> {
+    let images = json!({
+        "type": "array",
+        "items": { "type": "string" },
+        "description": "File names. Defaults to the selected images, or \
+                        the current one.",
+    });
+    let tags = json!({
+        "type": "array",
+        "items": { "type": "string" },
+    });
+    vec![
+        tool(
+            "go_to_image",
+            "Show an image in the slideshow.",
+            json!({
+                "image": {
+                    "type": "string",
+                    "description": "The file name, or part of it.",
+                },
+            }),
+            &["image"],
+        ),
+        tool("next_image", "Show the next image.", json!({}), &[]),
+        tool("previous_image", "Show the previous image.", json!({}), &[]),
+        tool("show_grid", "Go back to the grid.", json!({}), &[]),
+        tool(
+            "select_images",
+            "Change the images selected in the grid.",
+            json!({
+                "images": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "File names.",
+                },
+                "mode": {
+                    "type": "string",
+                    "enum": ["replace", "add", "remove"],
+                    "description": "Defaults to replace.",
+                },
+            }),
+            &["images"],
+        ),
+        tool(
+            "add_tags",
+            "Add keywords to images' XMP sidecars.",
+            json!({ "tags": tags, "images": images }),
+            &["tags"],
+        ),
+        tool(
+            "remove_tags",
+            "Remove keywords from images' XMP sidecars.",
+            json!({ "tags": tags, "images": images }),
+            &["tags"],
+        ),
+        tool(
+            "set_rating",
+            "Rate images from 0 to 5 stars.",
+            json!({
+                "rating": { "type": "integer", "minimum": 0, "maximum": 5 },
+                "images": images,
+            }),
+            &["rating"],
+        ),
+        tool(
+            "export_images",
+            "Write copies of images to an existing folder other than \
+             theirs. Existing files are never replaced.",
+            json!({
+                "folder": { "type": "string" },
+                "images": images,
+                "format": {
+                    "type": "string",
+                    "enum": ["original", "jpeg", "png"],
+                    "description": "Defaults to the original file.",
+                },
+                "max_size": {
+                    "type": "integer",
+                    "minimum": 1,
+                    "description": "The longest edge in pixels, for JPEG \
+                                    and PNG.",
+                },
+            }),
+            &["folder"],
+        ),
+    ]
+}
+
+// A Model Context Protocol server over newline-delimited JSON-RPC, as the
+// stdio transport frames it.
+#[derive(Clone)]
+pub struct McpServer {
+    library: Arc<dyn Library>,
+}
+
+impl McpServer {
+    pub fn new(library: impl Library) -> Self {
+        Self {
+            library: Arc::new(library),
+        }
+    }
+
+    // Answers messages until `reader` closes.
+    pub fn serve(
+        &self,
+        reader: impl BufRead,
+        mut writer: impl Write,
+    ) -> io::Result<()> {
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response = match serde_json::from_str::<Value>(&line) {
+                Ok(message) => self.handle(&message),
+                Err(e) => Some(error_response(Value::Null, -32700, &e)),
+            };
+            if let Some(response) = response {
+                writeln!(writer, "{response}")?;
+                writer.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    // The response to a request, or none for a notification.
+    pub fn handle(&self, message: &Value) -> Option<Value> {
+        let id = message.get("id").cloned()?;
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let result = match method {
+            "initialize" => Ok(initialize(params)),
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(self.list_tools()),
+            "tools/call" => Ok(self.call_tool(params)),
+            "resources/list" => self.list_resources(),
+            "resources/templates/list" => Ok(list_resource_templates()),
+            "resources/read" => self.read_resource(params),
+            _ => {
+                let error = format!("Unknown method {method}");
+                return Some(error_response(id, -32601, &error));
+            }
+        };
+        Some(match result {
+            Ok(result) => {
+                json!({ "jsonrpc": "2.0", "id": id, "result": result })
+            }
+            Err(error) => error_response(id, -32602, &error),
+        })
+    }
+
+    fn list_tools(&self) -> Value {
+        let tools: Vec<Value> = mcp_tools()
+            .into_iter()
+            .map(|tool| {
+                json!({
+                    "name": tool.name,
+                    "description": tool.description,
+                    "inputSchema": *tool.input_schema,
+                })
+            })
+            .collect();
+        json!({ "tools": tools })
+    }
+
+    // Tool failures are results the agent sees, not protocol errors.
+    fn call_tool(&self, params: &Value) -> Value {
+        let arguments = match &params["arguments"] {
+            Value::Null => Value::Object(Map::new()),
+            arguments => arguments.clone(),
+        };
+        let call = json!({ "name": params["name"], "arguments": arguments });
+        let result = serde_json::from_value(call)
+            .map_err(|e| format!("Bad call to {}: {e}", params["name"]))
+            .and_then(|call| self.run_tool(call));
+        let (text, is_error) = match result {
+            Ok(text) => (text, false),
+            Err(error) => (error, true),
+        };
+        json!({
+            "content": [{ "type": "text", "text": text }],
+            "isError": is_error,
+        })
+    }
+
+    fn run_tool(&self, call: McpTool) -> Result<String, String> {
+        let view = self.library.view()?;
+        match call {
+            McpTool::GoToImage { image } => {
+                let source = named(&view.images, &image)?;
+                let name = source.file_name();
+                self.library.apply(LibraryChange::ShowImage(source))?;
+                Ok(format!("Showing {name}"))
+            }
+            McpTool::NextImage {} => {
+                self.library.apply(LibraryChange::NextImage)?;
+                Ok(self.current_image())
+            }
+            McpTool::PreviousImage {} => {
+                self.library.apply(LibraryChange::PreviousImage)?;
+                Ok(self.current_image())
+            }
+            McpTool::ShowGrid {} => {
+                self.library.apply(LibraryChange::ShowGrid)?;
+                Ok("Showing the grid".to_string())
+            }
+            McpTool::SelectImages { images, mode } => {
+                let chosen = images
+                    .iter()
+                    .map(|name| named(&view.images, name))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let mut selected = match mode {
+                    SelectMode::Replace => Vec::new(),
+                    SelectMode::Add | SelectMode::Remove => view.selected,
+                };
+                match mode {
+                    SelectMode::Remove => {
+                        selected.retain(|source| !chosen.contains(source))
+                    }
+                    _ => {
+                        for source in chosen {
+                            if !selected.contains(&source) {
+                                selected.push(source);
+                            }
+                        }
+                    }
+                }
+                let count = selected.len();
+                self.library.apply(LibraryChange::Select(selected))?;
+                Ok(format!("{count} images selected"))
+            }
+            McpTool::AddTags { tags, images } => {
+                let sources = targets(&view, &images)?;
+                for tag in &tags {
+                    tools::add_tag(&sources, tag)?;
+                }
+                Ok(format!(
+                    "Tagged {} images with {}",
+                    sources.len(),
+                    tags.join(", ")
+                ))
+            }
+            McpTool::RemoveTags { tags, images } => {
+                let sources = targets(&view, &images)?;
+                for tag in &tags {
+                    tools::remove_tag(&sources, tag)?;
+                }
+                Ok(format!(
+                    "Removed {} from {} images",
+                    tags.join(", "),
+                    sources.len()
+                ))
+            }
+            McpTool::SetRating { rating, images } => {
+                let sources = targets(&view, &images)?;
+                tools::set_rating(&sources, rating.0)
+            }
+            McpTool::ExportImages {
+                folder,
+                images,
+                format,
+                max_size,
+            } => {
+                let sources = targets(&view, &images)?;
+                tools::export(&sources, &folder, format, max_size)
+            }
+        }
+    }
+
+    fn current_image(&self) -> String {
+        match self.library.view().map(|view| view.current) {
+            Ok(Some(source)) => format!("Showing {}", source.file_name()),
+            Ok(None) => "No images are loaded".to_string(),
+            Err(error) => error,
+        }
+    }
+
+    fn list_resources(&self) -> Result<Value, String> {
+        let view = self.library.view()?;
+        let mut resources = vec![json!({
+            "uri": IMAGES_URI,
+            "name": "images",
+            "description": "The open images, with which are shown, selected \
+                            and current.",
+            "mimeType": "application/json",
+        })];
+        resources.extend(view.images.iter().enumerate().map(|(i, source)| {
+            json!({
+                "uri": format!("{IMAGES_URI}/{i}"),
+                "name": source.file_name(),
+                "mimeType": "application/json",
+            })
+        }));
+        Ok(json!({ "resources": resources }))
+    }
+
+    // `images/{index}` is an image's metadata and `images/{index}/thumbnail`
+    // a small JPEG of it, counting in the folder's order.
+    fn read_resource(&self, params: &Value) -> Result<Value, String> {
+        let uri = params["uri"].as_str().ok_or("Missing uri")?;
+        let view = self.library.view()?;
+        let content = if uri == IMAGES_URI {
+            json!({
+                "uri": uri,
+                "mimeType": "application/json",
+                "text": image_list(&view).to_string(),
+            })
+        } else {
+            let path = uri
+                .strip_prefix(IMAGES_URI)
+                .and_then(|path| path.strip_prefix('/'))
+                .ok_or_else(|| format!("Unknown resource {uri}"))?;
+            let (index, thumbnail) = match path.strip_suffix("/thumbnail") {
+                Some(index) => (index, true),
+                None => (path, false),
+            };
+            let source = index
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| view.images.get(i))
+                .ok_or_else(|| format!("Unknown resource {uri}"))?;
+            if thumbnail {
+                json!({
+                    "uri": uri,
+                    "mimeType": "image/jpeg",
+                    "blob": thumbnail_base64(source)?,
+                })
+            } else {
+                json!({
+                    "uri": uri,
+                    "mimeType": "application/json",
+                    "text": metadata(source).to_string(),
+                })
+            }
+        };
+        Ok(json!({ "contents": [content] }))
+    }
+}
+
+fn initialize(params: &Value) -> Value {
+    let requested = params["protocolVersion"].as_str().unwrap_or_default();
+    let version = if SUPPORTED_VERSIONS.contains(&requested) {
+        requested
+    } else {
+        PROTOCOL_VERSION
+    };
+    json!({
+        "protocolVersion": version,
+        "capabilities": {
+            "tools": {},
+            "resources": {},
+        },
+        "serverInfo": {
+            "name": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+    })
+}
+
+fn list_resource_templates() -> Value {
+    json!({
+        "resourceTemplates": [
+            {
+                "uriTemplate": format!("{IMAGES_URI}/{{index}}"),
+                "name": "metadata",
+                "description": "An image's size, rating, keywords and alt \
+                                text.",
+                "mimeType": "application/json",
+            },
+            {
+                "uriTemplate": format!("{IMAGES_URI}/{{index}}/thumbnail"),
+                "name": "thumbnail",
+                "mimeType": "image/jpeg",
+            },
+        ],
+    })
+}
+
+fn error_response(id: Value, code: i32, message: &impl ToString) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message.to_string() },
+    })
+}
+
+fn named(images: &[ImageSource], name: &str) -> Result<ImageSource, String> {
+    tools::find_image(images, name)
+        .map(|i| images[i].clone())
+        .ok_or_else(|| format!("No image named {name}"))
+}
+
+// The images a tool names, or else the selected ones, or else the current
+// one.
+fn targets(
+    view: &LibraryView,
+    names: &[String],
+) -> Result<Vec<ImageSource>, String> {
+    if !names.is_empty() {
+        return names.iter().map(|name| named(&view.images, name)).collect();
+    }
+    if !view.selected.is_empty() {
+        return Ok(view.selected.clone());
+    }
+    view.current
+        .clone()
+        .map(|source| vec![source])
+        .ok_or_else(|| "No images are loaded".to_string())
+}
+
+fn image_list(view: &LibraryView) -> Value {
+    let images: Vec<Value> = view
+        .images
+        .iter()
+        .enumerate()
+        .map(|(i, source)| {
+            json!({
+                "uri": format!("{IMAGES_URI}/{i}"),
+                "name": source.file_name(),
+                "path": source.path(),
+                "shown": view.shown.iter().position(|s| s == source),
+                "selected": view.selected.contains(source),
+                "current": view.current.as_ref() == Some(source),
+            })
+        })
+        .collect();
+    json!({
+        "page": if view.slideshow { "slideshow" } else { "grid" },
+        "images": images,
+    })
+}
+
+fn metadata(source: &ImageSource) -> Value {
+    let xmp = Xmp::load(source).unwrap_or_default();
+    let (width, height) = tools::dimensions(source).unzip();
+    json!({
+        "name": source.file_name(),
+        "path": source.path(),
+        "width": width,
+        "height": height,
+        "rating": xmp.rating(),
+        "keywords": xmp.keywords(),
+        "description": xmp.description(),
+    })
+}
+
+fn thumbnail_base64(source: &ImageSource) -> Result<String, String> {
+    let image = attachments::decode_rgba(source)?;
+    let image = attachments::fit_long_edge(image, THUMBNAIL_SIZE);
+    let bytes = attachments::encode(&image, AttachmentFormat::Jpeg, 85)?;
+    Ok(attachments::base64(&bytes))
+}
+
+// Serves stdin and stdout on a thread of its own, so stdout must carry
+// nothing else.
+pub fn serve_stdio(server: McpServer) {
+    std::thread::spawn(move || {
+        let stdin = io::stdin().lock();
+        if let Err(e) = server.serve(stdin, io::stdout().lock()) {
+            eprintln!("MCP over stdio stopped: {e}");
+        }
+    });
+}
+
+// Serves each client of a Unix socket at `address` on a thread of its own.
+#[cfg(unix)]
+pub fn serve_socket(server: McpServer, address: &str) -> io::Result<()> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    use std::os::unix::net::UnixListener;
+
+    // A socket left behind by an earlier run is replaced, anything else
+    // at the path is left alone.
+    match std::fs::symlink_metadata(address) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            std::fs::remove_file(address)?
+        }
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "the path exists and isn't a socket",
+            ));
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let listener = UnixListener::bind(address)?;
+    // Clients can tag files and write exports, so only the user connects.
+    let private = std::fs::Permissions::from_mode(0o600);
+    if let Err(e) = std::fs::set_permissions(address, private) {
+        let _ = std::fs::remove_file(address);
+        return Err(e);
+    }
+    std::thread::spawn(move || {
+        let clients = listener
+            .incoming()
+            .map(|stream| stream.and_then(|s| Ok((s.try_clone()?, s))));
+        serve_clients(server, clients);
+    });
+    Ok(())
+}
+
+// Without Unix sockets, `address` is a loopback TCP address like
+// `127.0.0.1:7410`.
+#[cfg(not(unix))]
+pub fn serve_socket(server: McpServer, address: &str) -> io::Result<()> {
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind(address)?;
+    if !listener.local_addr()?.ip().is_loopback() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the MCP socket only listens on loopback addresses",
+        ));
+    }
+    std::thread::spawn(move || {
+        let clients = listener
+            .incoming()
+            .map(|stream| stream.and_then(|s| Ok((s.try_clone()?, s))));
+        serve_clients(server, clients);
+    });
+    Ok(())
+}
+
+// Each client is a reading and a writing end of the same stream.
+fn serve_clients<S: io::Read + Write + Send + 'static>(
+    server: McpServer,
+    clients: impl Iterator<Item = io::Result<(S, S)>>,
+) {
+    for client in clients {
+        let (reader, writer) = match client {
+            Ok(client) => client,
+            Err(e) => {
+                eprintln!("MCP socket error: {e}");
+                continue;
+            }
+        };
+        let server = server.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = server.serve(BufReader::new(reader), writer) {
+                eprintln!("MCP client disconnected: {e}");
+            }
+        });
+    }
+}
@@ -0,0 +1,135 @@
+use futures::stream;
+use moly_kit::protocol::*;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+const PROVIDER: &str = "scripted";
+
+#[derive(Default)]
+struct Script {
+    models: Vec<String>,
+    replies: VecDeque<Vec<ClientResult<MessageContent>>>,
+    sent: Vec<Vec<Message>>,
+    // The names of the tools offered with each `send`.
+    sent_tools: Vec<Vec<String>>,
+}
+
+// A `BotClient` that answers from a script instead of a service, so the
+// chats can run offline and deterministically. Clones share the script.
+#[derive(Clone, Default)]
+pub struct ScriptedClient(Arc<Mutex<Script>>);
+
+impl ScriptedClient {
+    pub fn new(models: &[&str]) -> Self {
+        let client = Self::default();
+        client.0.lock().unwrap().models =
+            models.iter().map(|m| m.to_string()).collect();
+        client
+    }
+
+    // Queues a reply streamed in `chunks`, where every update carries the
+    // text so far, as the real clients do.
+    pub fn reply_text(&self, chunks: &[&str]) {
+        let mut text = String::new();
+        let updates = chunks
+            .iter()
+            .map(|chunk| {
+                text.push_str(chunk);
+                ClientResult::new_ok(MessageContent {
+                    text: text.clone(),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        self.0.lock().unwrap().replies.push_back(updates);
+    }
+
+    pub fn reply_attachment(&self, attachment: Attachment) {
+        let content = MessageContent {
+            attachments: vec![attachment],
+            ..Default::default()
+        };
+        self.0
+            .lock()
+            .unwrap()
+            .replies
+            .push_back(vec![ClientResult::new_ok(content)]);
+    }
+
+    // Queues a reply that only calls tools.
+    pub fn reply_tool_calls(&self, calls: Vec<ToolCall>) {
+        let content = MessageContent {
+            tool_calls: calls,
+            ..Default::default()
+        };
+        self.0
+            .lock()
+            .unwrap()
+            .replies
+            .push_back(vec![ClientResult::new_ok(content)]);
+    }
+
+    // Queues a failed request, like a provider turning it down.
+    pub fn reply_error(&self, message: &str) {
+        let error =
+            ClientError::new(ClientErrorKind::Response, message.to_string());
+        self.0
+            .lock()
+            .unwrap()
+            .replies
+            .push_back(vec![ClientResult::new_err(vec![error])]);
+    }
+
+    // The messages of every `send` so far, oldest first.
+    pub fn sent(&self) -> Vec<Vec<Message>> {
+        self.0.lock().unwrap().sent.clone()
+    }
+
+    pub fn sent_tools(&self) -> Vec<Vec<String>> {
+        self.0.lock().unwrap().sent_tools.clone()
+    }
+}
+
+impl BotClient for ScriptedClient {
+    fn bots(&self) -> BoxPlatformSendFuture<'static, ClientResult<Vec<Bot>>> {
+        let bots = self
+            .0
+            .lock()
+            .unwrap()
+            .models
+            .iter()
+            .map(|model| Bot {
+                id: BotId::new(model, PROVIDER),
+                name: model.clone(),
+                avatar: Picture::Grapheme("S".to_string()),
+            })
+            .collect();
+        Box::pin(async move { ClientResult::new_ok(bots) })
+    }
+
+    fn send(
+        &mut self,
+        _bot_id: &BotId,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> BoxPlatformSendStream<'static, ClientResult<MessageContent>> {
+        let mut script = self.0.lock().unwrap();
+        script.sent.push(messages.to_vec());
+        script
+            .sent_tools
+            .push(tools.iter().map(|tool| tool.name.clone()).collect());
+
+        let updates: Vec<_> = match script.replies.pop_front() {
+            Some(reply) => reply,
+            None => vec![ClientResult::new_err(vec![ClientError::new(
+                ClientErrorKind::Response,
+                "No scripted reply left".to_string(),
+            )])],
+        };
+        Box::pin(stream::iter(updates))
+    }
+
+    fn clone_box(&self) -> Box<dyn BotClient> {
+        Box::new(self.clone())
+    }
+}
@@ -0,0 +1,268 @@
+use serde_json::{Value, json};
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::attachments::base64;
+
+// A request as the server received it.
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub body: Value,
+}
+
+enum Reply {
+    // Streamed as chat completion chunks.
+    Text(Vec<String>),
+    Image(Vec<u8>),
+    Embedding(Vec<f32>),
+}
+
+#[derive(Default)]
+struct ServerScript {
+    models: Vec<String>,
+    replies: VecDeque<Reply>,
+    requests: Vec<Request>,
+}
+
+// A local stand-in for an OpenAI-compatible service, answering `/models`,
+// `/chat/completions`, `/images/generations` and `/embeddings` from a script so the real
+// clients can be exercised without a network.
+pub struct MockServer {
+    url: String,
+    script: Arc<Mutex<ServerScript>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl MockServer {
+    pub fn start(models: &[&str]) -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/v1", listener.local_addr()?);
+
+        let script = Arc::new(Mutex::new(ServerScript {
+            models: models.iter().map(|m| m.to_string()).collect(),
+            ..Default::default()
+        }));
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let server_script = Arc::clone(&script);
+        let server_stopped = Arc::clone(&stopped);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                if server_stopped.load(Ordering::Relaxed) {
+                    break;
+                }
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                let script = Arc::clone(&server_script);
+                std::thread::spawn(move || {
+                    if let Err(e) = handle(stream, &script) {
+                        eprintln!("Mock server error: {e}");
+                    }
+                });
+            }
+        });
+
+        Ok(Self {
+            url,
+            script,
+            stopped,
+        })
+    }
+
+    // The base URL to configure a provider or client with.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn reply_text(&self, chunks: &[&str]) {
+        let chunks = chunks.iter().map(|c| c.to_string()).collect();
+        self.script
+            .lock()
+            .unwrap()
+            .replies
+            .push_back(Reply::Text(chunks));
+    }
+
+    pub fn reply_image(&self, bytes: Vec<u8>) {
+        self.script
+            .lock()
+            .unwrap()
+            .replies
+            .push_back(Reply::Image(bytes));
+    }
+
+    pub fn reply_embedding(&self, vector: &[f32]) {
+        self.script
+            .lock()
+            .unwrap()
+            .replies
+            .push_back(Reply::Embedding(vector.to_vec()));
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.script.lock().unwrap().requests.clone()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        // Wakes the accept loop so it sees the flag.
+        let addr = self.url.trim_start_matches("http://");
+        let addr = addr.trim_end_matches("/v1");
+        let _ = TcpStream::connect(addr);
+    }
+}
+
+fn handle(stream: TcpStream, script: &Mutex<ServerScript>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+
+    let mut script = script.lock().unwrap();
+    script.requests.push(Request {
+        method: method.clone(),
+        path: path.clone(),
+        body: body.clone(),
+    });
+
+    let route = path.trim_start_matches("/v1");
+    let mut stream = stream;
+    match (method.as_str(), route) {
+        ("GET", "/models") => {
+            let data: Vec<Value> = script
+                .models
+                .iter()
+                .map(|id| {
+                    json!({
+                        "id": id,
+                        "object": "model",
+                        "created": 0,
+                        "owned_by": "mock",
+                    })
+                })
+                .collect();
+            respond_json(
+                &mut stream,
+                &json!({ "object": "list", "data": data }),
+            )
+        }
+        ("POST", "/chat/completions") => match script.replies.pop_front() {
+            Some(Reply::Text(chunks)) => {
+                let model = body["model"].as_str().unwrap_or_default();
+                respond_chunks(&mut stream, model, &chunks)
+            }
+            _ => respond_error(&mut stream, 500, "No scripted text reply"),
+        },
+        ("POST", "/images/generations") => match script.replies.pop_front() {
+            Some(Reply::Image(bytes)) => respond_json(
+                &mut stream,
+                &json!({
+                    "created": 0,
+                    "data": [{ "b64_json": base64(&bytes) }],
+                }),
+            ),
+            _ => respond_error(&mut stream, 500, "No scripted image reply"),
+        },
+        ("POST", "/embeddings") => match script.replies.pop_front() {
+            Some(Reply::Embedding(vector)) => respond_json(
+                &mut stream,
+                &json!({
+                    "object": "list",
+                    "data": [{
+                        "object": "embedding",
+                        "index": 0,
+                        "embedding": vector,
+                    }],
+                }),
+            ),
+            _ => respond_error(&mut stream, 500, "No scripted embedding"),
+        },
+        _ => respond_error(&mut stream, 404, "Unknown route"),
+    }
+}
+
+fn respond_json(stream: &mut TcpStream, value: &Value) -> io::Result<()> {
+    let body = value.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+fn respond_error(
+    stream: &mut TcpStream,
+    status: u16,
+    message: &str,
+) -> io::Result<()> {
+    let body = json!({ "error": { "message": message } }).to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {status} Error\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+// Streams the reply as server-sent events, one chunk per event.
+fn respond_chunks(
+    stream: &mut TcpStream,
+    model: &str,
+    chunks: &[String],
+) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\
+         Cache-Control: no-cache\r\nConnection: close\r\n\r\n"
+    )?;
+
+    for (i, chunk) in chunks.iter().enumerate() {
+        let finish_reason = (i + 1 == chunks.len()).then_some("stop");
+        let event = json!({
+            "id": "mock",
+            "object": "chat.completion.chunk",
+            "created": 0,
+            "model": model,
+            "choices": [{
+                "index": 0,
+                "delta": { "role": "assistant", "content": chunk },
+                "finish_reason": finish_reason,
+            }],
+        });
+        write!(stream, "data: {event}\n\n")?;
+        stream.flush()?;
+    }
+    write!(stream, "data: [DONE]\n\n")?;
+    stream.flush()
+}
@@ -0,0 +1,251 @@
+use makepad_widgets::*;
+
+use crate::commands::{Command, CommandScope, Keymap, fuzzy_score};
+
+live_design! {
+    use link::widgets::*;
+    use link::shaders::*;
+    use crate::theme::*;
+
+    CommandPaletteItem = <View> {
+        width: Fill,
+        height: Fit,
+        padding: {
+            left: 10,
+            right: 10,
+            top: 6,
+            bottom: 6,
+        },
+        cursor: Hand,
+        show_bg: true,
+        draw_bg: {
+            instance selected: 0.0,
+            color: (THEME_ACCENT),
+
+            fn pixel(self) -> vec4 {
+                return self.color * self.selected;
+            }
+        },
+
+        title = <Label> {
+            text: "",
+        }
+        <Filler> {}
+        binding = <Label> {
+            text: "",
+            draw_text: {
+                color: #aaa,
+            },
+        }
+    }
+
+    pub CommandPalette = {{CommandPalette}} {
+        width: Fill,
+        height: Fill,
+        align: {
+            x: 0.5,
+        },
+        padding: {
+            top: 80,
+        },
+        visible: false,
+
+        <RoundedView> {
+            width: 500,
+            height: Fit,
+            flow: Down,
+            spacing: 5,
+            padding: 10,
+            show_bg: true,
+            draw_bg: {
+                color: #000D,
+            },
+
+            query = <TextInput> {
+                width: Fill,
+                empty_text: "Type a command...",
+            }
+            results = <PortalList> {
+                height: 300,
+                flow: Down,
+
+                Item = <CommandPaletteItem> {}
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, DefaultNone)]
+pub enum CommandPaletteAction {
+    Run(Command),
+    None,
+}
+
+#[derive(Live, LiveHook, Widget)]
+pub struct CommandPalette {
+    #[deref]
+    view: View,
+    #[rust]
+    open: bool,
+    // Every command with the text of its current binding.
+    #[rust]
+    entries: Vec<(Command, String)>,
+    // Indices into `entries`, best match first.
+    #[rust]
+    matches: Vec<usize>,
+    #[rust]
+    selected: usize,
+}
+
+impl Widget for CommandPalette {
+    fn draw_walk(
+        &mut self,
+        cx: &mut Cx2d,
+        scope: &mut Scope,
+        walk: Walk,
+    ) -> DrawStep {
+        while let Some(item) = self.view.draw_walk(cx, scope, walk).step() {
+            let Some(mut list) = item.as_portal_list().borrow_mut() else {
+                continue;
+            };
+            list.set_item_range(cx, 0, self.matches.len());
+
+            while let Some(item_idx) = list.next_visible_item(cx) {
+                let Some(&entry_idx) = self.matches.get(item_idx) else {
+                    continue;
+                };
+                let (command, binding) = &self.entries[entry_idx];
+
+                let item = list.item(cx, item_idx, live_id!(Item));
+                item.label(id!(title)).set_text(cx, command.title());
+                item.label(id!(binding)).set_text(cx, binding);
+
+                let selected = (item_idx == self.selected) as u8 as f64;
+                item.apply_over(
+                    cx,
+                    live! {
+                        draw_bg: { selected: (selected) }
+                    },
+                );
+
+                item.draw_all(cx, &mut Scope::empty());
+            }
+        }
+        DrawStep::done()
+    }
+
+    fn handle_event(&mut self, cx: &mut Cx, event: &Event, scope: &mut Scope) {
+        if !self.open {
+            return;
+        }
+
+        let actions =
+            cx.capture_actions(|cx| self.view.handle_event(cx, event, scope));
+
+        if let Some(query) = self.view.text_input(id!(query)).changed(&actions)
+        {
+            self.filter(&query);
+            self.redraw(cx);
+        }
+
+        let results = self.view.portal_list(id!(results));
+        for (item_idx, item) in results.items_with_actions(&actions) {
+            let clicked = item
+                .as_view()
+                .finger_up(&actions)
+                .is_some_and(|e| e.is_over);
+            if clicked {
+                self.run(cx, scope, item_idx);
+            }
+        }
+
+        if let Event::KeyDown(e) = event {
+            match e.key_code {
+                KeyCode::ArrowDown => {
+                    let last = self.matches.len().saturating_sub(1);
+                    self.selected = (self.selected + 1).min(last);
+                    self.redraw(cx);
+                }
+                KeyCode::ArrowUp => {
+                    self.selected = self.selected.saturating_sub(1);
+                    self.redraw(cx);
+                }
+                KeyCode::ReturnKey => self.run(cx, scope, self.selected),
+                KeyCode::Escape => self.close(cx),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl CommandPalette {
+    fn filter(&mut self, query: &str) {
+        let mut scored: Vec<(usize, i32)> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (command, _))| {
+                let title = fuzzy_score(query, command.title());
+                let name = fuzzy_score(query, command.name());
+                title.max(name).map(|score| (i, score))
+            })
+            .collect();
+        // Stable, so an empty query keeps the registry's order.
+        if !query.trim().is_empty() {
+            scored.sort_by_key(|(_, score)| std::cmp::Reverse(*score));
+        }
+
+        self.matches = scored.into_iter().map(|(i, _)| i).collect();
+        self.selected = 0;
+    }
+
+    fn run(&mut self, cx: &mut Cx, scope: &mut Scope, item_idx: usize) {
+        let Some(&entry_idx) = self.matches.get(item_idx) else {
+            return;
+        };
+        let command = self.entries[entry_idx].0;
+
+        self.close(cx);
+        cx.widget_action(
+            self.widget_uid(),
+            &scope.path,
+            CommandPaletteAction::Run(command),
+        );
+    }
+
+    fn close(&mut self, cx: &mut Cx) {
+        self.open = false;
+        self.view.set_visible(cx, false);
+        cx.revert_key_focus();
+    }
+}
+
+impl CommandPaletteRef {
+    // Lists the commands that work on the page the palette is opened from.
+    pub fn open(&self, cx: &mut Cx, keymap: &Keymap, scope: CommandScope) {
+        let Some(mut inner) = self.borrow_mut() else {
+            return;
+        };
+
+        inner.entries = Command::all()
+            .filter(|command| {
+                *command != Command::CommandPalette && command.works_in(scope)
+            })
+            .map(|command| {
+                let binding = keymap
+                    .binding(command)
+                    .map(|chord| chord.to_string())
+                    .unwrap_or_default();
+                (command, binding)
+            })
+            .collect();
+        inner.filter("");
+        inner.open = true;
+        inner.view.set_visible(cx, true);
+
+        let query = inner.view.text_input(id!(query));
+        query.set_text(cx, "");
+        query.set_key_focus(cx);
+        inner.redraw(cx);
+    }
+}
@@ -0,0 +1,178 @@
+use moly_kit::{OpenAIClient, OpenAIImageClient};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+
+use crate::config;
+
+const PROVIDERS_FILE: &str = "providers.toml";
+const MODELS_FILE: &str = "models.toml";
+#[cfg(feature = "keyring")]
+const KEYRING_SERVICE: &str = "image_viewer";
+
+// An OpenAI-compatible endpoint, listed in `providers.toml` as:
+//
+//     [[provider]]
+//     name = "openai"
+//     url = "https://api.openai.com/v1"
+//     key = "sk-..."
+//     accepted_formats = ["image/jpeg", "image/png"]
+//
+// Without a `key`, the key is looked up in the OS keyring under the
+// `image_viewer` service with the provider's name as the user, and local
+// servers that need no key work with neither.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Provider {
+    pub name: String,
+    pub url: String,
+    #[serde(default)]
+    key: Option<String>,
+    // The image types the provider takes, in place of the ones in
+    // `attachments.toml`.
+    #[serde(default)]
+    pub accepted_formats: Option<Vec<String>>,
+}
+
+impl Provider {
+    pub fn api_key(&self) -> Result<Option<String>, String> {
+        if let Some(key) = &self.key {
+            return Ok(Some(key.clone()));
+        }
+
+        #[cfg(feature = "keyring")]
+        {
+            let entry = keyring::Entry::new(KEYRING_SERVICE, &self.name)
+                .map_err(|e| {
+                    format!("{}: keyring unavailable: {e}", self.name)
+                })?;
+            match entry.get_password() {
+                Ok(key) => return Ok(Some(key)),
+                Err(keyring::Error::NoEntry) => {}
+                Err(e) => {
+                    return Err(format!(
+                        "{}: couldn't read the key from the keyring: {e}",
+                        self.name
+                    ));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub fn chat_client(&self) -> Result<OpenAIClient, String> {
+        let mut client = OpenAIClient::new(self.url.clone());
+        if let Some(key) = self.api_key()? {
+            client
+                .set_key(&key)
+                .map_err(|e| format!("{}: invalid API key: {e}", self.name))?;
+        }
+        Ok(client)
+    }
+
+    pub fn image_client(&self) -> Result<OpenAIImageClient, String> {
+        let mut client = OpenAIImageClient::new(self.url.clone());
+        if let Some(key) = self.api_key()? {
+            client
+                .set_key(&key)
+                .map_err(|e| format!("{}: invalid API key: {e}", self.name))?;
+        }
+        Ok(client)
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Providers {
+    #[serde(default, rename = "provider")]
+    pub providers: Vec<Provider>,
+}
+
+impl Providers {
+    pub fn load() -> Result<Self, String> {
+        let path = config::config_file(PROVIDERS_FILE)
+            .ok_or_else(|| "No config directory for providers".to_string())?;
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(format!(
+                    "No providers configured. Add one to {}",
+                    path.display()
+                ));
+            }
+            Err(e) => return Err(format!("{}: {e}", path.display())),
+        };
+
+        let providers: Self = toml::from_str(&text)
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        if providers.providers.is_empty() {
+            return Err(format!("No providers listed in {}", path.display()));
+        }
+        for (i, provider) in providers.providers.iter().enumerate() {
+            if provider.url.trim().is_empty() {
+                return Err(format!("{}: missing url", provider.name));
+            }
+            let duplicate = providers.providers[..i]
+                .iter()
+                .any(|other| other.name == provider.name);
+            if duplicate {
+                return Err(format!(
+                    "{}: more than one provider with this name",
+                    provider.name
+                ));
+            }
+        }
+
+        Ok(providers)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Provider> {
+        self.providers.iter().find(|p| p.name == name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelChoice {
+    pub provider: String,
+    pub model: String,
+}
+
+impl fmt::Display for ModelChoice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.model, self.provider)
+    }
+}
+
+// The models picked in settings, kept apart from `providers.toml` so saving
+// them doesn't rewrite the user's file.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModelSelection {
+    pub chat: Option<ModelChoice>,
+    pub image: Option<ModelChoice>,
+}
+
+impl ModelSelection {
+    pub fn load() -> Self {
+        let Some(path) = config::config_file(MODELS_FILE) else {
+            return Self::default();
+        };
+        let Ok(text) = std::fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        toml::from_str(&text)
+            .inspect_err(|e| eprintln!("Error reading {path:?}: {e}"))
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = config::config_file(MODELS_FILE).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "no config directory")
+        })?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let text = toml::to_string(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        std::fs::write(path, text)
+    }
+}
@@ -0,0 +1,314 @@
+use makepad_widgets::*;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use crate::source::ImageSource;
+use crate::tagging::{self, ReviewQueue, TagSuggestion};
+
+live_design! {
+    use link::widgets::*;
+    use crate::theme::*;
+
+    SuggestionItem = <View> {
+        width: Fill,
+        height: Fit,
+        spacing: 10,
+        padding: {
+            left: 10,
+            right: 10,
+            top: 6,
+            bottom: 6,
+        },
+        cursor: Hand,
+        show_bg: true,
+        draw_bg: {
+            instance checked: 0.0,
+            color: (THEME_ACCENT),
+
+            fn pixel(self) -> vec4 {
+                return self.color * self.checked;
+            }
+        },
+
+        tag = <Label> {
+            text: "",
+        }
+        name = <Label> {
+            width: Fill,
+            text: "",
+            draw_text: {
+                color: #aaa,
+            },
+        }
+        confidence = <Label> {
+            text: "",
+        }
+    }
+
+    // Tags the model wasn't sure enough about, for the images selected in
+    // the grid or all of them.
+    pub TagReview = {{TagReview}} {
+        width: 350,
+        flow: Down,
+        spacing: 10,
+        padding: 20,
+        visible: false,
+        show_bg: true,
+        draw_bg: {
+            color: (THEME_PANEL),
+        },
+
+        <View> {
+            height: Fit,
+            align: {
+                y: 0.5,
+            },
+            spacing: 10,
+
+            <Label> {
+                width: Fill,
+                text: "Tag suggestions",
+            }
+            close_button = <Button> {
+                text: "Close",
+            }
+        }
+        <View> {
+            height: Fit,
+            spacing: 10,
+
+            select_all_button = <Button> {
+                text: "Select all",
+            }
+            accept_button = <Button> {
+                text: "Accept",
+            }
+            reject_button = <Button> {
+                text: "Reject",
+            }
+        }
+        status = <Label> {
+            width: Fill,
+            text: "",
+        }
+        suggestions = <PortalList> {
+            flow: Down,
+
+            Item = <SuggestionItem> {}
+        }
+    }
+}
+
+#[derive(Clone, Debug, DefaultNone)]
+pub enum TagReviewAction {
+    // Suggestions were accepted or rejected.
+    Changed,
+    None,
+}
+
+#[derive(Live, LiveHook, Widget)]
+pub struct TagReview {
+    #[deref]
+    view: View,
+    #[rust]
+    queue: ReviewQueue,
+    #[rust]
+    path: Option<PathBuf>,
+    // Only suggestions for these images are listed, unless it's empty.
+    #[rust]
+    filter: Vec<ImageSource>,
+    // Indices into the queue of the suggestions listed.
+    #[rust]
+    rows: Vec<usize>,
+    #[rust]
+    checked: HashSet<usize>,
+}
+
+impl Widget for TagReview {
+    fn draw_walk(
+        &mut self,
+        cx: &mut Cx2d,
+        scope: &mut Scope,
+        walk: Walk,
+    ) -> DrawStep {
+        while let Some(item) = self.view.draw_walk(cx, scope, walk).step() {
+            let Some(mut list) = item.as_portal_list().borrow_mut() else {
+                continue;
+            };
+            list.set_item_range(cx, 0, self.rows.len());
+
+            while let Some(item_idx) = list.next_visible_item(cx) {
+                let Some(&pending_idx) = self.rows.get(item_idx) else {
+                    continue;
+                };
+                let pending = &self.queue.pending[pending_idx];
+
+                let item = list.item(cx, item_idx, live_id!(Item));
+                item.label(id!(tag)).set_text(cx, &pending.tag);
+                item.label(id!(name))
+                    .set_text(cx, &pending.source.file_name());
+                let percent = (pending.confidence * 100.0).round();
+                item.label(id!(confidence))
+                    .set_text(cx, &format!("{percent}%"));
+                let checked = self.checked.contains(&pending_idx) as u8 as f64;
+                item.apply_over(
+                    cx,
+                    live! {
+                        draw_bg: { checked: (checked) }
+                    },
+                );
+
+                item.draw_all(cx, &mut Scope::empty());
+            }
+        }
+        DrawStep::done()
+    }
+
+    fn handle_event(&mut self, cx: &mut Cx, event: &Event, scope: &mut Scope) {
+        let actions =
+            cx.capture_actions(|cx| self.view.handle_event(cx, event, scope));
+
+        let suggestions = self.view.portal_list(id!(suggestions));
+        for (item_idx, item) in suggestions.items_with_actions(&actions) {
+            let clicked = item
+                .as_view()
+                .finger_up(&actions)
+                .is_some_and(|e| e.is_over);
+            let Some(&pending_idx) = self.rows.get(item_idx) else {
+                continue;
+            };
+            if clicked {
+                if !self.checked.remove(&pending_idx) {
+                    self.checked.insert(pending_idx);
+                }
+                self.redraw(cx);
+            }
+        }
+
+        if self.view.button(id!(select_all_button)).clicked(&actions) {
+            let all = self.rows.iter().all(|i| self.checked.contains(i));
+            if all {
+                self.checked.clear();
+            } else {
+                self.checked.extend(self.rows.iter().copied());
+            }
+            self.redraw(cx);
+        }
+
+        let accept = self.view.button(id!(accept_button)).clicked(&actions);
+        let reject = self.view.button(id!(reject_button)).clicked(&actions);
+        if (accept || reject) && !self.checked.is_empty() {
+            let indices: Vec<usize> = self.checked.drain().collect();
+            let result = if accept {
+                self.queue.accept(&indices)
+            } else {
+                self.queue.reject(&indices);
+                Ok(())
+            };
+            self.save();
+            self.update_rows(cx);
+            if let Err(error) = result {
+                self.view.label(id!(status)).set_text(cx, &error);
+            }
+            self.redraw(cx);
+            cx.widget_action(
+                self.widget_uid(),
+                &scope.path,
+                TagReviewAction::Changed,
+            );
+        }
+    }
+}
+
+impl TagReview {
+    fn update_rows(&mut self, cx: &mut Cx) {
+        let filter = &self.filter;
+        self.rows = self
+            .queue
+            .pending
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| filter.is_empty() || filter.contains(&p.source))
+            .map(|(i, _)| i)
+            .collect();
+        self.checked.retain(|i| self.rows.contains(i));
+
+        let status = if self.queue.pending.is_empty() {
+            "Nothing to review".to_string()
+        } else if self.filter.is_empty() {
+            format!("{} suggestions", self.rows.len())
+        } else {
+            format!(
+                "{} suggestions for the selected images, {} in all",
+                self.rows.len(),
+                self.queue.pending.len()
+            )
+        };
+        self.view.label(id!(status)).set_text(cx, &status);
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if let Err(e) = self.queue.save(path) {
+            eprintln!("Error saving {path:?}: {e}");
+        }
+    }
+}
+
+impl TagReviewRef {
+    // Reads the queue left by earlier runs.
+    pub fn reload(&self, cx: &mut Cx) {
+        let Some(mut inner) = self.borrow_mut() else {
+            return;
+        };
+
+        inner.path = tagging::review_file();
+        inner.queue = inner
+            .path
+            .as_deref()
+            .map(ReviewQueue::load)
+            .unwrap_or_default();
+        inner.checked.clear();
+        inner.update_rows(cx);
+        inner.redraw(cx);
+    }
+
+    // Replaces what was waiting for `source` with new suggestions.
+    pub fn queue(
+        &self,
+        cx: &mut Cx,
+        source: &ImageSource,
+        suggestions: Vec<TagSuggestion>,
+    ) {
+        let Some(mut inner) = self.borrow_mut() else {
+            return;
+        };
+
+        // Indices after the image's old suggestions shift.
+        inner.checked.clear();
+        inner.queue.queue(source, suggestions);
+        inner.save();
+        inner.update_rows(cx);
+        inner.redraw(cx);
+    }
+
+    pub fn set_filter(&self, cx: &mut Cx, sources: Vec<ImageSource>) {
+        let Some(mut inner) = self.borrow_mut() else {
+            return;
+        };
+
+        inner.filter = sources;
+        inner.update_rows(cx);
+        inner.redraw(cx);
+    }
+
+    // How many suggestions wait for each image.
+    pub fn pending_counts(&self) -> HashMap<ImageSource, usize> {
+        let Some(inner) = self.borrow() else {
+            return HashMap::new();
+        };
+        inner.queue.counts()
+    }
+}
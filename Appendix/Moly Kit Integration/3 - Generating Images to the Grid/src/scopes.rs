@@ -0,0 +1,211 @@
+use makepad_widgets::*;
+
+use crate::decoders::DecodedImage;
+use crate::display::DisplaySettings;
+
+pub const SCOPE_WIDTH: usize = 256;
+pub const SCOPE_HEIGHT: usize = 128;
+
+const LEVELS: usize = 256;
+const MAX_SAMPLES_PER_AXIS: usize = 512;
+const HIGHLIGHT_CLIP: f32 = 254.0 / 255.0;
+const SHADOW_CLIP: f32 = 1.0 / 255.0;
+
+pub struct ScopeRaster {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u32>,
+}
+
+impl ScopeRaster {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![0; width * height],
+        }
+    }
+
+    fn add(&mut self, x: usize, y: usize, rgb: [f32; 3]) {
+        let pixel = &mut self.data[y * self.width + x];
+        let [r, g, b] = unpack(*pixel);
+        let add = |c: u8, v: f32| (c as f32 + v * 255.0).min(255.0) as u8;
+        *pixel = pack(add(r, rgb[0]), add(g, rgb[1]), add(b, rgb[2]), 255);
+    }
+
+    pub fn into_texture(self, cx: &mut Cx) -> Texture {
+        Texture::new_with_format(
+            cx,
+            TextureFormat::VecBGRAu8_32 {
+                width: self.width,
+                height: self.height,
+                data: Some(self.data),
+                updated: TextureUpdated::Full,
+            },
+        )
+    }
+}
+
+pub struct Scopes {
+    pub histogram: ScopeRaster,
+    pub waveform: ScopeRaster,
+    pub parade: ScopeRaster,
+    pub highlights_clipped: f32,
+    pub shadows_clipped: f32,
+    // The brightest channel value in the file, before tone mapping.
+    pub peak: f32,
+}
+
+impl Scopes {
+    // Measures the image as `display` shows it.
+    pub fn compute(pixels: &DecodedImage, display: &DisplaySettings) -> Self {
+        let step_x = pixels.width.div_ceil(MAX_SAMPLES_PER_AXIS).max(1);
+        let step_y = pixels.height.div_ceil(MAX_SAMPLES_PER_AXIS).max(1);
+
+        let mut histogram = [[0u32; LEVELS]; 4];
+        let mut waveform = vec![[0u32; LEVELS]; SCOPE_WIDTH];
+        let mut parade = vec![[[0u32; LEVELS]; 3]; SCOPE_WIDTH];
+        let mut samples = 0u32;
+        let mut highlights = 0u32;
+        let mut shadows = 0u32;
+
+        let sample_points = || {
+            (0..pixels.height).step_by(step_y).flat_map(move |y| {
+                (0..pixels.width).step_by(step_x).map(move |x| (x, y))
+            })
+        };
+
+        let peak = sample_points()
+            .map(|(x, y)| {
+                let [r, g, b, _] = pixels.sample(x, y);
+                r.max(g).max(b)
+            })
+            .fold(0.0f32, f32::max);
+        let displayed =
+            |x: usize, y: usize| display.displayed(pixels.sample(x, y));
+        let range = sample_points()
+            .map(|(x, y)| {
+                let [r, g, b, _] = displayed(x, y);
+                r.max(g).max(b)
+            })
+            .fold(1.0f32, f32::max);
+        let level = |v: f32| {
+            ((v / range).clamp(0.0, 1.0) * (LEVELS - 1) as f32).round() as u8
+        };
+
+        for (x, y) in sample_points() {
+            let [rf, gf, bf, _] = displayed(x, y);
+            let (r, g, b) = (level(rf), level(gf), level(bf));
+            let luma = luminance(r, g, b);
+            let column = x * SCOPE_WIDTH / pixels.width;
+
+            histogram[0][luma as usize] += 1;
+            histogram[1][r as usize] += 1;
+            histogram[2][g as usize] += 1;
+            histogram[3][b as usize] += 1;
+            waveform[column][luma as usize] += 1;
+            for (channel, value) in [r, g, b].into_iter().enumerate() {
+                parade[column][channel][value as usize] += 1;
+            }
+
+            samples += 1;
+            if rf.max(gf).max(bf) >= HIGHLIGHT_CLIP {
+                highlights += 1;
+            }
+            if rf.max(gf).max(bf) <= SHADOW_CLIP {
+                shadows += 1;
+            }
+        }
+
+        let samples = samples.max(1) as f32;
+        Self {
+            histogram: rasterize_histogram(&histogram),
+            waveform: rasterize_waveform(&waveform),
+            parade: rasterize_parade(&parade),
+            highlights_clipped: highlights as f32 / samples,
+            shadows_clipped: shadows as f32 / samples,
+            peak,
+        }
+    }
+}
+
+const CHANNEL_COLORS: [[f32; 3]; 4] = [
+    [0.35, 0.35, 0.35],
+    [0.8, 0.1, 0.1],
+    [0.1, 0.8, 0.1],
+    [0.1, 0.2, 0.9],
+];
+
+fn rasterize_histogram(histogram: &[[u32; LEVELS]; 4]) -> ScopeRaster {
+    let mut raster = ScopeRaster::new(SCOPE_WIDTH, SCOPE_HEIGHT);
+
+    for (channel, bins) in histogram.iter().enumerate() {
+        let peak = bins.iter().copied().max().unwrap_or(0).max(1) as f32;
+        for (x, &count) in bins.iter().enumerate() {
+            let height = (count as f32 / peak).sqrt() * SCOPE_HEIGHT as f32;
+            for y in 0..height as usize {
+                raster.add(x, SCOPE_HEIGHT - 1 - y, CHANNEL_COLORS[channel]);
+            }
+        }
+    }
+
+    raster
+}
+
+fn rasterize_waveform(waveform: &[[u32; LEVELS]]) -> ScopeRaster {
+    let mut raster = ScopeRaster::new(SCOPE_WIDTH, SCOPE_HEIGHT);
+    let peak = peak_count(waveform.iter().flatten());
+
+    for (x, levels) in waveform.iter().enumerate() {
+        for (level, &count) in levels.iter().enumerate() {
+            let y = SCOPE_HEIGHT - 1 - level * SCOPE_HEIGHT / LEVELS;
+            let intensity = trace_intensity(count, peak);
+            raster.add(x, y, [intensity * 0.4, intensity, intensity * 0.4]);
+        }
+    }
+
+    raster
+}
+
+fn rasterize_parade(parade: &[[[u32; LEVELS]; 3]]) -> ScopeRaster {
+    let mut raster = ScopeRaster::new(SCOPE_WIDTH, SCOPE_HEIGHT);
+    let peak = peak_count(parade.iter().flatten().flatten());
+    let section = SCOPE_WIDTH / 3;
+
+    for (column, channels) in parade.iter().enumerate() {
+        for (channel, levels) in channels.iter().enumerate() {
+            let x = channel * section + column * section / SCOPE_WIDTH;
+            let color = CHANNEL_COLORS[channel + 1];
+            for (level, &count) in levels.iter().enumerate() {
+                let y = SCOPE_HEIGHT - 1 - level * SCOPE_HEIGHT / LEVELS;
+                let intensity = trace_intensity(count, peak) * 1.5;
+                raster.add(x, y, color.map(|c| c * intensity));
+            }
+        }
+    }
+
+    raster
+}
+
+fn peak_count<'a>(counts: impl Iterator<Item = &'a u32>) -> f32 {
+    counts.copied().max().unwrap_or(0).max(1) as f32
+}
+
+fn trace_intensity(count: u32, peak: f32) -> f32 {
+    if count == 0 {
+        return 0.0;
+    }
+    ((count as f32).ln_1p() / peak.ln_1p()).max(0.15)
+}
+
+fn luminance(r: u8, g: u8, b: u8) -> u8 {
+    (0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32).round() as u8
+}
+
+fn unpack(pixel: u32) -> [u8; 3] {
+    [(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8]
+}
+
+fn pack(r: u8, g: u8, b: u8, a: u8) -> u32 {
+    (a as u32) << 24 | (r as u32) << 16 | (g as u32) << 8 | b as u32
+}
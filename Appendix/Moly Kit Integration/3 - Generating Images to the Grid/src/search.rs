@@ -0,0 +1,584 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+use crate::attachments::{self, AttachmentFormat};
+use crate::config;
+use crate::providers::Providers;
+use crate::source::ImageSource;
+
+const SEARCH_FILE: &str = "search.toml";
+const INDEX_FILE: &str = "search_index.json";
+// Images go to embedding endpoints this small, which is plenty for CLIP.
+const ENDPOINT_IMAGE_SIZE: u32 = 512;
+// How many new vectors are kept in memory before the index is saved.
+const SAVE_EVERY: usize = 25;
+
+// Which model embeds images and queries, read from `search.toml`. Either an
+// OpenAI-compatible endpoint from `providers.toml` that embeds images, such
+// as one serving a CLIP model:
+//
+//     provider = "local"
+//     model = "clip-vit-base-patch32"
+//
+// or, built with the `onnx` feature, a CLIP model exported to ONNX, as a
+// folder with `vision_model.onnx`, `text_model.onnx` and `tokenizer.json`:
+//
+//     onnx_dir = "/home/me/models/clip"
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SearchSettings {
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub onnx_dir: Option<PathBuf>,
+}
+
+impl SearchSettings {
+    pub fn load() -> Self {
+        let Some(path) = config::config_file(SEARCH_FILE) else {
+            return Self::default();
+        };
+        let Ok(text) = std::fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        toml::from_str(&text)
+            .inspect_err(|e| eprintln!("Error reading {path:?}: {e}"))
+            .unwrap_or_default()
+    }
+
+    // `None` when search isn't set up.
+    pub(crate) fn embedder(
+        &self,
+        providers: &Providers,
+    ) -> Result<Option<Box<dyn Embedder>>, String> {
+        if let Some(dir) = &self.onnx_dir {
+            #[cfg(feature = "onnx")]
+            return Ok(Some(Box::new(onnx::OnnxEmbedder::load(dir)?)));
+            #[cfg(not(feature = "onnx"))]
+            return Err(format!(
+                "Can't use {}: built without the onnx feature",
+                dir.display()
+            ));
+        }
+
+        let (Some(name), Some(model)) = (&self.provider, &self.model) else {
+            return Ok(None);
+        };
+        let provider = providers
+            .get(name)
+            .ok_or_else(|| format!("Provider '{name}' isn't configured"))?;
+        let embedder =
+            EndpointEmbedder::new(&provider.url, provider.api_key()?, model);
+        Ok(Some(Box::new(embedder)))
+    }
+}
+
+// Turns images and text into vectors that are close when they match.
+// Calls block, so they belong on a thread of their own.
+pub trait Embedder: Send {
+    // Vectors from different models can't be compared, so the index is
+    // only kept for the model that built it.
+    fn model(&self) -> String;
+    fn embed_image(&mut self, source: &ImageSource)
+    -> Result<Vec<f32>, String>;
+    fn embed_text(&mut self, text: &str) -> Result<Vec<f32>, String>;
+}
+
+// An OpenAI-compatible `/embeddings` endpoint. Images are sent as data URLs
+// with `"modality": "image"`, which the servers that embed them go by.
+pub struct EndpointEmbedder {
+    client: reqwest::blocking::Client,
+    url: String,
+    key: Option<String>,
+    model: String,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingReply {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    embedding: Vec<f32>,
+}
+
+impl EndpointEmbedder {
+    pub fn new(url: &str, key: Option<String>, model: &str) -> Self {
+        Self {
+            client: reqwest::blocking::Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            key,
+            model: model.to_string(),
+        }
+    }
+
+    fn embed(&self, input: String, image: bool) -> Result<Vec<f32>, String> {
+        let mut body = json!({ "model": self.model, "input": [input] });
+        if image {
+            body["modality"] = json!("image");
+        }
+        let mut request = self
+            .client
+            .post(format!("{}/embeddings", self.url))
+            .json(&body);
+        if let Some(key) = &self.key {
+            request = request.bearer_auth(key);
+        }
+
+        let response = request.send().map_err(|e| e.to_string())?;
+        let status = response.status();
+        if !status.is_success() {
+            let text = response.text().unwrap_or_default();
+            return Err(format!("{}: {status}: {text}", self.url));
+        }
+        let reply: EmbeddingReply = response
+            .json()
+            .map_err(|e| format!("Unexpected reply from {}: {e}", self.url))?;
+        reply
+            .data
+            .into_iter()
+            .next()
+            .map(|data| data.embedding)
+            .filter(|embedding| !embedding.is_empty())
+            .ok_or_else(|| format!("{} returned no embedding", self.url))
+    }
+}
+
+impl Embedder for EndpointEmbedder {
+    fn model(&self) -> String {
+        format!("{}/{}", self.url, self.model)
+    }
+
+    fn embed_image(
+        &mut self,
+        source: &ImageSource,
+    ) -> Result<Vec<f32>, String> {
+        let image = attachments::fit_long_edge(
+            attachments::decode_rgba(source)?,
+            ENDPOINT_IMAGE_SIZE,
+        );
+        let jpeg = attachments::encode(&image, AttachmentFormat::Jpeg, 90)
+            .map_err(|e| format!("Error encoding {source}: {e}"))?;
+        let url =
+            format!("data:image/jpeg;base64,{}", attachments::base64(&jpeg));
+        self.embed(url, true)
+    }
+
+    fn embed_text(&mut self, text: &str) -> Result<Vec<f32>, String> {
+        self.embed(text.to_string(), false)
+    }
+}
+
+#[cfg(feature = "onnx")]
+mod onnx {
+    use image::imageops::{self, FilterType};
+    use ort::session::{Session, SessionOutputs};
+    use ort::value::Tensor;
+    use std::path::Path;
+    use tokenizers::Tokenizer;
+
+    use super::Embedder;
+    use crate::attachments;
+    use crate::source::ImageSource;
+
+    const IMAGE_SIZE: u32 = 224;
+    const MEAN: [f32; 3] = [0.481_454_66, 0.457_827_5, 0.408_210_73];
+    const STD: [f32; 3] = [0.268_629_54, 0.261_302_6, 0.275_777_1];
+    const CONTEXT_LENGTH: usize = 77;
+
+    // CLIP's two halves as exported for ONNX, with its tokenizer.
+    pub struct OnnxEmbedder {
+        vision: Session,
+        text: Session,
+        tokenizer: Tokenizer,
+        name: String,
+    }
+
+    impl OnnxEmbedder {
+        pub fn load(dir: &Path) -> Result<Self, String> {
+            let session = |name: &str| {
+                Session::builder()
+                    .and_then(|builder| {
+                        builder.commit_from_file(dir.join(name))
+                    })
+                    .map_err(|e| format!("Error loading {name}: {e}"))
+            };
+            let tokenizer = Tokenizer::from_file(dir.join("tokenizer.json"))
+                .map_err(|e| format!("Error loading tokenizer.json: {e}"))?;
+            Ok(Self {
+                vision: session("vision_model.onnx")?,
+                text: session("text_model.onnx")?,
+                tokenizer,
+                name: format!("onnx:{}", dir.display()),
+            })
+        }
+    }
+
+    impl Embedder for OnnxEmbedder {
+        fn model(&self) -> String {
+            self.name.clone()
+        }
+
+        // Preprocessed the way CLIP was trained: the short side scaled to
+        // 224, the middle cropped and the channels normalized.
+        fn embed_image(
+            &mut self,
+            source: &ImageSource,
+        ) -> Result<Vec<f32>, String> {
+            let image = attachments::decode_rgba(source)?;
+            let scale =
+                IMAGE_SIZE as f64 / image.width().min(image.height()) as f64;
+            let width =
+                ((image.width() as f64 * scale).round() as u32).max(IMAGE_SIZE);
+            let height = ((image.height() as f64 * scale).round() as u32)
+                .max(IMAGE_SIZE);
+            let resized =
+                imageops::resize(&image, width, height, FilterType::CatmullRom);
+            let cropped = imageops::crop_imm(
+                &resized,
+                (width - IMAGE_SIZE) / 2,
+                (height - IMAGE_SIZE) / 2,
+                IMAGE_SIZE,
+                IMAGE_SIZE,
+            )
+            .to_image();
+
+            let size = IMAGE_SIZE as usize;
+            let mut pixels = vec![0.0; 3 * size * size];
+            for (x, y, pixel) in cropped.enumerate_pixels() {
+                for c in 0..3 {
+                    let value = pixel[c] as f32 / 255.0;
+                    pixels[(c * size + y as usize) * size + x as usize] =
+                        (value - MEAN[c]) / STD[c];
+                }
+            }
+            let input = Tensor::from_array(([1, 3, size, size], pixels))
+                .map_err(|e| e.to_string())?;
+            let outputs = self
+                .vision
+                .run(ort::inputs!["pixel_values" => input])
+                .map_err(|e| e.to_string())?;
+            output(&outputs, "image_embeds")
+        }
+
+        fn embed_text(&mut self, text: &str) -> Result<Vec<f32>, String> {
+            let encoding = self
+                .tokenizer
+                .encode(text, true)
+                .map_err(|e| e.to_string())?;
+            let mut ids: Vec<i64> = encoding
+                .get_ids()
+                .iter()
+                .take(CONTEXT_LENGTH)
+                .map(|&id| id as i64)
+                .collect();
+            let mut mask = vec![1_i64; ids.len()];
+            ids.resize(CONTEXT_LENGTH, 0);
+            mask.resize(CONTEXT_LENGTH, 0);
+
+            let ids = Tensor::from_array(([1, CONTEXT_LENGTH], ids))
+                .map_err(|e| e.to_string())?;
+            let mask = Tensor::from_array(([1, CONTEXT_LENGTH], mask))
+                .map_err(|e| e.to_string())?;
+            let outputs = self
+                .text
+                .run(ort::inputs![
+                    "input_ids" => ids,
+                    "attention_mask" => mask,
+                ])
+                .map_err(|e| e.to_string())?;
+            output(&outputs, "text_embeds")
+        }
+    }
+
+    fn output(
+        outputs: &SessionOutputs,
+        name: &str,
+    ) -> Result<Vec<f32>, String> {
+        let value = outputs
+            .get(name)
+            .ok_or_else(|| format!("The model has no {name} output"))?;
+        let (_, data) = value
+            .try_extract_tensor::<f32>()
+            .map_err(|e| e.to_string())?;
+        Ok(data.to_vec())
+    }
+}
+
+pub fn index_file() -> Option<PathBuf> {
+    config::config_file(INDEX_FILE)
+}
+
+// What the file looked like when it was embedded, so changed images are
+// embedded again. Images in archives go by the archive.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Stamp {
+    modified: u64,
+    len: u64,
+}
+
+impl Stamp {
+    pub fn of(source: &ImageSource) -> Option<Self> {
+        let path = source.file_path().or(source.archive())?;
+        let metadata = std::fs::metadata(path).ok()?;
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH);
+        Some(Self {
+            modified: modified.ok()?.as_secs(),
+            len: metadata.len(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct IndexEntry {
+    source: ImageSource,
+    stamp: Stamp,
+    vector: Vec<f32>,
+}
+
+// Each image's vector, normalized so similarity is a dot product.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SearchIndex {
+    model: String,
+    entries: Vec<IndexEntry>,
+    #[serde(skip)]
+    positions: HashMap<ImageSource, usize>,
+}
+
+impl SearchIndex {
+    pub fn new(model: &str) -> Self {
+        Self {
+            model: model.to_string(),
+            ..Default::default()
+        }
+    }
+
+    // Starts over when the index was built by another model.
+    pub fn load(path: &Path, model: &str) -> Self {
+        let index = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|json| {
+                serde_json::from_str::<Self>(&json)
+                    .inspect_err(|e| eprintln!("Error reading {path:?}: {e}"))
+                    .ok()
+            })
+            .filter(|index| index.model == model);
+        let Some(mut index) = index else {
+            return Self::new(model);
+        };
+        index.positions = index
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| (entry.source.clone(), i))
+            .collect();
+        index
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let json = serde_json::to_string(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        std::fs::write(path, json)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Whether the image has a vector from its current contents.
+    pub fn is_current(&self, source: &ImageSource) -> bool {
+        let Some(&i) = self.positions.get(source) else {
+            return false;
+        };
+        Stamp::of(source) == Some(self.entries[i].stamp)
+    }
+
+    pub fn insert(
+        &mut self,
+        source: &ImageSource,
+        stamp: Stamp,
+        vector: &[f32],
+    ) {
+        let entry = IndexEntry {
+            source: source.clone(),
+            stamp,
+            vector: normalized(vector),
+        };
+        match self.positions.get(source) {
+            Some(&i) => self.entries[i] = entry,
+            None => {
+                self.positions.insert(source.clone(), self.entries.len());
+                self.entries.push(entry);
+            }
+        }
+    }
+
+    pub fn vector(&self, source: &ImageSource) -> Option<&[f32]> {
+        let &i = self.positions.get(source)?;
+        Some(&self.entries[i].vector)
+    }
+
+    // `sources` most similar to `query` first. Images not in the index yet
+    // follow in their order.
+    pub fn rank(
+        &self,
+        query: &[f32],
+        sources: &[ImageSource],
+    ) -> Vec<ImageSource> {
+        let query = normalized(query);
+        let mut scored: Vec<(f32, &ImageSource)> = sources
+            .iter()
+            .map(|source| {
+                let score = self
+                    .vector(source)
+                    .filter(|vector| vector.len() == query.len())
+                    .map(|vector| dot(vector, &query))
+                    .unwrap_or(f32::NEG_INFINITY);
+                (score, source)
+            })
+            .collect();
+        // Stable, so the unindexed keep their order.
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored
+            .into_iter()
+            .map(|(_, source)| source.clone())
+            .collect()
+    }
+}
+
+fn normalized(vector: &[f32]) -> Vec<f32> {
+    let length = dot(vector, vector).sqrt();
+    if length == 0.0 {
+        return vector.to_vec();
+    }
+    vector.iter().map(|v| v / length).collect()
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+// The embedder and index shared by the indexing thread and searches, which
+// all block and belong off the UI thread.
+#[derive(Clone)]
+pub struct Search {
+    embedder: Arc<Mutex<Box<dyn Embedder>>>,
+    index: Arc<Mutex<SearchIndex>>,
+    index_file: Option<PathBuf>,
+}
+
+impl Search {
+    pub fn new(
+        embedder: Box<dyn Embedder>,
+        index_file: Option<PathBuf>,
+    ) -> Self {
+        let model = embedder.model();
+        let index = match &index_file {
+            Some(path) => SearchIndex::load(path, &model),
+            None => SearchIndex::new(&model),
+        };
+        Self {
+            embedder: Arc::new(Mutex::new(embedder)),
+            index: Arc::new(Mutex::new(index)),
+            index_file,
+        }
+    }
+
+    // `None` when search isn't set up in `search.toml`.
+    pub(crate) fn load(providers: &Providers) -> Result<Option<Self>, String> {
+        let embedder = SearchSettings::load().embedder(providers)?;
+        Ok(embedder.map(|embedder| Self::new(embedder, index_file())))
+    }
+
+    // Embeds the images without a current vector, reporting how many of
+    // them are done. Returns how many failed.
+    pub fn update(
+        &self,
+        sources: &[ImageSource],
+        cancelled: &AtomicBool,
+        mut on_progress: impl FnMut(usize, usize),
+    ) -> usize {
+        let stale: Vec<&ImageSource> = {
+            let index = self.index.lock().unwrap();
+            sources.iter().filter(|s| !index.is_current(s)).collect()
+        };
+
+        let mut failed = 0;
+        for (i, source) in stale.iter().enumerate() {
+            if cancelled.load(Ordering::Relaxed) {
+                break;
+            }
+            let Some(stamp) = Stamp::of(source) else {
+                failed += 1;
+                continue;
+            };
+            let result = self.embedder.lock().unwrap().embed_image(source);
+            match result {
+                Ok(vector) => {
+                    let mut index = self.index.lock().unwrap();
+                    index.insert(source, stamp, &vector);
+                }
+                Err(e) => {
+                    eprintln!("Error indexing {source}: {e}");
+                    failed += 1;
+                }
+            }
+            if (i + 1) % SAVE_EVERY == 0 {
+                self.save();
+            }
+            on_progress(i + 1, stale.len());
+        }
+        if !stale.is_empty() {
+            self.save();
+        }
+        failed
+    }
+
+    // `sources` ranked by how well they match a description.
+    pub fn search(
+        &self,
+        query: &str,
+        sources: &[ImageSource],
+    ) -> Result<Vec<ImageSource>, String> {
+        let vector = self.embedder.lock().unwrap().embed_text(query)?;
+        Ok(self.index.lock().unwrap().rank(&vector, sources))
+    }
+
+    // `sources` ranked by how much they look like `source`, which comes
+    // first.
+    pub fn similar(
+        &self,
+        source: &ImageSource,
+        sources: &[ImageSource],
+    ) -> Result<Vec<ImageSource>, String> {
+        let indexed = self.index.lock().unwrap().vector(source).map(Vec::from);
+        let vector = match indexed {
+            Some(vector) => vector,
+            None => self.embedder.lock().unwrap().embed_image(source)?,
+        };
+        Ok(self.index.lock().unwrap().rank(&vector, sources))
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.index_file else {
+            return;
+        };
+        let index = self.index.lock().unwrap();
+        if let Err(e) = index.save(path) {
+            eprintln!("Error saving {path:?}: {e}");
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use makepad_widgets::*;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::PathBuf;
+
+use crate::config;
+use crate::display::Background;
+use crate::source::ImageSource;
+
+const SESSION_FILE: &str = "session.json";
+const MAX_RECENT_ROOTS: usize = 10;
+
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Page {
+    #[default]
+    ImageBrowser,
+    Slideshow,
+    Compare,
+    Settings,
+    Conversations,
+}
+
+impl Page {
+    pub fn live_id(self) -> LiveId {
+        match self {
+            Self::ImageBrowser => live_id!(image_browser),
+            Self::Slideshow => live_id!(slideshow),
+            Self::Compare => live_id!(compare),
+            Self::Settings => live_id!(settings),
+            Self::Conversations => live_id!(history),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct WindowGeometry {
+    pub position: [f64; 2],
+    pub size: [f64; 2],
+}
+
+impl From<&WindowGeom> for WindowGeometry {
+    fn from(geom: &WindowGeom) -> Self {
+        Self {
+            position: [geom.position.x, geom.position.y],
+            size: [geom.inner_size.x, geom.inner_size.y],
+        }
+    }
+}
+
+// Everything needed to pick up where the last launch left off. Fields
+// missing from older files fall back to their defaults.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    // Most recently opened first.
+    pub roots: Vec<PathBuf>,
+    pub page: Page,
+    pub current_image: Option<ImageSource>,
+    pub grid_row: usize,
+    pub window: Option<WindowGeometry>,
+    pub show_scopes: bool,
+    pub show_loupe: bool,
+    pub show_tone_controls: bool,
+    pub spread: bool,
+    pub right_to_left: bool,
+    pub background: Background,
+    // `None` follows the desktop's light or dark preference.
+    pub theme: Option<String>,
+    pub hide_image_chat: bool,
+    pub hide_slideshow_chat: bool,
+    pub show_selection_chat: bool,
+    // The grid's order while it's ranked by a search or filtered, with the
+    // query that ranked it, if any.
+    pub grid_order: Option<Vec<ImageSource>>,
+    pub search: String,
+}
+
+impl Session {
+    pub fn load() -> Option<Self> {
+        let path = config::config_file(SESSION_FILE)?;
+        let json = std::fs::read_to_string(&path).ok()?;
+        serde_json::from_str(&json)
+            .inspect_err(|e| eprintln!("Error reading {path:?}: {e}"))
+            .ok()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = config::config_file(SESSION_FILE).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "no config directory")
+        })?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let json =
+            serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        std::fs::write(path, json)
+    }
+}
+
+pub fn add_recent_root(roots: &mut Vec<PathBuf>, root: PathBuf) {
+    roots.retain(|r| *r != root);
+    roots.insert(0, root);
+    roots.truncate(MAX_RECENT_ROOTS);
+}
@@ -1,22 +1,34 @@
-use moly_kit::{OpenAIClient, protocol::*};
+use futures::StreamExt;
+use futures::channel::mpsc::{self, UnboundedSender};
+use moly_kit::protocol::*;
+use moly_kit::utils::asynchronous::spawn;
 use std::sync::{Arc, Mutex};
 
+use crate::attachments::{
+    self, AttachmentOptions, PreparedAttachment, PreparedImages,
+};
+use crate::source::ImageSource;
+
+// Stops a model that keeps calling tools without ever answering.
+const MAX_TOOL_ROUNDS: usize = 8;
+
+// Runs the calls the model makes to the app's tools, with a result for
+// each.
+pub type ToolHandler = Arc<
+    dyn Fn(Vec<ToolCall>) -> BoxPlatformSendFuture<'static, Vec<ToolResult>>
+        + Send
+        + Sync,
+>;
+
 struct SlideshowClientInner {
-    attachment: Option<Attachment>,
-    openai_client: OpenAIClient,
+    attachments: Vec<Attachment>,
+    client: Box<dyn BotClient>,
+    tools: Vec<Tool>,
+    tool_handler: Option<ToolHandler>,
 }
 
 pub struct SlideshowClient(Arc<Mutex<SlideshowClientInner>>);
 
-impl From<OpenAIClient> for SlideshowClient {
-    fn from(openai_client: OpenAIClient) -> Self {
-        SlideshowClient(Arc::new(Mutex::new(SlideshowClientInner {
-            attachment: None,
-            openai_client,
-        })))
-    }
-}
-
 impl Clone for SlideshowClient {
     fn clone(&self) -> Self {
         SlideshowClient(Arc::clone(&self.0))
@@ -25,7 +37,7 @@ impl Clone for SlideshowClient {
 
 impl BotClient for SlideshowClient {
     fn bots(&self) -> BoxPlatformSendFuture<'static, ClientResult<Vec<Bot>>> {
-        self.0.lock().unwrap().openai_client.bots()
+        self.0.lock().unwrap().client.bots()
     }
 
     fn send(
@@ -36,12 +48,13 @@ impl BotClient for SlideshowClient {
     ) -> BoxPlatformSendStream<'static, ClientResult<MessageContent>> {
         let mut messages = messages.to_vec();
 
-        if let Some(attachment) = &self.0.lock().unwrap().attachment {
+        let attachments = self.0.lock().unwrap().attachments.clone();
+        if !attachments.is_empty() {
             messages.insert(
                 0,
                 Message {
                     content: MessageContent {
-                        attachments: vec![attachment.clone()],
+                        attachments,
                         ..Default::default()
                     },
                     from: EntityId::User,
@@ -50,11 +63,21 @@ impl BotClient for SlideshowClient {
             );
         }
 
-        self.0
-            .lock()
-            .unwrap()
-            .openai_client
-            .send(bot_id, &messages, tools)
+        let inner = self.0.lock().unwrap();
+        let Some(handler) = inner.tool_handler.clone() else {
+            return inner.client.send(bot_id, &messages, tools);
+        };
+        let mut tools = tools.to_vec();
+        tools.extend(inner.tools.iter().cloned());
+
+        let client = inner.client.clone_box();
+        let bot_id = bot_id.clone();
+        let (sender, receiver) = mpsc::unbounded();
+        spawn(async move {
+            send_with_tools(client, bot_id, messages, tools, handler, sender)
+                .await;
+        });
+        Box::pin(receiver)
     }
 
     fn clone_box(&self) -> Box<dyn BotClient> {
@@ -63,7 +86,133 @@ impl BotClient for SlideshowClient {
 }
 
 impl SlideshowClient {
-    pub fn set_attachment(&self, attachment: Option<Attachment>) {
-        self.0.lock().unwrap().attachment = attachment;
+    pub fn new(client: impl BotClient + 'static) -> Self {
+        SlideshowClient(Arc::new(Mutex::new(SlideshowClientInner {
+            attachments: Vec::new(),
+            client: Box::new(client),
+            tools: Vec::new(),
+            tool_handler: None,
+        })))
+    }
+
+    // Offers `tools` to the model along with the chat's own, with `handler`
+    // running the calls to them.
+    pub fn set_tools(&self, tools: Vec<Tool>, handler: ToolHandler) {
+        let mut inner = self.0.lock().unwrap();
+        inner.tools = tools;
+        inner.tool_handler = Some(handler);
+    }
+
+    pub fn set_attachments(&self, attachments: Vec<Attachment>) {
+        self.0.lock().unwrap().attachments = attachments;
     }
+
+    // Sends the image along with every following message, prepared as
+    // `options` say. Decoding and encoding can take a while for large
+    // images, so this is best called off the UI thread.
+    pub fn attach_image(
+        &self,
+        source: &ImageSource,
+        options: &AttachmentOptions,
+    ) -> Result<PreparedAttachment, String> {
+        let prepared = attachments::prepare(source, options)?;
+        self.set_attachments(vec![prepared.attachment.clone()]);
+        Ok(prepared)
+    }
+
+    // What the chat says before sending the image: how much smaller it got,
+    // or, as an error, why it couldn't be attached and nothing is sent.
+    pub fn prepare_send(
+        &self,
+        source: &ImageSource,
+        options: &AttachmentOptions,
+    ) -> Result<Option<String>, String> {
+        Ok(self.attach_image(source, options)?.summary())
+    }
+
+    // Like `attach_image`, for as many of `sources` as `options` allow.
+    pub fn attach_images(
+        &self,
+        sources: &[ImageSource],
+        options: &AttachmentOptions,
+    ) -> PreparedImages {
+        let prepared = attachments::prepare_all(sources, options);
+        self.set_attachments(
+            prepared
+                .attachments
+                .iter()
+                .map(|p| p.attachment.clone())
+                .collect(),
+        );
+        prepared
+    }
+}
+
+// Sends until the model answers without calling tools, running the calls in
+// between. The chat only sees text, with a line for each round of calls.
+async fn send_with_tools(
+    mut client: Box<dyn BotClient>,
+    bot_id: BotId,
+    mut messages: Vec<Message>,
+    tools: Vec<Tool>,
+    handler: ToolHandler,
+    sender: UnboundedSender<ClientResult<MessageContent>>,
+) {
+    let mut earlier = String::new();
+    for _ in 0..MAX_TOOL_ROUNDS {
+        let mut stream = client.send(&bot_id, &messages, &tools);
+        let mut last = None;
+        while let Some(result) = stream.next().await {
+            if !result.errors().is_empty() {
+                let _ = sender.unbounded_send(result);
+                return;
+            }
+            let Some(content) = result.into_value() else {
+                continue;
+            };
+            let shown = MessageContent {
+                text: format!("{earlier}{}", content.text),
+                tool_calls: Vec::new(),
+                ..content.clone()
+            };
+            let _ = sender.unbounded_send(ClientResult::new_ok(shown));
+            last = Some(content);
+        }
+
+        let Some(content) = last.filter(|c| !c.tool_calls.is_empty()) else {
+            return;
+        };
+        if !content.text.is_empty() {
+            earlier.push_str(&content.text);
+            earlier.push_str("\n\n");
+        }
+        let names: Vec<&str> =
+            content.tool_calls.iter().map(|c| c.name.as_str()).collect();
+        earlier.push_str(&format!("_Used {}_\n\n", names.join(", ")));
+        let _ = sender.unbounded_send(ClientResult::new_ok(MessageContent {
+            text: earlier.clone(),
+            ..Default::default()
+        }));
+
+        let results = handler(content.tool_calls.clone()).await;
+        messages.push(Message {
+            from: EntityId::Bot(bot_id.clone()),
+            content,
+            ..Default::default()
+        });
+        messages.push(Message {
+            from: EntityId::Tool,
+            content: MessageContent {
+                tool_results: results,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+    }
+
+    let _ =
+        sender.unbounded_send(ClientResult::new_err(vec![ClientError::new(
+            ClientErrorKind::Response,
+            "The model kept calling tools without answering".to_string(),
+        )]));
 }
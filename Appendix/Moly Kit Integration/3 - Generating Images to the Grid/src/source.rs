@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::archive;
+
+// Where an image's bytes come from. Images inside archives are read on
+// demand, so browsing one never extracts it to disk.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ImageSource {
+    File(PathBuf),
+    ArchiveEntry { archive: PathBuf, entry: String },
+}
+
+impl ImageSource {
+    // A path that looks like the image would if the archive were a folder.
+    // Only meant for extension lookups and display, it can't be opened.
+    pub fn path(&self) -> PathBuf {
+        match self {
+            Self::File(path) => path.clone(),
+            Self::ArchiveEntry { archive, entry } => archive.join(entry),
+        }
+    }
+
+    pub fn file_path(&self) -> Option<&Path> {
+        match self {
+            Self::File(path) => Some(path),
+            Self::ArchiveEntry { .. } => None,
+        }
+    }
+
+    pub fn archive(&self) -> Option<&Path> {
+        match self {
+            Self::File(_) => None,
+            Self::ArchiveEntry { archive, .. } => Some(archive),
+        }
+    }
+
+    pub fn file_name(&self) -> String {
+        self.path()
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    pub fn read(&self) -> io::Result<Vec<u8>> {
+        match self {
+            Self::File(path) => std::fs::read(path),
+            Self::ArchiveEntry { archive, entry } => {
+                archive::read_entry(archive, entry)
+            }
+        }
+    }
+
+    pub fn is_comic(&self) -> bool {
+        self.archive().is_some_and(archive::is_comic)
+    }
+}
+
+impl fmt::Display for ImageSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path().display())
+    }
+}
@@ -0,0 +1,380 @@
+use moly_kit::protocol::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+use crate::attachments::AttachmentOptions;
+use crate::batch::{BatchProgress, BatchRunner};
+use crate::config;
+use crate::source::ImageSource;
+use crate::tools::tool;
+use crate::xmp::Xmp;
+
+const TAGS_FILE: &str = "tags.toml";
+const REVIEW_FILE: &str = "tag_review.json";
+const SUGGEST_TAGS_TOOL: &str = "suggest_tags";
+
+// The controlled vocabulary and how tagging runs, read from `tags.toml`:
+//
+//     vocabulary = ["beach", "portrait", "night", "food"]
+//     threshold = 0.8
+//     concurrency = 4
+//     retries = 3
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct TagSettings {
+    pub vocabulary: Vec<String>,
+    // Tags at least this confident are written, the rest await review.
+    pub threshold: f32,
+    pub concurrency: usize,
+    pub retries: u32,
+}
+
+impl Default for TagSettings {
+    fn default() -> Self {
+        Self {
+            vocabulary: Vec::new(),
+            threshold: 0.8,
+            concurrency: 4,
+            retries: 3,
+        }
+    }
+}
+
+impl TagSettings {
+    pub fn load() -> Self {
+        let Some(path) = config::config_file(TAGS_FILE) else {
+            return Self::default();
+        };
+        let Ok(text) = std::fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        toml::from_str(&text)
+            .inspect_err(|e| eprintln!("Error reading {path:?}: {e}"))
+            .unwrap_or_default()
+    }
+
+    // Only allows tags from the vocabulary.
+    fn schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "tags": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "tag": { "type": "string", "enum": self.vocabulary },
+                            "confidence": {
+                                "type": "number",
+                                "minimum": 0,
+                                "maximum": 1,
+                            },
+                        },
+                        "required": ["tag", "confidence"],
+                        "additionalProperties": false,
+                    },
+                },
+            },
+            "required": ["tags"],
+            "additionalProperties": false,
+        })
+    }
+
+    // Where the model can call tools, its reply is a call to this one, so
+    // the provider holds it to the schema.
+    fn schema_tool(&self) -> Tool {
+        tool(
+            SUGGEST_TAGS_TOOL,
+            "Suggest tags for the image from the vocabulary.",
+            self.schema()["properties"].clone(),
+            &["tags"],
+        )
+    }
+
+    // Asks for JSON following the schema, for models that reply in text.
+    fn prompt(&self) -> String {
+        format!(
+            "Tag this image using only tags from this list: {}. Give each \
+             tag that applies a confidence from 0 to 1. Reply with JSON \
+             matching this schema and nothing else:\n{}",
+            self.vocabulary.join(", "),
+            self.schema()
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TagSuggestion {
+    pub tag: String,
+    pub confidence: f32,
+}
+
+#[derive(Deserialize)]
+struct TagReply {
+    tags: Vec<TagSuggestion>,
+}
+
+// Reads the model's JSON, even inside a code block, keeping only tags from
+// the vocabulary in its spelling, most confident first.
+pub fn parse_suggestions(
+    reply: &str,
+    vocabulary: &[String],
+) -> Result<Vec<TagSuggestion>, String> {
+    let start = reply.find('{');
+    let end = reply.rfind('}');
+    let json = match (start, end) {
+        (Some(start), Some(end)) if start < end => &reply[start..=end],
+        _ => return Err(format!("Expected JSON, got: {reply}")),
+    };
+    let parsed: TagReply = serde_json::from_str(json)
+        .map_err(|e| format!("Unexpected reply ({e}): {reply}"))?;
+
+    let mut suggestions: Vec<TagSuggestion> = Vec::new();
+    for suggestion in parsed.tags {
+        let Some(tag) = vocabulary
+            .iter()
+            .find(|t| t.eq_ignore_ascii_case(suggestion.tag.trim()))
+        else {
+            continue;
+        };
+        let confidence = suggestion.confidence.clamp(0.0, 1.0);
+        match suggestions.iter_mut().find(|s| s.tag == *tag) {
+            Some(existing) => {
+                existing.confidence = existing.confidence.max(confidence)
+            }
+            None => suggestions.push(TagSuggestion {
+                tag: tag.clone(),
+                confidence,
+            }),
+        }
+    }
+    suggestions.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+    Ok(suggestions)
+}
+
+pub fn review_file() -> Option<PathBuf> {
+    config::config_file(REVIEW_FILE)
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PendingTag {
+    pub source: ImageSource,
+    pub tag: String,
+    pub confidence: f32,
+}
+
+// Suggestions below the threshold, waiting to be accepted or rejected.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ReviewQueue {
+    pub pending: Vec<PendingTag>,
+}
+
+impl ReviewQueue {
+    pub fn load(path: &Path) -> Self {
+        let Ok(json) = std::fs::read_to_string(path) else {
+            return Self::default();
+        };
+        serde_json::from_str(&json)
+            .inspect_err(|e| eprintln!("Error reading {path:?}: {e}"))
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        std::fs::write(path, json)
+    }
+
+    // Replaces what was waiting for `source` with new suggestions.
+    pub fn queue(&mut self, source: &ImageSource, tags: Vec<TagSuggestion>) {
+        self.pending.retain(|p| p.source != *source);
+        self.pending.extend(tags.into_iter().map(|s| PendingTag {
+            source: source.clone(),
+            tag: s.tag,
+            confidence: s.confidence,
+        }));
+    }
+
+    // Writes the tags at `indices` to their images' keywords. Tags whose
+    // image couldn't be written stay queued.
+    pub fn accept(&mut self, indices: &[usize]) -> Result<(), String> {
+        let mut by_source: HashMap<ImageSource, Vec<String>> = HashMap::new();
+        for &i in indices {
+            if let Some(pending) = self.pending.get(i) {
+                by_source
+                    .entry(pending.source.clone())
+                    .or_default()
+                    .push(pending.tag.clone());
+            }
+        }
+
+        let mut errors = Vec::new();
+        let mut written = Vec::new();
+        for (source, tags) in by_source {
+            match add_keywords(&source, &tags) {
+                Ok(()) => written.push(source),
+                Err(e) => errors.push(format!("{source}: {e}")),
+            }
+        }
+
+        let mut i = 0;
+        self.pending.retain(|pending| {
+            let accepted =
+                indices.contains(&i) && written.contains(&pending.source);
+            i += 1;
+            !accepted
+        });
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("; "))
+        }
+    }
+
+    pub fn reject(&mut self, indices: &[usize]) {
+        let mut i = 0;
+        self.pending.retain(|_| {
+            let rejected = indices.contains(&i);
+            i += 1;
+            !rejected
+        });
+    }
+
+    pub fn counts(&self) -> HashMap<ImageSource, usize> {
+        let mut counts = HashMap::new();
+        for pending in &self.pending {
+            *counts.entry(pending.source.clone()).or_default() += 1;
+        }
+        counts
+    }
+}
+
+fn add_keywords(source: &ImageSource, tags: &[String]) -> Result<(), String> {
+    Xmp::edit(source, |xmp| xmp.add_keywords(tags)).map_err(|e| e.to_string())
+}
+
+pub type TagProgress = BatchProgress;
+
+#[derive(Clone, Debug)]
+pub enum TagEvent {
+    // `applied` went to the image's keywords, `queued` awaits review.
+    Tagged {
+        source: ImageSource,
+        applied: Vec<String>,
+        queued: Vec<TagSuggestion>,
+    },
+    Failed {
+        source: ImageSource,
+        error: String,
+    },
+}
+
+// Tags each image with a vision model from the vocabulary, writing the
+// confident tags to its XMP keywords. The rest come back in the events for
+// the caller to queue for review.
+pub struct TagJob {
+    client: Box<dyn BotClient>,
+    model: String,
+    sources: Vec<ImageSource>,
+    settings: TagSettings,
+    attachment_options: AttachmentOptions,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl TagJob {
+    pub fn new(
+        client: impl BotClient + 'static,
+        model: &str,
+        sources: Vec<ImageSource>,
+        settings: TagSettings,
+        attachment_options: AttachmentOptions,
+    ) -> Self {
+        Self {
+            client: Box::new(client),
+            model: model.to_string(),
+            sources,
+            settings,
+            attachment_options,
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    // Setting it stops the job before the next image.
+    pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.cancelled)
+    }
+
+    pub async fn run(
+        self,
+        mut on_update: impl FnMut(&TagProgress, &TagEvent) + Send,
+    ) -> Result<TagProgress, String> {
+        let Self {
+            client,
+            model,
+            sources,
+            settings,
+            attachment_options,
+            cancelled,
+        } = self;
+        if settings.vocabulary.is_empty() {
+            return Err(format!("No vocabulary. List the tags in {TAGS_FILE}"));
+        }
+        let runner = BatchRunner::connect(
+            client,
+            &model,
+            attachment_options,
+            settings.retries,
+            settings.concurrency,
+            cancelled,
+        )
+        .await?
+        .with_schema(settings.schema_tool());
+
+        let mut progress = TagProgress {
+            total: sources.len(),
+            ..Default::default()
+        };
+        let prompt = settings.prompt();
+        runner
+            .run(
+                sources,
+                &mut progress,
+                |_| prompt.clone(),
+                |source, reply| {
+                    let suggestions =
+                        parse_suggestions(&reply, &settings.vocabulary)?;
+                    let (applied, queued): (Vec<_>, Vec<_>) = suggestions
+                        .into_iter()
+                        .partition(|s| s.confidence >= settings.threshold);
+                    let applied: Vec<String> =
+                        applied.into_iter().map(|s| s.tag).collect();
+                    if !applied.is_empty() {
+                        add_keywords(source, &applied)?;
+                    }
+                    Ok((applied, queued))
+                },
+                |progress, source, result| {
+                    let event = match result {
+                        Ok((applied, queued)) => TagEvent::Tagged {
+                            source,
+                            applied,
+                            queued,
+                        },
+                        Err(error) => TagEvent::Failed { source, error },
+                    };
+                    on_update(progress, &event);
+                },
+            )
+            .await;
+        Ok(progress)
+    }
+}
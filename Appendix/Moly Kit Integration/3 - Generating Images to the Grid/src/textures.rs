@@ -0,0 +1,171 @@
+use makepad_widgets::Texture;
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+
+use crate::source::ImageSource;
+
+// Grid tiles are 256 points, doubled for high density displays.
+pub const THUMBNAIL_SIZE: usize = 512;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TextureSize {
+    // For the slideshow.
+    Full,
+    // For grid tiles and selection chips, at most `THUMBNAIL_SIZE`.
+    Thumbnail,
+}
+
+impl TextureSize {
+    // How many decoded textures of the size are kept.
+    fn capacity(self) -> usize {
+        match self {
+            Self::Full => 8,
+            Self::Thumbnail => 400,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum CachedTexture {
+    Pending,
+    Loaded(Texture),
+    // Not decoded again until it's evicted, so a broken file isn't read on
+    // every redraw.
+    Failed,
+}
+
+// Textures decoded in the background. Once a size is over its capacity, its
+// least recently used textures go, and failures with them.
+#[derive(Default)]
+pub struct TextureCache {
+    entries: HashMap<(ImageSource, TextureSize), (CachedTexture, u64)>,
+    clock: u64,
+}
+
+impl TextureCache {
+    // `None` if the texture was never requested.
+    pub fn get(
+        &mut self,
+        source: &ImageSource,
+        size: TextureSize,
+    ) -> Option<CachedTexture> {
+        self.clock += 1;
+        let (texture, used) = self.entries.get_mut(&(source.clone(), size))?;
+        *used = self.clock;
+        Some(texture.clone())
+    }
+
+    // Like `get`, without counting as a use, for loaded textures only.
+    pub fn peek(
+        &self,
+        source: &ImageSource,
+        size: TextureSize,
+    ) -> Option<&Texture> {
+        match self.entries.get(&(source.clone(), size))? {
+            (CachedTexture::Loaded(texture), _) => Some(texture),
+            _ => None,
+        }
+    }
+
+    pub fn has_failed(&self, source: &ImageSource, size: TextureSize) -> bool {
+        let entry = self.entries.get(&(source.clone(), size));
+        matches!(entry, Some((CachedTexture::Failed, _)))
+    }
+
+    // Marks the texture as requested, so it's decoded only once.
+    pub fn mark_pending(&mut self, source: &ImageSource, size: TextureSize) {
+        self.clock += 1;
+        self.entries.insert(
+            (source.clone(), size),
+            (CachedTexture::Pending, self.clock),
+        );
+    }
+
+    pub fn insert(
+        &mut self,
+        source: ImageSource,
+        size: TextureSize,
+        texture: Texture,
+    ) {
+        self.finish(source, size, CachedTexture::Loaded(texture));
+    }
+
+    pub fn mark_failed(&mut self, source: ImageSource, size: TextureSize) {
+        self.finish(source, size, CachedTexture::Failed);
+    }
+
+    fn finish(
+        &mut self,
+        source: ImageSource,
+        size: TextureSize,
+        texture: CachedTexture,
+    ) {
+        self.clock += 1;
+        self.entries.insert((source, size), (texture, self.clock));
+
+        let mut finished: Vec<(u64, ImageSource)> = self
+            .entries
+            .iter()
+            .filter(|((_, s), (texture, _))| {
+                *s == size && !matches!(texture, CachedTexture::Pending)
+            })
+            .map(|((source, _), (_, used))| (*used, source.clone()))
+            .collect();
+        let excess = finished.len().saturating_sub(size.capacity());
+        if excess == 0 {
+            return;
+        }
+        finished.sort_unstable_by_key(|(used, _)| *used);
+        for (_, source) in finished.into_iter().take(excess) {
+            self.entries.remove(&(source, size));
+        }
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+// A few threads shared by all texture decoding. The newest job runs first,
+// so the tiles scrolled to come before the ones scrolled past.
+struct DecodePool {
+    jobs: Mutex<Vec<Job>>,
+    ready: Condvar,
+}
+
+impl DecodePool {
+    fn work(&self) {
+        loop {
+            let job = {
+                let mut jobs = self.jobs.lock().unwrap();
+                loop {
+                    match jobs.pop() {
+                        Some(job) => break job,
+                        None => jobs = self.ready.wait(jobs).unwrap(),
+                    }
+                }
+            };
+            // A decoder panicking on a bad file mustn't take the worker.
+            let _ = panic::catch_unwind(AssertUnwindSafe(job));
+        }
+    }
+}
+
+pub fn spawn_decode(job: impl FnOnce() + Send + 'static) {
+    static POOL: OnceLock<Arc<DecodePool>> = OnceLock::new();
+    let pool = POOL.get_or_init(|| {
+        let pool = Arc::new(DecodePool {
+            jobs: Mutex::new(Vec::new()),
+            ready: Condvar::new(),
+        });
+        let workers = std::thread::available_parallelism()
+            .map_or(2, |n| n.get())
+            .clamp(2, 4);
+        for _ in 0..workers {
+            let pool = Arc::clone(&pool);
+            std::thread::spawn(move || pool.work());
+        }
+        pool
+    });
+    pool.jobs.lock().unwrap().push(Box::new(job));
+    pool.ready.notify_one();
+}
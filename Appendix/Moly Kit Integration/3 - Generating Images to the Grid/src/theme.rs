@@ -0,0 +1,356 @@
+use makepad_widgets::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+
+use crate::config;
+
+// Defaults for the DSL, matching the dark theme. Widgets that follow the
+// theme at runtime get their actual values from `Theme`.
+live_design! {
+    pub THEME_BACKGROUND = #1e1e1e
+    pub THEME_PANEL = #2b2b2b
+    pub THEME_OVERLAY = #000A
+    pub THEME_CHAT = #fff
+    pub THEME_ACCENT = #4a8fe3
+    pub THEME_BUTTON = #FFF0
+    pub THEME_BUTTON_PRESSED = #FFF2
+    pub THEME_RADIUS = 4.0
+    pub THEME_SPACING = 5.0
+}
+
+const THEMES_DIR: &str = "themes";
+pub const BUILT_IN_THEMES: [&str; 3] = ["dark", "light", "high_contrast"];
+
+#[derive(Clone, Debug)]
+pub struct Theme {
+    pub name: String,
+    // Behind the grid and the slideshow.
+    pub background: Vec4,
+    pub panel: Vec4,
+    // Behind the chat panels, whose messages are drawn in dark text.
+    pub chat: Vec4,
+    // Translucent panels drawn over images, like badges and controls. These
+    // stay dark in every theme so their white labels read over any image.
+    pub overlay: Vec4,
+    // Selected grid items.
+    pub accent: Vec4,
+    pub button: Vec4,
+    pub button_pressed: Vec4,
+    pub radius: f64,
+    pub spacing: f64,
+}
+
+impl Theme {
+    pub fn dark() -> Self {
+        Self {
+            name: "dark".to_string(),
+            background: hex(0x1e1e1eff),
+            panel: hex(0x2b2b2bff),
+            chat: hex(0xffffffff),
+            overlay: hex(0x000000aa),
+            accent: hex(0x4a8fe3ff),
+            button: hex(0xffffff00),
+            button_pressed: hex(0xffffff22),
+            radius: 4.0,
+            spacing: 5.0,
+        }
+    }
+
+    pub fn light() -> Self {
+        Self {
+            name: "light".to_string(),
+            background: hex(0xf4f4f4ff),
+            panel: hex(0xe4e4e4ff),
+            chat: hex(0xffffffff),
+            accent: hex(0x2f74d0ff),
+            button: hex(0x00000000),
+            button_pressed: hex(0x00000022),
+            ..Self::dark()
+        }
+    }
+
+    pub fn high_contrast() -> Self {
+        Self {
+            name: "high_contrast".to_string(),
+            background: hex(0x000000ff),
+            panel: hex(0x000000ff),
+            chat: hex(0xffffffff),
+            overlay: hex(0x000000ff),
+            accent: hex(0xffd400ff),
+            button: hex(0x00000000),
+            button_pressed: hex(0xffffff66),
+            radius: 0.0,
+            spacing: 8.0,
+        }
+    }
+
+    pub fn built_in(name: &str) -> Option<Self> {
+        match name {
+            "dark" => Some(Self::dark()),
+            "light" => Some(Self::light()),
+            "high_contrast" => Some(Self::high_contrast()),
+            _ => None,
+        }
+    }
+
+    // Loads a built-in theme or one from the themes folder. `None` follows
+    // the desktop's light or dark preference.
+    pub fn load(name: Option<&str>) -> Self {
+        let Some(name) = name else {
+            return Self::system();
+        };
+        if let Some(theme) = Self::built_in(name) {
+            return theme;
+        }
+
+        let loaded = theme_file(name)
+            .ok_or_else(|| "no config directory".to_string())
+            .and_then(|path| {
+                let text = std::fs::read_to_string(&path)
+                    .map_err(|e| format!("{path:?}: {e}"))?;
+                Self::parse(name, &text).map_err(|e| format!("{path:?}: {e}"))
+            });
+        loaded.unwrap_or_else(|e| {
+            eprintln!("Error loading theme {name}: {e}");
+            Self::system()
+        })
+    }
+
+    pub fn system() -> Self {
+        if system_prefers_dark() {
+            Self::dark()
+        } else {
+            Self::light()
+        }
+    }
+
+    // Theme files start from a built-in theme and override some tokens:
+    //
+    //     base = "dark"
+    //
+    //     [colors]
+    //     accent = "#e0a030"
+    //
+    //     [sizes]
+    //     radius = 0
+    fn parse(name: &str, text: &str) -> Result<Self, String> {
+        #[derive(Deserialize)]
+        struct ThemeFile {
+            base: Option<String>,
+            #[serde(default)]
+            colors: HashMap<String, String>,
+            #[serde(default)]
+            sizes: HashMap<String, f64>,
+        }
+
+        let file: ThemeFile =
+            toml::from_str(text).map_err(|e| e.to_string())?;
+        let mut theme = match file.base.as_deref() {
+            Some(base) => Self::built_in(base)
+                .ok_or_else(|| format!("unknown base theme `{base}`"))?,
+            None => Self::system(),
+        };
+        theme.name = name.to_string();
+
+        for (token, color) in file.colors {
+            let color = parse_color(&color).ok_or_else(|| {
+                format!("invalid color `{color}` for {token}")
+            })?;
+            let slot = match token.as_str() {
+                "background" => &mut theme.background,
+                "panel" => &mut theme.panel,
+                "chat" => &mut theme.chat,
+                "overlay" => &mut theme.overlay,
+                "accent" => &mut theme.accent,
+                "button" => &mut theme.button,
+                "button_pressed" => &mut theme.button_pressed,
+                _ => return Err(format!("unknown color `{token}`")),
+            };
+            *slot = color;
+        }
+        for (token, size) in file.sizes {
+            match token.as_str() {
+                "radius" => theme.radius = size,
+                "spacing" => theme.spacing = size,
+                _ => return Err(format!("unknown size `{token}`")),
+            }
+        }
+
+        Ok(theme)
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+fn hex(rgba: u32) -> Vec4 {
+    let [r, g, b, a] = rgba.to_be_bytes();
+    vec4(
+        r as f32 / 255.0,
+        g as f32 / 255.0,
+        b as f32 / 255.0,
+        a as f32 / 255.0,
+    )
+}
+
+// Accepts the same `#rgb`, `#rgba`, `#rrggbb` and `#rrggbbaa` forms as the
+// DSL.
+pub(crate) fn parse_color(color: &str) -> Option<Vec4> {
+    let digits = color.strip_prefix('#')?;
+    let expanded: String = match digits.len() {
+        3 | 4 => digits.chars().flat_map(|c| [c, c]).collect(),
+        6 | 8 => digits.to_string(),
+        _ => return None,
+    };
+
+    let value = u32::from_str_radix(&expanded, 16).ok()?;
+    Some(if expanded.len() == 6 {
+        hex(value << 8 | 0xff)
+    } else {
+        hex(value)
+    })
+}
+
+fn theme_file(name: &str) -> Option<PathBuf> {
+    config::config_dir()
+        .map(|dir| dir.join(THEMES_DIR).join(format!("{name}.toml")))
+}
+
+// The names of the themes in the themes folder.
+pub fn user_themes() -> Vec<String> {
+    let Some(dir) = config::config_dir().map(|dir| dir.join(THEMES_DIR)) else {
+        return Vec::new();
+    };
+    let Ok(entries) = dir.read_dir() else {
+        return Vec::new();
+    };
+
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != "toml" {
+                return None;
+            }
+            path.file_stem()?.to_str().map(String::from)
+        })
+        .filter(|name| !BUILT_IN_THEMES.contains(&name.as_str()))
+        .collect();
+    names.sort();
+    names
+}
+
+// The theme after `current` when cycling: following the desktop, then the
+// built-in themes, then the user's, then back to following the desktop.
+pub fn next_theme(current: Option<&str>) -> Option<String> {
+    let names: Vec<String> = BUILT_IN_THEMES
+        .iter()
+        .map(|name| name.to_string())
+        .chain(user_themes())
+        .collect();
+
+    match current {
+        None => names.into_iter().next(),
+        Some(current) => names
+            .iter()
+            .position(|name| name == current)
+            .and_then(|idx| names.get(idx + 1).cloned()),
+    }
+}
+
+// Asks the desktop for its color scheme, assuming dark when it won't say.
+pub fn system_prefers_dark() -> bool {
+    let output = |program: &str, args: &[&str]| {
+        std::process::Command::new(program)
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+
+    if cfg!(target_os = "macos") {
+        // Only set while dark mode is on.
+        return output("defaults", &["read", "-g", "AppleInterfaceStyle"])
+            .is_some_and(|style| style.trim() == "Dark");
+    }
+    if cfg!(target_os = "windows") {
+        let key = r"HKCU\Software\Microsoft\Windows\CurrentVersion\Themes\Personalize";
+        return output("reg", &["query", key, "/v", "AppsUseLightTheme"])
+            .is_none_or(|value| !value.trim_end().ends_with("0x1"));
+    }
+
+    let scheme = output(
+        "gsettings",
+        &["get", "org.gnome.desktop.interface", "color-scheme"],
+    );
+    // GNOME reports `default` when no preference is set, which is light.
+    scheme.is_none_or(|scheme| scheme.contains("prefer-dark"))
+}
+
+// Watches the active theme so edits to a theme file, or to the desktop's
+// preference when following it, show up without restarting.
+#[derive(Default)]
+pub struct ThemeWatcher {
+    name: Option<String>,
+    modified: Option<SystemTime>,
+    // Asking the desktop runs a program, so it's done off the UI thread,
+    // and a poll sees the answer to the one before.
+    system_dark: Arc<AtomicBool>,
+    asking: Arc<AtomicBool>,
+    seen_dark: bool,
+}
+
+impl ThemeWatcher {
+    pub fn watch(&mut self, name: Option<&str>) {
+        self.name = name.map(String::from);
+        self.modified = self.file_modified();
+        self.seen_dark = self.system_dark.load(Ordering::Relaxed);
+        self.ask_system();
+    }
+
+    fn ask_system(&self) {
+        if self.asking.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        let system_dark = Arc::clone(&self.system_dark);
+        let asking = Arc::clone(&self.asking);
+        std::thread::spawn(move || {
+            system_dark.store(system_prefers_dark(), Ordering::Relaxed);
+            asking.store(false, Ordering::Relaxed);
+        });
+    }
+
+    fn file_modified(&self) -> Option<SystemTime> {
+        let name = self.name.as_deref()?;
+        if Theme::built_in(name).is_some() {
+            return None;
+        }
+        std::fs::metadata(theme_file(name)?).ok()?.modified().ok()
+    }
+
+    // Returns whether the theme has to be loaded again.
+    pub fn poll(&mut self) -> bool {
+        match &self.name {
+            Some(_) => {
+                let modified = self.file_modified();
+                let changed = modified != self.modified;
+                self.modified = modified;
+                changed
+            }
+            None => {
+                self.ask_system();
+                let system_dark = self.system_dark.load(Ordering::Relaxed);
+                let changed = system_dark != self.seen_dark;
+                self.seen_dark = system_dark;
+                changed
+            }
+        }
+    }
+}
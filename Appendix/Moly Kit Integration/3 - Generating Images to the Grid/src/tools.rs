@@ -0,0 +1,582 @@
+use futures::channel::oneshot;
+use image::imageops;
+use image::metadata::Orientation as ExifOrientation;
+use image::{ImageFormat, ImageReader, RgbaImage};
+use moly_kit::protocol::*;
+use serde::{Deserialize, Deserializer};
+use serde_json::{Value, json};
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::attachments::{self, AttachmentFormat};
+use crate::compare::edited_path;
+use crate::source::ImageSource;
+use crate::xmp::Xmp;
+
+// What the chat model may do in the viewer. Images are named by file name,
+// and tools that take `images` work on the selected ones without them.
+pub fn app_tools() -> Vec<Tool> {
+    let images = json!({
+        "type": "array",
+        "items": { "type": "string" },
+        "description": "File names. Defaults to the selected images, or \
+                        the current one.",
+    });
+    vec![
+        tool(
+            "go_to_image",
+            "Show an image in the slideshow.",
+            json!({
+                "name": {
+                    "type": "string",
+                    "description": "The file name, or part of it.",
+                },
+            }),
+            &["name"],
+        ),
+        tool(
+            "filter_grid",
+            "Show only the images that match in the grid. Without any \
+             criteria, shows them all again.",
+            json!({
+                "name": {
+                    "type": "string",
+                    "description": "Part of the file name.",
+                },
+                "min_rating": { "type": "integer", "minimum": 0, "maximum": 5 },
+                "orientation": {
+                    "type": "string",
+                    "enum": ["landscape", "portrait", "square"],
+                },
+                "tags": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Keywords the images must all have.",
+                },
+            }),
+            &[],
+        ),
+        tool(
+            "set_rating",
+            "Rate images from 0 to 5 stars.",
+            json!({
+                "rating": { "type": "integer", "minimum": 0, "maximum": 5 },
+                "images": images,
+            }),
+            &["rating"],
+        ),
+        tool(
+            "add_tag",
+            "Add a keyword to images.",
+            json!({
+                "tag": { "type": "string" },
+                "images": images,
+            }),
+            &["tag"],
+        ),
+        tool(
+            "open_slideshow",
+            "Start a slideshow of the images in the grid.",
+            json!({}),
+            &[],
+        ),
+        tool(
+            "rotate",
+            "Rotate images clockwise, saving an edited copy.",
+            json!({
+                "degrees": { "type": "integer", "enum": [90, 180, 270] },
+                "images": images,
+            }),
+            &["degrees"],
+        ),
+        tool(
+            "crop",
+            "Crop images, saving an edited copy. The rectangle is in \
+             fractions of the image, from its top left corner.",
+            json!({
+                "x": { "type": "number", "minimum": 0, "maximum": 1 },
+                "y": { "type": "number", "minimum": 0, "maximum": 1 },
+                "width": { "type": "number", "minimum": 0, "maximum": 1 },
+                "height": { "type": "number", "minimum": 0, "maximum": 1 },
+                "images": images,
+            }),
+            &["x", "y", "width", "height"],
+        ),
+        tool(
+            "search",
+            "Rank the grid by how well the images match a description, \
+             best first.",
+            json!({
+                "query": { "type": "string" },
+            }),
+            &["query"],
+        ),
+    ]
+}
+
+pub(crate) fn tool(
+    name: &str,
+    description: &str,
+    properties: Value,
+    required: &[&str],
+) -> Tool {
+    let schema = json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    });
+    Tool {
+        name: name.to_string(),
+        description: Some(description.to_string()),
+        input_schema: Arc::new(schema.as_object().cloned().unwrap_or_default()),
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "name", content = "arguments", rename_all = "snake_case")]
+pub enum ToolRequest {
+    GoToImage {
+        name: String,
+    },
+    FilterGrid(GridFilter),
+    SetRating {
+        rating: Rating,
+        #[serde(default)]
+        images: Vec<String>,
+    },
+    AddTag {
+        tag: String,
+        #[serde(default)]
+        images: Vec<String>,
+    },
+    OpenSlideshow {},
+    Rotate {
+        degrees: u32,
+        #[serde(default)]
+        images: Vec<String>,
+    },
+    Crop {
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        #[serde(default)]
+        images: Vec<String>,
+    },
+    Search {
+        query: String,
+    },
+}
+
+impl ToolRequest {
+    pub fn parse(call: &ToolCall) -> Result<Self, String> {
+        let value = json!({
+            "name": call.name,
+            "arguments": call.arguments,
+        });
+        serde_json::from_value(value)
+            .map_err(|e| format!("Bad call to {}: {e}", call.name))
+    }
+
+    // Whether it writes to files, which the user confirms first.
+    pub fn is_destructive(&self) -> bool {
+        matches!(
+            self,
+            Self::SetRating { .. }
+                | Self::AddTag { .. }
+                | Self::Rotate { .. }
+                | Self::Crop { .. }
+        )
+    }
+
+    // The `images` of the tools that take them, which the app looks up
+    // before asking, so the same images are confirmed and changed.
+    pub fn images(&self) -> Option<&[String]> {
+        match self {
+            Self::SetRating { images, .. }
+            | Self::AddTag { images, .. }
+            | Self::Rotate { images, .. }
+            | Self::Crop { images, .. } => Some(images),
+            _ => None,
+        }
+    }
+
+    // A line for the confirmation, naming the images it works on.
+    pub fn describe(&self, targets: &[ImageSource]) -> String {
+        let named: Vec<String> =
+            targets.iter().map(ImageSource::file_name).collect();
+        let named = named.join(", ");
+        match self {
+            Self::GoToImage { name } => format!("Show {name}"),
+            Self::FilterGrid(_) => "Filter the grid".to_string(),
+            Self::SetRating { rating, .. } => {
+                format!("Rate {named} {} stars", rating.0)
+            }
+            Self::AddTag { tag, .. } => format!("Tag {named} with \"{tag}\""),
+            Self::OpenSlideshow {} => "Start a slideshow".to_string(),
+            Self::Rotate { degrees, .. } => {
+                format!("Rotate {named} by {degrees}°")
+            }
+            Self::Crop { .. } => format!("Crop {named}"),
+            Self::Search { query } => format!("Search for \"{query}\""),
+        }
+    }
+}
+
+// Stars from 0 to 5. Models send them as 4, 4.0 or "4", and ones out of
+// range are brought into it, but anything else is a bad call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rating(pub u8);
+
+impl<'de> Deserialize<'de> for Rating {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        let number = match &value {
+            Value::Number(number) => number.as_f64(),
+            Value::String(text) => text.trim().parse().ok(),
+            _ => None,
+        };
+        match number {
+            Some(number) if number.is_finite() => {
+                Ok(Self(number.round().clamp(0.0, 5.0) as u8))
+            }
+            _ => Err(serde::de::Error::custom(format!(
+                "the rating {value} isn't a number of stars"
+            ))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Orientation {
+    Landscape,
+    Portrait,
+    Square,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct GridFilter {
+    pub name: Option<String>,
+    pub min_rating: Option<u8>,
+    pub orientation: Option<Orientation>,
+    pub tags: Vec<String>,
+}
+
+impl GridFilter {
+    // Reads sidecars and image headers, so it's best kept off the UI
+    // thread.
+    pub fn matches(&self, source: &ImageSource) -> bool {
+        if let Some(name) = &self.name {
+            let file_name = source.file_name().to_lowercase();
+            if !file_name.contains(&name.to_lowercase()) {
+                return false;
+            }
+        }
+
+        if self.min_rating.is_some() || !self.tags.is_empty() {
+            let xmp = Xmp::load(source).unwrap_or_default();
+            let rating = xmp.rating().unwrap_or(0);
+            if self.min_rating.is_some_and(|min| rating < min) {
+                return false;
+            }
+            let keywords = xmp.keywords();
+            let tagged = self.tags.iter().all(|tag| {
+                keywords.iter().any(|k| k.eq_ignore_ascii_case(tag))
+            });
+            if !tagged {
+                return false;
+            }
+        }
+
+        if let Some(orientation) = self.orientation {
+            let Some((width, height)) = upright_dimensions(source) else {
+                return false;
+            };
+            let actual = match width.cmp(&height) {
+                std::cmp::Ordering::Greater => Orientation::Landscape,
+                std::cmp::Ordering::Less => Orientation::Portrait,
+                std::cmp::Ordering::Equal => Orientation::Square,
+            };
+            if actual != orientation {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+// From the header alone where the format allows it.
+pub(crate) fn dimensions(source: &ImageSource) -> Option<(u32, u32)> {
+    if let Some(path) = source.file_path() {
+        let reader = ImageReader::open(path).ok()?.with_guessed_format();
+        return reader.ok()?.into_dimensions().ok();
+    }
+    let bytes = source.read().ok()?;
+    let reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format();
+    reader.ok()?.into_dimensions().ok()
+}
+
+// As the image is shown, with a quarter turn in its EXIF swapping the sides.
+fn upright_dimensions(source: &ImageSource) -> Option<(u32, u32)> {
+    let bytes = source.read().ok()?;
+    let reader = ImageReader::new(Cursor::new(&bytes)).with_guessed_format();
+    let (width, height) = reader.ok()?.into_dimensions().ok()?;
+    let turned = matches!(
+        attachments::orientation(&bytes),
+        ExifOrientation::Rotate90
+            | ExifOrientation::Rotate270
+            | ExifOrientation::Rotate90FlipH
+            | ExifOrientation::Rotate270FlipH
+    );
+    Some(if turned {
+        (height, width)
+    } else {
+        (width, height)
+    })
+}
+
+// An exact file name first, then one containing `name`, ignoring case.
+pub fn find_image(sources: &[ImageSource], name: &str) -> Option<usize> {
+    let name = name.to_lowercase();
+    let names: Vec<String> = sources
+        .iter()
+        .map(|s| s.file_name().to_lowercase())
+        .collect();
+    names
+        .iter()
+        .position(|n| *n == name)
+        .or_else(|| names.iter().position(|n| n.contains(&name)))
+}
+
+// Runs blocking work on a thread of its own.
+pub async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, String> {
+    let (sender, receiver) = oneshot::channel();
+    std::thread::spawn(move || {
+        let _ = sender.send(work());
+    });
+    receiver.await.map_err(|e| e.to_string())
+}
+
+pub fn set_rating(
+    sources: &[ImageSource],
+    rating: u8,
+) -> Result<String, String> {
+    for source in sources {
+        Xmp::edit(source, |xmp| xmp.set_rating(rating))
+            .map_err(|e| format!("{source}: {e}"))?;
+    }
+    Ok(format!("Rated {} images {rating} stars", sources.len()))
+}
+
+pub fn add_tag(sources: &[ImageSource], tag: &str) -> Result<String, String> {
+    for source in sources {
+        Xmp::edit(source, |xmp| xmp.add_keywords(&[tag.to_string()]))
+            .map_err(|e| format!("{source}: {e}"))?;
+    }
+    Ok(format!("Tagged {} images with \"{tag}\"", sources.len()))
+}
+
+pub fn remove_tag(
+    sources: &[ImageSource],
+    tag: &str,
+) -> Result<String, String> {
+    for source in sources {
+        Xmp::edit(source, |xmp| {
+            let mut keywords = xmp.keywords();
+            keywords.retain(|k| !k.eq_ignore_ascii_case(tag));
+            xmp.set_keywords(&keywords)
+        })
+        .map_err(|e| format!("{source}: {e}"))?;
+    }
+    Ok(format!("Removed \"{tag}\" from {} images", sources.len()))
+}
+
+pub fn rotate(sources: &[ImageSource], degrees: u32) -> Result<String, String> {
+    let rotate: fn(&RgbaImage) -> RgbaImage = match degrees {
+        90 => imageops::rotate90,
+        180 => imageops::rotate180,
+        270 => imageops::rotate270,
+        _ => return Err(format!("Can't rotate by {degrees}°")),
+    };
+    let written = edit_all(sources, |image| Ok(rotate(&image)))?;
+    Ok(format!("Saved rotated copies: {written}"))
+}
+
+pub fn crop(
+    sources: &[ImageSource],
+    (x, y, width, height): (f32, f32, f32, f32),
+) -> Result<String, String> {
+    let written = edit_all(sources, |image| {
+        let (w, h) = (image.width() as f32, image.height() as f32);
+        let left = (x.clamp(0.0, 1.0) * w) as u32;
+        let top = (y.clamp(0.0, 1.0) * h) as u32;
+        let right = ((x + width).clamp(0.0, 1.0) * w) as u32;
+        let bottom = ((y + height).clamp(0.0, 1.0) * h) as u32;
+        if right <= left || bottom <= top {
+            return Err("The crop is empty".to_string());
+        }
+        let cropped =
+            imageops::crop_imm(&image, left, top, right - left, bottom - top);
+        Ok(cropped.to_image())
+    })?;
+    Ok(format!("Saved cropped copies: {written}"))
+}
+
+// Edits go to the copy Compare shows next to the original, building on
+// earlier edits. Returns the names written.
+fn edit_all(
+    sources: &[ImageSource],
+    edit: impl Fn(RgbaImage) -> Result<RgbaImage, String>,
+) -> Result<String, String> {
+    let mut written = Vec::new();
+    for source in sources {
+        let path = edit_image(source, &edit)?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        written.push(name.into_owned());
+    }
+    Ok(written.join(", "))
+}
+
+fn edit_image(
+    source: &ImageSource,
+    edit: impl Fn(RgbaImage) -> Result<RgbaImage, String>,
+) -> Result<PathBuf, String> {
+    let path = source.file_path().ok_or_else(|| {
+        format!("{source}: images in archives can't be edited")
+    })?;
+    let target = edited_path(path)
+        .ok_or_else(|| format!("{source}: no name for an edited copy"))?;
+    let base = if target.is_file() {
+        ImageSource::File(target.clone())
+    } else {
+        source.clone()
+    };
+    let image = edit(attachments::decode_rgba(&base)?)?;
+
+    let extension = target
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let result = match extension.as_str() {
+        "jpg" | "jpeg" => {
+            attachments::encode(&image, AttachmentFormat::Jpeg, 92).and_then(
+                |bytes| {
+                    std::fs::write(&target, bytes).map_err(|e| e.to_string())
+                },
+            )
+        }
+        "png" => image
+            .save_with_format(&target, ImageFormat::Png)
+            .map_err(|e| e.to_string()),
+        _ => Err(format!("can't write .{extension} files")),
+    };
+    result.map_err(|e| format!("{}: {e}", target.display()))?;
+    Ok(target)
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    // The file as it is, which can't be resized.
+    #[default]
+    Original,
+    Jpeg,
+    Png,
+}
+
+// Writes the images to `folder`, shrunk to `max_size` on the long edge
+// when given. The folder must exist already, so a mistyped path isn't
+// quietly created. It never overwrites a file, and won't write next to the
+// images themselves. Returns the names written.
+pub fn export(
+    sources: &[ImageSource],
+    folder: &Path,
+    format: ExportFormat,
+    max_size: Option<u32>,
+) -> Result<String, String> {
+    if format == ExportFormat::Original && max_size.is_some() {
+        return Err("Only JPEG and PNG exports can be resized".to_string());
+    }
+    if !folder.is_dir() {
+        return Err(format!("{} isn't an existing folder", folder.display()));
+    }
+    let folder = folder
+        .canonicalize()
+        .map_err(|e| format!("{}: {e}", folder.display()))?;
+
+    // Checked up front, so a refused export writes nothing.
+    let mut targets = Vec::new();
+    for source in sources {
+        let home = source
+            .file_path()
+            .or_else(|| source.archive())
+            .and_then(Path::parent)
+            .and_then(|dir| dir.canonicalize().ok());
+        if home.as_deref() == Some(folder.as_path()) {
+            return Err(format!(
+                "Can't export into {}, where the images are",
+                folder.display()
+            ));
+        }
+        let name = PathBuf::from(source.file_name());
+        let target = match format {
+            ExportFormat::Original => folder.join(&name),
+            ExportFormat::Jpeg => folder.join(name.with_extension("jpg")),
+            ExportFormat::Png => folder.join(name.with_extension("png")),
+        };
+        if target.exists() || targets.contains(&target) {
+            return Err(format!("{} already exists", target.display()));
+        }
+        targets.push(target);
+    }
+
+    let mut written = Vec::new();
+    for (source, target) in sources.iter().zip(targets) {
+        let result = match format {
+            ExportFormat::Original => source.read().map_err(|e| e.to_string()),
+            _ => attachments::decode_rgba(source).and_then(|image| {
+                let image = match max_size {
+                    Some(size) => attachments::fit_long_edge(image, size),
+                    None => image,
+                };
+                match format {
+                    ExportFormat::Png => {
+                        let mut bytes = Vec::new();
+                        image
+                            .write_to(
+                                &mut Cursor::new(&mut bytes),
+                                ImageFormat::Png,
+                            )
+                            .map_err(|e| e.to_string())?;
+                        Ok(bytes)
+                    }
+                    _ => {
+                        attachments::encode(&image, AttachmentFormat::Jpeg, 92)
+                    }
+                }
+            }),
+        };
+        result
+            .and_then(|bytes| write_new(&target, &bytes))
+            .map_err(|e| format!("{}: {e}", target.display()))?;
+        written.push(target.display().to_string());
+    }
+    Ok(format!("Exported {}", written.join(", ")))
+}
+
+// Fails rather than replace a file that appeared since it was checked.
+fn write_new(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|e| e.to_string())?;
+    file.write_all(bytes).map_err(|e| e.to_string())
+}
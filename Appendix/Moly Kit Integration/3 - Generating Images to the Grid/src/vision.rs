@@ -0,0 +1,166 @@
+use futures::StreamExt;
+use futures::channel::oneshot;
+use moly_kit::protocol::*;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::attachments::{self, AttachmentOptions};
+use crate::source::ImageSource;
+
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+// A chat model that batch jobs ask about one image at a time, retrying
+// failed requests a little apart.
+pub struct VisionModel {
+    client: Mutex<Box<dyn BotClient>>,
+    bot_id: BotId,
+    options: AttachmentOptions,
+    retries: u32,
+    // A tool whose arguments follow the schema replies should have, for
+    // models that can call tools. Its arguments become the reply.
+    schema: Option<Tool>,
+    // Set once the model turned down the tool, asking for text from then on.
+    schema_refused: AtomicBool,
+}
+
+impl VisionModel {
+    // Fails when the provider can't be reached or doesn't list `model`.
+    pub async fn connect(
+        client: Box<dyn BotClient>,
+        model: &str,
+        options: AttachmentOptions,
+        retries: u32,
+    ) -> Result<Self, String> {
+        let result = client.bots().await;
+        let errors: Vec<String> =
+            result.errors().iter().map(|e| e.to_string()).collect();
+        if !errors.is_empty() {
+            return Err(errors.join("; "));
+        }
+        let bot_id = result
+            .into_value()
+            .unwrap_or_default()
+            .into_iter()
+            .find(|bot| bot.id.id() == model)
+            .map(|bot| bot.id)
+            .ok_or_else(|| format!("Model '{model}' not found"))?;
+
+        Ok(Self {
+            client: Mutex::new(client),
+            bot_id,
+            options,
+            retries,
+            schema: None,
+            schema_refused: AtomicBool::new(false),
+        })
+    }
+
+    pub fn with_schema(mut self, schema: Tool) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    fn tools(&self) -> &[Tool] {
+        match &self.schema {
+            Some(tool) if !self.schema_refused.load(Ordering::Relaxed) => {
+                std::slice::from_ref(tool)
+            }
+            _ => &[],
+        }
+    }
+
+    // The text of the model's reply to `prompt` with the image attached, or
+    // the arguments of its call to the schema tool as JSON.
+    pub async fn ask(
+        &self,
+        source: &ImageSource,
+        prompt: String,
+    ) -> Result<String, String> {
+        // Decoding and encoding block, so they get a thread of their own.
+        let (sender, receiver) = oneshot::channel();
+        let (thread_source, options) = (source.clone(), self.options.clone());
+        std::thread::spawn(move || {
+            let _ = sender.send(attachments::prepare(&thread_source, &options));
+        });
+        let prepared = receiver.await.map_err(|e| e.to_string())??;
+
+        let message = Message {
+            from: EntityId::User,
+            content: MessageContent {
+                text: prompt,
+                attachments: vec![prepared.attachment],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let mut attempt = 0;
+        loop {
+            let tools = self.tools();
+            let result = match self.send(&message, tools).await {
+                // Models without tools refuse the request, so it's tried
+                // again as text, which the prompt asks for too.
+                Err(_) if !tools.is_empty() => {
+                    let result = self.send(&message, &[]).await;
+                    if result.is_ok() {
+                        self.schema_refused.store(true, Ordering::Relaxed);
+                    }
+                    result
+                }
+                result => result,
+            };
+            match result {
+                Ok(reply) => return Ok(reply),
+                Err(_) if attempt < self.retries => {
+                    attempt += 1;
+                    futures_timer::Delay::new(RETRY_DELAY * attempt).await;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    async fn send(
+        &self,
+        message: &Message,
+        tools: &[Tool],
+    ) -> Result<String, String> {
+        let mut client = self.client.lock().unwrap().clone_box();
+        last_reply(client.as_mut(), &self.bot_id, message, tools).await
+    }
+}
+
+// The reply's text, or the arguments of its call to one of `tools` as JSON.
+async fn last_reply(
+    client: &mut dyn BotClient,
+    bot_id: &BotId,
+    message: &Message,
+    tools: &[Tool],
+) -> Result<String, String> {
+    let mut stream = client.send(bot_id, std::slice::from_ref(message), tools);
+    let mut last = MessageContent::default();
+    while let Some(result) = stream.next().await {
+        let errors: Vec<String> =
+            result.errors().iter().map(|e| e.to_string()).collect();
+        if !errors.is_empty() {
+            return Err(errors.join("; "));
+        }
+        if let Some(content) = result.into_value() {
+            last = content;
+        }
+    }
+
+    let call = last
+        .tool_calls
+        .into_iter()
+        .find(|call| tools.iter().any(|tool| tool.name == call.name));
+    if let Some(call) = call {
+        return Ok(serde_json::Value::Object(call.arguments).to_string());
+    }
+    let text = last.text.trim().to_string();
+    if text.is_empty() {
+        return Err("The model returned an empty reply".to_string());
+    }
+    Ok(text)
+}
@@ -0,0 +1,309 @@
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Mutex, PoisonError};
+
+use crate::source::ImageSource;
+
+const DC_NAMESPACE: &str = "http://purl.org/dc/elements/1.1/";
+const XMP_NAMESPACE: &str = "http://ns.adobe.com/xap/1.0/";
+
+const EMPTY_PACKET: &str = r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:dc="http://purl.org/dc/elements/1.1/">
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>
+"#;
+
+// The sidecar holding an image's XMP metadata, next to it with `.xmp`
+// appended so `photo.jpg` and `photo.png` don't share one. Images inside
+// archives have none, as archives aren't written to.
+pub fn sidecar_path(source: &ImageSource) -> Option<PathBuf> {
+    let path = source.file_path()?;
+    let mut name = path.file_name()?.to_os_string();
+    name.push(".xmp");
+    Some(path.with_file_name(name))
+}
+
+// An XMP sidecar, edited as text so properties written by other tools are
+// kept as they are.
+#[derive(Clone, Debug)]
+pub struct Xmp {
+    text: String,
+}
+
+impl Default for Xmp {
+    fn default() -> Self {
+        Self {
+            text: EMPTY_PACKET.to_string(),
+        }
+    }
+}
+
+impl Xmp {
+    // The image's sidecar, or an empty one when it has none yet.
+    pub fn load(source: &ImageSource) -> io::Result<Self> {
+        let path = sidecar_path(source).ok_or_else(no_sidecar)?;
+        match std::fs::read_to_string(path) {
+            Ok(text) => Ok(Self { text }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                Ok(Self::default())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    // Written next to the sidecar and renamed over it, so a crash or a full
+    // disk midway leaves the old one whole.
+    fn save(&self, source: &ImageSource) -> io::Result<()> {
+        let path = sidecar_path(source).ok_or_else(no_sidecar)?;
+        let mut name = std::ffi::OsString::from(".");
+        name.push(path.file_name().unwrap_or_default());
+        name.push(".tmp");
+        let temp = path.with_file_name(name);
+        std::fs::write(&temp, &self.text)
+            .and_then(|()| std::fs::rename(&temp, &path))
+            .inspect_err(|_| {
+                let _ = std::fs::remove_file(&temp);
+            })
+    }
+
+    // Loads the image's sidecar, changes it and writes it back. The jobs,
+    // the chat's tools and agents may all edit sidecars at once, so edits
+    // take turns, or one could write over what another just added.
+    pub fn edit(
+        source: &ImageSource,
+        change: impl FnOnce(&mut Self) -> io::Result<()>,
+    ) -> io::Result<()> {
+        static EDITING: Mutex<()> = Mutex::new(());
+        let _turn = EDITING.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let mut xmp = Self::load(source)?;
+        change(&mut xmp)?;
+        xmp.save(source)
+    }
+
+    // The default-language `dc:description`, which is where alt text goes,
+    // or the first language there is when it has none.
+    pub fn description(&self) -> Option<String> {
+        let property = self.property("dc:description")?;
+        let items = list_entries(property);
+        let default = items.iter().find(|(tag, _)| {
+            tag.contains("xml:lang=\"x-default\"")
+                || tag.contains("xml:lang='x-default'")
+        });
+        let (_, description) = default.or(items.first())?;
+        Some(description.clone()).filter(|d| !d.is_empty())
+    }
+
+    pub fn set_description(&mut self, description: &str) -> io::Result<()> {
+        let value = format!(
+            "<rdf:Alt>\n     <rdf:li xml:lang=\"x-default\">{}</rdf:li>\n    \
+             </rdf:Alt>",
+            escape(description)
+        );
+        self.set_property("dc:description", &value)
+    }
+
+    // The `dc:subject` keywords.
+    pub fn keywords(&self) -> Vec<String> {
+        self.property("dc:subject")
+            .map(list_items)
+            .unwrap_or_default()
+    }
+
+    pub fn set_keywords(&mut self, keywords: &[String]) -> io::Result<()> {
+        let items: String = keywords
+            .iter()
+            .map(|k| format!("\n     <rdf:li>{}</rdf:li>", escape(k)))
+            .collect();
+        let value = format!("<rdf:Bag>{items}\n    </rdf:Bag>");
+        self.set_property("dc:subject", &value)
+    }
+
+    // Adds the keywords it doesn't have yet.
+    pub fn add_keywords(&mut self, keywords: &[String]) -> io::Result<()> {
+        let mut all = self.keywords();
+        for keyword in keywords {
+            if !all.contains(keyword) {
+                all.push(keyword.clone());
+            }
+        }
+        self.set_keywords(&all)
+    }
+
+    // The `xmp:Rating` from 0 to 5, which some tools write as an attribute
+    // of the description. Rejected images have -1 and count as unrated.
+    pub fn rating(&self) -> Option<u8> {
+        let value = match self.property("xmp:Rating") {
+            Some(value) => value,
+            None => {
+                self.attribute_range("xmp:Rating").map(|r| &self.text[r])?
+            }
+        };
+        value.trim().parse::<u8>().ok().filter(|r| *r <= 5)
+    }
+
+    pub fn set_rating(&mut self, rating: u8) -> io::Result<()> {
+        let rating = rating.min(5).to_string();
+        match self.attribute_range("xmp:Rating") {
+            Some(range) => {
+                self.text.replace_range(range, &rating);
+                Ok(())
+            }
+            None => self.set_property("xmp:Rating", &rating),
+        }
+    }
+
+    // Where the value of `name="..."` is, quotes excluded.
+    fn attribute_range(&self, name: &str) -> Option<std::ops::Range<usize>> {
+        let prefix = format!("{name}=\"");
+        let start = self.text.find(&prefix)? + prefix.len();
+        let end = start + self.text[start..].find('"')?;
+        Some(start..end)
+    }
+
+    // The inside of `<name ...>...</name>`, if the property is set.
+    fn property(&self, name: &str) -> Option<&str> {
+        let (start, end) = self.property_range(name)?;
+        let element = &self.text[start..end];
+        let inner_start = element.find('>')? + 1;
+        let inner_end = element.rfind("</")?;
+        element.get(inner_start..inner_end)
+    }
+
+    // Where `<name ...>...</name>` or `<name .../>` is, tags included.
+    // Attributes, like the `rdf:datatype` some tools add, are allowed.
+    fn property_range(&self, name: &str) -> Option<(usize, usize)> {
+        let open = format!("<{name}");
+        let close = format!("</{name}>");
+        let mut from = 0;
+        loop {
+            let start = from + self.text[from..].find(&open)?;
+            let rest = &self.text[start + open.len()..];
+            // `<dc:subject` mustn't find `<dc:subjectOther`.
+            let whole_name = rest.starts_with(char::is_whitespace)
+                || rest.starts_with('>')
+                || rest.starts_with("/>");
+            if !whole_name {
+                from = start + open.len();
+                continue;
+            }
+            let tag_end = start + open.len() + rest.find('>')? + 1;
+            if self.text[..tag_end].ends_with("/>") {
+                return Some((start, tag_end));
+            }
+            let end =
+                tag_end + self.text[tag_end..].find(&close)? + close.len();
+            return Some((start, end));
+        }
+    }
+
+    // Fails on a sidecar with no description to put the property in, rather
+    // than replacing what other tools wrote.
+    fn set_property(&mut self, name: &str, value: &str) -> io::Result<()> {
+        // Simple values stay on the line, as whitespace would be kept.
+        let element = if value.starts_with('<') {
+            format!("<{name}>\n    {value}\n   </{name}>")
+        } else {
+            format!("<{name}>{value}</{name}>")
+        };
+        if let Some((start, end)) = self.property_range(name) {
+            self.text.replace_range(start..end, &element);
+            return Ok(());
+        }
+
+        self.declare_namespace(name);
+        if let Some(end) = self.text.find("</rdf:Description>") {
+            self.text.insert_str(end, &format!(" {element}\n  "));
+            return Ok(());
+        }
+        // A description with only attributes, as some tools write it.
+        let self_closing = self
+            .text
+            .find("<rdf:Description")
+            .and_then(|i| self.text[i..].find("/>").map(|end| i + end));
+        let end = self_closing.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the sidecar has no rdf:Description",
+            )
+        })?;
+        self.text.replace_range(
+            end..end + 2,
+            &format!(">\n   {element}\n  </rdf:Description>"),
+        );
+        Ok(())
+    }
+
+    // Declares the namespace of the property `name`, if it isn't already.
+    fn declare_namespace(&mut self, name: &str) {
+        let (prefix, namespace) = match name.split_once(':') {
+            Some(("dc", _)) => ("dc", DC_NAMESPACE),
+            Some(("xmp", _)) => ("xmp", XMP_NAMESPACE),
+            _ => return,
+        };
+        if self.text.contains(&format!("xmlns:{prefix}=")) {
+            return;
+        }
+        let tag = "<rdf:Description";
+        if let Some(i) = self.text.find(tag) {
+            self.text.insert_str(
+                i + tag.len(),
+                &format!(" xmlns:{prefix}=\"{namespace}\""),
+            );
+        }
+    }
+}
+
+fn no_sidecar() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "images inside archives can't have metadata written",
+    )
+}
+
+// The text of each `<rdf:li>` in a property's `Alt`, `Bag` or `Seq`.
+fn list_items(property: &str) -> Vec<String> {
+    list_entries(property)
+        .into_iter()
+        .map(|(_, text)| text)
+        .collect()
+}
+
+// Each `<rdf:li>`'s opening tag, for its language, and its text.
+fn list_entries(property: &str) -> Vec<(&str, String)> {
+    let mut items = Vec::new();
+    let mut rest = property;
+    while let Some(start) = rest.find("<rdf:li") {
+        rest = &rest[start..];
+        let Some(open_end) = rest.find('>') else {
+            break;
+        };
+        let Some(close) = rest.find("</rdf:li>") else {
+            break;
+        };
+        let text = unescape(rest[open_end + 1..close].trim());
+        items.push((&rest[..open_end], text));
+        rest = &rest[close..];
+    }
+    items
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
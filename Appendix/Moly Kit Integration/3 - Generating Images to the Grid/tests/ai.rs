@@ -0,0 +1,654 @@
+mod common;
+
+use common::TempLibrary;
+use futures::StreamExt;
+use image_viewer::attachments::{self, AttachmentOptions};
+use image_viewer::captions::{CaptionJob, CaptionSettings};
+use image_viewer::generation::{add_to_grid, run_generation_tasks};
+use image_viewer::mock::ScriptedClient;
+use image_viewer::mock_server::MockServer;
+use image_viewer::search::{EndpointEmbedder, Search};
+use image_viewer::slideshow_client::SlideshowClient;
+use image_viewer::source::ImageSource;
+use image_viewer::tagging::{ReviewQueue, TagEvent, TagJob, TagSettings};
+use image_viewer::tools::{self, GridFilter, Orientation, Rating, ToolRequest};
+use moly_kit::protocol::*;
+use moly_kit::{ChatTask, OpenAIClient, OpenAIImageClient};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+fn placeholder() -> ImageSource {
+    ImageSource::File(common::placeholder())
+}
+
+fn user_message(text: &str) -> Message {
+    Message {
+        from: EntityId::User,
+        content: MessageContent {
+            text: text.to_string(),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+// Drains a reply, returning its last update.
+async fn last_update(
+    client: &mut dyn BotClient,
+    bot_id: &BotId,
+    messages: &[Message],
+) -> MessageContent {
+    let mut stream = client.send(bot_id, messages, &[]);
+    let mut last = None;
+    while let Some(result) = stream.next().await {
+        assert!(result.errors().is_empty(), "{:?}", result.errors());
+        last = result.into_value().or(last);
+    }
+    last.expect("no reply")
+}
+
+async fn load_bot(client: impl BotClient + 'static, model: &str) -> BotId {
+    let mut context = BotContext::from(client);
+    let errors = context.load().await.into_errors();
+    assert!(errors.is_empty(), "{errors:?}");
+    context
+        .bots()
+        .into_iter()
+        .find(|bot| bot.id.id() == model)
+        .expect("model not listed")
+        .id
+}
+
+#[tokio::test]
+async fn scripted_chat_send_includes_the_current_image() {
+    let scripted = ScriptedClient::new(&["vision"]);
+    scripted.reply_text(&["A gray ", "placeholder."]);
+
+    let mut client = SlideshowClient::new(scripted.clone());
+    client
+        .attach_image(&placeholder(), &AttachmentOptions::default())
+        .unwrap();
+    let bot_id = load_bot(client.clone(), "vision").await;
+
+    let reply =
+        last_update(&mut client, &bot_id, &[user_message("What is this?")])
+            .await;
+    assert_eq!(reply.text, "A gray placeholder.");
+
+    let sent = scripted.sent();
+    assert_eq!(sent.len(), 1);
+    let attachment = &sent[0][0].content.attachments[0];
+    // Wider than the default max long edge, so it goes as a smaller JPEG.
+    assert_eq!(attachment.name, "placeholder.jpg");
+    assert_eq!(sent[0][1].content.text, "What is this?");
+}
+
+#[tokio::test]
+async fn scripted_client_reports_a_missing_reply() {
+    let mut scripted = ScriptedClient::new(&["vision"]);
+    let bot_id = load_bot(scripted.clone(), "vision").await;
+
+    let mut stream = scripted.send(&bot_id, &[user_message("Hi")], &[]);
+    let result = stream.next().await.unwrap();
+    assert!(!result.errors().is_empty());
+}
+
+#[tokio::test]
+async fn chat_send_streams_from_an_openai_compatible_server() {
+    let server = MockServer::start(&["gpt-mock"]).unwrap();
+    server.reply_text(&["Looks ", "like ", "a placeholder."]);
+
+    let mut client =
+        SlideshowClient::new(OpenAIClient::new(server.url().to_string()));
+    let options = AttachmentOptions {
+        max_long_edge: 16,
+        ..Default::default()
+    };
+    let summary = client.prepare_send(&placeholder(), &options).unwrap();
+    assert!(summary.is_some());
+    let missing = ImageSource::File(PathBuf::from("missing.png"));
+    assert!(client.prepare_send(&missing, &options).is_err());
+    let bot_id = load_bot(client.clone(), "gpt-mock").await;
+
+    let reply =
+        last_update(&mut client, &bot_id, &[user_message("Describe it")]).await;
+    assert_eq!(reply.text, "Looks like a placeholder.");
+
+    let requests = server.requests();
+    let chat = requests
+        .iter()
+        .find(|r| r.path.ends_with("/chat/completions"))
+        .expect("no chat request");
+    assert_eq!(chat.body["model"], "gpt-mock");
+    let body = chat.body.to_string();
+    assert!(body.contains("data:image/jpeg;base64,"));
+    assert!(body.contains("Describe it"));
+}
+
+#[tokio::test]
+async fn selection_sends_as_many_images_as_the_limit_allows() {
+    let scripted = ScriptedClient::new(&["vision"]);
+    scripted.reply_text(&["The second."]);
+
+    let mut client = SlideshowClient::new(scripted.clone());
+    let options = AttachmentOptions {
+        max_images: 2,
+        ..Default::default()
+    };
+    let selection = [placeholder(), placeholder(), placeholder()];
+    let prepared = client.attach_images(&selection, &options);
+    assert_eq!(prepared.attachments.len(), 2);
+    assert!(prepared.left_out.is_some());
+    let bot_id = load_bot(client.clone(), "vision").await;
+
+    last_update(&mut client, &bot_id, &[user_message("Which is sharpest?")])
+        .await;
+    let sent = scripted.sent();
+    assert_eq!(sent[0][0].content.attachments.len(), 2);
+}
+
+#[test]
+fn attachments_are_shrunk_to_the_max_long_edge() {
+    let options = AttachmentOptions {
+        max_long_edge: 16,
+        ..Default::default()
+    };
+    let prepared = attachments::prepare(&placeholder(), &options).unwrap();
+
+    assert!(prepared.reencoded.is_some());
+    assert_eq!(prepared.width.max(prepared.height), 16);
+    assert_eq!(prepared.attachment.name, "placeholder.jpg");
+    assert!(prepared.size < prepared.original_size);
+    assert!(prepared.summary().is_some());
+}
+
+// A 32x16 JPEG whose EXIF says it's to be turned a quarter clockwise.
+fn rotated_jpeg() -> Vec<u8> {
+    let mut jpeg = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new(&mut jpeg)
+        .encode_image(&image::RgbImage::new(32, 16))
+        .unwrap();
+    // An APP1 segment with a single EXIF entry: orientation 6.
+    let mut exif = b"Exif\0\0II*\0\x08\0\0\0\x01\0".to_vec();
+    exif.extend_from_slice(&[0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0]);
+    exif.extend_from_slice(&[0, 0, 0, 0]);
+    let length = (exif.len() as u16 + 2).to_be_bytes();
+    let segment = [&[0xFF, 0xE1], &length[..], &exif].concat();
+    jpeg.splice(2..2, segment);
+    jpeg
+}
+
+#[test]
+fn rotated_photos_are_turned_upright_before_their_exif_goes() {
+    let library = TempLibrary::new("rotated");
+    let path = library.dir().join("rotated.jpg");
+    std::fs::write(&path, rotated_jpeg()).unwrap();
+    let prepared = attachments::prepare(
+        &ImageSource::File(path),
+        &AttachmentOptions::default(),
+    )
+    .unwrap();
+    assert!(prepared.reencoded.is_some());
+    assert_eq!((prepared.width, prepared.height), (16, 32));
+}
+
+// A tar archive holding `files`, written by hand so the tests don't need the
+// tar crate.
+#[cfg(feature = "tar")]
+fn tar(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut tar = Vec::new();
+    for (name, data) in files {
+        let mut header = [0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..107].copy_from_slice(b"0000644");
+        header[108..115].copy_from_slice(b"0000000");
+        header[116..123].copy_from_slice(b"0000000");
+        let size = format!("{:011o}", data.len());
+        header[124..135].copy_from_slice(size.as_bytes());
+        header[136..147].copy_from_slice(b"00000000000");
+        header[148..156].copy_from_slice(b"        ");
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        let sum: u32 = header.iter().map(|&b| b as u32).sum();
+        header[148..155].copy_from_slice(format!("{sum:06o}\0").as_bytes());
+
+        tar.extend_from_slice(&header);
+        tar.extend_from_slice(data);
+        tar.resize(tar.len().next_multiple_of(512), 0);
+    }
+    tar.resize(tar.len() + 1024, 0);
+    tar
+}
+
+#[cfg(feature = "tar")]
+#[test]
+fn archive_entries_are_read_from_where_the_listing_found_them() {
+    let library = TempLibrary::new("archive");
+    let archive = library.dir().join("pages.tar");
+    std::fs::write(
+        &archive,
+        tar(&[("page10.png", b"tenth"), ("page2.png", b"second page")]),
+    )
+    .unwrap();
+    let entry = |name: &str| ImageSource::ArchiveEntry {
+        archive: archive.clone(),
+        entry: name.to_string(),
+    };
+
+    // Read in any order, each from its own offset.
+    assert_eq!(entry("page2.png").read().unwrap(), b"second page");
+    assert_eq!(entry("page10.png").read().unwrap(), b"tenth");
+    assert_eq!(entry("page2.png").read().unwrap(), b"second page");
+    assert_eq!(
+        entry("page3.png").read().unwrap_err().kind(),
+        std::io::ErrorKind::NotFound
+    );
+}
+
+// EPS files are shown by their preview, either hex in the comments or a
+// TIFF the binary header points to.
+#[cfg(feature = "eps")]
+#[test]
+fn eps_files_are_read_through_their_previews() {
+    let library = TempLibrary::new("eps");
+    let prepare = |name: &str, bytes: &[u8]| {
+        let path = library.dir().join(name);
+        std::fs::write(&path, bytes).unwrap();
+        let source = ImageSource::File(path);
+        attachments::prepare(&source, &AttachmentOptions::default()).unwrap()
+    };
+
+    let epsi = "%!PS-Adobe-3.0 EPSF-3.0\n\
+                %%BoundingBox: 0 0 4 2\n\
+                %%BeginPreview: 4 2 1 2\n\
+                %90\n\
+                %60\n\
+                %%EndPreview\n\
+                showpage\n";
+    let prepared = prepare("hex.eps", epsi.as_bytes());
+    assert_eq!((prepared.width, prepared.height), (4, 2));
+
+    let mut tiff = std::io::Cursor::new(Vec::new());
+    tiff::encoder::TiffEncoder::new(&mut tiff)
+        .unwrap()
+        .write_image::<tiff::encoder::colortype::RGB8>(3, 5, &[128; 45])
+        .unwrap();
+    let tiff = tiff.into_inner();
+    let postscript = b"%!PS-Adobe-3.0 EPSF-3.0\nshowpage\n";
+    let offset = |n: usize| (n as u32).to_le_bytes();
+    let mut eps = vec![0xC5, 0xD0, 0xD3, 0xC6];
+    eps.extend_from_slice(&offset(30));
+    eps.extend_from_slice(&offset(postscript.len()));
+    eps.extend_from_slice(&[0; 8]);
+    eps.extend_from_slice(&offset(30 + postscript.len()));
+    eps.extend_from_slice(&offset(tiff.len()));
+    eps.extend_from_slice(&[0xFF, 0xFF]);
+    eps.extend_from_slice(postscript);
+    eps.extend_from_slice(&tiff);
+    let prepared = prepare("tiff.eps", &eps);
+    assert_eq!((prepared.width, prepared.height), (3, 5));
+}
+
+#[tokio::test]
+async fn generated_images_are_saved_to_the_grid_folder() {
+    let png = std::fs::read(placeholder().path()).unwrap();
+    let server = MockServer::start(&["gpt-image-mock"]).unwrap();
+    server.reply_image(png.clone());
+
+    let mut client = OpenAIImageClient::new(server.url().to_string());
+    let bot_id = load_bot(client.clone(), "gpt-image-mock").await;
+
+    let reply =
+        last_update(&mut client, &bot_id, &[user_message("A red square")])
+            .await;
+
+    // The tasks the chat holds back for its hook while the reply comes in.
+    let mut message = Message {
+        from: EntityId::Bot(bot_id.clone()),
+        content: reply,
+        ..Default::default()
+    };
+    let mut tasks = vec![
+        ChatTask::InsertMessage(4, user_message("A red square")),
+        ChatTask::InsertMessage(5, message.clone()),
+    ];
+    message.metadata.is_writing = true;
+    tasks.push(ChatTask::UpdateMessage(5, message));
+
+    let library = TempLibrary::new("generation");
+    let mut performed = Vec::new();
+    let (sender, mut receiver) = futures::channel::mpsc::unbounded();
+    let generated = run_generation_tasks(
+        tasks,
+        library.dir(),
+        |task| performed.push(task),
+        move |source| sender.unbounded_send(source).unwrap(),
+    );
+    assert!(generated);
+
+    // The prompt and reply go on top, and the image is saved rather than
+    // kept in the chat.
+    let [
+        ChatTask::InsertMessage(0, prompt),
+        ChatTask::InsertMessage(1, _),
+    ] = &performed[..]
+    else {
+        panic!("the prompt and reply aren't inserted on top");
+    };
+    assert_eq!(prompt.from, EntityId::User);
+    let source = receiver.next().await.unwrap();
+    let path = source.file_path().unwrap();
+    assert_eq!(path.parent(), Some(library.dir()));
+    assert_eq!(std::fs::read(path).unwrap(), png);
+
+    // It joins the grid, and the folder order a ranking goes back to.
+    let mut shown = vec![placeholder()];
+    let mut unranked = vec![placeholder()];
+    add_to_grid(source.clone(), &mut shown, Some(&mut unranked));
+    assert_eq!(shown.last(), Some(&source));
+    assert_eq!(unranked.last(), Some(&source));
+}
+
+#[tokio::test]
+async fn caption_job_writes_alt_text_to_sidecars() {
+    let library = TempLibrary::new("captions");
+    let dir = library.dir();
+    let sources = library.images(&["a.png", "b.png"]);
+    let progress_file = dir.join("progress.json");
+
+    let scripted = ScriptedClient::new(&["vision"]);
+    scripted.reply_text(&["\"A gray placeholder.\""]);
+    scripted.reply_text(&["A gray placeholder."]);
+    let job = |sources: Vec<ImageSource>| {
+        CaptionJob::new(
+            scripted.clone(),
+            "vision",
+            sources,
+            CaptionSettings::default(),
+            AttachmentOptions::default(),
+        )
+        .with_progress_file(Some(progress_file.clone()))
+    };
+
+    let progress = job(sources.clone()).run(|_, _| {}).await.unwrap();
+    assert_eq!(progress.done, 2);
+    for source in &sources {
+        let sidecar = dir.join(format!("{}.xmp", source.file_name()));
+        let xmp = std::fs::read_to_string(sidecar).unwrap();
+        assert!(xmp.contains(">A gray placeholder.</rdf:li>"), "{xmp}");
+    }
+    assert!(!progress_file.exists());
+
+    // Images with a description are left alone the next time.
+    let progress = job(sources).run(|_, _| {}).await.unwrap();
+    assert_eq!(progress.skipped, 2);
+    assert_eq!(scripted.sent().len(), 2);
+}
+
+#[tokio::test]
+async fn tag_job_writes_confident_tags_and_queues_the_rest() {
+    let library = TempLibrary::new("tags");
+    let [source] = library.images(&["a.png"]).try_into().unwrap();
+    let sidecar = library.dir().join("a.png.xmp");
+
+    let scripted = ScriptedClient::new(&["vision"]);
+    scripted.reply_text(&[
+        "```json\n{\"tags\": [{\"tag\": \"Beach\", \"confidence\": 0.95}, ",
+        "{\"tag\": \"night\", \"confidence\": 0.4}, ",
+        "{\"tag\": \"cat\", \"confidence\": 0.9}]}\n```",
+    ]);
+    let settings = TagSettings {
+        vocabulary: vec!["beach".to_string(), "night".to_string()],
+        ..Default::default()
+    };
+    let job = TagJob::new(
+        scripted,
+        "vision",
+        vec![source.clone()],
+        settings,
+        AttachmentOptions::default(),
+    );
+
+    let mut queue = ReviewQueue::default();
+    let progress = job
+        .run(|_, event| {
+            if let TagEvent::Tagged {
+                source,
+                applied,
+                queued,
+            } = event
+            {
+                assert_eq!(applied, &["beach"]);
+                queue.queue(source, queued.clone());
+            }
+        })
+        .await
+        .unwrap();
+    assert_eq!(progress.done, 1);
+    let xmp = std::fs::read_to_string(&sidecar).unwrap();
+    assert!(xmp.contains("<rdf:li>beach</rdf:li>"), "{xmp}");
+    assert!(!xmp.contains("night"), "{xmp}");
+
+    // Accepting a suggestion adds it next to the confident ones.
+    assert_eq!(queue.pending.len(), 1);
+    assert_eq!(queue.pending[0].tag, "night");
+    queue.accept(&[0]).unwrap();
+    assert!(queue.pending.is_empty());
+    let xmp = std::fs::read_to_string(&sidecar).unwrap();
+    assert!(xmp.contains("<rdf:li>beach</rdf:li>"), "{xmp}");
+    assert!(xmp.contains("<rdf:li>night</rdf:li>"), "{xmp}");
+}
+
+#[tokio::test]
+async fn tag_replies_come_through_the_schema_tool_where_models_have_tools() {
+    let library = TempLibrary::new("tag_schema");
+    let sources = library.images(&["a.png", "b.png"]);
+
+    let scripted = ScriptedClient::new(&["vision"]);
+    scripted.reply_tool_calls(vec![ToolCall {
+        name: "suggest_tags".to_string(),
+        arguments: serde_json::json!({
+            "tags": [{ "tag": "beach", "confidence": 0.9 }],
+        })
+        .as_object()
+        .cloned()
+        .unwrap(),
+        ..Default::default()
+    }]);
+    // A model without tools turns the request down, and gets asked for
+    // text instead, for the rest of the job too.
+    scripted.reply_error("This model doesn't support tools");
+    scripted
+        .reply_text(&["{\"tags\": [{\"tag\": \"night\", \"confidence\": 1}]}"]);
+    scripted.reply_text(&["{\"tags\": []}"]);
+    let settings = TagSettings {
+        vocabulary: vec!["beach".to_string(), "night".to_string()],
+        concurrency: 1,
+        ..Default::default()
+    };
+
+    let mut applied_tags = Vec::new();
+    let progress = TagJob::new(
+        scripted.clone(),
+        "vision",
+        sources.clone(),
+        settings.clone(),
+        AttachmentOptions::default(),
+    )
+    .run(|_, event| {
+        if let TagEvent::Tagged { applied, .. } = event {
+            applied_tags.extend(applied.clone());
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(progress.done, 2);
+    assert_eq!(applied_tags, ["beach", "night"]);
+    assert_eq!(
+        scripted.sent_tools(),
+        [vec!["suggest_tags"], vec!["suggest_tags"], vec![]]
+    );
+
+    // The next job tries the tool again.
+    TagJob::new(
+        scripted.clone(),
+        "vision",
+        sources[..1].to_vec(),
+        settings,
+        AttachmentOptions::default(),
+    )
+    .run(|_, _| {})
+    .await
+    .unwrap();
+    assert_eq!(scripted.sent_tools()[3], ["suggest_tags"]);
+}
+
+// Blocking, as the embedders are meant for threads of their own.
+#[test]
+fn search_ranks_images_by_embedding_similarity() {
+    let library = TempLibrary::new("search");
+    let sources = library.images(&["bicycle.png", "beach.png"]);
+    let index_file = library.dir().join("index.json");
+
+    let server = MockServer::start(&[]).unwrap();
+    server.reply_embedding(&[1.0, 0.0]);
+    server.reply_embedding(&[0.0, 2.0]);
+    server.reply_embedding(&[0.2, 0.9]);
+    let embedder = EndpointEmbedder::new(server.url(), None, "clip");
+    let search = Search::new(Box::new(embedder), Some(index_file.clone()));
+
+    let failed = search.update(&sources, &AtomicBool::new(false), |_, _| {});
+    assert_eq!(failed, 0);
+    let ranked = search.search("a day at the beach", &sources).unwrap();
+    assert_eq!(ranked, [sources[1].clone(), sources[0].clone()]);
+    // Indexed images are compared without asking the server again.
+    let ranked = search.similar(&sources[0], &sources).unwrap();
+    assert_eq!(ranked, [sources[0].clone(), sources[1].clone()]);
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 3);
+    assert_eq!(requests[0].body["modality"], "image");
+    let input = requests[0].body["input"][0].as_str().unwrap();
+    assert!(input.starts_with("data:image/jpeg;base64,"));
+    assert_eq!(requests[2].body["input"][0], "a day at the beach");
+
+    // A saved index only needs the images that changed.
+    let embedder = EndpointEmbedder::new(server.url(), None, "clip");
+    let search = Search::new(Box::new(embedder), Some(index_file));
+    search.update(&sources, &AtomicBool::new(false), |_, _| {});
+    assert_eq!(server.requests().len(), 3);
+}
+
+#[tokio::test]
+async fn tool_calls_run_before_the_model_answers() {
+    let scripted = ScriptedClient::new(&["tools"]);
+    let arguments = serde_json::json!({ "rating": 4, "images": ["a.jpg"] });
+    scripted.reply_tool_calls(vec![ToolCall {
+        id: "call_1".to_string(),
+        name: "set_rating".to_string(),
+        arguments: arguments.as_object().cloned().unwrap(),
+        ..Default::default()
+    }]);
+    scripted.reply_text(&["Rated it."]);
+
+    let mut client = SlideshowClient::new(scripted.clone());
+    client.set_tools(
+        tools::app_tools(),
+        Arc::new(|calls: Vec<ToolCall>| {
+            Box::pin(async move {
+                let request = ToolRequest::parse(&calls[0]).unwrap();
+                assert!(request.is_destructive());
+                let targets = [ImageSource::File(PathBuf::from("a.jpg"))];
+                assert_eq!(request.describe(&targets), "Rate a.jpg 4 stars");
+                vec![ToolResult {
+                    tool_call_id: calls[0].id.clone(),
+                    content: "Rated 1 images 4 stars".to_string(),
+                    is_error: false,
+                }]
+            })
+        }),
+    );
+    let bot_id = load_bot(client.clone(), "tools").await;
+
+    let reply =
+        last_update(&mut client, &bot_id, &[user_message("Rate a.jpg 4")])
+            .await;
+    assert!(reply.text.contains("_Used set_rating_"), "{}", reply.text);
+    assert!(reply.text.ends_with("Rated it."), "{}", reply.text);
+    assert!(reply.tool_calls.is_empty());
+
+    // The second request carries the call and its result.
+    let sent = scripted.sent();
+    assert_eq!(sent.len(), 2);
+    let round = &sent[1][sent[1].len() - 2..];
+    assert_eq!(round[0].content.tool_calls[0].name, "set_rating");
+    assert_eq!(round[1].from, EntityId::Tool);
+    assert_eq!(round[1].content.tool_results[0].tool_call_id, "call_1");
+}
+
+#[test]
+fn ratings_are_read_however_models_write_them() {
+    use serde_json::json;
+
+    let rating = |value: serde_json::Value| {
+        let call = ToolCall {
+            name: "set_rating".to_string(),
+            arguments: json!({ "rating": value }).as_object().cloned().unwrap(),
+            ..Default::default()
+        };
+        match ToolRequest::parse(&call)? {
+            ToolRequest::SetRating { rating, .. } => Ok(rating),
+            request => panic!("not a rating: {request:?}"),
+        }
+    };
+    assert_eq!(rating(json!(4)), Ok(Rating(4)));
+    assert_eq!(rating(json!(4.0)), Ok(Rating(4)));
+    assert_eq!(rating(json!(" 4 ")), Ok(Rating(4)));
+    assert_eq!(rating(json!(9)), Ok(Rating(5)));
+    assert_eq!(rating(json!(-1)), Ok(Rating(0)));
+    assert!(rating(json!("four")).is_err());
+    assert!(rating(json!(null)).is_err());
+}
+
+#[test]
+fn rating_tool_writes_sidecars_the_grid_filter_reads() {
+    let library = TempLibrary::new("tools");
+    let sources = library.images(&["keep.png", "skip.png"]);
+
+    tools::set_rating(&sources[..1], 5).unwrap();
+    tools::add_tag(&sources, "placeholder").unwrap();
+    let call = ToolCall {
+        name: "filter_grid".to_string(),
+        arguments: serde_json::json!({
+            "min_rating": 4,
+            "tags": ["Placeholder"],
+            "orientation": "landscape",
+        })
+        .as_object()
+        .cloned()
+        .unwrap(),
+        ..Default::default()
+    };
+    let Ok(ToolRequest::FilterGrid(filter)) = ToolRequest::parse(&call) else {
+        panic!("not a filter");
+    };
+    assert!(filter.matches(&sources[0]));
+    assert!(!filter.matches(&sources[1]));
+
+    // Orientation goes by how the image is shown, not how it's stored.
+    let rotated = library.dir().join("rotated.jpg");
+    std::fs::write(&rotated, rotated_jpeg()).unwrap();
+    let filter = GridFilter {
+        orientation: Some(Orientation::Portrait),
+        ..Default::default()
+    };
+    assert!(filter.matches(&ImageSource::File(rotated)));
+
+    // A sidecar with nowhere to put the rating is left as it is.
+    let sidecar = library.dir().join("skip.png.xmp");
+    std::fs::write(&sidecar, "<x:xmpmeta/>").unwrap();
+    assert!(tools::set_rating(&sources[1..], 3).is_err());
+    assert_eq!(std::fs::read_to_string(&sidecar).unwrap(), "<x:xmpmeta/>");
+}
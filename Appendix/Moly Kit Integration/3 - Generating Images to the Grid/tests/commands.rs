@@ -0,0 +1,124 @@
+use image_viewer::commands::{
+    Command, CommandScope, KeyChord, Keymap, fuzzy_score,
+};
+
+fn chord(text: &str) -> KeyChord {
+    KeyChord::parse(text).unwrap()
+}
+
+#[test]
+fn chords_are_parsed_however_they_are_written() {
+    assert_eq!(chord("Ctrl+Shift+P"), chord("shift + control + p"));
+    assert_eq!(chord("Cmd+,").to_string(), "Cmd+,");
+    assert_eq!(chord("Alt+Shift+]").to_string(), "Alt+Shift+]");
+    // `+` splits the modifiers from the key, so the key is the last part.
+    assert_ne!(chord("Ctrl+P"), chord("P"));
+
+    assert!(KeyChord::parse("Ctrl+Nope").is_err());
+    assert!(KeyChord::parse("Hyper+P").is_err());
+    assert!(KeyChord::parse("").is_err());
+}
+
+#[test]
+fn user_bindings_replace_the_defaults_they_name() {
+    let mut keymap = Keymap::with_defaults();
+    let slideshow = CommandScope::Slideshow;
+    assert_eq!(
+        keymap.command_for(chord("L"), slideshow),
+        Some(Command::ToggleLoupe)
+    );
+
+    keymap
+        .apply_user_bindings(
+            r#"
+            next_image = "N"
+            toggle_chat = ["T", "Ctrl+K"]
+            toggle_loupe = []
+            "#,
+        )
+        .unwrap();
+    assert_eq!(
+        keymap.command_for(chord("N"), slideshow),
+        Some(Command::NextImage)
+    );
+    assert_eq!(
+        keymap.command_for(chord("Ctrl+K"), slideshow),
+        Some(Command::ToggleChat)
+    );
+    assert_eq!(keymap.command_for(chord("L"), slideshow), None);
+    assert_eq!(keymap.binding(Command::ToggleLoupe), None);
+
+    // Taking a default's key takes it from the default too.
+    keymap.apply_user_bindings(r#"toggle_blue = "G""#).unwrap();
+    assert_eq!(
+        keymap.command_for(chord("G"), slideshow),
+        Some(Command::ToggleBlue)
+    );
+    assert_eq!(keymap.binding(Command::ToggleGreen), None);
+
+    // Mistakes are reported while the rest still applies.
+    let error = keymap
+        .apply_user_bindings(
+            r#"
+            no_such_command = "Q"
+            develop_raw = "Ctrl+Nope"
+            cycle_theme = "Ctrl+T"
+            "#,
+        )
+        .unwrap_err();
+    assert!(error.contains("no_such_command"), "{error}");
+    assert!(error.contains("Nope"), "{error}");
+    assert_eq!(
+        keymap.command_for(chord("Ctrl+T"), CommandScope::Grid),
+        Some(Command::CycleTheme)
+    );
+}
+
+#[test]
+fn keys_only_run_commands_where_they_work() {
+    let mut keymap = Keymap::with_defaults();
+
+    // Slideshow keys do nothing on the grid, global ones work everywhere.
+    assert_eq!(keymap.command_for(chord("R"), CommandScope::Grid), None);
+    assert_eq!(
+        keymap.command_for(chord("Ctrl+Shift+P"), CommandScope::Compare),
+        Some(Command::CommandPalette)
+    );
+
+    // So a key can mean one thing on the grid and another in the slideshow.
+    keymap.apply_user_bindings(r#"open_compare = "R""#).unwrap();
+    assert_eq!(
+        keymap.command_for(chord("R"), CommandScope::Grid),
+        Some(Command::OpenCompare)
+    );
+    assert_eq!(
+        keymap.command_for(chord("R"), CommandScope::Slideshow),
+        Some(Command::ToggleRed)
+    );
+
+    // A global binding takes the key from every page.
+    keymap.apply_user_bindings(r#"cycle_theme = "R""#).unwrap();
+    for scope in [CommandScope::Grid, CommandScope::Slideshow] {
+        assert_eq!(
+            keymap.command_for(chord("R"), scope),
+            Some(Command::CycleTheme)
+        );
+    }
+}
+
+#[test]
+fn fuzzy_scores_prefer_word_starts_and_runs() {
+    assert_eq!(fuzzy_score("xyz", "Toggle loupe"), None);
+    assert_eq!(fuzzy_score("pool", "Toggle loupe"), None);
+    assert!(fuzzy_score("", "Toggle loupe").is_some());
+    assert!(fuzzy_score("TL", "toggle loupe").is_some());
+
+    let score = |query, text| fuzzy_score(query, text).unwrap();
+    assert!(score("tr", "Toggle red") > score("tr", "Toggle more"));
+    assert!(
+        score("scopes", "Toggle scopes")
+            > score("scopes", "Show color palettes")
+    );
+    // Equally good matches go to the shorter title.
+    assert!(score("next", "Next page") > score("next", "Next image"));
+}
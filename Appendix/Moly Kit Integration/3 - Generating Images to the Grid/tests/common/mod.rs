@@ -0,0 +1,46 @@
+use image_viewer::source::ImageSource;
+use std::path::{Path, PathBuf};
+
+pub fn placeholder() -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    dir.join("resources/placeholder.png")
+}
+
+// A folder of its own under the temporary one, removed when dropped, so a
+// failing test doesn't leave it behind for the next run.
+pub struct TempLibrary {
+    dir: PathBuf,
+}
+
+impl TempLibrary {
+    // `name` tells the tests' folders apart, as they run at once.
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir()
+            .join(format!("image_viewer_{name}_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Self { dir }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Copies of the placeholder, named `names`.
+    pub fn images(&self, names: &[&str]) -> Vec<ImageSource> {
+        names
+            .iter()
+            .map(|name| {
+                let path = self.dir.join(name);
+                std::fs::copy(placeholder(), &path).unwrap();
+                ImageSource::File(path)
+            })
+            .collect()
+    }
+}
+
+impl Drop for TempLibrary {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
@@ -0,0 +1,216 @@
+mod common;
+
+use common::TempLibrary;
+use image_viewer::mcp::{Library, LibraryChange, LibraryView, McpServer};
+use serde_json::{Value, json};
+use std::sync::{Arc, Mutex};
+
+// Stands in for the UI, applying changes to a view of its own.
+#[derive(Clone, Default)]
+struct FakeLibrary(Arc<Mutex<LibraryView>>);
+
+impl Library for FakeLibrary {
+    fn view(&self) -> Result<LibraryView, String> {
+        Ok(self.0.lock().unwrap().clone())
+    }
+
+    fn apply(&self, change: LibraryChange) -> Result<(), String> {
+        let mut view = self.0.lock().unwrap();
+        match change {
+            LibraryChange::ShowImage(source) => {
+                view.current = Some(source);
+                view.slideshow = true;
+            }
+            LibraryChange::Select(sources) => view.selected = sources,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+fn request(server: &McpServer, id: u64, method: &str, params: Value) -> Value {
+    let message = json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": method,
+        "params": params,
+    });
+    let response = server.handle(&message).expect("no response");
+    assert_eq!(response["id"], id);
+    response
+}
+
+fn call_tool(server: &McpServer, name: &str, arguments: Value) -> String {
+    let params = json!({ "name": name, "arguments": arguments });
+    let response = request(server, 1, "tools/call", params);
+    let result = &response["result"];
+    assert_eq!(result["isError"], false, "{result}");
+    result["content"][0]["text"].as_str().unwrap().to_string()
+}
+
+#[test]
+fn agents_browse_select_and_tag_the_open_images() {
+    let folder = TempLibrary::new("mcp");
+    let dir = folder.dir();
+    let sources = folder.images(&["beach.png", "city.png"]);
+
+    let library = FakeLibrary::default();
+    *library.0.lock().unwrap() = LibraryView {
+        images: sources.clone(),
+        shown: sources.clone(),
+        current: Some(sources[0].clone()),
+        ..Default::default()
+    };
+    let server = McpServer::new(library.clone());
+
+    let response = request(
+        &server,
+        1,
+        "initialize",
+        json!({ "protocolVersion": "2025-03-26" }),
+    );
+    assert_eq!(response["result"]["protocolVersion"], "2025-03-26");
+    let initialized = json!({
+        "jsonrpc": "2.0",
+        "method": "notifications/initialized",
+    });
+    assert!(server.handle(&initialized).is_none());
+
+    let response = request(&server, 2, "tools/list", json!({}));
+    let tools = response["result"]["tools"].as_array().unwrap();
+    assert!(tools.iter().any(|t| t["name"] == "export_images"));
+
+    // Changes go through the library, as they would to the UI.
+    call_tool(&server, "select_images", json!({ "images": ["city"] }));
+    assert_eq!(library.view().unwrap().selected, [sources[1].clone()]);
+    call_tool(&server, "go_to_image", json!({ "image": "city.png" }));
+    assert_eq!(library.view().unwrap().current, Some(sources[1].clone()));
+
+    // Tagging defaults to the selection.
+    call_tool(&server, "add_tags", json!({ "tags": ["skyline"] }));
+    let params = json!({ "uri": "image-viewer://images/1" });
+    let response = request(&server, 3, "resources/read", params);
+    let text = response["result"]["contents"][0]["text"].as_str().unwrap();
+    let metadata: Value = serde_json::from_str(text).unwrap();
+    assert_eq!(metadata["keywords"], json!(["skyline"]));
+    assert_eq!(metadata["width"], 2250);
+
+    let params = json!({ "uri": "image-viewer://images" });
+    let response = request(&server, 4, "resources/read", params);
+    let text = response["result"]["contents"][0]["text"].as_str().unwrap();
+    let list: Value = serde_json::from_str(text).unwrap();
+    assert_eq!(list["page"], "slideshow");
+    assert_eq!(list["images"][1]["selected"], true);
+    assert_eq!(list["images"][0]["selected"], false);
+
+    let params = json!({ "uri": "image-viewer://images/0/thumbnail" });
+    let response = request(&server, 5, "resources/read", params);
+    let content = &response["result"]["contents"][0];
+    assert_eq!(content["mimeType"], "image/jpeg");
+    assert!(content["blob"].as_str().unwrap().starts_with("/9j/"));
+
+    let exported = dir.join("exported");
+    let arguments = json!({
+        "folder": exported,
+        "images": ["beach.png"],
+        "format": "jpeg",
+        "max_size": 300,
+    });
+    // Folders aren't made up.
+    let params =
+        json!({ "name": "export_images", "arguments": arguments.clone() });
+    let response = request(&server, 6, "tools/call", params);
+    assert_eq!(response["result"]["isError"], true);
+    assert!(!exported.exists());
+    std::fs::create_dir(&exported).unwrap();
+    call_tool(&server, "export_images", arguments.clone());
+    assert!(exported.join("beach.jpg").is_file());
+
+    // Nothing is overwritten, least of all the originals.
+    let params = json!({ "name": "export_images", "arguments": arguments });
+    let response = request(&server, 7, "tools/call", params);
+    assert_eq!(response["result"]["isError"], true);
+    let arguments = json!({ "folder": dir, "format": "png" });
+    let params = json!({ "name": "export_images", "arguments": arguments });
+    let response = request(&server, 8, "tools/call", params);
+    assert_eq!(response["result"]["isError"], true);
+    let original = std::fs::read(common::placeholder()).unwrap();
+    assert_eq!(std::fs::read(dir.join("city.png")).unwrap(), original);
+
+    let response = request(&server, 9, "resources/unknown", json!({}));
+    assert_eq!(response["error"]["code"], -32601);
+}
+
+// As other tools write them, with attributes on the properties and alt text
+// in several languages.
+const FOREIGN_SIDECAR: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:dc="http://purl.org/dc/elements/1.1/"
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/">
+   <xmp:Rating rdf:datatype="http://www.w3.org/2001/XMLSchema#integer">4</xmp:Rating>
+   <dc:description xml:space="preserve">
+    <rdf:Alt>
+     <rdf:li xml:lang="de">Ein Strand.</rdf:li>
+     <rdf:li xml:lang="x-default">A beach.</rdf:li>
+    </rdf:Alt>
+   </dc:description>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+"#;
+
+#[test]
+fn sidecars_from_other_tools_are_read_and_edited_in_place() {
+    let folder = TempLibrary::new("mcp_sidecars");
+    let sources = folder.images(&["beach.png"]);
+    let sidecar = folder.dir().join("beach.png.xmp");
+    std::fs::write(&sidecar, FOREIGN_SIDECAR).unwrap();
+
+    let library = FakeLibrary::default();
+    *library.0.lock().unwrap() = LibraryView {
+        images: sources.clone(),
+        shown: sources,
+        ..Default::default()
+    };
+    let server = McpServer::new(library);
+
+    let params = json!({ "uri": "image-viewer://images/0" });
+    let response = request(&server, 1, "resources/read", params);
+    let text = response["result"]["contents"][0]["text"].as_str().unwrap();
+    let metadata: Value = serde_json::from_str(text).unwrap();
+    assert_eq!(metadata["rating"], 4);
+    assert_eq!(metadata["description"], "A beach.");
+
+    let arguments = json!({ "rating": "two", "images": ["beach.png"] });
+    let params = json!({ "name": "set_rating", "arguments": arguments });
+    let response = request(&server, 2, "tools/call", params);
+    assert_eq!(response["result"]["isError"], true);
+    let arguments = json!({ "rating": "2", "images": ["beach.png"] });
+    call_tool(&server, "set_rating", arguments);
+    let xmp = std::fs::read_to_string(&sidecar).unwrap();
+    assert_eq!(xmp.matches("<xmp:Rating").count(), 1, "{xmp}");
+    assert!(xmp.contains("<xmp:Rating>2</xmp:Rating>"), "{xmp}");
+    assert!(xmp.contains("Ein Strand."), "{xmp}");
+}
+
+#[cfg(unix)]
+#[test]
+fn only_the_user_can_connect_to_the_socket() {
+    use image_viewer::mcp::serve_socket;
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = TempLibrary::new("mcp_socket");
+    let socket = temp.dir().join("mcp.sock");
+    let address = socket.to_str().unwrap();
+    let server = McpServer::new(FakeLibrary::default());
+    serve_socket(server.clone(), address).unwrap();
+    let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    // Whatever else is at the path stays.
+    let file = temp.dir().join("notes.txt");
+    std::fs::write(&file, "keep").unwrap();
+    assert!(serve_socket(server, file.to_str().unwrap()).is_err());
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "keep");
+}